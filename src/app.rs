@@ -1,13 +1,41 @@
 use crossterm::event::{KeyCode, KeyEvent};
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::time::{Duration, Instant};
 
-use crate::mail::{Account, Message};
-use crate::storage::Config;
+use crate::mail::{
+    build_server_threads, build_threads, folder_tree, resolve_mappings, Account, AuthMethod, Flag,
+    Folder, FolderNode, FolderOperation, MailClient, MailError, Message, ServerThread, Thread,
+    ThreadNode,
+};
+use crate::search::{Query, SearchEngine};
+use crate::storage::{
+    Config, Database, FetchedRange, MailOperation, PreviewPosition, SavedSearch, SearchHit,
+};
+
+/// 同期でサーバーから取得するメッセージ数
+const SYNC_MESSAGE_LIMIT: usize = 50;
+
+/// サーバー側の検索で取得するメッセージ数
+const SERVER_SEARCH_LIMIT: usize = 50;
+
+/// オフライン操作の反映をこの回数失敗したら破棄する
+const MAX_REPLAY_ATTEMPTS: u32 = 3;
+
+/// 選択がこの時間変わらなければプレビューの本文を取得する（スクロール中に取得しないため）
+const PREVIEW_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// ペインの大きさ（%）を変えるときの刻みと範囲
+const PANE_RESIZE_STEP: u16 = 5;
+const PANE_MIN_PERCENT: u16 = 10;
+const PANE_MAX_PERCENT: u16 = 90;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AppMode {
     MailList,
     MailView,
+    ThreadView,
     Compose,
     Help,
     #[allow(dead_code)]
@@ -20,6 +48,96 @@ pub enum InputMode {
     Search,
     #[allow(dead_code)]
     Command,
+    Label,
+    /// 移動先フォルダーの入力
+    Move,
+    /// 検索を保存するときの名前の入力
+    SaveSearch,
+    /// 作成するフォルダー・変更後のフォルダー名の入力
+    FolderName,
+    /// フォルダー操作の確認（y/n）
+    Confirm,
+}
+
+/// メール一覧の1行（スレッド表示では木の中の位置も持つ）
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageRow {
+    /// `App::messages` での位置
+    pub index: usize,
+    /// スレッドの木の深さ（スレッド表示でなければ常に0）
+    pub depth: usize,
+    /// このメッセージより下にある返信の数
+    pub replies: usize,
+    /// 返信を畳んでいるか
+    pub collapsed: bool,
+}
+
+/// フォルダー一覧の項目
+#[derive(Debug, Clone, PartialEq)]
+pub enum FolderEntry {
+    /// サーバーのフォルダー（未読数はキャッシュから数える）
+    Folder {
+        node: FolderNode,
+        unread: usize,
+        /// 子フォルダーを畳んでいるか
+        collapsed: bool,
+    },
+    /// 保存した検索（開くと全アカウントを検索し直す）
+    SavedSearch {
+        name: String,
+        query: String,
+        unread: usize,
+    },
+}
+
+/// キー操作から発生する、メールクライアントを必要とする非同期処理
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppAction {
+    /// 現在のフォルダーをサーバーと同期してキャッシュに反映
+    SyncFolder,
+    /// オフライン中の操作をサーバーへ反映（アカウントID）
+    ReplayJournal(String),
+    LoadThreads,
+    OpenThread(String),
+    /// 会話をアーカイブ（アカウントID・スレッドID）
+    ArchiveThread {
+        account_id: String,
+        thread_id: String,
+    },
+    /// 会話をミュート（アカウントID・スレッドID）
+    MuteThread {
+        account_id: String,
+        thread_id: String,
+    },
+    LabelThread {
+        account_id: String,
+        thread_id: String,
+        label: String,
+    },
+    /// キャッシュの検索結果にサーバー側の検索結果を加える（検索クエリ）
+    SearchServer(String),
+    /// 確認済みのフォルダー操作をサーバーで実行
+    ManageFolder(FolderOperation),
+    /// 本文を省いて取得したメッセージの本文を取得（アカウントID・フォルダー・メッセージID）
+    LoadBody {
+        account_id: String,
+        folder: String,
+        id: String,
+    },
+}
+
+impl AppAction {
+    /// 現在のアカウントではなく、特定のアカウントを対象にする処理ならそのID
+    fn account_id(&self) -> Option<&str> {
+        match self {
+            AppAction::ReplayJournal(account_id)
+            | AppAction::ArchiveThread { account_id, .. }
+            | AppAction::MuteThread { account_id, .. }
+            | AppAction::LabelThread { account_id, .. }
+            | AppAction::LoadBody { account_id, .. } => Some(account_id),
+            _ => None,
+        }
+    }
 }
 
 pub struct App {
@@ -33,11 +151,54 @@ pub struct App {
     pub current_message: Option<Message>,
     pub accounts: Vec<Account>,
     pub current_account_index: usize,
-    #[allow(dead_code)]
     pub current_folder: String,
+    /// 現在のアカウントのフォルダー一覧（サーバーから取得したもの、なければキャッシュ）
+    pub folders: Vec<Folder>,
+    /// フォルダーごとの未読数（キャッシュから数える）
+    pub folder_unread: HashMap<String, usize>,
+    /// 子フォルダーを畳んでいるフォルダー
+    pub collapsed_folders: HashSet<String>,
+    /// スレッド表示で返信を畳んでいるメッセージ
+    pub collapsed_threads: HashSet<(String, String, String)>,
+    /// 組み立て済みのメール一覧の行（`invalidate_message_rows` で捨てる）
+    message_rows_cache: OnceCell<Vec<MessageRow>>,
+    /// サーバーが組んだスレッドの木（アカウントID・フォルダー・木）
+    server_threads: Option<(String, String, Vec<ServerThread>)>,
+    /// プレビューで選択中のメッセージ、選択した時刻、本文の取得を頼んだか
+    preview_selection: Option<((String, String, String), Instant, bool)>,
+    /// 名前の入力中・確認中のフォルダー操作
+    pub folder_operation: Option<FolderOperation>,
+    pub folder_name_input: String,
     pub search_query: String,
+    /// 表示中の検索結果のクエリ（フォルダーを表示しているときはNone）
+    pub active_search: Option<String>,
+    /// 開いている保存した検索の名前
+    pub current_saved_search: Option<String>,
+    /// 保存した検索と未読数
+    pub saved_searches: Vec<(SavedSearch, usize)>,
+    pub search_name_input: String,
+    /// フォルダー一覧を操作中か（Tabで切り替え）
+    pub folder_focus: bool,
+    /// 検索結果のスニペット（アカウントID・フォルダー・メッセージIDごと）
+    pub search_snippets: HashMap<(String, String, String), String>,
     pub status_message: String,
     pub config: Config,
+    pub conversation_mode: bool,
+    pub threads: Vec<Thread>,
+    pub current_thread: Option<Thread>,
+    pub show_quoted: bool,
+    pub thread_scroll: u16,
+    pub label_input: String,
+    pub pending_actions: Vec<AppAction>,
+    /// ローカルのメッセージキャッシュ（開けなかった場合はNone）
+    pub database: Option<Database>,
+    /// 最後の通信でサーバーに到達できたか
+    pub online: bool,
+    /// ユーザーが明示的にオフラインで作業しているか
+    pub work_offline: bool,
+    /// サーバーへ未反映の操作数
+    pub pending_operation_count: usize,
+    pub folder_input: String,
 }
 
 impl Default for App {
@@ -54,9 +215,36 @@ impl Default for App {
             accounts: Vec::new(),
             current_account_index: 0,
             current_folder: "INBOX".to_string(),
+            folders: Vec::new(),
+            folder_unread: HashMap::new(),
+            collapsed_folders: HashSet::new(),
+            collapsed_threads: HashSet::new(),
+            message_rows_cache: OnceCell::new(),
+            server_threads: None,
+            preview_selection: None,
+            folder_operation: None,
+            folder_name_input: String::new(),
             search_query: String::new(),
+            active_search: None,
+            current_saved_search: None,
+            saved_searches: Vec::new(),
+            search_name_input: String::new(),
+            folder_focus: false,
+            search_snippets: HashMap::new(),
             status_message: "Ready".to_string(),
             config: Config::default(),
+            conversation_mode: false,
+            threads: Vec::new(),
+            current_thread: None,
+            show_quoted: false,
+            thread_scroll: 0,
+            label_input: String::new(),
+            pending_actions: Vec::new(),
+            database: None,
+            online: false,
+            work_offline: false,
+            pending_operation_count: 0,
+            folder_input: String::new(),
         };
 
         // デフォルトで最初のアイテムを選択
@@ -94,22 +282,65 @@ impl App {
             InputMode::Normal => self.handle_normal_key_event(key_event),
             InputMode::Search => self.handle_search_key_event(key_event),
             InputMode::Command => self.handle_command_key_event(key_event),
+            InputMode::Label => self.handle_label_key_event(key_event),
+            InputMode::Move => self.handle_move_key_event(key_event),
+            InputMode::SaveSearch => self.handle_save_search_key_event(key_event),
+            InputMode::FolderName => self.handle_folder_name_key_event(key_event),
+            InputMode::Confirm => self.handle_confirm_key_event(key_event),
         }
     }
 
     fn handle_normal_key_event(&mut self, key_event: KeyEvent) -> Result<(), Box<dyn Error>> {
         match self.mode {
+            AppMode::MailList if self.folder_focus => match key_event.code {
+                KeyCode::Char('q') => self.quit(),
+                KeyCode::Char('h') => self.show_help(),
+                KeyCode::Tab | KeyCode::Esc => self.folder_focus = false,
+                KeyCode::Char('j') | KeyCode::Down => self.select_next_folder(),
+                KeyCode::Char('k') | KeyCode::Up => self.select_previous_folder(),
+                KeyCode::Enter => self.open_selected_folder(),
+                KeyCode::Char(' ') => self.toggle_selected_folder(),
+                KeyCode::Right => self.expand_selected_folder(true),
+                KeyCode::Left => self.expand_selected_folder(false),
+                KeyCode::Char('x') => self.delete_selected_saved_search(),
+                KeyCode::Char('n') => self.start_create_folder(),
+                KeyCode::Char('r') => self.start_rename_folder(),
+                KeyCode::Char('d') => self.confirm_delete_folder(),
+                KeyCode::Char('s') => self.confirm_toggle_subscription(),
+                KeyCode::Char('A') => self.toggle_subscribed_only(),
+                _ => {}
+            },
             AppMode::MailList => match key_event.code {
                 KeyCode::Char('q') => self.quit(),
+                KeyCode::Tab => self.folder_focus = true,
+                KeyCode::Char('S') => self.start_save_search_input(),
                 KeyCode::Char('h') => self.show_help(),
                 KeyCode::Char('j') | KeyCode::Down => self.select_next_mail(),
                 KeyCode::Char('k') | KeyCode::Up => self.select_previous_mail(),
+                KeyCode::Enter if self.conversation_mode => self.open_selected_thread(),
                 KeyCode::Enter => self.open_selected_mail(),
+                KeyCode::Char('t') => self.toggle_conversation_mode(),
+                KeyCode::Char('T') => self.toggle_thread_tree(),
+                KeyCode::Char('p') => self.toggle_preview_pane(),
+                KeyCode::Char('P') => self.toggle_preview_position(),
+                KeyCode::Char('+') => self.resize_message_list(true),
+                KeyCode::Char('-') => self.resize_message_list(false),
+                KeyCode::Char('>') => self.resize_folder_pane(true),
+                KeyCode::Char('<') => self.resize_folder_pane(false),
+                KeyCode::Char(' ') if !self.conversation_mode => self.toggle_selected_thread(),
+                KeyCode::Char('a') if self.conversation_mode => self.archive_selected_thread(),
+                KeyCode::Char('M') if self.conversation_mode => self.mute_selected_thread(),
+                KeyCode::Char('L') if self.conversation_mode => self.start_label_input(),
                 KeyCode::Char('c') => self.mode = AppMode::Compose,
                 KeyCode::Char('r') => self.reply_to_selected_mail(),
                 KeyCode::Char('R') => self.reply_all_to_selected_mail(),
                 KeyCode::Char('f') => self.forward_selected_mail(),
                 KeyCode::Char('d') => self.delete_selected_mail(),
+                KeyCode::Char('u') => self.toggle_read(),
+                KeyCode::Char('s') => self.toggle_star(),
+                KeyCode::Char('m') => self.start_move_input(),
+                KeyCode::Char('g') => self.request_sync(),
+                KeyCode::Char('o') => self.toggle_offline(),
                 KeyCode::Char('/') => {
                     self.input_mode = InputMode::Search;
                     self.search_query.clear();
@@ -123,6 +354,27 @@ impl App {
                 KeyCode::Char('R') => self.reply_all_to_current_mail(),
                 KeyCode::Char('f') => self.forward_current_mail(),
                 KeyCode::Char('d') => self.delete_current_mail(),
+                KeyCode::Char('u') => self.toggle_read(),
+                KeyCode::Char('s') => self.toggle_star(),
+                KeyCode::Char('m') => self.start_move_input(),
+                _ => {}
+            },
+            AppMode::ThreadView => match key_event.code {
+                KeyCode::Char('q') | KeyCode::Esc => {
+                    self.mode = AppMode::MailList;
+                    self.current_thread = None;
+                }
+                KeyCode::Char('h') => self.show_help(),
+                KeyCode::Char('j') | KeyCode::Down => {
+                    self.thread_scroll = self.thread_scroll.saturating_add(1);
+                }
+                KeyCode::Char('k') | KeyCode::Up => {
+                    self.thread_scroll = self.thread_scroll.saturating_sub(1);
+                }
+                KeyCode::Char('z') => self.show_quoted = !self.show_quoted,
+                KeyCode::Char('a') => self.archive_current_thread(),
+                KeyCode::Char('M') => self.mute_current_thread(),
+                KeyCode::Char('L') => self.start_label_input(),
                 _ => {}
             },
             AppMode::Compose => match key_event.code {
@@ -171,11 +423,275 @@ impl App {
         Ok(())
     }
 
+    fn handle_label_key_event(&mut self, key_event: KeyEvent) -> Result<(), Box<dyn Error>> {
+        match key_event.code {
+            KeyCode::Enter => {
+                let label = self.label_input.trim().to_string();
+                if let Some(thread) = self.target_thread() {
+                    if !label.is_empty() {
+                        let action = AppAction::LabelThread {
+                            account_id: thread.account_id.clone(),
+                            thread_id: thread.id.clone(),
+                            label,
+                        };
+                        self.pending_actions.push(action);
+                    }
+                }
+                self.label_input.clear();
+                self.input_mode = InputMode::Normal;
+            }
+            KeyCode::Esc => {
+                self.label_input.clear();
+                self.input_mode = InputMode::Normal;
+            }
+            KeyCode::Char(c) => {
+                self.label_input.push(c);
+            }
+            KeyCode::Backspace => {
+                self.label_input.pop();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_move_key_event(&mut self, key_event: KeyEvent) -> Result<(), Box<dyn Error>> {
+        match key_event.code {
+            KeyCode::Enter => {
+                let to_folder = self.folder_input.trim().to_string();
+                if let Some(message) = self.target_message() {
+                    if !to_folder.is_empty() && to_folder != message.folder {
+                        self.perform_operation(&message, MailOperation::Move { to_folder });
+                    }
+                }
+                self.folder_input.clear();
+                self.input_mode = InputMode::Normal;
+            }
+            KeyCode::Esc => {
+                self.folder_input.clear();
+                self.input_mode = InputMode::Normal;
+            }
+            KeyCode::Char(c) => {
+                self.folder_input.push(c);
+            }
+            KeyCode::Backspace => {
+                self.folder_input.pop();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_save_search_key_event(&mut self, key_event: KeyEvent) -> Result<(), Box<dyn Error>> {
+        match key_event.code {
+            KeyCode::Enter => {
+                let name = self.search_name_input.trim().to_string();
+                if !name.is_empty() {
+                    self.save_active_search(&name);
+                }
+                self.search_name_input.clear();
+                self.input_mode = InputMode::Normal;
+            }
+            KeyCode::Esc => {
+                self.search_name_input.clear();
+                self.input_mode = InputMode::Normal;
+            }
+            KeyCode::Char(c) => {
+                self.search_name_input.push(c);
+            }
+            KeyCode::Backspace => {
+                self.search_name_input.pop();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_folder_name_key_event(&mut self, key_event: KeyEvent) -> Result<(), Box<dyn Error>> {
+        match key_event.code {
+            KeyCode::Enter => {
+                let name = self.folder_name_input.trim().to_string();
+                self.folder_name_input.clear();
+                self.folder_operation = match self.folder_operation.take() {
+                    Some(FolderOperation::Create(_)) if !name.is_empty() => {
+                        Some(FolderOperation::Create(name))
+                    }
+                    Some(FolderOperation::Rename { from, .. })
+                        if !name.is_empty() && name != from =>
+                    {
+                        Some(FolderOperation::Rename { from, to: name })
+                    }
+                    _ => None,
+                };
+                self.input_mode = if self.folder_operation.is_some() {
+                    InputMode::Confirm
+                } else {
+                    InputMode::Normal
+                };
+            }
+            KeyCode::Esc => {
+                self.folder_name_input.clear();
+                self.folder_operation = None;
+                self.input_mode = InputMode::Normal;
+            }
+            KeyCode::Char(c) => {
+                self.folder_name_input.push(c);
+            }
+            KeyCode::Backspace => {
+                self.folder_name_input.pop();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_confirm_key_event(&mut self, key_event: KeyEvent) -> Result<(), Box<dyn Error>> {
+        match key_event.code {
+            KeyCode::Char('y') | KeyCode::Char('Y') | KeyCode::Enter => {
+                if let Some(operation) = self.folder_operation.take() {
+                    self.status_message = "サーバーに反映しています...".to_string();
+                    self.pending_actions
+                        .push(AppAction::ManageFolder(operation));
+                }
+                self.input_mode = InputMode::Normal;
+            }
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                self.folder_operation = None;
+                self.status_message = "キャンセルしました".to_string();
+                self.input_mode = InputMode::Normal;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 一覧に表示中の項目数（会話モードではスレッド数）
+    fn list_len(&self) -> usize {
+        if self.conversation_mode {
+            self.threads.len()
+        } else {
+            self.message_rows().len()
+        }
+    }
+
+    /// スレッドの木で表示するか（検索結果は関連度順のまま表示する）
+    pub fn thread_tree_enabled(&self) -> bool {
+        self.config.ui.show_thread_tree && self.active_search.is_none()
+    }
+
+    /// メール一覧に表示する行
+    ///
+    /// スレッド表示では木を親の直後に子が来る順に並べ、畳んだメッセージの返信は含めない。
+    /// 木は表示中のフォルダーをサーバーが組んだものがあればそれを使い、なければJWZアルゴリズムで組む。
+    /// 組んだ行は一覧が変わるまで使い回す。
+    pub fn message_rows(&self) -> &[MessageRow] {
+        self.message_rows_cache
+            .get_or_init(|| self.build_message_rows())
+    }
+
+    /// メッセージ・畳んだスレッド・表示方法を変えたら呼び、次の描画で行を組み直す
+    fn invalidate_message_rows(&mut self) {
+        self.message_rows_cache.take();
+    }
+
+    fn build_message_rows(&self) -> Vec<MessageRow> {
+        if !self.thread_tree_enabled() {
+            return (0..self.messages.len())
+                .map(|index| MessageRow {
+                    index,
+                    depth: 0,
+                    replies: 0,
+                    collapsed: false,
+                })
+                .collect();
+        }
+
+        let nodes = match &self.server_threads {
+            Some((account_id, folder, threads))
+                if self
+                    .messages
+                    .iter()
+                    .all(|m| &m.account_id == account_id && &m.folder == folder) =>
+            {
+                build_server_threads(&self.messages, threads)
+            }
+            _ => build_threads(&self.messages),
+        };
+
+        let mut rows = Vec::new();
+        for node in nodes {
+            self.push_thread_rows(&node, 0, &mut rows);
+        }
+        rows
+    }
+
+    fn push_thread_rows(&self, node: &ThreadNode, depth: usize, rows: &mut Vec<MessageRow>) {
+        let collapsed = !node.children.is_empty()
+            && self
+                .collapsed_threads
+                .contains(&message_key(&self.messages[node.message]));
+        rows.push(MessageRow {
+            index: node.message,
+            depth,
+            replies: node.reply_count(),
+            collapsed,
+        });
+        if !collapsed {
+            for child in &node.children {
+                self.push_thread_rows(child, depth + 1, rows);
+            }
+        }
+    }
+
+    /// 一覧で選択中のメッセージ（プレビューに表示するもの）
+    pub fn selected_message(&self) -> Option<&Message> {
+        let selected = self.mail_list_state.selected()?;
+        let row = self.message_rows().get(selected)?;
+        self.messages.get(row.index)
+    }
+
+    /// 選択中のメッセージの返信を畳む・開く
+    fn toggle_selected_thread(&mut self) {
+        let Some(selected) = self.mail_list_state.selected() else {
+            return;
+        };
+        let Some(row) = self.message_rows().get(selected).cloned() else {
+            return;
+        };
+        if row.replies == 0 {
+            return;
+        }
+        let key = message_key(&self.messages[row.index]);
+        if !self.collapsed_threads.remove(&key) {
+            self.collapsed_threads.insert(key);
+        }
+        self.invalidate_message_rows();
+    }
+
+    /// スレッド表示と日付順の表示を切り替える（選択中のメッセージは保つ）
+    fn toggle_thread_tree(&mut self) {
+        let selected = self.selected_message().map(message_key);
+        self.config.ui.show_thread_tree = !self.config.ui.show_thread_tree;
+        self.invalidate_message_rows();
+        self.status_message = if self.config.ui.show_thread_tree {
+            "スレッド表示に切り替えました".to_string()
+        } else {
+            "日付順の表示に切り替えました".to_string()
+        };
+
+        let rows = self.message_rows();
+        let position = selected.and_then(|key| {
+            rows.iter()
+                .position(|row| message_key(&self.messages[row.index]) == key)
+        });
+        self.mail_list_state.select(Some(position.unwrap_or(0)));
+    }
+
     // メール操作メソッド
     fn select_next_mail(&mut self) {
         let i = match self.mail_list_state.selected() {
             Some(i) => {
-                if i >= self.messages.len().saturating_sub(1) {
+                if i >= self.list_len().saturating_sub(1) {
                     0
                 } else {
                     i + 1
@@ -190,7 +706,7 @@ impl App {
         let i = match self.mail_list_state.selected() {
             Some(i) => {
                 if i == 0 {
-                    self.messages.len().saturating_sub(1)
+                    self.list_len().saturating_sub(1)
                 } else {
                     i - 1
                 }
@@ -201,10 +717,871 @@ impl App {
     }
 
     fn open_selected_mail(&mut self) {
-        if let Some(i) = self.mail_list_state.selected() {
-            if let Some(message) = self.messages.get(i) {
-                self.current_message = Some(message.clone());
-                self.mode = AppMode::MailView;
+        if let Some(message) = self.selected_message().cloned() {
+            self.current_message = Some(message.clone());
+            self.mode = AppMode::MailView;
+            if message.body_pending && self.is_online() {
+                self.request_body(&message);
+            }
+            if message.is_unread() {
+                self.perform_operation(
+                    &message,
+                    MailOperation::SetFlag {
+                        flag: Flag::Seen,
+                        enabled: true,
+                    },
+                );
+            }
+        }
+    }
+
+    /// プレビューを表示するか（メール一覧を表示中で、会話モードでないとき）
+    pub fn preview_visible(&self) -> bool {
+        self.config.ui.show_preview_pane
+            && self.mode == AppMode::MailList
+            && !self.conversation_mode
+    }
+
+    /// 選択が落ち着いたら、プレビューするメッセージの本文を取得する
+    ///
+    /// メインループから定期的に呼ぶ。選択中のメッセージが `PREVIEW_DEBOUNCE` の間
+    /// 変わらなかったときだけ取得を頼むので、スクロール中は通信しない。
+    pub fn tick(&mut self) {
+        if !self.preview_visible() {
+            self.preview_selection = None;
+            return;
+        }
+        let Some(message) = self.selected_message().filter(|m| m.body_pending) else {
+            self.preview_selection = None;
+            return;
+        };
+        let key = message_key(message);
+
+        match &self.preview_selection {
+            Some((selected, since, requested)) if *selected == key => {
+                if *requested || since.elapsed() < PREVIEW_DEBOUNCE || !self.is_online() {
+                    return;
+                }
+                let message = message.clone();
+                self.request_body(&message);
+                self.preview_selection = Some((key, Instant::now(), true));
+            }
+            _ => self.preview_selection = Some((key, Instant::now(), false)),
+        }
+    }
+
+    fn request_body(&mut self, message: &Message) {
+        // 検索結果では他のアカウントのメッセージも開ける
+        let action = AppAction::LoadBody {
+            account_id: message.account_id.clone(),
+            folder: message.folder.clone(),
+            id: message.id.clone(),
+        };
+        if !self.pending_actions.contains(&action) {
+            self.pending_actions.push(action);
+        }
+    }
+
+    fn toggle_preview_pane(&mut self) {
+        self.config.ui.show_preview_pane = !self.config.ui.show_preview_pane;
+        self.status_message = if self.config.ui.show_preview_pane {
+            "プレビューを表示しています".to_string()
+        } else {
+            "プレビューを隠しました".to_string()
+        };
+    }
+
+    fn toggle_preview_position(&mut self) {
+        self.config.ui.preview_position = match self.config.ui.preview_position {
+            PreviewPosition::Bottom => PreviewPosition::Right,
+            PreviewPosition::Right => PreviewPosition::Bottom,
+        };
+        self.config.ui.show_preview_pane = true;
+        self.status_message = match self.config.ui.preview_position {
+            PreviewPosition::Bottom => "プレビューを一覧の下に表示しています".to_string(),
+            PreviewPosition::Right => "プレビューを一覧の右に表示しています".to_string(),
+        };
+    }
+
+    /// メール一覧を大きく・小さくする（残りがプレビュー）
+    fn resize_message_list(&mut self, grow: bool) {
+        if !self.config.ui.show_preview_pane {
+            return;
+        }
+        let size = match self.config.ui.preview_position {
+            PreviewPosition::Bottom => &mut self.config.ui.message_list_height,
+            PreviewPosition::Right => &mut self.config.ui.message_list_width,
+        };
+        *size = resize_percent(*size, grow);
+        self.status_message = format!("メール一覧: {}%", size);
+    }
+
+    fn resize_folder_pane(&mut self, grow: bool) {
+        let width = resize_percent(self.config.ui.folder_pane_width, grow);
+        self.config.ui.folder_pane_width = width;
+        self.status_message = format!("フォルダー一覧: {}%", width);
+    }
+
+    /// 省いていた本文をサーバーから取得し、キャッシュと表示中のメッセージを置き換える
+    async fn load_body(
+        &mut self,
+        mail_client: &MailClient,
+        account: &Account,
+        folder: String,
+        id: String,
+    ) {
+        if !self.ensure_connected(mail_client, account).await {
+            return;
+        }
+        let mut message = match mail_client
+            .fetch_full_message(&account.id, &folder, &id)
+            .await
+        {
+            Ok(message) => message,
+            Err(e) => {
+                if matches!(e, MailError::Connection(_)) {
+                    self.online = false;
+                }
+                self.status_message = format!("本文の取得に失敗しました: {}", e);
+                return;
+            }
+        };
+
+        // フラグと日付は一覧で取得したもの（オフライン中の操作を反映済み）を使う
+        let key = (account.id.clone(), folder, id);
+        if let Some(listed) = self.messages.iter().find(|m| message_key(m) == key) {
+            message.flags = listed.flags.clone();
+            message.date = listed.date;
+            message.thread_id = listed.thread_id.clone();
+        }
+
+        if let Some(database) = self.database.as_mut() {
+            if let Err(e) = database.store_message(&message) {
+                self.status_message = format!("本文をキャッシュに保存できませんでした: {}", e);
+            }
+        }
+        if let Some(listed) = self.messages.iter_mut().find(|m| message_key(m) == key) {
+            *listed = message.clone();
+            self.invalidate_message_rows();
+        }
+        if let Some(current) = self
+            .current_message
+            .as_mut()
+            .filter(|m| message_key(m) == key)
+        {
+            *current = message;
+        }
+    }
+
+    // スレッド操作メソッド
+    /// キャッシュ済みのメッセージを表示（ネットワークを使わない）
+    pub fn load_cached_messages(&mut self) {
+        let Some(account_id) = self.get_current_account().map(|a| a.id.clone()) else {
+            return;
+        };
+        let Some(database) = self.database.as_ref() else {
+            return;
+        };
+
+        match database.get_messages(
+            &account_id,
+            &self.current_folder,
+            Some(self.config.app.max_messages_per_folder),
+            None,
+        ) {
+            Ok(messages) => {
+                self.status_message =
+                    format!("キャッシュから {} 件を表示しています", messages.len());
+                self.messages = messages;
+                self.clear_search_results();
+                self.clamp_mail_selection();
+            }
+            Err(e) => {
+                self.status_message = format!("キャッシュの読み込みに失敗しました: {}", e);
+            }
+        }
+        self.refresh_pending_count();
+        self.refresh_folders();
+        self.refresh_saved_searches();
+    }
+
+    fn refresh_pending_count(&mut self) {
+        if let Some(database) = self.database.as_ref() {
+            self.pending_operation_count = database.pending_operation_count().unwrap_or(0);
+        }
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online && !self.work_offline
+    }
+
+    /// ステータスバーに出す接続状態と未送信の操作数
+    pub fn connection_status(&self) -> String {
+        let state = if self.is_online() {
+            "オンライン"
+        } else {
+            "オフライン"
+        };
+        format!("[{}] 未送信: {}", state, self.pending_operation_count)
+    }
+
+    fn toggle_offline(&mut self) {
+        self.work_offline = !self.work_offline;
+        if self.work_offline {
+            self.status_message =
+                "オフラインモードに切り替えました（操作は接続時に反映します）".to_string();
+        } else {
+            self.request_sync();
+        }
+    }
+
+    /// 操作対象のメッセージ（メール表示中はそのメッセージ、一覧では選択中のもの）
+    fn target_message(&self) -> Option<Message> {
+        match self.mode {
+            AppMode::MailView => self.current_message.clone(),
+            AppMode::MailList if !self.conversation_mode => self.selected_message().cloned(),
+            _ => None,
+        }
+    }
+
+    fn toggle_read(&mut self) {
+        if let Some(message) = self.target_message() {
+            let enabled = message.is_unread();
+            self.perform_operation(
+                &message,
+                MailOperation::SetFlag {
+                    flag: Flag::Seen,
+                    enabled,
+                },
+            );
+        }
+    }
+
+    fn toggle_star(&mut self) {
+        if let Some(message) = self.target_message() {
+            let enabled = !message.is_flagged();
+            self.perform_operation(
+                &message,
+                MailOperation::SetFlag {
+                    flag: Flag::Flagged,
+                    enabled,
+                },
+            );
+        }
+    }
+
+    fn start_move_input(&mut self) {
+        if self.target_message().is_some() {
+            self.folder_input.clear();
+            self.input_mode = InputMode::Move;
+        }
+    }
+
+    /// 操作をキャッシュとジャーナルに記録し、オンラインならサーバーへの反映を予約
+    ///
+    /// ネットワークがなくてもキャッシュ上はすぐに反映される。
+    fn perform_operation(&mut self, message: &Message, operation: MailOperation) {
+        let Some(database) = self.database.as_mut() else {
+            self.status_message = "キャッシュが利用できないため操作できません".to_string();
+            return;
+        };
+
+        if let Err(e) = database.record_operation(
+            &message.account_id,
+            &message.folder,
+            &message.id,
+            &operation,
+        ) {
+            self.status_message = format!("操作の記録に失敗しました: {}", e);
+            return;
+        }
+
+        let description = match &operation {
+            MailOperation::SetFlag {
+                flag: Flag::Seen,
+                enabled: true,
+            } => "既読にしました".to_string(),
+            MailOperation::SetFlag {
+                flag: Flag::Seen,
+                enabled: false,
+            } => "未読にしました".to_string(),
+            MailOperation::SetFlag { enabled: true, .. } => "スターを付けました".to_string(),
+            MailOperation::SetFlag { .. } => "スターを外しました".to_string(),
+            MailOperation::Move { to_folder } => format!("「{}」へ移動しました", to_folder),
+            MailOperation::Delete => "削除しました".to_string(),
+        };
+
+        self.apply_operation_to_view(message, &operation);
+        self.refresh_pending_count();
+        self.refresh_folders();
+        self.refresh_saved_searches();
+
+        if self.is_online() {
+            self.status_message = description;
+            // 検索結果では他のアカウントのメッセージも操作できる
+            let action = AppAction::ReplayJournal(message.account_id.clone());
+            if !self.pending_actions.contains(&action) {
+                self.pending_actions.push(action);
+            }
+        } else {
+            self.status_message = format!("{}（オフライン: 接続時に反映します）", description);
+        }
+    }
+
+    /// 表示中の一覧とメッセージに操作を反映
+    fn apply_operation_to_view(&mut self, target: &Message, operation: &MailOperation) {
+        // 検索結果には別のアカウントやフォルダーの同じIDが並ぶことがある
+        let key = message_key(target);
+        match operation {
+            MailOperation::SetFlag { flag, enabled } => {
+                let targets = self
+                    .messages
+                    .iter_mut()
+                    .filter(|m| message_key(m) == key)
+                    .chain(
+                        self.current_message
+                            .iter_mut()
+                            .filter(|m| message_key(m) == key),
+                    );
+                for message in targets {
+                    message.flags.retain(|f| f != flag);
+                    if *enabled {
+                        message.flags.push(flag.clone());
+                    }
+                }
+            }
+            MailOperation::Move { .. } | MailOperation::Delete => {
+                self.messages.retain(|m| message_key(m) != key);
+                self.invalidate_message_rows();
+                self.clamp_mail_selection();
+                if self
+                    .current_message
+                    .as_ref()
+                    .is_some_and(|m| message_key(m) == key)
+                {
+                    self.current_message = None;
+                    self.mode = AppMode::MailList;
+                }
+            }
+        }
+    }
+
+    /// 現在のフォルダーの同期を予約
+    pub fn request_sync(&mut self) {
+        if self.work_offline {
+            self.status_message = "オフラインモードです（o でオンラインに戻ります）".to_string();
+            return;
+        }
+        if !self.pending_actions.contains(&AppAction::SyncFolder) {
+            self.status_message = "サーバーと同期しています...".to_string();
+            self.pending_actions.push(AppAction::SyncFolder);
+        }
+    }
+
+    fn clamp_mail_selection(&mut self) {
+        let selected = self.mail_list_state.selected().unwrap_or(0);
+        self.mail_list_state.select(Some(
+            selected.min(self.message_rows().len().saturating_sub(1)),
+        ));
+    }
+
+    /// 必要ならサーバーへ接続し、オンラインかどうかを返す
+    async fn ensure_connected(&mut self, mail_client: &MailClient, account: &Account) -> bool {
+        if self.work_offline {
+            return false;
+        }
+
+        if account.imap.auth_method == AuthMethod::OAuth2 && account.tokens.is_none() {
+            self.status_message =
+                "OAuth2認証が完了していません。認証後に再起動してください。".to_string();
+            return false;
+        }
+
+        if !mail_client.is_connected(&account.id).await {
+            if let Err(e) = mail_client.connect_imap(&account.id).await {
+                self.online = false;
+                self.status_message = format!("接続できません（オフラインで動作中）: {}", e);
+                return false;
+            }
+        }
+
+        self.online = true;
+        true
+    }
+
+    /// サーバーから取得したメッセージをキャッシュにマージして表示を更新
+    async fn sync_current_folder(&mut self, mail_client: &MailClient, account: &Account) {
+        if !self.ensure_connected(mail_client, account).await {
+            return;
+        }
+
+        if account.pop3.is_some() {
+            self.download_pop3(mail_client, account).await;
+            return;
+        }
+
+        let messages = match mail_client
+            .fetch_messages(&account.id, &self.current_folder, Some(SYNC_MESSAGE_LIMIT))
+            .await
+        {
+            Ok(messages) => messages,
+            Err(e) => {
+                if matches!(e, MailError::Connection(_)) {
+                    self.online = false;
+                }
+                self.status_message = format!("同期に失敗しました（キャッシュを表示中）: {}", e);
+                return;
+            }
+        };
+
+        let fetched = messages.len();
+        // 上限まで取得したときは、取得したUIDの範囲より古いキャッシュを残す
+        let range = if fetched < SYNC_MESSAGE_LIMIT {
+            FetchedRange::All
+        } else if mail_client.uses_imap_uids(&account.id).await {
+            messages
+                .iter()
+                .filter_map(|m| m.id.parse::<u32>().ok())
+                .min()
+                .map_or(FetchedRange::Partial, FetchedRange::FromUid)
+        } else {
+            FetchedRange::Partial
+        };
+
+        match self.database.as_mut() {
+            Some(database) => {
+                if let Err(e) =
+                    database.merge_folder(&account.id, &self.current_folder, &messages, range)
+                {
+                    self.status_message = format!("キャッシュの更新に失敗しました: {}", e);
+                    self.messages = messages;
+                    self.clear_search_results();
+                    self.clamp_mail_selection();
+                    return;
+                }
+                self.load_cached_messages();
+            }
+            None => {
+                self.messages = messages;
+                self.clear_search_results();
+                self.clamp_mail_selection();
+            }
+        }
+
+        if self.config.ui.show_thread_tree {
+            // 取得できなければクライアントでJWZアルゴリズムを使って組む
+            self.server_threads = mail_client
+                .fetch_server_threads(&account.id, &self.current_folder)
+                .await
+                .ok()
+                .flatten()
+                .map(|threads| (account.id.clone(), self.current_folder.clone(), threads));
+            self.invalidate_message_rows();
+        }
+
+        self.status_message = format!("同期しました（{} 件を取得）", fetched);
+    }
+
+    /// POP3で未取得のメッセージをダウンロードしてキャッシュに保存
+    ///
+    /// POP3アカウントのメッセージはキャッシュにしかないので、サーバーの一覧とマージはしない。
+    async fn download_pop3(&mut self, mail_client: &MailClient, account: &Account) {
+        let Some(database) = self.database.as_mut() else {
+            self.status_message = "キャッシュが利用できないためPOP3で受信できません".to_string();
+            return;
+        };
+
+        let known = match database.pop3_uidls(&account.id) {
+            Ok(known) => known,
+            Err(e) => {
+                self.status_message = format!("取得済みのメッセージを読み込めませんでした: {}", e);
+                return;
+            }
+        };
+
+        let result = mail_client
+            .download_pop3(&account.id, &known, |uidl, message| {
+                database
+                    .store_pop3_message(uidl, message)
+                    .map_err(|e| MailError::Io(e.to_string()))
+            })
+            .await;
+
+        match result {
+            Ok(summary) => {
+                let pruned = database.prune_pop3_uidls(&account.id, &summary.on_server);
+                self.load_cached_messages();
+                self.status_message = if summary.deleted > 0 {
+                    format!(
+                        "{} 件を受信しました（サーバーから {} 件を削除）",
+                        summary.downloaded, summary.deleted
+                    )
+                } else {
+                    format!("{} 件を受信しました", summary.downloaded)
+                };
+                if summary.unparsable > 0 {
+                    self.status_message += &format!(
+                        "（{} 件は解析できなかったためサーバーに残しました）",
+                        summary.unparsable
+                    );
+                }
+                if let Err(e) = pruned {
+                    self.status_message += &format!("（取得済みUIDLの整理に失敗: {}）", e);
+                }
+            }
+            Err(e) => {
+                if matches!(e, MailError::Connection(_)) {
+                    self.online = false;
+                }
+                // 失敗するまでに保存したメッセージは表示する
+                self.load_cached_messages();
+                self.status_message = format!("受信に失敗しました（キャッシュを表示中）: {}", e);
+            }
+        }
+    }
+
+    /// 反映待ちの操作があるアカウント
+    fn accounts_with_pending_operations(&mut self) -> Vec<Account> {
+        let account_ids = match self
+            .database
+            .as_ref()
+            .map(|db| db.pending_operation_accounts())
+        {
+            Some(Ok(account_ids)) => account_ids,
+            Some(Err(e)) => {
+                self.status_message = format!("未送信の操作を読み込めませんでした: {}", e);
+                return Vec::new();
+            }
+            None => return Vec::new(),
+        };
+
+        self.accounts
+            .iter()
+            .filter(|a| account_ids.contains(&a.id))
+            .cloned()
+            .collect()
+    }
+
+    /// オフライン中に記録した操作を順にサーバーへ反映
+    ///
+    /// サーバー側でメッセージが既に消えている操作は競合として破棄し、
+    /// 接続が切れた場合は残りを次の機会に回す。
+    async fn replay_journal(&mut self, mail_client: &MailClient, account: &Account) {
+        let pending = match self
+            .database
+            .as_ref()
+            .map(|db| db.pending_operations(&account.id))
+        {
+            Some(Ok(pending)) if !pending.is_empty() => pending,
+            Some(Err(e)) => {
+                self.status_message = format!("未送信の操作を読み込めませんでした: {}", e);
+                return;
+            }
+            _ => return,
+        };
+
+        if !self.ensure_connected(mail_client, account).await {
+            return;
+        }
+
+        let mut applied = 0;
+        let mut discarded = 0;
+        // 何度も失敗したため諦めた操作（最後の1件を知らせる）
+        let mut abandoned = None;
+        for pending_op in &pending {
+            let result = match &pending_op.operation {
+                MailOperation::SetFlag { flag, enabled } => {
+                    mail_client
+                        .set_message_flag(
+                            &account.id,
+                            &pending_op.message_id,
+                            &pending_op.folder,
+                            flag,
+                            *enabled,
+                        )
+                        .await
+                }
+                MailOperation::Move { to_folder } => {
+                    mail_client
+                        .move_message(
+                            &account.id,
+                            &pending_op.message_id,
+                            &pending_op.folder,
+                            to_folder,
+                        )
+                        .await
+                }
+                MailOperation::Delete => {
+                    mail_client
+                        .delete_message(&account.id, &pending_op.message_id, &pending_op.folder)
+                        .await
+                }
+            };
+
+            let Some(database) = self.database.as_mut() else {
+                return;
+            };
+            let outcome = match result {
+                Ok(()) => {
+                    applied += 1;
+                    database.complete_operation(pending_op.id)
+                }
+                Err(MailError::Connection(e)) => {
+                    self.online = false;
+                    self.status_message =
+                        format!("接続が切れました（残りの操作は次回反映します）: {}", e);
+                    break;
+                }
+                Err(MailError::NotFound(_)) => {
+                    discarded += 1;
+                    database.complete_operation(pending_op.id)
+                }
+                Err(e) => match database.record_operation_failure(pending_op.id, &e.to_string()) {
+                    Ok(attempts) if attempts >= MAX_REPLAY_ATTEMPTS => {
+                        abandoned = Some(format!(
+                            "操作 {:?} を {} 回反映できなかったため破棄しました: {}",
+                            pending_op.operation, attempts, e
+                        ));
+                        discarded += 1;
+                        database.complete_operation(pending_op.id)
+                    }
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e),
+                },
+            };
+
+            if let Err(e) = outcome {
+                self.status_message = format!("未送信の操作を更新できませんでした: {}", e);
+                break;
+            }
+        }
+
+        self.refresh_pending_count();
+        if !self.online {
+            return;
+        }
+        if let Some(message) = abandoned {
+            self.status_message = message;
+        } else if discarded > 0 {
+            self.status_message = format!(
+                "{} 件の操作を反映しました（{} 件はサーバー側と競合したため破棄しました）",
+                applied, discarded
+            );
+        } else if applied > 0 {
+            self.status_message = format!("{} 件の操作をサーバーに反映しました", applied);
+        }
+    }
+
+    fn toggle_conversation_mode(&mut self) {
+        self.conversation_mode = !self.conversation_mode;
+        self.mail_list_state.select(Some(0));
+        if self.conversation_mode {
+            self.status_message = "会話表示に切り替えました".to_string();
+            self.pending_actions.push(AppAction::LoadThreads);
+        } else {
+            self.status_message = "メッセージ表示に切り替えました".to_string();
+        }
+    }
+
+    fn selected_thread(&self) -> Option<&Thread> {
+        self.mail_list_state
+            .selected()
+            .and_then(|i| self.threads.get(i))
+    }
+
+    /// ラベル付与などの対象となるスレッド
+    fn target_thread(&self) -> Option<&Thread> {
+        match self.mode {
+            AppMode::ThreadView => self.current_thread.as_ref(),
+            _ => self.selected_thread(),
+        }
+    }
+
+    fn open_selected_thread(&mut self) {
+        if let Some(thread) = self.selected_thread() {
+            let action = AppAction::OpenThread(thread.id.clone());
+            self.pending_actions.push(action);
+        }
+    }
+
+    /// 会話の操作は、そのスレッドのアカウントに対して行う
+    fn archive_selected_thread(&mut self) {
+        if let Some(thread) = self.selected_thread() {
+            let action = AppAction::ArchiveThread {
+                account_id: thread.account_id.clone(),
+                thread_id: thread.id.clone(),
+            };
+            self.pending_actions.push(action);
+        }
+    }
+
+    fn mute_selected_thread(&mut self) {
+        if let Some(thread) = self.selected_thread() {
+            let action = AppAction::MuteThread {
+                account_id: thread.account_id.clone(),
+                thread_id: thread.id.clone(),
+            };
+            self.pending_actions.push(action);
+        }
+    }
+
+    fn archive_current_thread(&mut self) {
+        if let Some(thread) = &self.current_thread {
+            self.pending_actions.push(AppAction::ArchiveThread {
+                account_id: thread.account_id.clone(),
+                thread_id: thread.id.clone(),
+            });
+        }
+    }
+
+    fn mute_current_thread(&mut self) {
+        if let Some(thread) = &self.current_thread {
+            self.pending_actions.push(AppAction::MuteThread {
+                account_id: thread.account_id.clone(),
+                thread_id: thread.id.clone(),
+            });
+        }
+    }
+
+    fn start_label_input(&mut self) {
+        if self.target_thread().is_some() {
+            self.label_input.clear();
+            self.input_mode = InputMode::Label;
+        }
+    }
+
+    /// スレッドを一覧から取り除く（アーカイブ・ミュート後）
+    fn remove_thread(&mut self, thread_id: &str) {
+        self.threads.retain(|t| t.id != thread_id);
+        if self
+            .current_thread
+            .as_ref()
+            .is_some_and(|t| t.id == thread_id)
+        {
+            self.current_thread = None;
+            self.mode = AppMode::MailList;
+        }
+        let selected = self.mail_list_state.selected().unwrap_or(0);
+        self.mail_list_state
+            .select(Some(selected.min(self.threads.len().saturating_sub(1))));
+    }
+
+    /// キー操作で積まれた非同期処理を実行
+    pub async fn process_pending_actions(&mut self, mail_client: &MailClient) {
+        let actions = std::mem::take(&mut self.pending_actions);
+        for action in actions {
+            let account = match action.account_id() {
+                Some(account_id) => self.accounts.iter().find(|a| a.id == account_id),
+                None => self.get_current_account(),
+            };
+            // 対象のアカウントがない処理だけを飛ばし、残りの処理は続ける
+            let Some(account) = account.cloned() else {
+                self.status_message = "アカウントが設定されていません".to_string();
+                continue;
+            };
+            let account_id = account.id.clone();
+
+            match action {
+                AppAction::SyncFolder => {
+                    let saved_search = self.current_saved_search.clone();
+                    // サーバーの状態を取り込む前にオフライン中の操作を反映する。
+                    // 検索結果から操作した他のアカウントの分もここで反映する
+                    for target in self.accounts_with_pending_operations() {
+                        self.replay_journal(mail_client, &target).await;
+                    }
+                    self.sync_current_folder(mail_client, &account).await;
+                    if self.is_online() {
+                        self.load_folders(mail_client, &account).await;
+                    }
+                    // 保存した検索を表示中なら同期した内容で検索し直す
+                    if let Some(name) = saved_search {
+                        self.open_saved_search(&name);
+                    }
+                }
+                AppAction::ReplayJournal(_) => self.replay_journal(mail_client, &account).await,
+                AppAction::LoadThreads => {
+                    match mail_client
+                        .fetch_threads(
+                            &account_id,
+                            &self.current_folder,
+                            Some(self.config.app.max_messages_per_folder),
+                        )
+                        .await
+                    {
+                        Ok(threads) => {
+                            self.status_message =
+                                format!("{} 件の会話を読み込みました", threads.len());
+                            self.threads = threads;
+                            self.mail_list_state.select(Some(0));
+                        }
+                        Err(e) => {
+                            self.status_message = format!("会話の読み込みに失敗しました: {}", e);
+                            self.conversation_mode = false;
+                        }
+                    }
+                }
+                AppAction::OpenThread(thread_id) => {
+                    match mail_client
+                        .fetch_thread(&account_id, &thread_id, &self.current_folder)
+                        .await
+                    {
+                        Ok(thread) => {
+                            self.current_thread = Some(thread);
+                            self.thread_scroll = 0;
+                            self.show_quoted = false;
+                            self.mode = AppMode::ThreadView;
+                        }
+                        Err(e) => {
+                            self.status_message = format!("会話の取得に失敗しました: {}", e);
+                        }
+                    }
+                }
+                AppAction::ArchiveThread { thread_id, .. } => {
+                    match mail_client.archive_thread(&account_id, &thread_id).await {
+                        Ok(_) => {
+                            self.remove_thread(&thread_id);
+                            self.status_message = "会話をアーカイブしました".to_string();
+                        }
+                        Err(e) => {
+                            self.status_message = format!("アーカイブに失敗しました: {}", e);
+                        }
+                    }
+                }
+                AppAction::MuteThread { thread_id, .. } => {
+                    match mail_client.mute_thread(&account_id, &thread_id).await {
+                        Ok(_) => {
+                            self.remove_thread(&thread_id);
+                            self.status_message = "会話をミュートしました".to_string();
+                        }
+                        Err(e) => {
+                            self.status_message = format!("ミュートに失敗しました: {}", e);
+                        }
+                    }
+                }
+                AppAction::SearchServer(query) => {
+                    self.search_server(mail_client, &account, &query).await
+                }
+                AppAction::ManageFolder(operation) => {
+                    self.manage_folder(mail_client, &account, operation).await
+                }
+                AppAction::LoadBody { folder, id, .. } => {
+                    self.load_body(mail_client, &account, folder, id).await
+                }
+                AppAction::LabelThread {
+                    thread_id, label, ..
+                } => {
+                    match mail_client
+                        .label_thread(&account_id, &thread_id, &label)
+                        .await
+                    {
+                        Ok(_) => {
+                            self.status_message = format!("ラベル「{}」を付けました", label);
+                        }
+                        Err(e) => {
+                            self.status_message = format!("ラベル付けに失敗しました: {}", e);
+                        }
+                    }
+                }
             }
         }
     }
@@ -225,8 +1602,9 @@ impl App {
     }
 
     fn delete_selected_mail(&mut self) {
-        // TODO: 削除機能の実装
-        self.status_message = "Delete功能は未実装です".to_string();
+        if let Some(message) = self.target_message() {
+            self.perform_operation(&message, MailOperation::Delete);
+        }
     }
 
     fn reply_to_current_mail(&mut self) {
@@ -245,8 +1623,9 @@ impl App {
     }
 
     fn delete_current_mail(&mut self) {
-        // TODO: 現在のメールの削除
-        self.status_message = "Delete功能は未実装です".to_string();
+        if let Some(message) = self.target_message() {
+            self.perform_operation(&message, MailOperation::Delete);
+        }
     }
 
     fn send_composed_mail(&mut self) {
@@ -254,12 +1633,558 @@ impl App {
         self.status_message = "Send功能は未実装です".to_string();
     }
 
+    /// 検索クエリ（`from:alice is:unread` など）で全アカウント・全フォルダーのキャッシュを検索
+    ///
+    /// ネットワークは使わない。検索語の関連度（bm25）順に並べ、一致箇所のスニペットを付ける。
+    /// キャッシュが使えない場合は表示中のメッセージから探す。
     fn perform_search(&mut self) {
-        // TODO: 検索機能の実装
-        self.status_message = format!("Searching for: {}", self.search_query);
+        let query = self.search_query.trim().to_string();
+        if query.is_empty() {
+            self.load_cached_messages();
+            return;
+        }
+        self.run_search(query, true);
+    }
+
+    /// 検索して結果を一覧に表示する（`search_server` ならサーバー側の検索も予約する）
+    fn run_search(&mut self, query: String, search_server: bool) {
+        let result = match self.database.as_ref() {
+            Some(database) => Query::parse(&query).and_then(|parsed| {
+                database
+                    .search_messages(&parsed, None)
+                    .map_err(|e| e.to_string())
+            }),
+            None => SearchEngine::new()
+                .search(&query, &self.messages)
+                .map(|found| {
+                    found
+                        .into_iter()
+                        .map(|message| SearchHit {
+                            message: message.clone(),
+                            snippet: None,
+                        })
+                        .collect()
+                }),
+        };
+
+        match result {
+            Ok(hits) => {
+                self.status_message = format!("「{}」の検索結果: {} 件", query, hits.len());
+                self.search_snippets = hits
+                    .iter()
+                    .filter_map(|hit| {
+                        let snippet = hit.snippet.clone()?;
+                        Some((message_key(&hit.message), snippet))
+                    })
+                    .collect();
+                self.messages = hits.into_iter().map(|hit| hit.message).collect();
+                self.mail_list_state.select(Some(0));
+                self.active_search = Some(query.clone());
+                self.current_saved_search = None;
+                self.invalidate_message_rows();
+                if search_server && !self.work_offline {
+                    self.pending_actions.push(AppAction::SearchServer(query));
+                }
+            }
+            Err(e) => {
+                self.status_message = format!("検索に失敗しました: {}", e);
+            }
+        }
+    }
+
+    /// サーバー側で現在のフォルダー（`folder:` があればそのフォルダー）を検索し、
+    /// キャッシュにないメッセージを検索結果の後ろに加える
+    ///
+    /// 見つかったメッセージはキャッシュにも保存するので、次からはローカルの検索で見つかる。
+    async fn search_server(&mut self, mail_client: &MailClient, account: &Account, input: &str) {
+        // Maildir・POP3のメッセージはすべてキャッシュにある
+        if account.maildir.is_some() || account.pop3.is_some() {
+            return;
+        }
+        let Ok(query) = Query::parse(input) else {
+            return;
+        };
+        if !self.ensure_connected(mail_client, account).await {
+            return;
+        }
+
+        let folder = query.folder().unwrap_or(&self.current_folder).to_string();
+        let result = mail_client
+            .search_messages(&account.id, &folder, &query, SERVER_SEARCH_LIMIT)
+            .await;
+
+        // 待っている間に別の検索やフォルダーの表示に移っていたら捨てる
+        if self.active_search.as_deref() != Some(input) {
+            return;
+        }
+
+        let found = match result {
+            Ok(found) => found,
+            Err(e) => {
+                self.status_message = format!(
+                    "「{}」の検索結果: {} 件（サーバーの検索に失敗しました: {}）",
+                    input,
+                    self.messages.len(),
+                    e
+                );
+                return;
+            }
+        };
+
+        let mut added = 0;
+        let mut cache_error = None;
+        for message in found {
+            let key = message_key(&message);
+            if self.messages.iter().any(|m| message_key(m) == key) {
+                continue;
+            }
+            // キャッシュ済みのメッセージは本文を含むので上書きしない
+            if let Some(database) = self.database.as_mut() {
+                let cached = database
+                    .has_message(&message.account_id, &message.folder, &message.id)
+                    .unwrap_or(true);
+                if !cached {
+                    if let Err(e) = database.store_message(&message) {
+                        cache_error = Some(e);
+                    }
+                }
+            }
+            if let Some(snippet) = query.snippet(&message) {
+                self.search_snippets.insert(key, snippet);
+            }
+            self.messages.push(message);
+            added += 1;
+        }
+        self.invalidate_message_rows();
+
+        self.status_message = format!(
+            "「{}」の検索結果: {} 件（うちサーバーのみ {} 件）",
+            input,
+            self.messages.len(),
+            added
+        );
+        if let Some(e) = cache_error {
+            self.status_message += &format!("（キャッシュへの保存に失敗: {}）", e);
+        }
+        self.refresh_saved_searches();
+    }
+
+    fn clear_search_results(&mut self) {
+        self.active_search = None;
+        self.current_saved_search = None;
+        self.search_snippets.clear();
+        self.invalidate_message_rows();
+    }
+
+    /// フォルダー一覧の項目（フォルダーの階層の後に保存した検索）
+    ///
+    /// 畳んだフォルダーの子孫は含めない。
+    pub fn folder_entries(&self) -> Vec<FolderEntry> {
+        let visible = self.visible_folders();
+        let mappings = self
+            .get_current_account()
+            .map(|account| resolve_mappings(&account.imap.folders, &self.folders))
+            .unwrap_or_default();
+
+        let mut folders = Vec::new();
+        let mut hidden_below = None;
+        for node in folder_tree(&visible, &mappings) {
+            if hidden_below.is_some_and(|depth| node.depth > depth) {
+                continue;
+            }
+            let collapsed = node.has_children && self.collapsed_folders.contains(&node.name);
+            hidden_below = collapsed.then_some(node.depth);
+            folders.push(FolderEntry::Folder {
+                unread: self.folder_unread.get(&node.name).copied().unwrap_or(0),
+                node,
+                collapsed,
+            });
+        }
+
+        let saved_searches =
+            self.saved_searches
+                .iter()
+                .map(|(saved, unread)| FolderEntry::SavedSearch {
+                    name: saved.name.clone(),
+                    query: saved.query.clone(),
+                    unread: *unread,
+                });
+        folders.extend(saved_searches);
+        folders
+    }
+
+    /// 一覧に出すフォルダー（まだ取得していなければ設定の特別なフォルダー）
+    fn visible_folders(&self) -> Vec<Folder> {
+        if !self.folders.is_empty() {
+            return self
+                .folders
+                .iter()
+                .filter(|folder| folder.subscribed || !self.config.ui.subscribed_folders_only)
+                .cloned()
+                .collect();
+        }
+        let Some(account) = self.get_current_account() else {
+            return Vec::new();
+        };
+        let mut folders: Vec<Folder> = account
+            .imap
+            .folders
+            .iter()
+            .map(|mapping| Folder::new(mapping.server_name.clone(), None))
+            .collect();
+        let inbox = account.get_inbox_folder();
+        if !folders.iter().any(|folder| folder.name == inbox) {
+            folders.insert(0, Folder::new(inbox, None));
+        }
+        folders
+    }
+
+    fn select_next_folder(&mut self) {
+        let len = self.folder_entries().len();
+        let selected = self.folder_list_state.selected().unwrap_or(0);
+        self.folder_list_state
+            .select(Some((selected + 1).min(len.saturating_sub(1))));
+    }
+
+    fn select_previous_folder(&mut self) {
+        let selected = self.folder_list_state.selected().unwrap_or(0);
+        self.folder_list_state
+            .select(Some(selected.saturating_sub(1)));
+    }
+
+    fn selected_folder_entry(&self) -> Option<FolderEntry> {
+        let selected = self.folder_list_state.selected()?;
+        self.folder_entries().into_iter().nth(selected)
+    }
+
+    /// 選択中のフォルダーを開く（保存した検索なら全アカウントを検索し直す）
+    fn open_selected_folder(&mut self) {
+        match self.selected_folder_entry() {
+            Some(FolderEntry::Folder { node, .. }) if !node.selectable => {
+                self.toggle_selected_folder();
+            }
+            Some(FolderEntry::Folder { node, .. }) => {
+                self.current_folder = node.name;
+                self.load_cached_messages();
+                self.folder_focus = false;
+                self.request_sync();
+            }
+            Some(FolderEntry::SavedSearch { name, .. }) => {
+                self.open_saved_search(&name);
+                self.folder_focus = false;
+            }
+            None => {}
+        }
+    }
+
+    /// 選択中のフォルダーの子フォルダーを畳む・広げる
+    fn toggle_selected_folder(&mut self) {
+        if let Some(FolderEntry::Folder { collapsed, .. }) = self.selected_folder_entry() {
+            self.expand_selected_folder(collapsed);
+        }
+    }
+
+    fn expand_selected_folder(&mut self, expand: bool) {
+        let Some(FolderEntry::Folder { node, .. }) = self.selected_folder_entry() else {
+            return;
+        };
+        if !node.has_children {
+            return;
+        }
+        if expand {
+            self.collapsed_folders.remove(&node.name);
+        } else {
+            self.collapsed_folders.insert(node.name);
+        }
+    }
+
+    /// 選択中の、サーバーにあるフォルダー（受信箱は名前の変更や削除ができないので除く）
+    fn selected_manageable_folder(&mut self) -> Option<Folder> {
+        if !self.is_online() {
+            self.status_message = "フォルダーの操作はオンラインのときだけできます".to_string();
+            return None;
+        }
+        let Some(FolderEntry::Folder { node, .. }) = self.selected_folder_entry() else {
+            return None;
+        };
+        if node.name.eq_ignore_ascii_case("INBOX") {
+            self.status_message = "受信箱は変更できません".to_string();
+            return None;
+        }
+        self.folders.iter().find(|f| f.name == node.name).cloned()
+    }
+
+    fn start_create_folder(&mut self) {
+        if !self.is_online() {
+            self.status_message = "フォルダーの操作はオンラインのときだけできます".to_string();
+            return;
+        }
+        self.folder_operation = Some(FolderOperation::Create(String::new()));
+        self.folder_name_input.clear();
+        self.input_mode = InputMode::FolderName;
+    }
+
+    fn start_rename_folder(&mut self) {
+        let Some(folder) = self.selected_manageable_folder() else {
+            return;
+        };
+        self.folder_name_input = folder.name.clone();
+        self.folder_operation = Some(FolderOperation::Rename {
+            from: folder.name,
+            to: String::new(),
+        });
+        self.input_mode = InputMode::FolderName;
+    }
+
+    fn confirm_delete_folder(&mut self) {
+        let Some(folder) = self.selected_manageable_folder() else {
+            return;
+        };
+        self.folder_operation = Some(FolderOperation::Delete(folder.name));
+        self.input_mode = InputMode::Confirm;
+    }
+
+    fn confirm_toggle_subscription(&mut self) {
+        let Some(folder) = self.selected_manageable_folder() else {
+            return;
+        };
+        self.folder_operation = Some(FolderOperation::Subscribe {
+            name: folder.name,
+            subscribed: !folder.subscribed,
+        });
+        self.input_mode = InputMode::Confirm;
+    }
+
+    /// 購読しているフォルダーだけを表示する・すべて表示する
+    fn toggle_subscribed_only(&mut self) {
+        let only = !self.config.ui.subscribed_folders_only;
+        self.config.ui.subscribed_folders_only = only;
+        self.folder_list_state.select(Some(0));
+        self.status_message = if only {
+            "購読しているフォルダーだけを表示しています".to_string()
+        } else {
+            "すべてのフォルダーを表示しています".to_string()
+        };
+    }
+
+    /// 確認済みのフォルダー操作をサーバーで実行し、キャッシュとフォルダー一覧に反映する
+    async fn manage_folder(
+        &mut self,
+        mail_client: &MailClient,
+        account: &Account,
+        operation: FolderOperation,
+    ) {
+        if !self.ensure_connected(mail_client, account).await {
+            return;
+        }
+        if let Err(e) = mail_client.manage_folder(&account.id, &operation).await {
+            if matches!(e, MailError::Connection(_)) {
+                self.online = false;
+            }
+            self.status_message = format!("フォルダーを変更できませんでした: {}", e);
+            return;
+        }
+
+        let result = match (&operation, self.database.as_mut()) {
+            (FolderOperation::Rename { from, to }, Some(database)) => {
+                let delimiter = self
+                    .folders
+                    .iter()
+                    .find(|f| f.name == *from)
+                    .and_then(|f| f.delimiter.clone());
+                database.rename_cached_folder(&account.id, from, to, delimiter.as_deref())
+            }
+            (FolderOperation::Delete(name), Some(database)) => {
+                database.delete_cached_folder(&account.id, name)
+            }
+            _ => Ok(()),
+        };
+
+        match &operation {
+            FolderOperation::Rename { from, to } if self.current_folder == *from => {
+                self.current_folder = to.clone();
+            }
+            FolderOperation::Delete(name) if self.current_folder == *name => {
+                self.current_folder = account.get_inbox_folder();
+                self.load_cached_messages();
+            }
+            _ => {}
+        }
+
+        self.load_folders(mail_client, account).await;
+        self.status_message = match operation {
+            FolderOperation::Create(name) => format!("フォルダー「{}」を作成しました", name),
+            FolderOperation::Rename { to, .. } => format!("フォルダー名を「{}」に変更しました", to),
+            FolderOperation::Delete(name) => format!("フォルダー「{}」を削除しました", name),
+            FolderOperation::Subscribe {
+                name,
+                subscribed: true,
+            } => format!("フォルダー「{}」を購読しました", name),
+            FolderOperation::Subscribe { name, .. } => {
+                format!("フォルダー「{}」の購読をやめました", name)
+            }
+        };
+        if let Err(e) = result {
+            self.status_message += &format!("（キャッシュの更新に失敗: {}）", e);
+        }
+    }
+
+    /// サーバーからフォルダー一覧を取得してキャッシュに保存
+    async fn load_folders(&mut self, mail_client: &MailClient, account: &Account) {
+        let folders = match mail_client.get_folders(&account.id).await {
+            Ok(folders) => folders,
+            Err(e) => {
+                self.status_message = format!("フォルダー一覧を取得できませんでした: {}", e);
+                return;
+            }
+        };
+        if let Some(database) = self.database.as_mut() {
+            if let Err(e) = database.replace_folders(&account.id, &folders) {
+                self.status_message = format!("フォルダー一覧を保存できませんでした: {}", e);
+            }
+        }
+        self.folders = folders;
+        self.refresh_folders();
+    }
+
+    /// キャッシュからフォルダー一覧と各フォルダーの未読数を読み直す
+    pub fn refresh_folders(&mut self) {
+        let Some(account_id) = self.get_current_account().map(|a| a.id.clone()) else {
+            return;
+        };
+        let Some(database) = self.database.as_ref() else {
+            return;
+        };
+        match database.folders(&account_id) {
+            Ok(folders) if !folders.is_empty() => self.folders = folders,
+            Ok(_) => {}
+            Err(e) => {
+                self.status_message = format!("フォルダー一覧を読み込めませんでした: {}", e);
+            }
+        }
+        self.folder_unread = self
+            .visible_folders()
+            .into_iter()
+            .filter_map(|folder| {
+                let (_, unread) = database.get_folder_stats(&account_id, &folder.name).ok()?;
+                Some((folder.name, unread))
+            })
+            .collect();
+    }
+
+    fn open_saved_search(&mut self, name: &str) {
+        let Some((saved, _)) = self.saved_searches.iter().find(|(s, _)| s.name == name) else {
+            return;
+        };
+        let query = saved.query.clone();
+        self.run_search(query.clone(), false);
+        if self.active_search.as_ref() == Some(&query) {
+            self.current_saved_search = Some(name.to_string());
+            self.status_message = format!("「{}」: {} 件", name, self.messages.len());
+        }
+    }
+
+    fn start_save_search_input(&mut self) {
+        if self.active_search.is_none() {
+            self.status_message = "検索結果を表示しているときに保存できます".to_string();
+            return;
+        }
+        self.search_name_input = self.current_saved_search.clone().unwrap_or_default();
+        self.input_mode = InputMode::SaveSearch;
+    }
+
+    /// 表示中の検索を名前を付けて保存（同じ名前があれば置き換える）
+    fn save_active_search(&mut self, name: &str) {
+        let (Some(query), Some(database)) = (self.active_search.clone(), self.database.as_mut())
+        else {
+            self.status_message = "キャッシュが利用できないため保存できません".to_string();
+            return;
+        };
+        match database.save_search(name, &query) {
+            Ok(()) => {
+                self.current_saved_search = Some(name.to_string());
+                self.status_message = format!("検索を「{}」として保存しました", name);
+                self.refresh_saved_searches();
+            }
+            Err(e) => {
+                self.status_message = format!("検索を保存できませんでした: {}", e);
+            }
+        }
+    }
+
+    fn delete_selected_saved_search(&mut self) {
+        let Some(FolderEntry::SavedSearch { name, .. }) = self.selected_folder_entry() else {
+            return;
+        };
+        let Some(database) = self.database.as_mut() else {
+            return;
+        };
+        match database.delete_saved_search(&name) {
+            Ok(()) => {
+                self.status_message = format!("保存した検索「{}」を削除しました", name);
+                if self.current_saved_search.as_deref() == Some(name.as_str()) {
+                    self.current_saved_search = None;
+                }
+                self.refresh_saved_searches();
+                self.select_previous_folder();
+            }
+            Err(e) => {
+                self.status_message = format!("保存した検索を削除できませんでした: {}", e);
+            }
+        }
+    }
+
+    /// 保存した検索と未読数をキャッシュから読み直す
+    pub fn refresh_saved_searches(&mut self) {
+        let Some(database) = self.database.as_ref() else {
+            return;
+        };
+        let saved_searches = match database.saved_searches() {
+            Ok(saved_searches) => saved_searches,
+            Err(e) => {
+                self.status_message = format!("保存した検索を読み込めませんでした: {}", e);
+                return;
+            }
+        };
+        self.saved_searches = saved_searches
+            .into_iter()
+            .map(|saved| {
+                let unread = Query::parse(&saved.query)
+                    .ok()
+                    .and_then(|query| database.count_unread(&query).ok())
+                    .unwrap_or(0);
+                (saved, unread)
+            })
+            .collect();
+    }
+
+    /// 検索結果として表示中のメッセージのスニペット
+    pub fn search_snippet(&self, message: &Message) -> Option<&str> {
+        self.search_snippets
+            .get(&message_key(message))
+            .map(String::as_str)
     }
 
     pub fn get_current_account(&self) -> Option<&Account> {
         self.accounts.get(self.current_account_index)
     }
 }
+
+/// ペインの大きさ（%）を1段階変える
+fn resize_percent(percent: u16, grow: bool) -> u16 {
+    let percent = if grow {
+        percent.saturating_add(PANE_RESIZE_STEP)
+    } else {
+        percent.saturating_sub(PANE_RESIZE_STEP)
+    };
+    percent.clamp(PANE_MIN_PERCENT, PANE_MAX_PERCENT)
+}
+
+/// メッセージを一意に表すキー（アカウントID・フォルダー・メッセージID）
+fn message_key(message: &Message) -> (String, String, String) {
+    (
+        message.account_id.clone(),
+        message.folder.clone(),
+        message.id.clone(),
+    )
+}
@@ -0,0 +1,224 @@
+use clap::{Args, Parser, Subcommand};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::mail::{mime, Account, Flag, MailClient, Message};
+use crate::search::Query;
+use crate::storage::mbox::{self, MboxFormat, MboxReader};
+use crate::storage::{Config, Database};
+
+/// 一度にキャッシュから読み出すメッセージ数（エクスポート時）
+const EXPORT_PAGE_SIZE: usize = 500;
+
+/// 引数なしで起動するとTUIを開く
+#[derive(Parser)]
+#[command(name = "rustmail", version, about = "Terminal mail client")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// mboxファイル（Thunderbird・muttなど）のメッセージをアカウントのフォルダーへ取り込む
+    ImportMbox(ImportMboxArgs),
+    /// フォルダーまたは検索結果をmboxファイルに書き出す
+    ExportMbox(ExportMboxArgs),
+}
+
+#[derive(Args)]
+pub struct ImportMboxArgs {
+    /// 取り込むmboxファイル
+    pub file: PathBuf,
+    /// 取り込み先のアカウント（ID・名前・メールアドレス）
+    #[arg(long)]
+    pub account: String,
+    /// 取り込み先のフォルダー
+    #[arg(long, default_value = "INBOX")]
+    pub folder: String,
+    #[arg(long, value_enum, default_value_t = MboxFormat::Mboxrd)]
+    pub format: MboxFormat,
+    /// サーバーには送らず、ローカルキャッシュにだけ保存する
+    #[arg(long)]
+    pub local: bool,
+}
+
+#[derive(Args)]
+pub struct ExportMboxArgs {
+    /// 書き出すmboxファイル
+    pub output: PathBuf,
+    /// 書き出すアカウント（ID・名前・メールアドレス）
+    #[arg(long)]
+    pub account: String,
+    /// 書き出すフォルダー
+    #[arg(long, conflicts_with = "search", required_unless_present = "search")]
+    pub folder: Option<String>,
+    /// 検索クエリ（`from:alice after:2024-01-01` など）に一致するメッセージを書き出す
+    #[arg(long)]
+    pub search: Option<String>,
+    #[arg(long, value_enum, default_value_t = MboxFormat::Mboxrd)]
+    pub format: MboxFormat,
+}
+
+pub async fn run(command: Command, config: &Config) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::ImportMbox(args) => import_mbox(config, args).await,
+        Command::ExportMbox(args) => export_mbox(config, args),
+    }
+}
+
+/// mboxを1通ずつ読み、ローカルキャッシュまたはサーバー（IMAP APPENDなど）へ追加する
+async fn import_mbox(config: &Config, args: ImportMboxArgs) -> Result<(), Box<dyn Error>> {
+    let account = find_account(config, &args.account)?;
+
+    let file = File::open(&args.file)?;
+    let total_bytes = file.metadata()?.len().max(1);
+    let mut reader = MboxReader::new(BufReader::new(file), args.format);
+
+    let mut database = None;
+    let mut mail_client = None;
+    if args.local {
+        database = Some(Database::new(config.get_database_file())?);
+    } else {
+        let mut client = MailClient::new();
+        client
+            .token_provider()
+            .set_persist_hook(Box::new(crate::persist_refreshed_tokens));
+        client.add_account(account.clone())?;
+        client.connect_imap(&account.id).await?;
+        mail_client = Some(client);
+    }
+
+    let mut imported = 0;
+    let mut failed = 0;
+    while let Some(raw) = reader.next() {
+        let raw = raw?;
+
+        // 削除済みの印は取り込まない（IMAPではEXPUNGEで消えてしまう）
+        let flags: Vec<Flag> = mbox::status_flags(&raw)
+            .into_iter()
+            .filter(|flag| *flag != Flag::Deleted)
+            .collect();
+
+        let result = if let Some(database) = database.as_mut() {
+            let id = format!("mbox-{}", uuid::Uuid::new_v4());
+            mime::parse_message(&raw, id, &account.id, &args.folder)
+                .map_err(|e| e.to_string())
+                .and_then(|mut message| {
+                    message.flags = flags;
+                    database.store_message(&message).map_err(|e| e.to_string())
+                })
+        } else if let Some(client) = mail_client.as_ref() {
+            client
+                .append_message(&account.id, &args.folder, &raw, &flags)
+                .await
+                .map_err(|e| e.to_string())
+        } else {
+            Ok(())
+        };
+
+        match result {
+            Ok(()) => imported += 1,
+            Err(e) => {
+                failed += 1;
+                eprintln!(
+                    "\r警告: {} 通目を取り込めませんでした: {}",
+                    imported + failed,
+                    e
+                );
+            }
+        }
+
+        eprint!(
+            "\r取り込み中: {:>3}% ({} 件)",
+            reader.bytes_read() * 100 / total_bytes,
+            imported
+        );
+    }
+
+    eprintln!(
+        "\r{} 件を {} / {} に取り込みました（失敗 {} 件）",
+        imported, account.name, args.folder, failed
+    );
+
+    if let Some(client) = mail_client {
+        client.disconnect_all(&account.id).await?;
+    }
+
+    Ok(())
+}
+
+/// キャッシュ済みのフォルダーまたは検索結果をmboxに書き出す
+fn export_mbox(config: &Config, args: ExportMboxArgs) -> Result<(), Box<dyn Error>> {
+    let account = find_account(config, &args.account)?;
+    let database = Database::new(config.get_database_file())?;
+
+    let messages: Vec<Message> = match (&args.search, &args.folder) {
+        (Some(query), _) => database
+            .search_messages(&Query::parse(query)?, Some(&account.id))?
+            .into_iter()
+            .map(|hit| hit.message)
+            .collect(),
+        (None, Some(folder)) => {
+            let mut messages = Vec::new();
+            loop {
+                let page = database.get_messages(
+                    &account.id,
+                    folder,
+                    Some(EXPORT_PAGE_SIZE),
+                    Some(messages.len()),
+                )?;
+                let done = page.len() < EXPORT_PAGE_SIZE;
+                messages.extend(page);
+                if done {
+                    break;
+                }
+            }
+            messages
+        }
+        (None, None) => Vec::new(),
+    };
+
+    let mut writer = BufWriter::new(File::create(&args.output)?);
+    let total = messages.len().max(1);
+    let mut exported = 0;
+
+    // mboxは古い順に並べる
+    for (index, message) in messages.iter().rev().enumerate() {
+        match mime::to_rfc5322(message) {
+            Ok(raw) => {
+                let sender = message.from.first().map(|a| a.email.as_str()).unwrap_or("");
+                mbox::write_message(&mut writer, &raw, sender, message.date, args.format)?;
+                exported += 1;
+            }
+            Err(e) => eprintln!(
+                "\r警告: 「{}」を書き出せませんでした: {}",
+                message.subject, e
+            ),
+        }
+        eprint!(
+            "\r書き出し中: {:>3}% ({} 件)",
+            (index + 1) * 100 / total,
+            exported
+        );
+    }
+    writer.flush()?;
+
+    eprintln!(
+        "\r{} 件を {} に書き出しました",
+        exported,
+        args.output.display()
+    );
+    Ok(())
+}
+
+fn find_account(config: &Config, name: &str) -> Result<Account, Box<dyn Error>> {
+    config
+        .accounts
+        .iter()
+        .find(|account| account.id == name || account.name == name || account.email == name)
+        .cloned()
+        .ok_or_else(|| format!("Account not found: {}", name).into())
+}
@@ -1,18 +1,69 @@
-use super::oauth::{GoogleOAuthConfig, GoogleTokens};
+use super::oauth::{OAuthConfig, OAuthTokens};
+use chrono::{DateTime, Duration, Utc};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     pub id: String,
     pub name: String,
     pub email: String,
+    /// Maildirアカウントでは省略できる
+    #[serde(default)]
     pub imap: ImapConfig,
+    /// JMAPアカウントでは省略できる
+    #[serde(default)]
     pub smtp: SmtpConfig,
     pub signature: Option<String>,
     pub default_folder: String,
     pub enabled: bool,
-    pub oauth_config: Option<GoogleOAuthConfig>,
-    pub tokens: Option<GoogleTokens>,
+    pub oauth_config: Option<OAuthConfig>,
+    /// 設定ファイルには書き込まず、秘密情報の保存先で管理する
+    #[serde(default, skip_serializing)]
+    pub tokens: Option<OAuthTokens>,
+    /// 設定されている場合はIMAPの代わりにローカルのMaildirを読む
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maildir: Option<MaildirConfig>,
+    /// 設定されている場合はIMAP/SMTPの代わりにJMAPを使う
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jmap: Option<JmapConfig>,
+    /// 設定されている場合はIMAPの代わりにPOP3で受信し、ローカルのキャッシュに保存する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pop3: Option<Pop3Config>,
+}
+
+/// mbsync/offlineimapなどで同期したMaildir
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaildirConfig {
+    /// 受信箱のMaildir（`~/` で始まる場合はホームディレクトリから）
+    pub path: PathBuf,
+}
+
+/// JMAPサーバー（Fastmail、Stalwartなど）
+///
+/// 認証情報（ユーザー名・パスワード・認証方式）は `imap` の設定を使う。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JmapConfig {
+    /// セッションリソースのURL（空の場合はメールアドレスのドメインの `/.well-known/jmap`）
+    #[serde(default)]
+    pub session_url: String,
+}
+
+/// POP3サーバー（古いプロバイダーのアカウントなど）
+///
+/// 接続先と認証情報（サーバー・ポート・TLS・ユーザー名・パスワード）は `imap` の設定を使う。
+/// どちらの削除設定もない場合、メッセージはサーバーに残し続ける。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Pop3Config {
+    /// USER/PASSの代わりにAPOPでログインする（パスワードを平文で送らない）
+    #[serde(default)]
+    pub apop: bool,
+    /// ダウンロードしてから指定の日数が経ったメッセージをサーバーから削除する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leave_on_server_days: Option<u32>,
+    /// ダウンロードしたメッセージをすぐにサーバーから削除する
+    #[serde(default)]
+    pub delete_after_download: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,7 +71,12 @@ pub struct ImapConfig {
     pub server: String,
     pub port: u16,
     pub username: String,
-    pub password: String, // 実際の実装では暗号化して保存
+    /// 設定ファイルには書き込まず、キーリング等に保存する
+    #[serde(default, skip_serializing)]
+    pub password: String,
+    /// パスワードを出力するコマンド（`pass show mail/work` など）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
     pub use_tls: bool,
     pub use_starttls: bool,
     pub auth_method: AuthMethod,
@@ -32,7 +88,12 @@ pub struct SmtpConfig {
     pub server: String,
     pub port: u16,
     pub username: String,
-    pub password: String, // 実際の実装では暗号化して保存
+    /// 設定ファイルには書き込まず、キーリング等に保存する
+    #[serde(default, skip_serializing)]
+    pub password: String,
+    /// パスワードを出力するコマンド（`pass show mail/work` など）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
     pub use_tls: bool,
     pub use_starttls: bool,
     pub auth_method: AuthMethod,
@@ -60,14 +121,31 @@ pub enum FolderType {
     Sent,
     Drafts,
     Trash,
-    #[allow(dead_code)]
     Spam,
-    #[allow(dead_code)]
     Archive,
+    /// すべてのメッセージを集めた仮想フォルダー（Gmailの「すべてのメール」など）
+    All,
     #[allow(dead_code)]
     Custom(String),
 }
 
+impl FolderType {
+    /// 設定に対応がないときの表示名
+    pub fn default_local_name(&self) -> String {
+        match self {
+            FolderType::Inbox => "受信箱",
+            FolderType::Sent => "送信済み",
+            FolderType::Drafts => "下書き",
+            FolderType::Trash => "ゴミ箱",
+            FolderType::Spam => "迷惑メール",
+            FolderType::Archive => "アーカイブ",
+            FolderType::All => "すべてのメール",
+            FolderType::Custom(name) => name,
+        }
+        .to_string()
+    }
+}
+
 impl Default for Account {
     fn default() -> Self {
         Self {
@@ -81,6 +159,9 @@ impl Default for Account {
             enabled: true,
             oauth_config: None,
             tokens: None,
+            maildir: None,
+            jmap: None,
+            pop3: None,
         }
     }
 }
@@ -92,6 +173,7 @@ impl Default for ImapConfig {
             port: 993,
             username: "user@example.com".to_string(),
             password: "password".to_string(),
+            password_command: None,
             use_tls: true,
             use_starttls: false,
             auth_method: AuthMethod::Plain,
@@ -128,6 +210,7 @@ impl Default for SmtpConfig {
             port: 587,
             username: "user@example.com".to_string(),
             password: "password".to_string(),
+            password_command: None,
             use_tls: true,
             use_starttls: true,
             auth_method: AuthMethod::Plain,
@@ -154,6 +237,9 @@ impl Account {
             enabled: true,
             oauth_config: None,
             tokens: None,
+            maildir: None,
+            jmap: None,
+            pop3: None,
         }
     }
 
@@ -170,19 +256,25 @@ impl Account {
             return Err("Invalid email format".to_string());
         }
 
-        if self.imap.server.is_empty() {
+        if let Some(maildir) = &self.maildir {
+            if maildir.path.as_os_str().is_empty() {
+                return Err("Maildir path cannot be empty".to_string());
+            }
+        } else if self.jmap.is_none() && self.imap.server.is_empty() {
             return Err("IMAP server cannot be empty".to_string());
         }
 
-        if self.smtp.server.is_empty() {
+        // JMAPはEmailSubmissionで送信するのでSMTPの設定は不要
+        if self.jmap.is_none() && self.smtp.server.is_empty() {
             return Err("SMTP server cannot be empty".to_string());
         }
 
-        if self.imap.username.is_empty() {
+        // JMAPのユーザー名が空の場合はパスワードをAPIトークンとして使う
+        if self.maildir.is_none() && self.jmap.is_none() && self.imap.username.is_empty() {
             return Err("IMAP username cannot be empty".to_string());
         }
 
-        if self.smtp.username.is_empty() {
+        if self.jmap.is_none() && self.smtp.username.is_empty() {
             return Err("SMTP username cannot be empty".to_string());
         }
 
@@ -228,6 +320,29 @@ impl ImapConfig {
     }
 }
 
+impl MaildirConfig {
+    /// `~/` を展開したMaildirのルート
+    pub fn root(&self) -> PathBuf {
+        match self.path.strip_prefix("~") {
+            Ok(rest) => dirs::home_dir()
+                .map(|home| home.join(rest))
+                .unwrap_or_else(|| self.path.clone()),
+            Err(_) => self.path.clone(),
+        }
+    }
+}
+
+impl Pop3Config {
+    /// `downloaded_at` にダウンロードしたメッセージを `now` の時点でサーバーから削除するか
+    pub fn should_delete(&self, downloaded_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        if self.delete_after_download {
+            return true;
+        }
+        self.leave_on_server_days
+            .is_some_and(|days| now - downloaded_at >= Duration::days(days.into()))
+    }
+}
+
 impl SmtpConfig {
     pub fn get_connection_url(&self) -> String {
         let scheme = if self.use_tls { "smtps" } else { "smtp" };
@@ -1,8 +1,15 @@
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::Mutex;
 
-use super::oauth::{GoogleOAuthClient, OAuthFlowManager};
-use super::{Account, GmailApiClient, ImapClient, MailError, MailResult, Message, SmtpClient};
+use super::oauth::{DeviceAuthorization, OAuthClient, OAuthFlowManager, OAuthProvider};
+use super::{
+    Account, AuthMethod, Folder, FolderOperation, GmailApiClient, ImapClient, JmapClient,
+    MailError, MailResult, MaildirClient, Message, Pop3Client, Pop3Summary, ServerThread,
+    SmtpClient, Thread, TokenProvider,
+};
+use crate::search::Query;
 
 pub struct MailClient {
     accounts: Vec<Account>,
@@ -10,6 +17,10 @@ pub struct MailClient {
     smtp_connections: Mutex<HashMap<String, SmtpClient>>,
     oauth_flow_manager: Mutex<OAuthFlowManager>,
     gmail_api_clients: Mutex<HashMap<String, GmailApiClient>>,
+    maildir_clients: Mutex<HashMap<String, MaildirClient>>,
+    jmap_clients: Mutex<HashMap<String, JmapClient>>,
+    pop3_clients: Mutex<HashMap<String, Pop3Client>>,
+    token_provider: Arc<TokenProvider>,
 }
 
 impl MailClient {
@@ -20,9 +31,18 @@ impl MailClient {
             smtp_connections: Mutex::new(HashMap::new()),
             oauth_flow_manager: Mutex::new(OAuthFlowManager::new()),
             gmail_api_clients: Mutex::new(HashMap::new()),
+            maildir_clients: Mutex::new(HashMap::new()),
+            jmap_clients: Mutex::new(HashMap::new()),
+            pop3_clients: Mutex::new(HashMap::new()),
+            token_provider: Arc::new(TokenProvider::new()),
         }
     }
 
+    /// Gmail API / IMAP / SMTPで共有するトークンプロバイダー
+    pub fn token_provider(&self) -> Arc<TokenProvider> {
+        self.token_provider.clone()
+    }
+
     pub fn add_account(&mut self, account: Account) -> MailResult<()> {
         account.validate().map_err(MailError::Parse)?;
         self.accounts.push(account);
@@ -54,23 +74,44 @@ impl MailClient {
 
     /// OAuth2認証フローを開始
     pub async fn start_oauth_flow(&self, account_id: &str) -> MailResult<String> {
+        self.start_oauth_flow_with_redirect(account_id, None).await
+    }
+
+    /// リダイレクトURIを指定してOAuth2認証フローを開始
+    ///
+    /// ループバックリスナーがエフェメラルポートで待ち受ける場合に使う
+    pub async fn start_oauth_flow_with_redirect(
+        &self,
+        account_id: &str,
+        redirect_uri: Option<&str>,
+    ) -> MailResult<String> {
         let account = self
             .get_account(account_id)
             .ok_or_else(|| MailError::Parse("Account not found".to_string()))?;
 
-        let oauth_config = account
+        let mut oauth_config = account
             .oauth_config
             .as_ref()
-            .ok_or_else(|| MailError::Authentication("No OAuth config".to_string()))?;
+            .ok_or_else(|| MailError::Authentication("No OAuth config".to_string()))?
+            .clone();
+
+        if let Some(redirect_uri) = redirect_uri {
+            oauth_config.redirect_uri = redirect_uri.to_string();
+        }
 
-        let oauth_client = GoogleOAuthClient::new(oauth_config.clone()).map_err(|e| {
+        let oauth_client = OAuthClient::new(oauth_config.clone()).map_err(|e| {
             MailError::Authentication(format!("OAuth client creation failed: {}", e))
         })?;
 
-        let (auth_url, csrf_token) = oauth_client.get_authorization_url();
+        let (auth_url, csrf_token, pkce_verifier) = oauth_client.get_authorization_url();
 
         let mut flow_manager = self.oauth_flow_manager.lock().await;
-        flow_manager.start_flow(account_id.to_string(), csrf_token);
+        flow_manager.start_flow(
+            account_id.to_string(),
+            csrf_token,
+            pkce_verifier,
+            oauth_config.redirect_uri,
+        );
 
         Ok(auth_url.to_string())
     }
@@ -83,7 +124,7 @@ impl MailClient {
         state: String,
     ) -> MailResult<()> {
         // 必要な情報を先に取得
-        let oauth_config = self
+        let mut oauth_config = self
             .get_account(account_id)
             .ok_or_else(|| MailError::Parse("Account not found".to_string()))?
             .oauth_config
@@ -92,20 +133,21 @@ impl MailClient {
             .clone();
 
         // CSRF検証
-        {
+        let flow = {
             let mut flow_manager = self.oauth_flow_manager.lock().await;
             flow_manager
                 .validate_and_complete_flow(account_id, &state)
-                .map_err(|e| MailError::Authentication(e.to_string()))?;
-        }
+                .map_err(|e| MailError::Authentication(e.to_string()))?
+        };
+        oauth_config.redirect_uri = flow.redirect_uri;
 
         // トークン取得
-        let oauth_client = GoogleOAuthClient::new(oauth_config).map_err(|e| {
+        let oauth_client = OAuthClient::new(oauth_config).map_err(|e| {
             MailError::Authentication(format!("OAuth client creation failed: {}", e))
         })?;
 
         let tokens = oauth_client
-            .exchange_code_for_token(authorization_code)
+            .exchange_code_for_token(authorization_code, flow.pkce_verifier)
             .await
             .map_err(|e| MailError::Authentication(format!("Token exchange failed: {}", e)))?;
 
@@ -113,42 +155,78 @@ impl MailClient {
         let account = self
             .get_account_mut(account_id)
             .ok_or_else(|| MailError::Parse("Account not found".to_string()))?;
-        account.tokens = Some(tokens);
+        account.tokens = Some(tokens.clone());
+
+        let account = account.clone();
+        self.token_provider.register(&account).await;
+        self.token_provider.set_tokens(account_id, tokens).await;
 
         Ok(())
     }
 
-    /// OAuth2トークンを更新
-    pub async fn refresh_oauth_token(&mut self, account_id: &str) -> MailResult<()> {
+    /// デバイスフロー（RFC 8628）を開始
+    pub async fn start_device_flow(&self, account_id: &str) -> MailResult<DeviceAuthorization> {
+        let oauth_client = self.oauth_client_for(account_id)?;
+
+        oauth_client
+            .start_device_authorization()
+            .await
+            .map_err(|e| MailError::Authentication(format!("Device authorization failed: {}", e)))
+    }
+
+    /// ユーザーの認可を待ってデバイスフローのトークンを保存
+    pub async fn complete_device_flow(
+        &mut self,
+        account_id: &str,
+        authorization: &DeviceAuthorization,
+    ) -> MailResult<()> {
+        let oauth_client = self.oauth_client_for(account_id)?;
+
+        let tokens = oauth_client
+            .poll_device_token(authorization)
+            .await
+            .map_err(|e| MailError::Authentication(format!("Device flow failed: {}", e)))?;
+
         let account = self
             .get_account_mut(account_id)
             .ok_or_else(|| MailError::Parse("Account not found".to_string()))?;
+        account.tokens = Some(tokens.clone());
 
-        let oauth_config = account
-            .oauth_config
-            .as_ref()
-            .ok_or_else(|| MailError::Authentication("No OAuth config".to_string()))?;
+        let account = account.clone();
+        self.token_provider.register(&account).await;
+        self.token_provider.set_tokens(account_id, tokens).await;
 
-        let current_tokens = account
-            .tokens
-            .as_ref()
-            .ok_or_else(|| MailError::Authentication("No tokens available".to_string()))?;
+        Ok(())
+    }
 
-        let refresh_token = current_tokens
-            .refresh_token
+    fn oauth_client_for(&self, account_id: &str) -> MailResult<OAuthClient> {
+        let oauth_config = self
+            .get_account(account_id)
+            .ok_or_else(|| MailError::Parse("Account not found".to_string()))?
+            .oauth_config
             .as_ref()
-            .ok_or_else(|| MailError::Authentication("No refresh token available".to_string()))?;
+            .ok_or_else(|| MailError::Authentication("No OAuth config".to_string()))?
+            .clone();
 
-        let oauth_client = GoogleOAuthClient::new(oauth_config.clone()).map_err(|e| {
-            MailError::Authentication(format!("OAuth client creation failed: {}", e))
-        })?;
+        OAuthClient::new(oauth_config)
+            .map_err(|e| MailError::Authentication(format!("OAuth client creation failed: {}", e)))
+    }
 
-        let new_tokens = oauth_client
-            .refresh_access_token(refresh_token.clone())
-            .await
-            .map_err(|e| MailError::Authentication(format!("Token refresh failed: {}", e)))?;
+    /// OAuth2トークンを更新
+    pub async fn refresh_oauth_token(&mut self, account_id: &str) -> MailResult<()> {
+        let account = self
+            .get_account(account_id)
+            .ok_or_else(|| MailError::Parse("Account not found".to_string()))?
+            .clone();
 
-        account.tokens = Some(new_tokens);
+        self.token_provider.register(&account).await;
+        let access_token = self.token_provider.force_refresh(account_id).await?;
+        if let Some(tokens) = self
+            .get_account_mut(account_id)
+            .and_then(|account| account.tokens.as_mut())
+        {
+            tokens.access_token = access_token;
+        }
 
         Ok(())
     }
@@ -159,22 +237,47 @@ impl MailClient {
             .get_account(account_id)
             .ok_or_else(|| MailError::Parse("Account not found".to_string()))?;
 
+        // Maildirアカウントはサーバーに接続せずローカルのディレクトリを読む
+        if account.maildir.is_some() {
+            let maildir_client = MaildirClient::new(account.clone())?;
+            maildir_client.connect()?;
+            let mut maildir_clients = self.maildir_clients.lock().await;
+            maildir_clients.insert(account_id.to_string(), maildir_client);
+            return Ok(());
+        }
+
+        // POP3アカウントはログインを確認するだけで、受信のたびに接続し直す
+        if account.pop3.is_some() {
+            let pop3_client = Pop3Client::new(account.clone());
+            pop3_client.connect().await?;
+            let mut pop3_clients = self.pop3_clients.lock().await;
+            pop3_clients.insert(account_id.to_string(), pop3_client);
+            return Ok(());
+        }
+
+        self.token_provider.register(account).await;
+
+        // JMAPアカウントは設定で指定する（IMAP/SMTPは使わない）
+        if account.jmap.is_some() {
+            let mut jmap_client =
+                JmapClient::new(account.clone()).with_token_provider(self.token_provider.clone());
+            jmap_client.connect().await?;
+            let mut jmap_clients = self.jmap_clients.lock().await;
+            jmap_clients.insert(account_id.to_string(), jmap_client);
+            return Ok(());
+        }
+
         // Gmailアカウントの場合は最初からGmail APIクライアントを使用
         if self.is_gmail_account(&account.email) {
-            println!(
-                "デバッグ: Gmailアカウントを検出しました。Gmail APIクライアントを使用します。"
-            );
-
-            let gmail_client = GmailApiClient::new(account.clone());
+            let gmail_client = GmailApiClient::new(account.clone())
+                .with_token_provider(self.token_provider.clone());
             match gmail_client.connect().await {
                 Ok(_) => {
-                    println!("デバッグ: Gmail API接続が成功しました");
                     let mut gmail_clients = self.gmail_api_clients.lock().await;
                     gmail_clients.insert(account_id.to_string(), gmail_client);
                     return Ok(());
                 }
                 Err(e) => {
-                    println!("デバッグ: Gmail API接続に失敗しました: {:?}", e);
                     return Err(MailError::Connection(format!(
                         "Gmail API connection failed: {}",
                         e
@@ -184,43 +287,56 @@ impl MailClient {
         }
 
         // Gmail以外のアカウントの場合はIMAPを使用
-        println!("デバッグ: 非Gmailアカウントです。IMAPクライアントを使用します。");
-        let mut imap_client = ImapClient::new(account.clone());
+        let mut imap_client =
+            ImapClient::new(account.clone()).with_token_provider(self.token_provider.clone());
         match imap_client.connect().await {
             Ok(_) => {
-                println!("デバッグ: IMAP接続が成功しました");
                 let mut connections = self.imap_connections.lock().await;
                 connections.insert(account_id.to_string(), imap_client);
                 Ok(())
             }
-            Err(MailError::Authentication(ref msg)) if msg.contains("timeout") => {
-                println!("デバッグ: IMAP OAuth2認証がタイムアウトしました。Gmail APIクライアントを試行します。");
-
+            Err(MailError::Authentication(ref msg))
+                if msg.contains("timeout") && Self::uses_google_oauth(account) =>
+            {
                 // Gmail APIクライアントを作成して接続テスト
-                let gmail_client = GmailApiClient::new(account.clone());
+                let gmail_client = GmailApiClient::new(account.clone())
+                    .with_token_provider(self.token_provider.clone());
                 match gmail_client.connect().await {
                     Ok(_) => {
-                        println!("デバッグ: Gmail API接続が成功しました");
                         let mut gmail_clients = self.gmail_api_clients.lock().await;
                         gmail_clients.insert(account_id.to_string(), gmail_client);
                         Ok(())
                     }
-                    Err(e) => {
-                        println!("デバッグ: Gmail API接続も失敗しました: {:?}", e);
-                        Err(MailError::Connection(format!(
-                            "IMAP OAuth2 timeout and Gmail API connection also failed: {}",
-                            e
-                        )))
-                    }
+                    Err(e) => Err(MailError::Connection(format!(
+                        "IMAP OAuth2 timeout and Gmail API connection also failed: {}",
+                        e
+                    ))),
                 }
             }
-            Err(e) => {
-                println!("デバッグ: IMAP接続に失敗しました: {:?}", e);
-                Err(e)
-            }
+            Err(e) => Err(e),
         }
     }
 
+    /// GoogleのOAuth2で認証するアカウントか（Gmail APIへのフォールバック可否）
+    fn uses_google_oauth(account: &Account) -> bool {
+        account
+            .oauth_config
+            .as_ref()
+            .is_some_and(|c| c.provider == OAuthProvider::Google)
+    }
+
+    /// IMAP・Gmail API・Maildir・JMAP・POP3のいずれかで接続済みかどうか
+    pub async fn is_connected(&self, account_id: &str) -> bool {
+        if self.gmail_api_clients.lock().await.contains_key(account_id)
+            || self.maildir_clients.lock().await.contains_key(account_id)
+            || self.jmap_clients.lock().await.contains_key(account_id)
+            || self.pop3_clients.lock().await.contains_key(account_id)
+        {
+            return true;
+        }
+        self.imap_connections.lock().await.contains_key(account_id)
+    }
+
     /// Gmailアカウントかどうかを判定
     fn is_gmail_account(&self, email: &str) -> bool {
         let email_lower = email.to_lowercase();
@@ -233,7 +349,22 @@ impl MailClient {
             .get_account(account_id)
             .ok_or_else(|| MailError::Parse("Account not found".to_string()))?;
 
-        let mut smtp_client = SmtpClient::new(account.clone());
+        // JMAPアカウントはEmailSubmissionで送信する
+        if account.jmap.is_some() {
+            return Ok(());
+        }
+
+        // OAuth2では共有のプロバイダーから有効なアクセストークンを受け取って接続する
+        let mut account = account.clone();
+        if account.smtp.auth_method == AuthMethod::OAuth2 {
+            self.token_provider.register(&account).await;
+            let access_token = self.token_provider.access_token(account_id).await?;
+            if let Some(tokens) = account.tokens.as_mut() {
+                tokens.access_token = access_token;
+            }
+        }
+
+        let mut smtp_client = SmtpClient::new(account);
         smtp_client.connect().await?;
 
         let mut connections = self.smtp_connections.lock().await;
@@ -244,6 +375,9 @@ impl MailClient {
 
     /// IMAP接続を切断
     pub async fn disconnect_imap(&self, account_id: &str) -> MailResult<()> {
+        self.maildir_clients.lock().await.remove(account_id);
+        self.jmap_clients.lock().await.remove(account_id);
+        self.pop3_clients.lock().await.remove(account_id);
         let mut connections = self.imap_connections.lock().await;
         if let Some(mut client) = connections.remove(account_id) {
             client.disconnect().await?;
@@ -267,11 +401,30 @@ impl MailClient {
         folder: &str,
         limit: Option<usize>,
     ) -> MailResult<Vec<Message>> {
+        {
+            let maildir_clients = self.maildir_clients.lock().await;
+            if let Some(maildir_client) = maildir_clients.get(account_id) {
+                return maildir_client.fetch_messages(folder, limit);
+            }
+        }
+
+        {
+            let mut jmap_clients = self.jmap_clients.lock().await;
+            if let Some(jmap_client) = jmap_clients.get_mut(account_id) {
+                return jmap_client.fetch_messages(folder, limit).await;
+            }
+        }
+
+        if self.is_pop3(account_id).await {
+            return Err(MailError::Protocol(
+                "POP3 accounts are read from the local cache".to_string(),
+            ));
+        }
+
         // まずGmail APIクライアントが利用可能かチェック
         {
             let gmail_clients = self.gmail_api_clients.lock().await;
             if let Some(gmail_client) = gmail_clients.get(account_id) {
-                println!("デバッグ: Gmail APIクライアントを使用してメッセージを取得します");
                 return gmail_client.fetch_messages(folder, limit).await;
             }
         }
@@ -282,10 +435,163 @@ impl MailClient {
             MailError::Connection("IMAP not connected and Gmail API not available".to_string())
         })?;
 
-        println!("デバッグ: IMAPクライアントを使用してメッセージを取得します");
         client.fetch_messages(folder, limit).await
     }
 
+    /// サーバー側でフォルダーを検索し、一致したメッセージを新しい順に最大 `limit` 件取得
+    ///
+    /// IMAPは `UID SEARCH`、Gmail APIは検索演算子（`q`）を使う。Maildir・POP3のメッセージは
+    /// すべてローカルのキャッシュにあり、JMAPのサーバー検索には対応していないので空を返す。
+    pub async fn search_messages(
+        &self,
+        account_id: &str,
+        folder: &str,
+        query: &Query,
+        limit: usize,
+    ) -> MailResult<Vec<Message>> {
+        if self.maildir_clients.lock().await.contains_key(account_id)
+            || self.jmap_clients.lock().await.contains_key(account_id)
+            || self.is_pop3(account_id).await
+        {
+            return Ok(Vec::new());
+        }
+
+        {
+            let gmail_clients = self.gmail_api_clients.lock().await;
+            if let Some(gmail_client) = gmail_clients.get(account_id) {
+                let q = query.to_gmail_search(folder).ok_or_else(|| {
+                    MailError::Protocol(
+                        "The query cannot be expressed as a Gmail search".to_string(),
+                    )
+                })?;
+                return gmail_client.search_messages(folder, &q, limit).await;
+            }
+        }
+
+        let mut connections = self.imap_connections.lock().await;
+        let client = connections
+            .get_mut(account_id)
+            .ok_or_else(|| MailError::Connection("IMAP not connected".to_string()))?;
+
+        client.search_messages(folder, query, limit).await
+    }
+
+    /// POP3の受信箱から未取得のメッセージをダウンロードする（`Pop3Client::download`）
+    pub async fn download_pop3<F>(
+        &self,
+        account_id: &str,
+        known: &HashMap<String, DateTime<Utc>>,
+        store: F,
+    ) -> MailResult<Pop3Summary>
+    where
+        F: FnMut(&str, &Message) -> MailResult<()>,
+    {
+        let pop3_clients = self.pop3_clients.lock().await;
+        let pop3_client = pop3_clients
+            .get(account_id)
+            .ok_or_else(|| MailError::Connection("POP3 not connected".to_string()))?;
+
+        pop3_client.download(known, store).await
+    }
+
+    /// POP3アカウントか（フォルダーとフラグはローカルのキャッシュにしかない）
+    async fn is_pop3(&self, account_id: &str) -> bool {
+        self.pop3_clients.lock().await.contains_key(account_id)
+    }
+
+    /// メッセージIDがIMAPのUIDか（同期で取得した範囲をUIDで表せる）
+    pub async fn uses_imap_uids(&self, account_id: &str) -> bool {
+        !self.maildir_clients.lock().await.contains_key(account_id)
+            && !self.jmap_clients.lock().await.contains_key(account_id)
+            && !self.is_pop3(account_id).await
+            && !self.gmail_api_clients.lock().await.contains_key(account_id)
+            && self.imap_connections.lock().await.contains_key(account_id)
+    }
+
+    /// サーバーが組んだフォルダーのスレッドの木を取得
+    ///
+    /// IMAPのTHREAD=REFERENCESに対応したサーバーだけが返し、それ以外は `None`。
+    pub async fn fetch_server_threads(
+        &self,
+        account_id: &str,
+        folder: &str,
+    ) -> MailResult<Option<Vec<ServerThread>>> {
+        if !self.uses_imap_uids(account_id).await {
+            return Ok(None);
+        }
+
+        let mut connections = self.imap_connections.lock().await;
+        match connections.get_mut(account_id) {
+            Some(client) => client.fetch_server_threads(folder).await,
+            None => Ok(None),
+        }
+    }
+
+    /// スレッド（会話）一覧を取得（Gmail APIアカウントのみ）
+    pub async fn fetch_threads(
+        &self,
+        account_id: &str,
+        folder: &str,
+        limit: Option<usize>,
+    ) -> MailResult<Vec<Thread>> {
+        let gmail_clients = self.gmail_api_clients.lock().await;
+        let gmail_client = gmail_clients.get(account_id).ok_or_else(|| {
+            MailError::Connection("Conversation view requires a Gmail API account".to_string())
+        })?;
+
+        gmail_client.fetch_threads(folder, limit).await
+    }
+
+    /// スレッドを全メッセージ付きで取得
+    pub async fn fetch_thread(
+        &self,
+        account_id: &str,
+        thread_id: &str,
+        folder: &str,
+    ) -> MailResult<Thread> {
+        let gmail_clients = self.gmail_api_clients.lock().await;
+        let gmail_client = gmail_clients.get(account_id).ok_or_else(|| {
+            MailError::Connection("Conversation view requires a Gmail API account".to_string())
+        })?;
+
+        gmail_client.fetch_thread(thread_id, folder).await
+    }
+
+    /// スレッド内の全メッセージをアーカイブ
+    pub async fn archive_thread(&self, account_id: &str, thread_id: &str) -> MailResult<()> {
+        let gmail_clients = self.gmail_api_clients.lock().await;
+        let gmail_client = gmail_clients
+            .get(account_id)
+            .ok_or_else(|| MailError::Connection("Gmail API not available".to_string()))?;
+
+        gmail_client.archive_thread(thread_id).await
+    }
+
+    /// スレッドをミュート
+    pub async fn mute_thread(&self, account_id: &str, thread_id: &str) -> MailResult<()> {
+        let gmail_clients = self.gmail_api_clients.lock().await;
+        let gmail_client = gmail_clients
+            .get(account_id)
+            .ok_or_else(|| MailError::Connection("Gmail API not available".to_string()))?;
+
+        gmail_client.mute_thread(thread_id).await
+    }
+
+    /// スレッド内の全メッセージにラベルを付与
+    pub async fn label_thread(
+        &self,
+        account_id: &str,
+        thread_id: &str,
+        label: &str,
+    ) -> MailResult<()> {
+        let gmail_clients = self.gmail_api_clients.lock().await;
+        let gmail_client = gmail_clients
+            .get(account_id)
+            .ok_or_else(|| MailError::Connection("Gmail API not available".to_string()))?;
+
+        gmail_client.label_thread(thread_id, label).await
+    }
+
     /// メッセージ本文を取得
     pub async fn fetch_message_body(
         &self,
@@ -293,9 +599,27 @@ impl MailClient {
         message_id: &str,
         folder: &str,
     ) -> MailResult<String> {
-        let uid: u32 = message_id
-            .parse()
-            .map_err(|_| MailError::Parse("Invalid message ID".to_string()))?;
+        {
+            let maildir_clients = self.maildir_clients.lock().await;
+            if let Some(maildir_client) = maildir_clients.get(account_id) {
+                return maildir_client.fetch_message_body(folder, message_id);
+            }
+        }
+
+        {
+            let mut jmap_clients = self.jmap_clients.lock().await;
+            if let Some(jmap_client) = jmap_clients.get_mut(account_id) {
+                return jmap_client.fetch_message_body(folder, message_id).await;
+            }
+        }
+
+        if self.is_pop3(account_id).await {
+            return Err(MailError::Protocol(
+                "POP3 accounts are read from the local cache".to_string(),
+            ));
+        }
+
+        let uid = Self::parse_uid(message_id)?;
 
         let mut connections = self.imap_connections.lock().await;
         let client = connections
@@ -305,8 +629,42 @@ impl MailClient {
         client.fetch_message_body(folder, uid).await
     }
 
+    /// 本文を省いて一覧を取得したメッセージ（IMAP）を、本文・添付ファイル付きで取得し直す
+    ///
+    /// 他の方式は一覧の取得で本文も取得しているので対象外。
+    pub async fn fetch_full_message(
+        &self,
+        account_id: &str,
+        folder: &str,
+        message_id: &str,
+    ) -> MailResult<Message> {
+        let uid = Self::parse_uid(message_id)?;
+
+        let mut connections = self.imap_connections.lock().await;
+        let client = connections
+            .get_mut(account_id)
+            .ok_or_else(|| MailError::Connection("IMAP not connected".to_string()))?;
+
+        client.fetch_full_message(folder, uid).await
+    }
+
     /// メールを送信
     pub async fn send_message(&self, account_id: &str, message: &Message) -> MailResult<()> {
+        {
+            let mut jmap_clients = self.jmap_clients.lock().await;
+            if let Some(jmap_client) = jmap_clients.get_mut(account_id) {
+                return jmap_client.send_message(message).await;
+            }
+        }
+
+        // OAuth2のトークンは失効するので、送信のたびに新しいトークンで接続し直す
+        if self
+            .get_account(account_id)
+            .is_some_and(|account| account.smtp.auth_method == AuthMethod::OAuth2)
+        {
+            self.connect_smtp(account_id).await?;
+        }
+
         let mut connections = self.smtp_connections.lock().await;
         let client = connections
             .get_mut(account_id)
@@ -323,37 +681,136 @@ impl MailClient {
         from_folder: &str,
         to_folder: &str,
     ) -> MailResult<()> {
-        let uid: u32 = message_id
-            .parse()
-            .map_err(|_| MailError::Parse("Invalid message ID".to_string()))?;
+        {
+            let maildir_clients = self.maildir_clients.lock().await;
+            if let Some(maildir_client) = maildir_clients.get(account_id) {
+                return maildir_client.move_message(message_id, from_folder, to_folder);
+            }
+        }
+
+        {
+            let mut jmap_clients = self.jmap_clients.lock().await;
+            if let Some(jmap_client) = jmap_clients.get_mut(account_id) {
+                return jmap_client
+                    .move_message(message_id, from_folder, to_folder)
+                    .await;
+            }
+        }
+
+        // POP3のメッセージはキャッシュにしかないので、サーバーに反映するものはない
+        if self.is_pop3(account_id).await {
+            return Ok(());
+        }
+
+        {
+            let gmail_clients = self.gmail_api_clients.lock().await;
+            if let Some(gmail_client) = gmail_clients.get(account_id) {
+                return gmail_client
+                    .move_message(message_id, from_folder, to_folder)
+                    .await;
+            }
+        }
+
+        let uid = Self::parse_uid(message_id)?;
 
         let mut connections = self.imap_connections.lock().await;
         let client = connections
             .get_mut(account_id)
             .ok_or_else(|| MailError::Connection("IMAP not connected".to_string()))?;
 
+        Self::ensure_message_exists(client, from_folder, uid).await?;
         client.move_message(from_folder, to_folder, uid).await
     }
 
-    /// メッセージを削除
+    /// メッセージを削除（Gmailではゴミ箱へ移動）
     pub async fn delete_message(
         &self,
         account_id: &str,
         message_id: &str,
         folder: &str,
     ) -> MailResult<()> {
-        let uid: u32 = message_id
-            .parse()
-            .map_err(|_| MailError::Parse("Invalid message ID".to_string()))?;
+        {
+            let maildir_clients = self.maildir_clients.lock().await;
+            if let Some(maildir_client) = maildir_clients.get(account_id) {
+                return maildir_client.delete_message(folder, message_id);
+            }
+        }
+
+        {
+            let mut jmap_clients = self.jmap_clients.lock().await;
+            if let Some(jmap_client) = jmap_clients.get_mut(account_id) {
+                return jmap_client.delete_message(message_id).await;
+            }
+        }
+
+        // POP3のメッセージはキャッシュにしかないので、サーバーに反映するものはない
+        if self.is_pop3(account_id).await {
+            return Ok(());
+        }
+
+        {
+            let gmail_clients = self.gmail_api_clients.lock().await;
+            if let Some(gmail_client) = gmail_clients.get(account_id) {
+                return gmail_client.trash_message(message_id).await;
+            }
+        }
+
+        let uid = Self::parse_uid(message_id)?;
 
         let mut connections = self.imap_connections.lock().await;
         let client = connections
             .get_mut(account_id)
             .ok_or_else(|| MailError::Connection("IMAP not connected".to_string()))?;
 
+        Self::ensure_message_exists(client, folder, uid).await?;
         client.delete_message(folder, uid).await
     }
 
+    /// 生メッセージをフォルダーに追加（mboxの取り込みなど）
+    pub async fn append_message(
+        &self,
+        account_id: &str,
+        folder: &str,
+        raw: &[u8],
+        flags: &[super::Flag],
+    ) -> MailResult<()> {
+        {
+            let maildir_clients = self.maildir_clients.lock().await;
+            if let Some(maildir_client) = maildir_clients.get(account_id) {
+                return maildir_client
+                    .append_message(folder, raw, flags)
+                    .map(|_| ());
+            }
+        }
+
+        {
+            let mut jmap_clients = self.jmap_clients.lock().await;
+            if let Some(jmap_client) = jmap_clients.get_mut(account_id) {
+                return jmap_client.append_message(folder, raw, flags).await;
+            }
+        }
+
+        if self.is_pop3(account_id).await {
+            return Err(MailError::Protocol(
+                "POP3 cannot store messages on the server".to_string(),
+            ));
+        }
+
+        {
+            let gmail_clients = self.gmail_api_clients.lock().await;
+            if let Some(gmail_client) = gmail_clients.get(account_id) {
+                return gmail_client.import_message(folder, raw, flags).await;
+            }
+        }
+
+        let mut connections = self.imap_connections.lock().await;
+        let client = connections
+            .get_mut(account_id)
+            .ok_or_else(|| MailError::Connection("IMAP not connected".to_string()))?;
+
+        client.append_message(folder, raw, flags).await
+    }
+
     /// メッセージを既読にする
     pub async fn mark_as_read(
         &self,
@@ -361,27 +818,118 @@ impl MailClient {
         message_id: &str,
         folder: &str,
     ) -> MailResult<()> {
-        let uid: u32 = message_id
-            .parse()
-            .map_err(|_| MailError::Parse("Invalid message ID".to_string()))?;
+        self.set_message_flag(account_id, message_id, folder, &super::Flag::Seen, true)
+            .await
+    }
+
+    /// メッセージのフラグを付ける・外す
+    pub async fn set_message_flag(
+        &self,
+        account_id: &str,
+        message_id: &str,
+        folder: &str,
+        flag: &super::Flag,
+        enabled: bool,
+    ) -> MailResult<()> {
+        {
+            let maildir_clients = self.maildir_clients.lock().await;
+            if let Some(maildir_client) = maildir_clients.get(account_id) {
+                return maildir_client.set_message_flag(folder, message_id, flag, enabled);
+            }
+        }
+
+        {
+            let mut jmap_clients = self.jmap_clients.lock().await;
+            if let Some(jmap_client) = jmap_clients.get_mut(account_id) {
+                return jmap_client
+                    .set_message_flag(message_id, flag, enabled)
+                    .await;
+            }
+        }
+
+        // POP3のメッセージはキャッシュにしかないので、サーバーに反映するものはない
+        if self.is_pop3(account_id).await {
+            return Ok(());
+        }
+
+        {
+            let gmail_clients = self.gmail_api_clients.lock().await;
+            if let Some(gmail_client) = gmail_clients.get(account_id) {
+                return gmail_client
+                    .set_message_flag(message_id, flag, enabled)
+                    .await;
+            }
+        }
+
+        let uid = Self::parse_uid(message_id)?;
 
         let mut connections = self.imap_connections.lock().await;
         let client = connections
             .get_mut(account_id)
             .ok_or_else(|| MailError::Connection("IMAP not connected".to_string()))?;
 
-        client
-            .set_message_flags(folder, uid, &[super::Flag::Seen])
-            .await
+        Self::ensure_message_exists(client, folder, uid).await?;
+        if enabled {
+            client
+                .set_message_flags(folder, uid, std::slice::from_ref(flag))
+                .await
+        } else {
+            client
+                .remove_message_flags(folder, uid, std::slice::from_ref(flag))
+                .await
+        }
+    }
+
+    fn parse_uid(message_id: &str) -> MailResult<u32> {
+        message_id
+            .parse()
+            .map_err(|_| MailError::Parse("Invalid message ID".to_string()))
+    }
+
+    /// サーバー側でメッセージが削除・移動されていないか確認
+    async fn ensure_message_exists(
+        client: &mut ImapClient,
+        folder: &str,
+        uid: u32,
+    ) -> MailResult<()> {
+        if client.message_exists(folder, uid).await? {
+            Ok(())
+        } else {
+            Err(MailError::NotFound(format!(
+                "Message UID {} in {}",
+                uid, folder
+            )))
+        }
     }
 
     /// フォルダー一覧を取得
-    pub async fn get_folders(&self, account_id: &str) -> MailResult<Vec<String>> {
+    pub async fn get_folders(&self, account_id: &str) -> MailResult<Vec<Folder>> {
+        {
+            let maildir_clients = self.maildir_clients.lock().await;
+            if let Some(maildir_client) = maildir_clients.get(account_id) {
+                return maildir_client.list_folders();
+            }
+        }
+
+        {
+            let mut jmap_clients = self.jmap_clients.lock().await;
+            if let Some(jmap_client) = jmap_clients.get_mut(account_id) {
+                return jmap_client.list_folders().await;
+            }
+        }
+
+        // POP3には受信箱しかない
+        if self.is_pop3(account_id).await {
+            let account = self
+                .get_account(account_id)
+                .ok_or_else(|| MailError::Parse("Account not found".to_string()))?;
+            return Ok(vec![Folder::new(account.get_inbox_folder(), None)]);
+        }
+
         // まずGmail APIクライアントが利用可能かチェック
         {
             let gmail_clients = self.gmail_api_clients.lock().await;
             if let Some(gmail_client) = gmail_clients.get(account_id) {
-                println!("デバッグ: Gmail APIクライアントを使用してフォルダー一覧を取得します");
                 return gmail_client.list_folders().await;
             }
         }
@@ -392,10 +940,55 @@ impl MailClient {
             MailError::Connection("IMAP not connected and Gmail API not available".to_string())
         })?;
 
-        println!("デバッグ: IMAPクライアントを使用してフォルダー一覧を取得します");
         client.list_folders().await
     }
 
+    /// フォルダーを作成・名前変更・削除・購読（IMAPのフォルダーとGmail APIのラベル）
+    pub async fn manage_folder(
+        &self,
+        account_id: &str,
+        operation: &FolderOperation,
+    ) -> MailResult<()> {
+        let unsupported = self.maildir_clients.lock().await.contains_key(account_id)
+            || self.jmap_clients.lock().await.contains_key(account_id)
+            || self.is_pop3(account_id).await;
+        if unsupported {
+            return Err(MailError::Protocol(
+                "Folder management is only supported for IMAP and Gmail accounts".to_string(),
+            ));
+        }
+
+        {
+            let gmail_clients = self.gmail_api_clients.lock().await;
+            if let Some(gmail_client) = gmail_clients.get(account_id) {
+                return match operation {
+                    FolderOperation::Create(name) => gmail_client.create_label(name).await,
+                    FolderOperation::Rename { from, to } => {
+                        gmail_client.rename_label(from, to).await
+                    }
+                    FolderOperation::Delete(name) => gmail_client.delete_label(name).await,
+                    FolderOperation::Subscribe { name, subscribed } => {
+                        gmail_client.set_label_visible(name, *subscribed).await
+                    }
+                };
+            }
+        }
+
+        let mut connections = self.imap_connections.lock().await;
+        let client = connections
+            .get_mut(account_id)
+            .ok_or_else(|| MailError::Connection("IMAP not connected".to_string()))?;
+
+        match operation {
+            FolderOperation::Create(name) => client.create_folder(name).await,
+            FolderOperation::Rename { from, to } => client.rename_folder(from, to).await,
+            FolderOperation::Delete(name) => client.delete_folder(name).await,
+            FolderOperation::Subscribe { name, subscribed } => {
+                client.set_subscribed(name, *subscribed).await
+            }
+        }
+    }
+
     /// 接続状態をテスト
     pub async fn test_connections(&self, account_id: &str) -> MailResult<(bool, bool)> {
         let mut imap_ok = false;
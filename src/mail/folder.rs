@@ -0,0 +1,331 @@
+use super::account::{FolderMapping, FolderType};
+
+/// サーバー上のフォルダー（IMAPのLIST応答の1行、JMAPのメールボックス、Gmailのラベル）
+#[derive(Debug, Clone, PartialEq)]
+pub struct Folder {
+    /// サーバー上の完全な名前（`Projects/2024` など）
+    pub name: String,
+    /// 階層の区切り文字（階層のないサーバーではNone）
+    pub delimiter: Option<String>,
+    /// 開けるフォルダーか（IMAPの `\Noselect` が付いた階層だけのフォルダーはfalse）
+    pub selectable: bool,
+    /// サーバーが報告した役割（IMAPのSPECIAL-USE属性、JMAPのrole、Gmailのシステムラベル）
+    pub role: Option<FolderType>,
+    /// 購読しているか（購読中のフォルダーだけを表示する設定で使う）
+    pub subscribed: bool,
+}
+
+impl Folder {
+    pub fn new(name: impl Into<String>, delimiter: Option<&str>) -> Self {
+        Self {
+            name: name.into(),
+            delimiter: delimiter.map(str::to_string),
+            selectable: true,
+            role: None,
+            subscribed: true,
+        }
+    }
+
+    pub fn with_role(mut self, role: Option<FolderType>) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// 区切り文字で分けた階層（`Projects/2024` なら `["Projects", "2024"]`）
+    fn segments(&self) -> Vec<&str> {
+        match self.delimiter.as_deref() {
+            Some(delimiter) if !delimiter.is_empty() => self.name.split(delimiter).collect(),
+            _ => vec![self.name.as_str()],
+        }
+    }
+}
+
+/// フォルダーの管理操作（IMAPのフォルダーとGmailのラベル）
+#[derive(Debug, Clone, PartialEq)]
+pub enum FolderOperation {
+    Create(String),
+    Rename {
+        from: String,
+        to: String,
+    },
+    Delete(String),
+    /// 購読する（true）・購読をやめる（false）
+    Subscribe {
+        name: String,
+        subscribed: bool,
+    },
+}
+
+impl FolderOperation {
+    /// 確認ダイアログに出す説明
+    pub fn description(&self) -> String {
+        match self {
+            FolderOperation::Create(name) => format!("フォルダー「{}」を作成します", name),
+            FolderOperation::Rename { from, to } => {
+                format!("フォルダー「{}」の名前を「{}」に変更します", from, to)
+            }
+            FolderOperation::Delete(name) => format!(
+                "フォルダー「{}」を削除します（中のメッセージもサーバーから消えます。Gmailではラベルだけを外します）",
+                name
+            ),
+            FolderOperation::Subscribe {
+                name,
+                subscribed: true,
+            } => format!("フォルダー「{}」を購読します", name),
+            FolderOperation::Subscribe { name, .. } => {
+                format!("フォルダー「{}」の購読をやめます", name)
+            }
+        }
+    }
+}
+
+/// フォルダー一覧に表示する階層の1行
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderNode {
+    /// サーバー上の完全な名前
+    pub name: String,
+    /// 表示名（特別なフォルダーは設定の表示名、それ以外は階層の最後の部分）
+    pub label: String,
+    /// 階層の深さ（最上位が0）
+    pub depth: usize,
+    pub has_children: bool,
+    pub selectable: bool,
+    pub subscribed: bool,
+}
+
+/// 特別なフォルダーの対応（設定した対応を、サーバーが報告した役割で補ったもの）
+///
+/// 設定した対応は、そのフォルダーがサーバーにあれば（一覧がまだなければ常に）そのまま使う。
+/// サーバーにない場合は同じ役割のフォルダーに差し替え、表示名は設定のものを引き継ぐ。
+/// 設定にない役割は、役割の既定の表示名で後ろに加える。
+pub fn resolve_mappings(configured: &[FolderMapping], folders: &[Folder]) -> Vec<FolderMapping> {
+    let mut mappings: Vec<FolderMapping> = configured
+        .iter()
+        .map(|mapping| {
+            let exists = folders.is_empty()
+                || folders
+                    .iter()
+                    .any(|folder| folder.name == mapping.server_name);
+            let detected = folders
+                .iter()
+                .find(|folder| folder.role.as_ref() == Some(&mapping.folder_type));
+            match detected {
+                Some(folder) if !exists => FolderMapping::new(
+                    mapping.folder_type.clone(),
+                    folder.name.clone(),
+                    mapping.local_name.clone(),
+                ),
+                _ => mapping.clone(),
+            }
+        })
+        .collect();
+
+    for folder in folders {
+        let Some(role) = &folder.role else {
+            continue;
+        };
+        if !mappings.iter().any(|mapping| mapping.folder_type == *role) {
+            mappings.push(FolderMapping::new(
+                role.clone(),
+                folder.name.clone(),
+                role.default_local_name(),
+            ));
+        }
+    }
+    mappings
+}
+
+struct TreeNode {
+    name: String,
+    segment: String,
+    selectable: bool,
+    subscribed: bool,
+    children: Vec<TreeNode>,
+}
+
+/// フォルダーを区切り文字で入れ子にし、親の直後に子が来る順に並べる
+///
+/// 同じ階層では受信箱、`mappings` の特別なフォルダー（設定の順）、残りを名前順に並べる。
+/// 一覧に親がない子フォルダーには、開けない親を補う。
+pub fn folder_tree(folders: &[Folder], mappings: &[FolderMapping]) -> Vec<FolderNode> {
+    let mut roots: Vec<TreeNode> = Vec::new();
+    for folder in folders {
+        let segments = folder.segments();
+        let delimiter = folder.delimiter.as_deref().unwrap_or_default();
+        let mut siblings = &mut roots;
+        for depth in 0..segments.len() {
+            let name = segments[..=depth].join(delimiter);
+            let index = match siblings.iter().position(|node| node.name == name) {
+                Some(index) => index,
+                None => {
+                    siblings.push(TreeNode {
+                        name,
+                        segment: segments[depth].to_string(),
+                        selectable: false,
+                        subscribed: true,
+                        children: Vec::new(),
+                    });
+                    siblings.len() - 1
+                }
+            };
+            if depth + 1 == segments.len() {
+                siblings[index].selectable = folder.selectable;
+                siblings[index].subscribed = folder.subscribed;
+            }
+            siblings = &mut siblings[index].children;
+        }
+    }
+
+    let mut nodes = Vec::new();
+    flatten(roots, mappings, 0, &mut nodes);
+    nodes
+}
+
+fn flatten(
+    mut siblings: Vec<TreeNode>,
+    mappings: &[FolderMapping],
+    depth: usize,
+    nodes: &mut Vec<FolderNode>,
+) {
+    siblings
+        .sort_by_cached_key(|node| (special_rank(&node.name, mappings), node.name.to_lowercase()));
+    for node in siblings {
+        let label = mappings
+            .iter()
+            .find(|mapping| mapping.server_name == node.name)
+            .map(|mapping| mapping.local_name.clone())
+            .unwrap_or(node.segment);
+        nodes.push(FolderNode {
+            name: node.name,
+            label,
+            depth,
+            has_children: !node.children.is_empty(),
+            selectable: node.selectable,
+            subscribed: node.subscribed,
+        });
+        flatten(node.children, mappings, depth + 1, nodes);
+    }
+}
+
+/// 並び順（受信箱が0、特別なフォルダーは設定の順、それ以外は最後）
+fn special_rank(name: &str, mappings: &[FolderMapping]) -> usize {
+    if name.eq_ignore_ascii_case("INBOX") {
+        return 0;
+    }
+    mappings
+        .iter()
+        .position(|mapping| mapping.server_name == name)
+        .map_or(usize::MAX, |index| index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_folder_tree_nests_and_orders_special_folders_first() {
+        let mappings = vec![
+            FolderMapping {
+                folder_type: FolderType::Sent,
+                server_name: "[Gmail]/Sent Mail".to_string(),
+                local_name: "送信済み".to_string(),
+            },
+            FolderMapping {
+                folder_type: FolderType::Trash,
+                server_name: "Trash".to_string(),
+                local_name: "ゴミ箱".to_string(),
+            },
+        ];
+        let mut gmail = Folder::new("[Gmail]", Some("/"));
+        gmail.selectable = false;
+        let folders = vec![
+            Folder::new("Archive", Some("/")),
+            Folder::new("Trash", Some("/")),
+            Folder::new("[Gmail]/Spam", Some("/")),
+            Folder::new("[Gmail]/Sent Mail", Some("/")),
+            gmail,
+            Folder::new("INBOX", Some("/")),
+            // 親が一覧にない子フォルダー
+            Folder::new("Projects/2024/Q1", Some("/")),
+            Folder::new("Lists.rust", Some(".")),
+        ];
+
+        let nodes = folder_tree(&folders, &mappings);
+        let rows: Vec<(&str, &str, usize, bool, bool)> = nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.name.as_str(),
+                    node.label.as_str(),
+                    node.depth,
+                    node.has_children,
+                    node.selectable,
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("INBOX", "INBOX", 0, false, true),
+                ("Trash", "ゴミ箱", 0, false, true),
+                ("[Gmail]", "[Gmail]", 0, true, false),
+                ("[Gmail]/Sent Mail", "送信済み", 1, false, true),
+                ("[Gmail]/Spam", "Spam", 1, false, true),
+                ("Archive", "Archive", 0, false, true),
+                ("Lists", "Lists", 0, true, false),
+                ("Lists.rust", "rust", 1, false, true),
+                ("Projects", "Projects", 0, true, false),
+                ("Projects/2024", "2024", 1, true, false),
+                ("Projects/2024/Q1", "Q1", 2, false, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_mappings_fills_roles_and_keeps_overrides() {
+        let configured = vec![
+            FolderMapping::new(FolderType::Inbox, "INBOX".to_string(), "受信箱".to_string()),
+            // サーバーにないので役割で差し替える
+            FolderMapping::new(FolderType::Sent, "Sent".to_string(), "送信済み".to_string()),
+            // サーバーにあるので役割より優先する
+            FolderMapping::new(
+                FolderType::Trash,
+                "Deleted".to_string(),
+                "削除済み".to_string(),
+            ),
+        ];
+        let folders = vec![
+            Folder::new("INBOX", Some("/")),
+            Folder::new("Deleted", Some("/")),
+            Folder::new("[Gmail]/Sent Mail", Some("/")).with_role(Some(FolderType::Sent)),
+            Folder::new("[Gmail]/Trash", Some("/")).with_role(Some(FolderType::Trash)),
+            Folder::new("[Gmail]/All Mail", Some("/")).with_role(Some(FolderType::All)),
+            Folder::new("[Gmail]/Spam", Some("/")).with_role(Some(FolderType::Spam)),
+        ];
+
+        let mappings = resolve_mappings(&configured, &folders);
+        let rows: Vec<(FolderType, &str, &str)> = mappings
+            .iter()
+            .map(|m| {
+                (
+                    m.folder_type.clone(),
+                    m.server_name.as_str(),
+                    m.local_name.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (FolderType::Inbox, "INBOX", "受信箱"),
+                (FolderType::Sent, "[Gmail]/Sent Mail", "送信済み"),
+                (FolderType::Trash, "Deleted", "削除済み"),
+                (FolderType::All, "[Gmail]/All Mail", "すべてのメール"),
+                (FolderType::Spam, "[Gmail]/Spam", "迷惑メール"),
+            ]
+        );
+
+        // 一覧をまだ取得していなければ設定のまま
+        assert_eq!(resolve_mappings(&configured, &[]).len(), 3);
+    }
+}
@@ -1,11 +1,33 @@
-use crate::mail::{Account, Address, Flag, MailError, MailResult, Message, MessageBody};
-use anyhow::{Context, Result};
-use chrono::{DateTime, TimeZone, Utc};
+use crate::mail::{
+    Account, Address, Flag, Folder, FolderType, MailError, MailResult, Message, MessageBody,
+    Thread, TokenProvider,
+};
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
 use reqwest;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use std::sync::Arc;
 
 const GMAIL_API_BASE_URL: &str = "https://www.googleapis.com/gmail/v1";
 
+/// users.threads.listで1回に取得できるスレッド数の上限
+const MAX_THREADS_PER_PAGE: usize = 500;
+
+/// スレッドのヘッダーを同時に取得する数
+const THREAD_FETCH_CONCURRENCY: usize = 8;
+
+/// フォルダー一覧に出すシステムラベル（ラベルID, フォルダー名）
+const SYSTEM_FOLDERS: [(&str, &str); 7] = [
+    ("INBOX", "INBOX"),
+    ("STARRED", "STARRED"),
+    ("IMPORTANT", "IMPORTANT"),
+    ("SENT", "Sent"),
+    ("DRAFT", "Drafts"),
+    ("SPAM", "SPAM"),
+    ("TRASH", "Trash"),
+];
+
 #[derive(Debug, Deserialize)]
 struct GmailProfile {
     #[serde(rename = "emailAddress")]
@@ -34,6 +56,24 @@ struct GmailMessageRef {
     thread_id: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct GmailThreadList {
+    threads: Option<Vec<GmailThreadRef>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GmailThreadRef {
+    id: String,
+    snippet: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GmailThread {
+    id: String,
+    snippet: Option<String>,
+    messages: Option<Vec<GmailMessage>>,
+}
+
 #[derive(Debug, Deserialize)]
 struct GmailMessage {
     id: String,
@@ -97,6 +137,7 @@ struct GmailLabel {
 pub struct GmailApiClient {
     account: Account,
     http_client: reqwest::Client,
+    token_provider: Option<Arc<TokenProvider>>,
 }
 
 impl GmailApiClient {
@@ -104,23 +145,22 @@ impl GmailApiClient {
         Self {
             account,
             http_client: reqwest::Client::new(),
+            token_provider: None,
         }
     }
 
+    /// 共有トークンプロバイダーを使ってアクセストークンを取得するようにする
+    pub fn with_token_provider(mut self, token_provider: Arc<TokenProvider>) -> Self {
+        self.token_provider = Some(token_provider);
+        self
+    }
+
     /// 接続テスト（プロフィール取得）
     pub async fn connect(&self) -> MailResult<()> {
-        let access_token = self.get_access_token()?;
-
-        println!("デバッグ: Gmail API接続テスト中...");
-
         let url = format!("{}/users/me/profile", GMAIL_API_BASE_URL);
         let response = self
-            .http_client
-            .get(&url)
-            .bearer_auth(&access_token)
-            .send()
-            .await
-            .map_err(|e| MailError::Connection(format!("Gmail API request failed: {}", e)))?;
+            .send_authorized(self.http_client.get(&url), "Gmail API request failed")
+            .await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -131,31 +171,131 @@ impl GmailApiClient {
             )));
         }
 
-        let profile: GmailProfile = response
-            .json()
+        // プロフィールを解析できればAPIを利用できるとみなす
+        response
+            .json::<GmailProfile>()
             .await
             .map_err(|e| MailError::Connection(format!("Failed to parse Gmail profile: {}", e)))?;
 
-        println!("デバッグ: Gmail API接続成功");
-        println!("  メールアドレス: {}", profile.email_address);
-        println!("  メッセージ総数: {}", profile.messages_total);
-        println!("  スレッド総数: {}", profile.threads_total);
-
         Ok(())
     }
 
     /// フォルダー一覧を取得（ラベル一覧）
-    pub async fn list_folders(&self) -> MailResult<Vec<String>> {
-        let access_token = self.get_access_token()?;
+    ///
+    /// システムラベルは `SYSTEM_FOLDERS` にあるものだけを、他のアプリと同じフォルダー名で返す。
+    /// ユーザーのラベルは `/` で入れ子になり、ラベル一覧で非表示のものは購読していない扱いになる。
+    pub async fn list_folders(&self) -> MailResult<Vec<Folder>> {
+        let mut folders = Vec::new();
+        for label in self.fetch_labels().await? {
+            if label.label_type.as_deref() == Some("system") {
+                if let Some((_, name)) = SYSTEM_FOLDERS.iter().find(|(id, _)| *id == label.id) {
+                    folders.push(Folder::new(*name, None).with_role(label_role(&label.id)));
+                }
+            } else {
+                let mut folder = Folder::new(label.name, Some("/"));
+                folder.subscribed = label.label_list_visibility.as_deref() != Some("labelHide");
+                folders.push(folder);
+            }
+        }
+
+        Ok(folders)
+    }
 
+    /// ラベルを作成（フォルダーの作成）
+    pub async fn create_label(&self, name: &str) -> MailResult<()> {
         let url = format!("{}/users/me/labels", GMAIL_API_BASE_URL);
+        let body = serde_json::json!({
+            "name": name,
+            "labelListVisibility": "labelShow",
+            "messageListVisibility": "show",
+        });
+
         let response = self
-            .http_client
-            .get(&url)
-            .bearer_auth(&access_token)
-            .send()
-            .await
-            .map_err(|e| MailError::Protocol(format!("Gmail labels request failed: {}", e)))?;
+            .send_authorized(
+                self.http_client.post(&url).json(&body),
+                "Gmail label create failed",
+            )
+            .await?;
+
+        Self::check_label_response(response, "Gmail label create failed").await
+    }
+
+    /// ラベル名を変更（`親/子` の子のラベルも一緒に変更する）
+    pub async fn rename_label(&self, from: &str, to: &str) -> MailResult<()> {
+        let prefix = format!("{}/", from);
+        let labels: Vec<GmailLabel> = self
+            .fetch_labels()
+            .await?
+            .into_iter()
+            .filter(|label| label.name == from || label.name.starts_with(&prefix))
+            .collect();
+        if labels.is_empty() {
+            return Err(MailError::NotFound(format!("Label not found: {}", from)));
+        }
+
+        for label in labels {
+            let name = format!("{}{}", to, &label.name[from.len()..]);
+            self.patch_label(&label.id, serde_json::json!({ "name": name }))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// ラベルを削除（メッセージ自体は消えない）
+    pub async fn delete_label(&self, name: &str) -> MailResult<()> {
+        let label_id = self.resolve_label_id(name).await?;
+        let url = format!("{}/users/me/labels/{}", GMAIL_API_BASE_URL, label_id);
+
+        let response = self
+            .send_authorized(self.http_client.delete(&url), "Gmail label delete failed")
+            .await?;
+
+        Self::check_label_response(response, "Gmail label delete failed").await
+    }
+
+    /// ラベル一覧に表示する・しない（IMAPの購読に当たる）
+    pub async fn set_label_visible(&self, name: &str, visible: bool) -> MailResult<()> {
+        let label_id = self.resolve_label_id(name).await?;
+        let visibility = if visible { "labelShow" } else { "labelHide" };
+        self.patch_label(
+            &label_id,
+            serde_json::json!({ "labelListVisibility": visibility }),
+        )
+        .await
+    }
+
+    async fn patch_label(&self, label_id: &str, body: serde_json::Value) -> MailResult<()> {
+        let url = format!("{}/users/me/labels/{}", GMAIL_API_BASE_URL, label_id);
+
+        let response = self
+            .send_authorized(
+                self.http_client.patch(&url).json(&body),
+                "Gmail label update failed",
+            )
+            .await?;
+
+        Self::check_label_response(response, "Gmail label update failed").await
+    }
+
+    async fn check_label_response(response: reqwest::Response, context: &str) -> MailResult<()> {
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(MailError::Protocol(format!(
+                "{}: {} - {}",
+                context, status, error_text
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// ラベル一覧を取得
+    async fn fetch_labels(&self) -> MailResult<Vec<GmailLabel>> {
+        let url = format!("{}/users/me/labels", GMAIL_API_BASE_URL);
+        let response = self
+            .send_authorized(self.http_client.get(&url), "Gmail labels request failed")
+            .await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -171,14 +311,7 @@ impl GmailApiClient {
             .await
             .map_err(|e| MailError::Protocol(format!("Failed to parse Gmail labels: {}", e)))?;
 
-        let folder_names = labels
-            .labels
-            .unwrap_or_default()
-            .into_iter()
-            .map(|label| label.name)
-            .collect();
-
-        Ok(folder_names)
+        Ok(labels.labels.unwrap_or_default())
     }
 
     /// メッセージ一覧を取得
@@ -187,10 +320,8 @@ impl GmailApiClient {
         folder_name: &str,
         limit: Option<usize>,
     ) -> MailResult<Vec<Message>> {
-        let access_token = self.get_access_token()?;
-
-        // Gmail APIではラベルIDでフィルタリング
-        let label_id = self.convert_folder_to_label_id(folder_name);
+        // Gmail APIではラベルIDでフィルタリング（見つからなければ全メッセージ）
+        let label_id = self.resolve_label_id(folder_name).await.ok();
 
         let mut url = format!("{}/users/me/messages", GMAIL_API_BASE_URL);
         let mut params = vec![];
@@ -208,15 +339,9 @@ impl GmailApiClient {
             url.push_str(&params.join("&"));
         }
 
-        println!("デバッグ: Gmail API メッセージ一覧取得中... URL: {}", url);
-
         let response = self
-            .http_client
-            .get(&url)
-            .bearer_auth(&access_token)
-            .send()
-            .await
-            .map_err(|e| MailError::Protocol(format!("Gmail messages request failed: {}", e)))?;
+            .send_authorized(self.http_client.get(&url), "Gmail messages request failed")
+            .await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -232,54 +357,426 @@ impl GmailApiClient {
         })?;
 
         let message_refs = message_list.messages.unwrap_or_default();
-        println!(
-            "デバッグ: {} 件のメッセージ参照を取得しました",
-            message_refs.len()
-        );
 
         // 各メッセージの詳細を取得
         let mut messages = Vec::new();
-        for (i, message_ref) in message_refs.iter().take(limit.unwrap_or(10)).enumerate() {
-            println!(
-                "デバッグ: メッセージ {}/{} を取得中...",
-                i + 1,
-                message_refs.len()
-            );
-
+        for message_ref in message_refs.iter().take(limit.unwrap_or(10)) {
             match self
                 .fetch_message_details(&message_ref.id, folder_name)
                 .await
             {
                 Ok(message) => messages.push(message),
-                Err(e) => {
-                    println!("警告: メッセージ {} の取得に失敗: {}", message_ref.id, e);
-                    continue;
-                }
+                // 取得できなかったメッセージは飛ばして残りを表示する
+                Err(_) => continue,
             }
         }
 
         // 最新順にソート
-        messages.sort_by(|a, b| b.date.cmp(&a.date));
+        messages.sort_by_key(|message| std::cmp::Reverse(message.date));
+
+        Ok(messages)
+    }
+
+    /// Gmailの検索演算子（`q`）でフォルダー（ラベル）を検索し、一致したメッセージを最大 `limit` 件取得
+    pub async fn search_messages(
+        &self,
+        folder_name: &str,
+        q: &str,
+        limit: usize,
+    ) -> MailResult<Vec<Message>> {
+        let label_id = self.resolve_label_id(folder_name).await?;
+        let url = format!("{}/users/me/messages", GMAIL_API_BASE_URL);
+        let request = self.http_client.get(&url).query(&[
+            ("labelIds", label_id.as_str()),
+            ("q", q),
+            ("maxResults", &limit.to_string()),
+        ]);
+
+        let response = self
+            .send_authorized(request, "Gmail search request failed")
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(MailError::Protocol(format!(
+                "Gmail search request failed: {} - {}",
+                status, error_text
+            )));
+        }
+
+        let message_list: GmailMessageList = response.json().await.map_err(|e| {
+            MailError::Protocol(format!("Failed to parse Gmail message list: {}", e))
+        })?;
 
+        let mut messages = Vec::new();
+        for message_ref in message_list.messages.unwrap_or_default() {
+            match self
+                .fetch_message_details(&message_ref.id, folder_name)
+                .await
+            {
+                Ok(message) => messages.push(message),
+                // 取得できなかったメッセージは飛ばして残りを結果にする
+                Err(_) => continue,
+            }
+        }
+
+        messages.sort_by(|a, b| b.date.cmp(&a.date));
         Ok(messages)
     }
 
+    /// スレッド一覧を取得（各スレッドのメッセージ数と参加者を含む）
+    pub async fn fetch_threads(
+        &self,
+        folder_name: &str,
+        limit: Option<usize>,
+    ) -> MailResult<Vec<Thread>> {
+        let label_id = self.resolve_label_id(folder_name).await.ok();
+
+        let mut url = format!("{}/users/me/threads", GMAIL_API_BASE_URL);
+        let mut params = vec![];
+
+        if let Some(label) = label_id {
+            params.push(format!("labelIds={}", label));
+        }
+
+        if let Some(limit) = limit {
+            params.push(format!("maxResults={}", limit.min(MAX_THREADS_PER_PAGE)));
+        }
+
+        if !params.is_empty() {
+            url.push('?');
+            url.push_str(&params.join("&"));
+        }
+
+        let response = self
+            .send_authorized(self.http_client.get(&url), "Gmail threads request failed")
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(MailError::Protocol(format!(
+                "Gmail threads request failed: {} - {}",
+                status, error_text
+            )));
+        }
+
+        let thread_list: GmailThreadList = response.json().await.map_err(|e| {
+            MailError::Protocol(format!("Failed to parse Gmail thread list: {}", e))
+        })?;
+
+        let thread_refs = thread_list.threads.unwrap_or_default();
+
+        // 一覧表示にはヘッダーだけあれば十分なのでmetadata形式で、数件ずつ並行して取得
+        let mut threads: Vec<Thread> = stream::iter(thread_refs)
+            .map(|thread_ref| async move {
+                // 取得できなかったスレッド（一覧の取得後に削除されたものなど）は除く
+                let mut thread = self
+                    .get_thread(&thread_ref.id, folder_name, "metadata")
+                    .await
+                    .ok()?;
+                if thread.snippet.is_empty() {
+                    thread.snippet = thread_ref.snippet.unwrap_or_default();
+                }
+                Some(thread)
+            })
+            .buffer_unordered(THREAD_FETCH_CONCURRENCY)
+            .filter_map(|thread| async move { thread })
+            .collect()
+            .await;
+
+        // 最新の更新があったスレッド順にソート
+        threads.sort_by_key(|t| std::cmp::Reverse(t.latest_date()));
+
+        Ok(threads)
+    }
+
+    /// スレッドを本文付きで取得（users.threads.get）
+    pub async fn fetch_thread(&self, thread_id: &str, folder_name: &str) -> MailResult<Thread> {
+        self.get_thread(thread_id, folder_name, "full").await
+    }
+
+    async fn get_thread(
+        &self,
+        thread_id: &str,
+        folder_name: &str,
+        format: &str,
+    ) -> MailResult<Thread> {
+        let mut url = format!(
+            "{}/users/me/threads/{}?format={}",
+            GMAIL_API_BASE_URL, thread_id, format
+        );
+        if format == "metadata" {
+            for header in [
+                "From",
+                "To",
+                "Subject",
+                "Date",
+                "Message-ID",
+                "In-Reply-To",
+                "References",
+            ] {
+                url.push_str(&format!("&metadataHeaders={}", header));
+            }
+        }
+
+        let response = self
+            .send_authorized(self.http_client.get(&url), "Gmail thread request failed")
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(MailError::Protocol(format!(
+                "Gmail thread request failed: {} - {}",
+                status, error_text
+            )));
+        }
+
+        let gmail_thread: GmailThread = response
+            .json()
+            .await
+            .map_err(|e| MailError::Protocol(format!("Failed to parse Gmail thread: {}", e)))?;
+
+        let messages = gmail_thread
+            .messages
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|m| self.convert_gmail_message_to_message(m, folder_name).ok())
+            .collect();
+
+        let mut thread = Thread::new(gmail_thread.id, self.account.id.clone(), messages);
+        thread.snippet = gmail_thread.snippet.unwrap_or_default();
+
+        Ok(thread)
+    }
+
+    /// スレッド内の全メッセージのラベルを変更（users.threads.modify）
+    async fn modify_thread(
+        &self,
+        thread_id: &str,
+        add_label_ids: &[String],
+        remove_label_ids: &[String],
+    ) -> MailResult<()> {
+        let url = format!(
+            "{}/users/me/threads/{}/modify",
+            GMAIL_API_BASE_URL, thread_id
+        );
+        let body = serde_json::json!({
+            "addLabelIds": add_label_ids,
+            "removeLabelIds": remove_label_ids,
+        });
+
+        let response = self
+            .send_authorized(
+                self.http_client.post(&url).json(&body),
+                "Gmail thread modify failed",
+            )
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(MailError::Protocol(format!(
+                "Gmail thread modify failed: {} - {}",
+                status, error_text
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// スレッドをアーカイブ（受信トレイから外す）
+    pub async fn archive_thread(&self, thread_id: &str) -> MailResult<()> {
+        self.modify_thread(thread_id, &[], &["INBOX".to_string()])
+            .await
+    }
+
+    /// スレッドをミュート（以後の返信も受信トレイに戻らない）
+    pub async fn mute_thread(&self, thread_id: &str) -> MailResult<()> {
+        // MUTEDはAPIのラベル一覧には出てこないシステムラベル
+        self.modify_thread(thread_id, &["MUTED".to_string()], &["INBOX".to_string()])
+            .await
+    }
+
+    /// スレッドにラベルを付与（ラベル名またはラベルIDで指定）
+    pub async fn label_thread(&self, thread_id: &str, label_name: &str) -> MailResult<()> {
+        let label_id = self
+            .fetch_labels()
+            .await?
+            .into_iter()
+            .find(|label| label.name == label_name || label.id == label_name)
+            .map(|label| label.id)
+            .ok_or_else(|| MailError::Protocol(format!("Label not found: {}", label_name)))?;
+
+        self.modify_thread(thread_id, &[label_id], &[]).await
+    }
+
+    /// メッセージのラベルを変更（users.messages.modify）
+    async fn modify_message(
+        &self,
+        message_id: &str,
+        add_label_ids: &[String],
+        remove_label_ids: &[String],
+    ) -> MailResult<()> {
+        let url = format!(
+            "{}/users/me/messages/{}/modify",
+            GMAIL_API_BASE_URL, message_id
+        );
+        let body = serde_json::json!({
+            "addLabelIds": add_label_ids,
+            "removeLabelIds": remove_label_ids,
+        });
+
+        let response = self
+            .send_authorized(
+                self.http_client.post(&url).json(&body),
+                "Gmail message modify failed",
+            )
+            .await?;
+
+        Self::check_message_response(response, message_id, "Gmail message modify failed").await
+    }
+
+    /// メッセージのフラグを変更（既読はUNREAD、スターはSTARREDラベルで表現される）
+    pub async fn set_message_flag(
+        &self,
+        message_id: &str,
+        flag: &Flag,
+        enabled: bool,
+    ) -> MailResult<()> {
+        let (label, label_means_set) = match flag {
+            Flag::Seen => ("UNREAD", false),
+            Flag::Flagged => ("STARRED", true),
+            other => {
+                return Err(MailError::Protocol(format!(
+                    "Flag {:?} is not supported by Gmail",
+                    other
+                )))
+            }
+        };
+
+        let label = vec![label.to_string()];
+        if enabled == label_means_set {
+            self.modify_message(message_id, &label, &[]).await
+        } else {
+            self.modify_message(message_id, &[], &label).await
+        }
+    }
+
+    /// メッセージを別のフォルダー（ラベル）へ移動
+    pub async fn move_message(
+        &self,
+        message_id: &str,
+        from_folder: &str,
+        to_folder: &str,
+    ) -> MailResult<()> {
+        let from_label = self.resolve_label_id(from_folder).await?;
+        let to_label = self.resolve_label_id(to_folder).await?;
+        self.modify_message(message_id, &[to_label], &[from_label])
+            .await
+    }
+
+    /// メッセージをゴミ箱へ移動
+    pub async fn trash_message(&self, message_id: &str) -> MailResult<()> {
+        let url = format!(
+            "{}/users/me/messages/{}/trash",
+            GMAIL_API_BASE_URL, message_id
+        );
+        let response = self
+            .send_authorized(self.http_client.post(&url), "Gmail message trash failed")
+            .await?;
+
+        Self::check_message_response(response, message_id, "Gmail message trash failed").await
+    }
+
+    /// 生メッセージをラベル（フォルダー）に取り込む（users.messages.import）
+    pub async fn import_message(
+        &self,
+        folder_name: &str,
+        raw: &[u8],
+        flags: &[Flag],
+    ) -> MailResult<()> {
+        let mut label_ids = vec![self.resolve_label_id(folder_name).await?];
+        if !flags.contains(&Flag::Seen) {
+            label_ids.push("UNREAD".to_string());
+        }
+        if flags.contains(&Flag::Flagged) {
+            label_ids.push("STARRED".to_string());
+        }
+
+        let url = format!("{}/users/me/messages/import", GMAIL_API_BASE_URL);
+        let body = serde_json::json!({
+            "raw": general_purpose::URL_SAFE_NO_PAD.encode(raw),
+            "labelIds": label_ids,
+        });
+
+        let response = self
+            .send_authorized(
+                self.http_client.post(&url).json(&body),
+                "Gmail message import failed",
+            )
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(MailError::Protocol(format!(
+                "Gmail message import failed: {} - {}",
+                status, error_text
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// フォルダー名（またはラベル名・ラベルID）をラベルIDに変換
+    async fn resolve_label_id(&self, folder_name: &str) -> MailResult<String> {
+        if let Some(label_id) = self.convert_folder_to_label_id(folder_name) {
+            return Ok(label_id);
+        }
+
+        self.fetch_labels()
+            .await?
+            .into_iter()
+            .find(|label| label.name == folder_name || label.id == folder_name)
+            .map(|label| label.id)
+            .ok_or_else(|| MailError::NotFound(format!("Label not found: {}", folder_name)))
+    }
+
+    /// メッセージ操作のレスポンスを確認（404はメッセージがサーバー側で消えている）
+    async fn check_message_response(
+        response: reqwest::Response,
+        message_id: &str,
+        error_context: &str,
+    ) -> MailResult<()> {
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(MailError::NotFound(format!("Message {}", message_id)));
+        }
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(MailError::Protocol(format!(
+                "{}: {} - {}",
+                error_context, status, error_text
+            )));
+        }
+
+        Ok(())
+    }
+
     /// 個別メッセージの詳細を取得
     async fn fetch_message_details(
         &self,
         message_id: &str,
         folder_name: &str,
     ) -> MailResult<Message> {
-        let access_token = self.get_access_token()?;
-
         let url = format!("{}/users/me/messages/{}", GMAIL_API_BASE_URL, message_id);
         let response = self
-            .http_client
-            .get(&url)
-            .bearer_auth(&access_token)
-            .send()
-            .await
-            .map_err(|e| MailError::Protocol(format!("Gmail message request failed: {}", e)))?;
+            .send_authorized(self.http_client.get(&url), "Gmail message request failed")
+            .await?;
 
         if !response.status().is_success() {
             let status = response.status();
@@ -319,6 +816,9 @@ impl GmailApiClient {
         let mut from_header = String::new();
         let mut to_header = String::new();
         let mut date_header = String::new();
+        let mut message_id_header = None;
+        let mut in_reply_to_header = None;
+        let mut references_header = None;
 
         for header in headers {
             match header.name.to_lowercase().as_str() {
@@ -326,6 +826,9 @@ impl GmailApiClient {
                 "from" => from_header = header.value.clone(),
                 "to" => to_header = header.value.clone(),
                 "date" => date_header = header.value.clone(),
+                "message-id" => message_id_header = Some(header.value.clone()),
+                "in-reply-to" => in_reply_to_header = Some(header.value.clone()),
+                "references" => references_header = Some(header.value.clone()),
                 _ => {}
             }
         }
@@ -349,19 +852,19 @@ impl GmailApiClient {
                 .unwrap_or_else(|_| Utc::now())
         };
 
-        // メッセージ本文
-        let body_text = gmail_message.snippet.unwrap_or_default();
-        let body = MessageBody::new_plain(body_text);
+        // メッセージ本文（本文パートがなければスニペットで代用）
+        let body = Self::extract_body(payload).unwrap_or_else(|| {
+            MessageBody::new_plain(gmail_message.snippet.clone().unwrap_or_default())
+        });
 
         // フラグ（ラベルから推測）
+        let label_ids = gmail_message.label_ids.clone().unwrap_or_default();
         let mut flags = Vec::new();
-        if let Some(label_ids) = &gmail_message.label_ids {
-            for label_id in label_ids {
-                match label_id.as_str() {
-                    "UNREAD" => {}               // 既読フラグなし
-                    _ => flags.push(Flag::Seen), // その他は既読扱い
-                }
-            }
+        if !label_ids.iter().any(|l| l == "UNREAD") {
+            flags.push(Flag::Seen);
+        }
+        if label_ids.iter().any(|l| l == "STARRED") {
+            flags.push(Flag::Flagged);
         }
 
         let mut message = Message::new(
@@ -376,10 +879,38 @@ impl GmailApiClient {
 
         message.date = date;
         message.flags = flags;
+        message.thread_id = Some(gmail_message.thread_id);
+        message.set_thread_headers(
+            message_id_header.as_deref(),
+            in_reply_to_header.as_deref(),
+            references_header.as_deref(),
+        );
 
         Ok(message)
     }
 
+    /// ペイロードから本文を取り出す（text/plainを優先し、なければtext/html）
+    fn extract_body(payload: &GmailPayload) -> Option<MessageBody> {
+        if let Some(text) = Self::find_part_text(payload, "text/plain") {
+            return Some(MessageBody::new_plain(text));
+        }
+        Self::find_part_text(payload, "text/html").map(MessageBody::new_html)
+    }
+
+    fn find_part_text(payload: &GmailPayload, mime_type: &str) -> Option<String> {
+        if payload.mime_type.as_deref() == Some(mime_type) {
+            if let Some(data) = payload.body.as_ref().and_then(|b| b.data.as_ref()) {
+                return decode_base64url(data);
+            }
+        }
+
+        payload
+            .parts
+            .as_ref()?
+            .iter()
+            .find_map(|part| Self::find_part_text(part, mime_type))
+    }
+
     /// フォルダー名をGmailラベルIDに変換
     fn convert_folder_to_label_id(&self, folder_name: &str) -> Option<String> {
         match folder_name {
@@ -392,7 +923,11 @@ impl GmailApiClient {
     }
 
     /// アクセストークンを取得
-    fn get_access_token(&self) -> MailResult<String> {
+    async fn get_access_token(&self) -> MailResult<String> {
+        if let Some(token_provider) = &self.token_provider {
+            return token_provider.access_token(&self.account.id).await;
+        }
+
         Ok(self
             .account
             .tokens
@@ -401,4 +936,57 @@ impl GmailApiClient {
             .access_token
             .clone())
     }
+
+    /// 認証付きでリクエストを送信（401の場合はトークンを更新して1回だけ再送）
+    async fn send_authorized(
+        &self,
+        request: reqwest::RequestBuilder,
+        error_context: &str,
+    ) -> MailResult<reqwest::Response> {
+        let access_token = self.get_access_token().await?;
+        let retry_request = request.try_clone();
+
+        let response = request
+            .bearer_auth(&access_token)
+            .send()
+            .await
+            .map_err(|e| MailError::Connection(format!("{}: {}", error_context, e)))?;
+
+        if response.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let (Some(token_provider), Some(retry_request)) = (&self.token_provider, retry_request)
+        else {
+            return Ok(response);
+        };
+
+        let access_token = token_provider.force_refresh(&self.account.id).await?;
+
+        retry_request
+            .bearer_auth(&access_token)
+            .send()
+            .await
+            .map_err(|e| MailError::Connection(format!("{}: {}", error_context, e)))
+    }
+}
+
+/// Gmail APIのbase64url（パディング有無どちらも）をデコード
+fn decode_base64url(data: &str) -> Option<String> {
+    let bytes = general_purpose::URL_SAFE_NO_PAD
+        .decode(data.trim_end_matches('='))
+        .ok()?;
+    Some(String::from_utf8_lossy(&bytes).to_string())
+}
+
+/// システムラベルの役割
+fn label_role(label_id: &str) -> Option<FolderType> {
+    match label_id {
+        "INBOX" => Some(FolderType::Inbox),
+        "SENT" => Some(FolderType::Sent),
+        "DRAFT" => Some(FolderType::Drafts),
+        "SPAM" => Some(FolderType::Spam),
+        "TRASH" => Some(FolderType::Trash),
+        _ => None,
+    }
 }
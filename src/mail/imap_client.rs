@@ -1,16 +1,25 @@
-use async_imap::types::{Fetch, Flag as ImapFlag, Mailbox};
+use async_imap::error::Error as ImapError;
+use async_imap::types::{Fetch, Flag as ImapFlag, Mailbox, NameAttribute};
 use async_imap::{Authenticator, Client, Session};
 use async_native_tls::{TlsConnector, TlsStream};
 use base64::{engine::general_purpose, Engine as _};
 use futures::StreamExt;
+use std::sync::Arc;
 use tokio::net::TcpStream;
 use tokio_util::compat::TokioAsyncReadCompatExt;
 
-use super::{Account, Address, AuthMethod, Flag, MailError, MailResult, Message, MessageBody};
+use super::{
+    mime, Account, Address, AuthMethod, Flag, Folder, FolderType, MailError, MailResult, Message,
+    MessageBody, ServerThread, TokenProvider, BODY_PLACEHOLDER,
+};
+use crate::search::Query;
+
+type ImapStream = TlsStream<tokio_util::compat::Compat<TcpStream>>;
 
 pub struct ImapClient {
-    session: Option<Session<TlsStream<tokio_util::compat::Compat<TcpStream>>>>,
+    session: Option<Session<ImapStream>>,
     account: Account,
+    token_provider: Option<Arc<TokenProvider>>,
 }
 
 impl ImapClient {
@@ -18,23 +27,58 @@ impl ImapClient {
         Self {
             session: None,
             account,
+            token_provider: None,
+        }
+    }
+
+    /// 共有トークンプロバイダーを使ってアクセストークンを取得するようにする
+    pub fn with_token_provider(mut self, token_provider: Arc<TokenProvider>) -> Self {
+        self.token_provider = Some(token_provider);
+        self
+    }
+
+    /// OAuth2アクセストークンを取得
+    async fn oauth_access_token(&self) -> MailResult<String> {
+        if let Some(token_provider) = &self.token_provider {
+            return token_provider.access_token(&self.account.id).await;
         }
+
+        self.account
+            .tokens
+            .as_ref()
+            .map(|tokens| tokens.access_token.clone())
+            .ok_or_else(|| {
+                MailError::Authentication(
+                    "No OAuth2 tokens available. Please run OAuth2 flow first.".to_string(),
+                )
+            })
+    }
+
+    /// AUTHENTICATE XOAUTH2を実行（失敗時はクライアントを返して再試行できるようにする）
+    async fn authenticate_xoauth2(
+        client: Client<ImapStream>,
+        email: &str,
+        access_token: &str,
+    ) -> MailResult<Result<Session<ImapStream>, (ImapError, Client<ImapStream>)>> {
+        let authenticator = XOAuth2Authenticator::new(email, access_token);
+
+        tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            client.authenticate("XOAUTH2", authenticator),
+        )
+        .await
+        .map_err(|_| {
+            MailError::Authentication(
+                "OAuth2 IMAP authentication timeout (10 seconds). ネットワーク接続またはサーバーの問題の可能性があります。".to_string(),
+            )
+        })
     }
 
     /// IMAPサーバーに接続
     pub async fn connect(&mut self) -> MailResult<()> {
         let imap_config = &self.account.imap;
 
-        println!("デバッグ: IMAP接続開始");
-        println!("  サーバー: {}:{}", imap_config.server, imap_config.port);
-        println!(
-            "  TLS: {}, STARTTLS: {}",
-            imap_config.use_tls, imap_config.use_starttls
-        );
-        println!("  認証方式: {:?}", imap_config.auth_method);
-
         // TCP接続
-        println!("デバッグ: TCP接続を開始中...");
         let tcp_stream = tokio::time::timeout(
             std::time::Duration::from_secs(30),
             TcpStream::connect(&format!("{}:{}", imap_config.server, imap_config.port)),
@@ -43,13 +87,10 @@ impl ImapClient {
         .map_err(|_| MailError::Connection("TCP connection timeout (30 seconds)".to_string()))?
         .map_err(|e| MailError::Connection(format!("TCP connection failed: {}", e)))?;
 
-        println!("デバッグ: TCP接続が成功しました");
-
         // 互換性のためのcompat変換
         let compat_stream = tcp_stream.compat();
 
         // TLS設定
-        println!("デバッグ: TLS接続を開始中...");
         let connector = TlsConnector::new();
         let tls_stream = tokio::time::timeout(
             std::time::Duration::from_secs(30),
@@ -59,98 +100,46 @@ impl ImapClient {
         .map_err(|_| MailError::Connection("TLS connection timeout (30 seconds)".to_string()))?
         .map_err(|e| MailError::Connection(format!("TLS connection failed: {}", e)))?;
 
-        println!("デバッグ: TLS接続が成功しました");
-
         // IMAPクライアント作成
-        println!("デバッグ: IMAPクライアントを作成中...");
         let client = Client::new(tls_stream);
 
         // 認証
-        println!("デバッグ: 認証処理を開始中...");
         let session = match imap_config.auth_method {
             AuthMethod::OAuth2 => {
-                // OAuth2認証の実装
-                let tokens = self.account.tokens.as_ref().ok_or_else(|| {
-                    MailError::Authentication(
-                        "No OAuth2 tokens available. Please run OAuth2 flow first.".to_string(),
-                    )
-                })?;
-
-                let _oauth_config = self.account.oauth_config.as_ref().ok_or_else(|| {
-                    MailError::Authentication("No OAuth2 config available".to_string())
-                })?;
-
-                println!(
-                    "デバッグ: OAuth2認証を試行中 - ユーザー: {}",
-                    self.account.email
-                );
-                println!(
-                    "デバッグ: アクセストークン長: {} 文字",
-                    tokens.access_token.len()
-                );
-                println!(
-                    "デバッグ: アクセストークンの最初の50文字: {}",
-                    &tokens.access_token[..std::cmp::min(50, tokens.access_token.len())]
-                );
-
-                // XOAUTH2文字列を手動で生成
-                let auth_string = format!(
-                    "user={}\x01auth=Bearer {}\x01\x01",
-                    self.account.email, tokens.access_token
-                );
-                let auth_string_b64 = general_purpose::STANDARD.encode(auth_string.as_bytes());
-
-                println!(
-                    "デバッグ: XOAUTH2文字列を生成しました（長さ: {}文字）",
-                    auth_string_b64.len()
-                );
-                println!(
-                    "デバッグ: 認証文字列の最初の100文字: {}",
-                    &auth_string_b64[..std::cmp::min(100, auth_string_b64.len())]
-                );
-
-                // 改良されたXOAUTH2 Authenticatorを作成
-                let authenticator = XOAuth2Authenticator::new_with_string(auth_string_b64);
-
-                println!("デバッグ: XOAUTH2 Authenticatorを作成しました");
-                println!("デバッグ: IMAP AUTHENTICATE XOAUTH2 コマンドを実行中...");
+                let access_token = self.oauth_access_token().await?;
 
-                // async-imapのauthenticateメソッドを使用してOAuth2認証を実行（タイムアウト付き）
-                match tokio::time::timeout(
-                    std::time::Duration::from_secs(10),
-                    client.authenticate("XOAUTH2", authenticator),
-                )
-                .await
+                match Self::authenticate_xoauth2(client, &self.account.email, &access_token).await?
                 {
-                    Ok(Ok(session)) => {
-                        println!("デバッグ: OAuth2 IMAP認証が成功しました");
-                        session
-                    }
-                    Ok(Err(e)) => {
-                        println!("デバッグ: OAuth2 IMAP認証エラー: {:?}", e);
-                        return Err(MailError::Authentication(format!(
-                            "OAuth2 IMAP authentication failed: {:?}. トークンが期限切れの可能性があります。再認証を試してください。",
-                            e
-                        )));
-                    }
-                    Err(_) => {
-                        println!("デバッグ: OAuth2 IMAP認証がタイムアウトしました (10秒)");
-                        return Err(MailError::Authentication(
-                            "OAuth2 IMAP authentication timeout (10 seconds). ネットワーク接続またはサーバーの問題の可能性があります。".to_string()
-                        ));
+                    Ok(session) => session,
+                    Err((e, client)) => {
+                        // AUTHENTICATIONFAILED: トークンを更新して1回だけ再試行
+                        let Some(token_provider) = &self.token_provider else {
+                            return Err(MailError::Authentication(format!(
+                                "OAuth2 IMAP authentication failed: {:?}. トークンが期限切れの可能性があります。再認証を試してください。",
+                                e
+                            )));
+                        };
+
+                        let access_token = token_provider.force_refresh(&self.account.id).await?;
+
+                        Self::authenticate_xoauth2(client, &self.account.email, &access_token)
+                            .await?
+                            .map_err(|(e, _)| {
+                                MailError::Authentication(format!(
+                                    "OAuth2 IMAP authentication failed after token refresh: {:?}",
+                                    e
+                                ))
+                            })?
                     }
                 }
             }
-            AuthMethod::Plain | AuthMethod::Login => {
-                println!("デバッグ: 基本認証を試行中...");
-                tokio::time::timeout(
-                    std::time::Duration::from_secs(30),
-                    client.login(&imap_config.username, &imap_config.password),
-                )
-                .await
-                .map_err(|_| MailError::Authentication("Login timeout (30 seconds)".to_string()))?
-                .map_err(|e| MailError::Authentication(format!("Login failed: {:?}", e)))?
-            }
+            AuthMethod::Plain | AuthMethod::Login => tokio::time::timeout(
+                std::time::Duration::from_secs(30),
+                client.login(&imap_config.username, &imap_config.password),
+            )
+            .await
+            .map_err(|_| MailError::Authentication("Login timeout (30 seconds)".to_string()))?
+            .map_err(|e| MailError::Authentication(format!("Login failed: {:?}", e)))?,
             AuthMethod::CramMd5 => {
                 return Err(MailError::Authentication(
                     "CRAM-MD5 not implemented".to_string(),
@@ -158,7 +147,6 @@ impl ImapClient {
             }
         };
 
-        println!("デバッグ: 認証が完了しました");
         self.session = Some(session);
         Ok(())
     }
@@ -175,22 +163,38 @@ impl ImapClient {
     }
 
     /// フォルダーを選択
+    ///
+    /// 接続が切れていた場合（トークン失効によるBYEなど）は再接続して再試行する
     pub async fn select_folder(&mut self, folder_name: &str) -> MailResult<Mailbox> {
         let session = self
             .session
             .as_mut()
             .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
 
-        let mailbox = session
-            .select(folder_name)
-            .await
-            .map_err(|e| MailError::Protocol(format!("Folder selection failed: {:?}", e)))?;
-
-        Ok(mailbox)
+        match session.select(folder_name).await {
+            Ok(mailbox) => Ok(mailbox),
+            Err(ImapError::Io(_)) | Err(ImapError::ConnectionLost) => {
+                self.session = None;
+                self.connect().await?;
+
+                let session = self
+                    .session
+                    .as_mut()
+                    .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+                session
+                    .select(folder_name)
+                    .await
+                    .map_err(|e| MailError::Protocol(format!("Folder selection failed: {:?}", e)))
+            }
+            Err(e) => Err(MailError::Protocol(format!(
+                "Folder selection failed: {:?}",
+                e
+            ))),
+        }
     }
 
-    /// フォルダー一覧を取得
-    pub async fn list_folders(&mut self) -> MailResult<Vec<String>> {
+    /// フォルダー一覧を取得（階層の区切り文字、開けるかどうか、SPECIAL-USEの役割、購読状態を含む）
+    pub async fn list_folders(&mut self) -> MailResult<Vec<Folder>> {
         let session = self
             .session
             .as_mut()
@@ -201,10 +205,18 @@ impl ImapClient {
             .await
             .map_err(|e| MailError::Protocol(format!("Folder list failed: {:?}", e)))?;
 
-        let mut folder_names = Vec::new();
+        let mut folder_list = Vec::new();
         while let Some(folder_result) = folders.next().await {
             match folder_result {
-                Ok(folder) => folder_names.push(folder.name().to_string()),
+                Ok(name) => {
+                    let mut folder = Folder::new(name.name(), name.delimiter())
+                        .with_role(name.attributes().iter().find_map(special_use));
+                    folder.selectable = !name
+                        .attributes()
+                        .iter()
+                        .any(|attribute| matches!(attribute, NameAttribute::NoSelect));
+                    folder_list.push(folder);
+                }
                 Err(e) => {
                     return Err(MailError::Protocol(format!(
                         "Folder parsing failed: {:?}",
@@ -213,8 +225,105 @@ impl ImapClient {
                 }
             }
         }
+        drop(folders);
+
+        let subscribed = self.list_subscribed().await?;
+        for folder in &mut folder_list {
+            // 受信箱はLSUBに含まれなくても常に購読しているものとして扱う
+            folder.subscribed =
+                folder.name.eq_ignore_ascii_case("INBOX") || subscribed.contains(&folder.name);
+        }
+
+        Ok(folder_list)
+    }
+
+    /// 購読しているフォルダー名（LSUB）
+    ///
+    /// LIST-EXTENDEDの `LIST (SUBSCRIBED)` はIMAPライブラリが対応していないのでLSUBを使う。
+    async fn list_subscribed(&mut self) -> MailResult<Vec<String>> {
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
 
-        Ok(folder_names)
+        let mut folders = session
+            .lsub(Some(""), Some("*"))
+            .await
+            .map_err(|e| MailError::Protocol(format!("Subscription list failed: {:?}", e)))?;
+
+        let mut names = Vec::new();
+        while let Some(folder_result) = folders.next().await {
+            let name = folder_result
+                .map_err(|e| MailError::Protocol(format!("Folder parsing failed: {:?}", e)))?;
+            names.push(name.name().to_string());
+        }
+        Ok(names)
+    }
+
+    /// フォルダーを作成して購読する
+    pub async fn create_folder(&mut self, name: &str) -> MailResult<()> {
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        session
+            .create(name)
+            .await
+            .map_err(|e| MailError::Protocol(format!("Folder create failed: {:?}", e)))?;
+        session
+            .subscribe(name)
+            .await
+            .map_err(|e| MailError::Protocol(format!("Folder subscribe failed: {:?}", e)))
+    }
+
+    /// フォルダー名を変更（サブフォルダーも一緒に移る）
+    pub async fn rename_folder(&mut self, from: &str, to: &str) -> MailResult<()> {
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        session
+            .rename(from, to)
+            .await
+            .map_err(|e| MailError::Protocol(format!("Folder rename failed: {:?}", e)))?;
+        // 購読は名前に付くので、新しい名前で購読し直す
+        session.unsubscribe(from).await.ok();
+        session
+            .subscribe(to)
+            .await
+            .map_err(|e| MailError::Protocol(format!("Folder subscribe failed: {:?}", e)))
+    }
+
+    /// フォルダーを削除（中のメッセージも消える）
+    pub async fn delete_folder(&mut self, name: &str) -> MailResult<()> {
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        session
+            .delete(name)
+            .await
+            .map_err(|e| MailError::Protocol(format!("Folder delete failed: {:?}", e)))?;
+        session.unsubscribe(name).await.ok();
+        Ok(())
+    }
+
+    /// フォルダーを購読する・購読をやめる
+    pub async fn set_subscribed(&mut self, name: &str, subscribed: bool) -> MailResult<()> {
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        let result = if subscribed {
+            session.subscribe(name).await
+        } else {
+            session.unsubscribe(name).await
+        };
+        result.map_err(|e| MailError::Protocol(format!("Folder subscription failed: {:?}", e)))
     }
 
     /// メッセージ一覧を取得
@@ -223,22 +332,28 @@ impl ImapClient {
         folder_name: &str,
         limit: Option<usize>,
     ) -> MailResult<Vec<Message>> {
-        self.select_folder(folder_name).await?;
+        let mailbox = self.select_folder(folder_name).await?;
+        if mailbox.exists == 0 {
+            return Ok(Vec::new());
+        }
 
         let session = self
             .session
             .as_mut()
             .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
 
-        // 最新のメッセージから指定数取得
-        let sequence_set = if let Some(limit) = limit {
-            format!("1:{}", limit)
-        } else {
-            "1:*".to_string()
+        // シーケンス番号は受信順なので、末尾から指定数（最新のメッセージ）を取得
+        let start = match limit {
+            Some(limit) => (mailbox.exists as usize).saturating_sub(limit) + 1,
+            None => 1,
         };
+        let sequence_set = format!("{}:*", start);
 
         let mut messages = session
-            .fetch(&sequence_set, "ENVELOPE FLAGS INTERNALDATE RFC822.SIZE")
+            .fetch(
+                &sequence_set,
+                "UID ENVELOPE FLAGS INTERNALDATE RFC822.SIZE BODY.PEEK[HEADER.FIELDS (REFERENCES)]",
+            )
             .await
             .map_err(|e| MailError::Protocol(format!("Message fetch failed: {:?}", e)))?;
 
@@ -266,10 +381,6 @@ impl ImapClient {
         // 最新順にソート
         result.sort_by(|a, b| b.date.cmp(&a.date));
 
-        if let Some(limit) = limit {
-            result.truncate(limit);
-        }
-
         Ok(result)
     }
 
@@ -306,6 +417,33 @@ impl ImapClient {
         Err(MailError::Protocol("Message body not found".to_string()))
     }
 
+    /// メッセージ全体を取得して本文・添付ファイルを復号する（`\Seen` は付けない）
+    pub async fn fetch_full_message(&mut self, folder_name: &str, uid: u32) -> MailResult<Message> {
+        self.select_folder(folder_name).await?;
+
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        let mut fetches = session
+            .uid_fetch(&uid.to_string(), "BODY.PEEK[]")
+            .await
+            .map_err(|e| MailError::Protocol(format!("Message fetch failed: {:?}", e)))?;
+
+        let mut raw = None;
+        while let Some(fetch) = fetches.next().await {
+            let fetch =
+                fetch.map_err(|e| MailError::Protocol(format!("Message fetch failed: {:?}", e)))?;
+            if let Some(body) = fetch.body() {
+                raw = Some(body.to_vec());
+            }
+        }
+
+        let raw = raw.ok_or_else(|| MailError::NotFound(format!("Message {}", uid)))?;
+        mime::parse_message(&raw, uid.to_string(), &self.account.id, folder_name)
+    }
+
     /// メッセージをフラグ設定
     pub async fn set_message_flags(
         &mut self,
@@ -320,30 +458,226 @@ impl ImapClient {
             .as_mut()
             .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
 
-        // フラグを文字列に変換
-        let flag_strings: Vec<String> = flags
-            .iter()
-            .filter_map(|flag| match flag {
-                Flag::Seen => Some("\\Seen".to_string()),
-                Flag::Answered => Some("\\Answered".to_string()),
-                Flag::Flagged => Some("\\Flagged".to_string()),
-                Flag::Deleted => Some("\\Deleted".to_string()),
-                Flag::Draft => Some("\\Draft".to_string()),
-                _ => None,
-            })
-            .collect();
-
-        let flags_str = flag_strings.join(" ");
+        let flags_str = Self::flag_list(flags);
 
-        session
+        let updates = session
             .uid_store(&uid.to_string(), &format!("+FLAGS ({})", flags_str))
             .await
             .map_err(|e| MailError::Protocol(format!("Flag setting failed: {:?}", e)))?;
+        updates.collect::<Vec<_>>().await;
+
+        Ok(())
+    }
+
+    /// メッセージのフラグを外す
+    pub async fn remove_message_flags(
+        &mut self,
+        folder_name: &str,
+        uid: u32,
+        flags: &[Flag],
+    ) -> MailResult<()> {
+        self.select_folder(folder_name).await?;
+
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        let flags_str = Self::flag_list(flags);
+        let updates = session
+            .uid_store(&uid.to_string(), &format!("-FLAGS ({})", flags_str))
+            .await
+            .map_err(|e| MailError::Protocol(format!("Flag removal failed: {:?}", e)))?;
+        updates.collect::<Vec<_>>().await;
 
         Ok(())
     }
 
+    /// UIDのメッセージがフォルダーにまだあるか
+    ///
+    /// 存在しないUIDへのSTOREはエラーにならないため、オフライン操作を反映する前に確かめる
+    pub async fn message_exists(&mut self, folder_name: &str, uid: u32) -> MailResult<bool> {
+        self.select_folder(folder_name).await?;
+
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        let fetches = session
+            .uid_fetch(&uid.to_string(), "UID")
+            .await
+            .map_err(|e| MailError::Protocol(format!("UID fetch failed: {:?}", e)))?;
+        let found = fetches
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .any(|fetch| fetch.is_ok_and(|fetch| fetch.uid == Some(uid)));
+
+        Ok(found)
+    }
+
+    /// メッセージをフォルダーに追加（APPEND）
+    ///
+    /// async-imapのAPPENDはフラグを渡せないので、追加後にMessage-IDで探して付け直す
+    pub async fn append_message(
+        &mut self,
+        folder_name: &str,
+        raw: &[u8],
+        flags: &[Flag],
+    ) -> MailResult<()> {
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        session
+            .append(folder_name, to_crlf(raw))
+            .await
+            .map_err(|e| MailError::Protocol(format!("Append failed: {:?}", e)))?;
+
+        let Some(message_id) = mime::message_id(raw).filter(|_| !flags.is_empty()) else {
+            return Ok(());
+        };
+
+        self.select_folder(folder_name).await?;
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+        let uids = session
+            .uid_search(format!(
+                "HEADER Message-ID \"<{}>\"",
+                message_id.replace(['"', '\\'], "")
+            ))
+            .await
+            .map_err(|e| MailError::Protocol(format!("UID search failed: {:?}", e)))?;
+
+        match uids.into_iter().max() {
+            Some(uid) => self.set_message_flags(folder_name, uid, flags).await,
+            None => Ok(()),
+        }
+    }
+
+    /// サーバー側でフォルダーを検索し、一致したメッセージを新しい順に最大 `limit` 件取得
+    ///
+    /// Gmail（X-GM-EXT-1）ではGmailの検索演算子をそのまま渡せる `X-GM-RAW` を使う。
+    /// ESEARCHに対応したサーバーでは、一致したUIDを範囲でまとめて返す `UID SEARCH RETURN (ALL)` を使う。
+    pub async fn search_messages(
+        &mut self,
+        folder_name: &str,
+        query: &Query,
+        limit: usize,
+    ) -> MailResult<Vec<Message>> {
+        self.select_folder(folder_name).await?;
+
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        let capabilities = session.capabilities().await.ok();
+        let supports = |name: &str| {
+            capabilities
+                .as_ref()
+                .is_some_and(|capabilities| capabilities.has_str(name))
+        };
+        let criteria = supports("X-GM-EXT-1")
+            .then(|| query.to_x_gm_raw(folder_name))
+            .flatten()
+            .unwrap_or_else(|| query.to_imap_search(folder_name));
+
+        let mut uids = if supports("ESEARCH") {
+            let lines =
+                run_raw_command(session, &format!("UID SEARCH RETURN (ALL) {}", criteria)).await?;
+            parse_esearch(&lines)
+        } else {
+            session
+                .uid_search(&criteria)
+                .await
+                .map_err(|e| MailError::Protocol(format!("UID search failed: {:?}", e)))?
+                .into_iter()
+                .collect()
+        };
+
+        // UIDは追加順なので大きいものが新しい
+        uids.sort_unstable_by(|a, b| b.cmp(a));
+        uids.truncate(limit);
+        if uids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let uid_set = uids
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut fetches = session
+            .uid_fetch(
+                &uid_set,
+                "UID ENVELOPE FLAGS INTERNALDATE RFC822.SIZE BODY.PEEK[HEADER.FIELDS (REFERENCES)]",
+            )
+            .await
+            .map_err(|e| MailError::Protocol(format!("Message fetch failed: {:?}", e)))?;
+
+        let mut result = Vec::new();
+        while let Some(fetch) = fetches.next().await {
+            let fetch = fetch
+                .map_err(|e| MailError::Protocol(format!("Message parsing failed: {:?}", e)))?;
+            if let Some(message) = Self::parse_message(&fetch, folder_name, &self.account.id) {
+                result.push(message);
+            }
+        }
+
+        result.sort_by_key(|message| std::cmp::Reverse(message.date));
+        Ok(result)
+    }
+
+    /// サーバーがReferencesで組んだフォルダーのスレッドの木を取得
+    ///
+    /// THREAD=REFERENCES（RFC 5256）に対応していないサーバーでは `None` を返す。
+    pub async fn fetch_server_threads(
+        &mut self,
+        folder_name: &str,
+    ) -> MailResult<Option<Vec<ServerThread>>> {
+        self.select_folder(folder_name).await?;
+
+        let session = self
+            .session
+            .as_mut()
+            .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
+
+        let supported = session
+            .capabilities()
+            .await
+            .is_ok_and(|capabilities| capabilities.has_str("THREAD=REFERENCES"));
+        if !supported {
+            return Ok(None);
+        }
+
+        let lines = run_raw_command(session, "UID THREAD REFERENCES UTF-8 ALL").await?;
+        Ok(Some(parse_thread(&lines)))
+    }
+
+    /// フラグをIMAPのフラグリスト形式に変換
+    fn flag_list(flags: &[Flag]) -> String {
+        flags
+            .iter()
+            .filter_map(|flag| match flag {
+                Flag::Seen => Some("\\Seen"),
+                Flag::Answered => Some("\\Answered"),
+                Flag::Flagged => Some("\\Flagged"),
+                Flag::Deleted => Some("\\Deleted"),
+                Flag::Draft => Some("\\Draft"),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
     /// メッセージを移動
+    ///
+    /// MOVEに対応したサーバーでは `UID MOVE` を使う。未対応ならコピーしてから元のメッセージを消す。
     pub async fn move_message(
         &mut self,
         from_folder: &str,
@@ -357,47 +691,68 @@ impl ImapClient {
             .as_mut()
             .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
 
+        let supports_move = session
+            .capabilities()
+            .await
+            .is_ok_and(|capabilities| capabilities.has_str("MOVE"));
+        if supports_move {
+            return session
+                .uid_mv(uid.to_string(), to_folder)
+                .await
+                .map_err(|e| MailError::Protocol(format!("Message move failed: {:?}", e)));
+        }
+
         // メッセージをコピー
         session
             .uid_copy(&uid.to_string(), to_folder)
             .await
             .map_err(|e| MailError::Protocol(format!("Message copy failed: {:?}", e)))?;
 
-        // 元のメッセージに削除フラグを設定
-        session
-            .uid_store(&uid.to_string(), "+FLAGS (\\Deleted)")
-            .await
-            .map_err(|e| MailError::Protocol(format!("Delete flag setting failed: {:?}", e)))?;
-
-        // Expunge（実際に削除）
-        session
-            .expunge()
-            .await
-            .map_err(|e| MailError::Protocol(format!("Expunge failed: {:?}", e)))?;
-
-        Ok(())
+        self.expunge_message(uid).await
     }
 
     /// メッセージを削除
     pub async fn delete_message(&mut self, folder_name: &str, uid: u32) -> MailResult<()> {
         self.select_folder(folder_name).await?;
+        self.expunge_message(uid).await
+    }
 
+    /// 選択中のフォルダーから指定したUIDのメッセージを完全に削除
+    ///
+    /// UIDPLUSに対応したサーバーでは `UID EXPUNGE` で対象のメッセージだけを消す。
+    /// 未対応のサーバーでは `EXPUNGE` しかないため、他に削除フラグの付いたメッセージも消える。
+    async fn expunge_message(&mut self, uid: u32) -> MailResult<()> {
         let session = self
             .session
             .as_mut()
             .ok_or_else(|| MailError::Connection("Not connected".to_string()))?;
 
         // 削除フラグを設定
-        session
+        let updates = session
             .uid_store(&uid.to_string(), "+FLAGS (\\Deleted)")
             .await
             .map_err(|e| MailError::Protocol(format!("Delete flag setting failed: {:?}", e)))?;
+        updates.collect::<Vec<_>>().await;
 
-        // Expunge（実際に削除）
-        session
-            .expunge()
+        let supports_uidplus = session
+            .capabilities()
             .await
-            .map_err(|e| MailError::Protocol(format!("Expunge failed: {:?}", e)))?;
+            .is_ok_and(|capabilities| capabilities.has_str("UIDPLUS"));
+
+        // Expunge（実際に削除）
+        if supports_uidplus {
+            let expunged = session
+                .uid_expunge(uid.to_string())
+                .await
+                .map_err(|e| MailError::Protocol(format!("Expunge failed: {:?}", e)))?;
+            expunged.collect::<Vec<_>>().await;
+        } else {
+            let expunged = session
+                .expunge()
+                .await
+                .map_err(|e| MailError::Protocol(format!("Expunge failed: {:?}", e)))?;
+            expunged.collect::<Vec<_>>().await;
+        }
 
         Ok(())
     }
@@ -488,13 +843,27 @@ impl ImapClient {
             from,
             to,
             subject,
-            MessageBody::new_plain("本文を読み込み中...".to_string()),
+            MessageBody::new_plain(BODY_PLACEHOLDER.to_string()),
             account_id.to_string(),
             folder_name.to_string(),
         );
 
         message.date = date;
         message.flags = flags;
+        message.body_pending = true;
+
+        // スレッドの組み立てに使うヘッダー（ReferencesはENVELOPEにないので別に取得している）
+        let header = |value: &Option<std::borrow::Cow<'_, [u8]>>| {
+            value
+                .as_ref()
+                .map(|value| String::from_utf8_lossy(value).to_string())
+        };
+        message.set_thread_headers(
+            header(&envelope.message_id).as_deref(),
+            header(&envelope.in_reply_to).as_deref(),
+            None,
+        );
+        message.references = fetch.header().map(mime::references).unwrap_or_default();
 
         Some(message)
     }
@@ -523,10 +892,6 @@ impl XOAuth2Authenticator {
             auth_string: auth_string_b64,
         }
     }
-
-    fn new_with_string(auth_string: String) -> Self {
-        Self { auth_string }
-    }
 }
 
 impl Authenticator for XOAuth2Authenticator {
@@ -538,3 +903,270 @@ impl Authenticator for XOAuth2Authenticator {
         self.auth_string.clone()
     }
 }
+
+/// 生のコマンドの応答を見分けるタグ
+const RAW_COMMAND_TAG: &str = "RAW";
+
+/// async-imapが応答を解析できないコマンドを、下位のストリームで直接送って応答の行を読む
+///
+/// async-imapは解析できない応答を受け取るとそれ以降の応答も読めなくなるので、
+/// ESEARCHやTHREADの応答はこちらで読む。タグ付きの完了応答までの非タグ付き応答を返す。
+/// 応答にリテラルを含まないコマンドにだけ使う。
+async fn run_raw_command(
+    session: &mut Session<ImapStream>,
+    command: &str,
+) -> MailResult<Vec<String>> {
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    let stream: &mut ImapStream = session.as_mut();
+    stream
+        .write_all(format!("{} {}\r\n", RAW_COMMAND_TAG, command).as_bytes())
+        .await
+        .map_err(|e| MailError::Connection(format!("Failed to send command: {}", e)))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| MailError::Connection(format!("Failed to send command: {}", e)))?;
+
+    let mut lines = Vec::new();
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = stream
+            .read(&mut chunk)
+            .await
+            .map_err(|e| MailError::Connection(format!("Failed to read response: {}", e)))?;
+        if read == 0 {
+            return Err(MailError::Connection(
+                "Connection closed while reading response".to_string(),
+            ));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+
+        while let Some(end) = buffer.windows(2).position(|window| window == b"\r\n") {
+            let line: Vec<u8> = buffer.drain(..end + 2).collect();
+            let line = String::from_utf8_lossy(&line[..end]).into_owned();
+            if let Some(status) = line.strip_prefix(&format!("{} ", RAW_COMMAND_TAG)) {
+                return if status
+                    .get(..2)
+                    .is_some_and(|ok| ok.eq_ignore_ascii_case("OK"))
+                {
+                    Ok(lines)
+                } else {
+                    Err(MailError::Protocol(format!("Command failed: {}", status)))
+                };
+            }
+            lines.push(line);
+        }
+    }
+}
+
+/// `* ESEARCH` 応答の `ALL` に含まれるUIDを取り出す（RFC 4731）
+///
+/// 例: `* ESEARCH (TAG "RAW") UID ALL 4:6,9`。一致しなければ `ALL` 自体が省かれる。
+fn parse_esearch(lines: &[String]) -> Vec<u32> {
+    let mut uids = Vec::new();
+    for rest in lines
+        .iter()
+        .filter_map(|line| untagged_data(line, "ESEARCH"))
+    {
+        let mut tokens = rest.split_ascii_whitespace();
+        while let Some(token) = tokens.next() {
+            if token.eq_ignore_ascii_case("ALL") {
+                if let Some(set) = tokens.next() {
+                    uids.extend(parse_uid_set(set));
+                }
+            }
+        }
+    }
+    uids
+}
+
+/// `* THREAD` 応答のスレッドの木を取り出す（RFC 5256）
+///
+/// 例: `* THREAD (2)(3 6 (4 23)(44 7 96))`。並んだUIDは前のものの返信で、
+/// 最後に続く括弧はその前のUIDから分かれた枝。先頭が括弧なら親はフォルダーにない。
+fn parse_thread(lines: &[String]) -> Vec<ServerThread> {
+    let mut threads = Vec::new();
+    for rest in lines
+        .iter()
+        .filter_map(|line| untagged_data(line, "THREAD"))
+    {
+        let bytes = rest.as_bytes();
+        let mut position = 0;
+        while let Some(thread) = parse_thread_list(bytes, &mut position) {
+            threads.push(thread);
+        }
+    }
+    threads
+}
+
+/// `(` から対応する `)` までを1つの木として読む
+fn parse_thread_list(bytes: &[u8], position: &mut usize) -> Option<ServerThread> {
+    let skip_spaces = |position: &mut usize| {
+        while bytes.get(*position) == Some(&b' ') {
+            *position += 1;
+        }
+    };
+
+    skip_spaces(position);
+    if bytes.get(*position) != Some(&b'(') {
+        return None;
+    }
+    *position += 1;
+
+    let mut uids = Vec::new();
+    let mut branches = Vec::new();
+    loop {
+        skip_spaces(position);
+        match bytes.get(*position)? {
+            b'(' => branches.push(parse_thread_list(bytes, position)?),
+            b')' => {
+                *position += 1;
+                break;
+            }
+            b'0'..=b'9' => {
+                let start = *position;
+                while bytes.get(*position).is_some_and(u8::is_ascii_digit) {
+                    *position += 1;
+                }
+                uids.push(
+                    std::str::from_utf8(&bytes[start..*position])
+                        .ok()?
+                        .parse()
+                        .ok()?,
+                );
+            }
+            _ => return None,
+        }
+    }
+
+    let mut thread = ServerThread {
+        uid: uids.pop(),
+        children: branches,
+    };
+    while let Some(uid) = uids.pop() {
+        thread = ServerThread {
+            uid: Some(uid),
+            children: vec![thread],
+        };
+    }
+    Some(thread)
+}
+
+/// `* <name> ...` 形式の非タグ付き応答なら、名前より後ろを返す
+fn untagged_data<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix("* ")?;
+    let tail = rest.get(name.len()..)?;
+    (rest[..name.len()].eq_ignore_ascii_case(name) && (tail.is_empty() || tail.starts_with(' ')))
+        .then_some(tail)
+}
+
+/// `1:3,5` 形式のUIDの集合を展開
+fn parse_uid_set(set: &str) -> Vec<u32> {
+    let mut uids = Vec::new();
+    for part in set.split(',') {
+        match part.split_once(':') {
+            Some((first, last)) => {
+                if let (Ok(first), Ok(last)) = (first.parse::<u32>(), last.parse::<u32>()) {
+                    uids.extend(first.min(last)..=first.max(last));
+                }
+            }
+            None => uids.extend(part.parse::<u32>().ok()),
+        }
+    }
+    uids
+}
+
+/// IMAPのリテラルはCRLF改行でなければならないので、LFだけの行をCRLFにする
+fn to_crlf(raw: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(raw.len() + raw.len() / 40);
+    let mut previous = 0u8;
+    for &byte in raw {
+        if byte == b'\n' && previous != b'\r' {
+            converted.push(b'\r');
+        }
+        converted.push(byte);
+        previous = byte;
+    }
+    converted
+}
+
+/// LISTの属性が表すフォルダーの役割
+///
+/// SPECIAL-USE（RFC 6154）の属性に加えて、XLISTの属性名（`\AllMail`、`\Spam`、`\Inbox`）も
+/// LISTの応答に含まれていれば使う。XLISTコマンド自体はIMAPライブラリが応答を解析できないので送らない。
+fn special_use(attribute: &NameAttribute) -> Option<FolderType> {
+    match attribute {
+        NameAttribute::Sent => Some(FolderType::Sent),
+        NameAttribute::Drafts => Some(FolderType::Drafts),
+        NameAttribute::Trash => Some(FolderType::Trash),
+        NameAttribute::Junk => Some(FolderType::Spam),
+        NameAttribute::Archive => Some(FolderType::Archive),
+        NameAttribute::All => Some(FolderType::All),
+        NameAttribute::Extension(name) => match name.to_ascii_lowercase().as_str() {
+            "\\allmail" => Some(FolderType::All),
+            "\\spam" => Some(FolderType::Spam),
+            "\\inbox" => Some(FolderType::Inbox),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_esearch() {
+        let lines = vec![
+            "* 12 EXISTS".to_string(),
+            "* ESEARCH (TAG \"RAW\") UID ALL 4:6,9,12:10".to_string(),
+        ];
+        assert_eq!(parse_esearch(&lines), vec![4, 5, 6, 9, 10, 11, 12]);
+
+        // 一致するメッセージがなければALLは返らない
+        let lines = vec!["* ESEARCH (TAG \"RAW\") UID".to_string()];
+        assert!(parse_esearch(&lines).is_empty());
+    }
+
+    #[test]
+    fn test_parse_thread() {
+        let leaf = |uid| ServerThread {
+            uid: Some(uid),
+            children: Vec::new(),
+        };
+        let lines = vec!["* THREAD (2)(3 6 (4 23)(44 7 96))((5)(8))".to_string()];
+        assert_eq!(
+            parse_thread(&lines),
+            vec![
+                leaf(2),
+                ServerThread {
+                    uid: Some(3),
+                    children: vec![ServerThread {
+                        uid: Some(6),
+                        children: vec![
+                            ServerThread {
+                                uid: Some(4),
+                                children: vec![leaf(23)],
+                            },
+                            ServerThread {
+                                uid: Some(44),
+                                children: vec![ServerThread {
+                                    uid: Some(7),
+                                    children: vec![leaf(96)],
+                                }],
+                            },
+                        ],
+                    }],
+                },
+                ServerThread {
+                    uid: None,
+                    children: vec![leaf(5), leaf(8)],
+                },
+            ]
+        );
+        assert!(parse_thread(&["* THREAD".to_string()]).is_empty());
+    }
+}
@@ -0,0 +1,1324 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{
+    Account, Address, Attachment, AuthMethod, Flag, Folder, FolderType, MailError, MailResult,
+    Message, MessageBody, MessagePart, TokenProvider,
+};
+
+const CAPABILITY_CORE: &str = "urn:ietf:params:jmap:core";
+const CAPABILITY_MAIL: &str = "urn:ietf:params:jmap:mail";
+const CAPABILITY_SUBMISSION: &str = "urn:ietf:params:jmap:submission";
+
+/// Email/getで取得するプロパティ
+const EMAIL_PROPERTIES: &[&str] = &[
+    "id",
+    "threadId",
+    "mailboxIds",
+    "keywords",
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "subject",
+    "messageId",
+    "inReplyTo",
+    "references",
+    "receivedAt",
+    "textBody",
+    "htmlBody",
+    "attachments",
+    "bodyValues",
+];
+
+/// Email/changesで一度に受け取る変更の上限（超えた場合はキャッシュを捨てて取り直す）
+const MAX_CHANGES: usize = 500;
+
+/// セッションリソース（RFC 8620 2.）
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JmapSession {
+    api_url: String,
+    #[serde(default)]
+    upload_url: String,
+    primary_accounts: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JmapMailbox {
+    id: String,
+    name: String,
+    parent_id: Option<String>,
+    role: Option<String>,
+    #[serde(default = "default_subscribed")]
+    is_subscribed: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JmapEmail {
+    id: String,
+    thread_id: Option<String>,
+    #[serde(default)]
+    keywords: HashMap<String, bool>,
+    from: Option<Vec<JmapAddress>>,
+    to: Option<Vec<JmapAddress>>,
+    cc: Option<Vec<JmapAddress>>,
+    bcc: Option<Vec<JmapAddress>>,
+    subject: Option<String>,
+    message_id: Option<Vec<String>>,
+    in_reply_to: Option<Vec<String>>,
+    references: Option<Vec<String>>,
+    received_at: Option<String>,
+    #[serde(default)]
+    text_body: Vec<JmapBodyPart>,
+    #[serde(default)]
+    html_body: Vec<JmapBodyPart>,
+    #[serde(default)]
+    attachments: Vec<JmapBodyPart>,
+    #[serde(default)]
+    body_values: HashMap<String, JmapBodyValue>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JmapAddress {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JmapBodyPart {
+    part_id: Option<String>,
+    #[serde(rename = "type")]
+    content_type: Option<String>,
+    name: Option<String>,
+    size: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JmapBodyValue {
+    value: String,
+}
+
+/// JMAP（RFC 8620/8621）のクライアント
+///
+/// Email/changesの状態文字列を覚えておき、2回目以降の同期では追加・変更された
+/// メールだけをEmail/getで取得する。メッセージIDにはJMAPのEmail IDを使う。
+pub struct JmapClient {
+    account: Account,
+    http_client: reqwest::Client,
+    token_provider: Option<Arc<TokenProvider>>,
+    session: Option<JmapSession>,
+    /// セッションのメール用アカウントID
+    account_id: String,
+    mailboxes: Vec<JmapMailbox>,
+    /// 前回の同期時点のEmailの状態文字列
+    email_state: Option<String>,
+    /// 取得済みのメール（状態文字列以降に変更されたものは捨てて取り直す）
+    emails: HashMap<String, Message>,
+}
+
+impl JmapClient {
+    pub fn new(account: Account) -> Self {
+        Self {
+            account,
+            http_client: reqwest::Client::new(),
+            token_provider: None,
+            session: None,
+            account_id: String::new(),
+            mailboxes: Vec::new(),
+            email_state: None,
+            emails: HashMap::new(),
+        }
+    }
+
+    /// OAuth2アカウントのアクセストークンを共有のプロバイダーから取得する
+    pub fn with_token_provider(mut self, token_provider: Arc<TokenProvider>) -> Self {
+        self.token_provider = Some(token_provider);
+        self
+    }
+
+    /// セッションリソースを取得してメールボックス一覧を読み込む
+    pub async fn connect(&mut self) -> MailResult<()> {
+        let session_url = self.session_url()?;
+
+        let response = self
+            .authorize(self.http_client.get(&session_url))
+            .await?
+            .send()
+            .await
+            .map_err(|e| MailError::Connection(format!("JMAP session request failed: {}", e)))?;
+        let response = Self::check_status(response, "JMAP session request failed").await?;
+
+        let session: JmapSession = response
+            .json()
+            .await
+            .map_err(|e| MailError::Protocol(format!("Invalid JMAP session: {}", e)))?;
+        self.account_id = session
+            .primary_accounts
+            .get(CAPABILITY_MAIL)
+            .cloned()
+            .ok_or_else(|| MailError::Protocol("Server has no JMAP mail account".to_string()))?;
+        self.session = Some(session);
+
+        self.refresh_mailboxes().await?;
+        Ok(())
+    }
+
+    /// フォルダー（メールボックス）一覧
+    #[allow(dead_code)]
+    pub async fn list_folders(&mut self) -> MailResult<Vec<Folder>> {
+        self.refresh_mailboxes().await?;
+        Ok(self
+            .mailboxes
+            .iter()
+            .map(|mailbox| {
+                let mut folder = Folder::new(self.folder_name(mailbox), Some("/"))
+                    .with_role(mailbox.role.as_deref().and_then(mailbox_role));
+                folder.subscribed = mailbox.is_subscribed;
+                folder
+            })
+            .collect())
+    }
+
+    /// フォルダーの新しい順に最大 `limit` 件のメールを取得
+    ///
+    /// 前回の状態文字列からのEmail/changesで変更・削除されたメールをキャッシュから外し、
+    /// Email/queryの結果のうちキャッシュにないものだけをEmail/getで取得する。
+    pub async fn fetch_messages(
+        &mut self,
+        folder_name: &str,
+        limit: Option<usize>,
+    ) -> MailResult<Vec<Message>> {
+        let mailbox_id = self.mailbox_id(folder_name).await?;
+
+        let mut calls = Vec::new();
+        if let Some(state) = &self.email_state {
+            calls.push(json!([
+                "Email/changes",
+                {
+                    "accountId": self.account_id,
+                    "sinceState": state,
+                    "maxChanges": MAX_CHANGES,
+                },
+                "changes"
+            ]));
+        }
+        calls.push(json!([
+            "Email/query",
+            {
+                "accountId": self.account_id,
+                "filter": { "inMailbox": mailbox_id },
+                "sort": [{ "property": "receivedAt", "isAscending": false }],
+                "limit": limit.unwrap_or(50),
+            },
+            "query"
+        ]));
+
+        let responses = self.call(calls, &[CAPABILITY_MAIL]).await?;
+
+        if self.email_state.is_some() {
+            match method_response(&responses, "changes") {
+                Ok(changes) if changes["hasMoreChanges"] != Value::Bool(true) => {
+                    for id in string_list(&changes["updated"])
+                        .into_iter()
+                        .chain(string_list(&changes["destroyed"]))
+                    {
+                        self.emails.remove(&id);
+                    }
+                    self.email_state = changes["newState"].as_str().map(str::to_string);
+                }
+                // 変更が多すぎる・状態が古すぎる（cannotCalculateChanges）場合は取り直す
+                _ => {
+                    self.emails.clear();
+                    self.email_state = None;
+                }
+            }
+        }
+
+        let ids = string_list(&method_response(&responses, "query")?["ids"]);
+        let missing: Vec<&String> = ids
+            .iter()
+            .filter(|id| !self.emails.contains_key(*id))
+            .collect();
+
+        if !missing.is_empty() {
+            let (emails, state) = self.get_emails(&missing, folder_name).await?;
+            if self.email_state.is_none() {
+                self.email_state = state;
+            }
+            for message in emails {
+                self.emails.insert(message.id.clone(), message);
+            }
+        }
+
+        Ok(ids
+            .iter()
+            .filter_map(|id| self.emails.get(id))
+            .map(|message| Message {
+                folder: folder_name.to_string(),
+                ..message.clone()
+            })
+            .collect())
+    }
+
+    /// メッセージ本文を取得
+    #[allow(dead_code)]
+    pub async fn fetch_message_body(
+        &mut self,
+        folder_name: &str,
+        message_id: &str,
+    ) -> MailResult<String> {
+        if let Some(message) = self.emails.get(message_id) {
+            return Ok(message.body.get_display_content());
+        }
+
+        let id = message_id.to_string();
+        let (emails, _) = self.get_emails(&[&id], folder_name).await?;
+        emails
+            .into_iter()
+            .next()
+            .map(|message| message.body.get_display_content())
+            .ok_or_else(|| MailError::NotFound(format!("Message {}", message_id)))
+    }
+
+    /// フラグ（キーワード）を付ける・外す
+    pub async fn set_message_flag(
+        &mut self,
+        message_id: &str,
+        flag: &Flag,
+        enabled: bool,
+    ) -> MailResult<()> {
+        let keyword = keyword(flag)
+            .ok_or_else(|| MailError::Protocol(format!("Flag {:?} is not supported", flag)))?;
+        let value = if enabled {
+            Value::Bool(true)
+        } else {
+            Value::Null
+        };
+
+        self.update_email(
+            message_id,
+            json!({ (format!("keywords/{}", keyword)): value }),
+        )
+        .await
+    }
+
+    /// 別のフォルダー（メールボックス）へ移動
+    pub async fn move_message(
+        &mut self,
+        message_id: &str,
+        from_folder: &str,
+        to_folder: &str,
+    ) -> MailResult<()> {
+        let from = self.mailbox_id(from_folder).await?;
+        let to = self.mailbox_id(to_folder).await?;
+
+        self.update_email(
+            message_id,
+            json!({
+                (format!("mailboxIds/{}", from)): null,
+                (format!("mailboxIds/{}", to)): true,
+            }),
+        )
+        .await
+    }
+
+    /// メールを削除（Email/setのdestroy）
+    pub async fn delete_message(&mut self, message_id: &str) -> MailResult<()> {
+        let responses = self
+            .call(
+                vec![json!([
+                    "Email/set",
+                    { "accountId": self.account_id, "destroy": [message_id] },
+                    "set"
+                ])],
+                &[CAPABILITY_MAIL],
+            )
+            .await?;
+
+        check_set_result(
+            method_response(&responses, "set")?,
+            "notDestroyed",
+            message_id,
+        )?;
+        self.emails.remove(message_id);
+        Ok(())
+    }
+
+    /// 生メッセージをフォルダーに追加（アップロードしてEmail/import）
+    pub async fn append_message(
+        &mut self,
+        folder_name: &str,
+        raw: &[u8],
+        flags: &[Flag],
+    ) -> MailResult<()> {
+        let mailbox_id = self.mailbox_id(folder_name).await?;
+        let blob_id = self.upload(raw.to_vec(), "message/rfc822").await?;
+
+        let keywords: Map<String, Value> = flags
+            .iter()
+            .filter_map(keyword)
+            .map(|keyword| (keyword.to_string(), Value::Bool(true)))
+            .collect();
+
+        let responses = self
+            .call(
+                vec![json!([
+                    "Email/import",
+                    {
+                        "accountId": self.account_id,
+                        "emails": {
+                            "import": {
+                                "blobId": blob_id,
+                                "mailboxIds": { mailbox_id: true },
+                                "keywords": keywords,
+                            }
+                        }
+                    },
+                    "import"
+                ])],
+                &[CAPABILITY_MAIL],
+            )
+            .await?;
+
+        check_set_result(
+            method_response(&responses, "import")?,
+            "notCreated",
+            "import",
+        )
+    }
+
+    /// 下書きを作成してEmailSubmissionで送信し、成功したら送信済みへ移す
+    #[allow(dead_code)]
+    pub async fn send_message(&mut self, message: &Message) -> MailResult<()> {
+        let drafts = self.mailbox_id_for_role("drafts")?;
+        let sent = self.mailbox_id_for_role("sent").ok();
+        let identity_id = self.identity_id().await?;
+
+        let body = match &self.account.signature {
+            Some(signature) => format!(
+                "{}\n\n--\n{}",
+                message.body.get_display_content(),
+                signature
+            ),
+            None => message.body.get_display_content(),
+        };
+        let body_type = match message.body {
+            MessageBody::Html(_) => "text/html",
+            _ => "text/plain",
+        };
+
+        let mut attachments = Vec::new();
+        for attachment in &message.attachments {
+            let blob_id = self
+                .upload(attachment.data.clone(), &attachment.content_type)
+                .await?;
+            attachments.push(json!({
+                "blobId": blob_id,
+                "type": attachment.content_type,
+                "name": attachment.filename,
+                "disposition": "attachment",
+            }));
+        }
+
+        let addresses = |addresses: &[Address]| -> Vec<Value> {
+            addresses
+                .iter()
+                .map(|address| json!({ "name": address.name, "email": address.email }))
+                .collect()
+        };
+
+        let mut email = json!({
+            "mailboxIds": { (drafts.clone()): true },
+            "keywords": { "$draft": true, "$seen": true },
+            "from": [{ "name": self.account.name, "email": self.account.email }],
+            "to": addresses(&message.to),
+            "cc": addresses(&message.cc),
+            "bcc": addresses(&message.bcc),
+            "subject": message.subject,
+            "bodyValues": { "body": { "value": body } },
+            "attachments": attachments,
+        });
+        let body_key = if body_type == "text/html" {
+            "htmlBody"
+        } else {
+            "textBody"
+        };
+        email[body_key] = json!([{ "partId": "body", "type": body_type }]);
+
+        // 送信に成功したら下書きのキーワードを外して送信済みへ移す
+        let mut on_success = json!({
+            "keywords/$draft": null,
+            (format!("mailboxIds/{}", drafts)): null,
+        });
+        if let Some(sent) = &sent {
+            on_success[format!("mailboxIds/{}", sent)] = Value::Bool(true);
+        }
+
+        let responses = self
+            .call(
+                vec![
+                    json!([
+                        "Email/set",
+                        { "accountId": self.account_id, "create": { "draft": email } },
+                        "draft"
+                    ]),
+                    json!([
+                        "EmailSubmission/set",
+                        {
+                            "accountId": self.account_id,
+                            "create": {
+                                "submission": { "emailId": "#draft", "identityId": identity_id }
+                            },
+                            "onSuccessUpdateEmail": { "#submission": on_success },
+                        },
+                        "submission"
+                    ]),
+                ],
+                &[CAPABILITY_MAIL, CAPABILITY_SUBMISSION],
+            )
+            .await?;
+
+        check_set_result(method_response(&responses, "draft")?, "notCreated", "draft")?;
+        check_set_result(
+            method_response(&responses, "submission")?,
+            "notCreated",
+            "submission",
+        )
+    }
+
+    async fn refresh_mailboxes(&mut self) -> MailResult<()> {
+        let responses = self
+            .call(
+                vec![json!([
+                    "Mailbox/get",
+                    {
+                        "accountId": self.account_id,
+                        "ids": null,
+                        "properties": ["id", "name", "parentId", "role", "isSubscribed"],
+                    },
+                    "mailboxes"
+                ])],
+                &[CAPABILITY_MAIL],
+            )
+            .await?;
+
+        self.mailboxes =
+            serde_json::from_value(method_response(&responses, "mailboxes")?["list"].clone())
+                .map_err(|e| MailError::Protocol(format!("Invalid Mailbox/get response: {}", e)))?;
+        Ok(())
+    }
+
+    /// 受信箱は `INBOX`、それ以外は親をたどった `親/子` の形で表す
+    fn folder_name(&self, mailbox: &JmapMailbox) -> String {
+        if mailbox.role.as_deref() == Some("inbox") {
+            return "INBOX".to_string();
+        }
+
+        let mut name = mailbox.name.clone();
+        let mut parent_id = mailbox.parent_id.clone();
+        while let Some(parent) = parent_id
+            .as_ref()
+            .and_then(|id| self.mailboxes.iter().find(|m| &m.id == id))
+        {
+            name = format!("{}/{}", parent.name, name);
+            parent_id = parent.parent_id.clone();
+        }
+        name
+    }
+
+    /// フォルダー名（またはロール名）からメールボックスIDを探す
+    async fn mailbox_id(&mut self, folder_name: &str) -> MailResult<String> {
+        if let Some(id) = self.find_mailbox(folder_name) {
+            return Ok(id);
+        }
+
+        // 他のクライアントで作られたメールボックスかもしれないので読み直す
+        self.refresh_mailboxes().await?;
+        self.find_mailbox(folder_name)
+            .ok_or_else(|| MailError::NotFound(format!("Mailbox {}", folder_name)))
+    }
+
+    fn find_mailbox(&self, folder_name: &str) -> Option<String> {
+        self.mailboxes
+            .iter()
+            .find(|mailbox| self.folder_name(mailbox) == folder_name)
+            .or_else(|| {
+                self.mailboxes.iter().find(|mailbox| {
+                    mailbox
+                        .role
+                        .as_deref()
+                        .is_some_and(|role| role.eq_ignore_ascii_case(folder_name))
+                })
+            })
+            .map(|mailbox| mailbox.id.clone())
+    }
+
+    fn mailbox_id_for_role(&self, role: &str) -> MailResult<String> {
+        self.mailboxes
+            .iter()
+            .find(|mailbox| mailbox.role.as_deref() == Some(role))
+            .map(|mailbox| mailbox.id.clone())
+            .ok_or_else(|| MailError::NotFound(format!("Mailbox with role {}", role)))
+    }
+
+    /// 送信に使うIdentity（アカウントのメールアドレスと一致するもの、なければ最初のもの）
+    async fn identity_id(&self) -> MailResult<String> {
+        let responses = self
+            .call(
+                vec![json!([
+                    "Identity/get",
+                    { "accountId": self.account_id, "ids": null },
+                    "identities"
+                ])],
+                &[CAPABILITY_MAIL, CAPABILITY_SUBMISSION],
+            )
+            .await?;
+
+        let identities = method_response(&responses, "identities")?["list"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        identities
+            .iter()
+            .find(|identity| {
+                identity["email"]
+                    .as_str()
+                    .is_some_and(|email| email.eq_ignore_ascii_case(&self.account.email))
+            })
+            .or_else(|| identities.first())
+            .and_then(|identity| identity["id"].as_str())
+            .map(str::to_string)
+            .ok_or_else(|| MailError::NotFound("JMAP identity".to_string()))
+    }
+
+    /// Email/getで取得して `Message` に変換（あわせて状態文字列を返す）
+    async fn get_emails(
+        &self,
+        ids: &[&String],
+        folder_name: &str,
+    ) -> MailResult<(Vec<Message>, Option<String>)> {
+        let responses = self
+            .call(
+                vec![json!([
+                    "Email/get",
+                    {
+                        "accountId": self.account_id,
+                        "ids": ids,
+                        "properties": EMAIL_PROPERTIES,
+                        "fetchTextBodyValues": true,
+                        "fetchHTMLBodyValues": true,
+                    },
+                    "get"
+                ])],
+                &[CAPABILITY_MAIL],
+            )
+            .await?;
+
+        let response = method_response(&responses, "get")?;
+        let emails: Vec<JmapEmail> = serde_json::from_value(response["list"].clone())
+            .map_err(|e| MailError::Protocol(format!("Invalid Email/get response: {}", e)))?;
+        let state = response["state"].as_str().map(str::to_string);
+
+        Ok((
+            emails
+                .into_iter()
+                .map(|email| self.convert_email(email, folder_name))
+                .collect(),
+            state,
+        ))
+    }
+
+    async fn update_email(&mut self, message_id: &str, patch: Value) -> MailResult<()> {
+        let responses = self
+            .call(
+                vec![json!([
+                    "Email/set",
+                    { "accountId": self.account_id, "update": { message_id: patch } },
+                    "set"
+                ])],
+                &[CAPABILITY_MAIL],
+            )
+            .await?;
+
+        check_set_result(
+            method_response(&responses, "set")?,
+            "notUpdated",
+            message_id,
+        )?;
+        self.emails.remove(message_id);
+        Ok(())
+    }
+
+    /// バイナリをアップロードしてblobIdを得る
+    async fn upload(&self, data: Vec<u8>, content_type: &str) -> MailResult<String> {
+        let session = self.session()?;
+        let url = session.upload_url.replace("{accountId}", &self.account_id);
+
+        let response = self
+            .authorize(
+                self.http_client
+                    .post(&url)
+                    .header(reqwest::header::CONTENT_TYPE, content_type)
+                    .body(data),
+            )
+            .await?
+            .send()
+            .await
+            .map_err(|e| MailError::Connection(format!("JMAP upload failed: {}", e)))?;
+        let response = Self::check_status(response, "JMAP upload failed").await?;
+
+        let uploaded: Value = response
+            .json()
+            .await
+            .map_err(|e| MailError::Protocol(format!("Invalid upload response: {}", e)))?;
+        uploaded["blobId"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| MailError::Protocol("Upload response has no blobId".to_string()))
+    }
+
+    /// APIリクエストを送り、methodResponsesを返す
+    async fn call(&self, method_calls: Vec<Value>, using: &[&str]) -> MailResult<Vec<Value>> {
+        let session = self.session()?;
+        let mut capabilities = vec![CAPABILITY_CORE];
+        capabilities.extend_from_slice(using);
+        let request = json!({ "using": capabilities, "methodCalls": method_calls });
+
+        let response = self
+            .authorize(self.http_client.post(&session.api_url).json(&request))
+            .await?
+            .send()
+            .await
+            .map_err(|e| MailError::Connection(format!("JMAP request failed: {}", e)))?;
+        let response = Self::check_status(response, "JMAP request failed").await?;
+
+        let mut body: Value = response
+            .json()
+            .await
+            .map_err(|e| MailError::Protocol(format!("Invalid JMAP response: {}", e)))?;
+        match body["methodResponses"].take() {
+            Value::Array(responses) => Ok(responses),
+            _ => Err(MailError::Protocol(
+                "JMAP response has no methodResponses".to_string(),
+            )),
+        }
+    }
+
+    fn session(&self) -> MailResult<&JmapSession> {
+        self.session
+            .as_ref()
+            .ok_or_else(|| MailError::Connection("JMAP not connected".to_string()))
+    }
+
+    fn session_url(&self) -> MailResult<String> {
+        if let Some(url) = self
+            .account
+            .jmap
+            .as_ref()
+            .map(|jmap| jmap.session_url.as_str())
+            .filter(|url| !url.is_empty())
+        {
+            return Ok(url.to_string());
+        }
+
+        let domain = self
+            .account
+            .email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .ok_or_else(|| MailError::Parse("Invalid email address".to_string()))?;
+        Ok(format!("https://{}/.well-known/jmap", domain))
+    }
+
+    /// 認証ヘッダーを付ける
+    ///
+    /// OAuth2はアクセストークン、ユーザー名が空ならパスワードをAPIトークンとして
+    /// Bearerで送り、それ以外はBasic認証にする
+    async fn authorize(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> MailResult<reqwest::RequestBuilder> {
+        let imap = &self.account.imap;
+        if imap.auth_method == AuthMethod::OAuth2 {
+            let token = match &self.token_provider {
+                Some(provider) => provider.access_token(&self.account.id).await?,
+                None => self
+                    .account
+                    .tokens
+                    .as_ref()
+                    .map(|tokens| tokens.access_token.clone())
+                    .ok_or_else(|| {
+                        MailError::Authentication("No OAuth2 tokens available".to_string())
+                    })?,
+            };
+            return Ok(request.bearer_auth(token));
+        }
+
+        if imap.username.is_empty() {
+            Ok(request.bearer_auth(&imap.password))
+        } else {
+            Ok(request.basic_auth(&imap.username, Some(&imap.password)))
+        }
+    }
+
+    async fn check_status(
+        response: reqwest::Response,
+        error_context: &str,
+    ) -> MailResult<reqwest::Response> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let error_text = response.text().await.unwrap_or_default();
+        let message = format!("{}: {} - {}", error_context, status, error_text);
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            Err(MailError::Authentication(message))
+        } else {
+            Err(MailError::Protocol(message))
+        }
+    }
+
+    fn convert_email(&self, email: JmapEmail, folder_name: &str) -> Message {
+        let addresses = |addresses: Option<Vec<JmapAddress>>| -> Vec<Address> {
+            addresses
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|address| Some(Address::new(address.email?, address.name)))
+                .collect()
+        };
+
+        let part_text = |parts: &[JmapBodyPart]| -> Option<String> {
+            let texts: Vec<&str> = parts
+                .iter()
+                .filter_map(|part| email.body_values.get(part.part_id.as_ref()?))
+                .map(|value| value.value.as_str())
+                .collect();
+            (!texts.is_empty()).then(|| texts.concat())
+        };
+        let is_html = |parts: &[JmapBodyPart]| {
+            parts
+                .iter()
+                .all(|part| part.content_type.as_deref() == Some("text/html"))
+        };
+
+        // textBodyにはtext/plainがなければtext/htmlが入るので、htmlBodyと同じなら片方だけ
+        let text = part_text(&email.text_body).filter(|_| !is_html(&email.text_body));
+        let html = part_text(&email.html_body).filter(|_| is_html(&email.html_body));
+        let body = match (text, html) {
+            (Some(text), Some(html)) => MessageBody::Multipart {
+                parts: vec![
+                    MessagePart {
+                        content_type: "text/plain".to_string(),
+                        content: text,
+                        encoding: None,
+                    },
+                    MessagePart {
+                        content_type: "text/html".to_string(),
+                        content: html,
+                        encoding: None,
+                    },
+                ],
+            },
+            (Some(text), None) => MessageBody::new_plain(text),
+            (None, Some(html)) => MessageBody::new_html(html),
+            (None, None) => MessageBody::new_plain(String::new()),
+        };
+
+        let mut message = Message::new(
+            email.id,
+            addresses(email.from),
+            addresses(email.to),
+            email.subject.unwrap_or_default(),
+            body,
+            self.account.id.clone(),
+            folder_name.to_string(),
+        );
+        message.cc = addresses(email.cc);
+        message.bcc = addresses(email.bcc);
+        message.thread_id = email.thread_id;
+        message.message_id = email.message_id.and_then(|ids| ids.into_iter().next());
+        message.in_reply_to = email.in_reply_to.and_then(|ids| ids.into_iter().next());
+        message.references = email.references.unwrap_or_default();
+
+        if let Some(date) = email
+            .received_at
+            .as_deref()
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        {
+            message.date = date.with_timezone(&Utc);
+        }
+
+        message.flags = email
+            .keywords
+            .iter()
+            .filter(|(_, set)| **set)
+            .filter_map(|(keyword, _)| flag_from_keyword(keyword))
+            .collect();
+
+        // 添付ファイルの中身は開くときまで取得しない
+        message.attachments = email
+            .attachments
+            .into_iter()
+            .map(|part| Attachment {
+                filename: part.name.unwrap_or_else(|| "attachment".to_string()),
+                content_type: part
+                    .content_type
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+                size: part.size.unwrap_or(0),
+                data: Vec::new(),
+            })
+            .collect();
+
+        message
+    }
+}
+
+/// callIdに対応するメソッドの結果（errorならエラーにする）
+fn method_response<'a>(responses: &'a [Value], call_id: &str) -> MailResult<&'a Value> {
+    let response = responses
+        .iter()
+        .find(|response| response[2].as_str() == Some(call_id))
+        .ok_or_else(|| MailError::Protocol(format!("No JMAP response for {}", call_id)))?;
+
+    if response[0].as_str() == Some("error") {
+        let error_type = response[1]["type"].as_str().unwrap_or("unknown");
+        return Err(MailError::Protocol(format!(
+            "JMAP method error ({}): {}",
+            error_type,
+            response[1]["description"].as_str().unwrap_or_default()
+        )));
+    }
+
+    Ok(&response[1])
+}
+
+/// /setの結果を確認（notFoundはサーバー側で消えたものとして扱う）
+fn check_set_result(response: &Value, failed_key: &str, id: &str) -> MailResult<()> {
+    let Some(error) = response[failed_key].get(id).filter(|e| !e.is_null()) else {
+        return Ok(());
+    };
+
+    let error_type = error["type"].as_str().unwrap_or("unknown");
+    let description = error["description"].as_str().unwrap_or_default();
+    if error_type == "notFound" {
+        Err(MailError::NotFound(format!("Email {}", id)))
+    } else {
+        Err(MailError::Protocol(format!(
+            "JMAP {} failed ({}): {}",
+            failed_key, error_type, description
+        )))
+    }
+}
+
+fn string_list(value: &Value) -> Vec<String> {
+    value
+        .as_array()
+        .map(|values| {
+            values
+                .iter()
+                .filter_map(|v| v.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn keyword(flag: &Flag) -> Option<&'static str> {
+    match flag {
+        Flag::Seen => Some("$seen"),
+        Flag::Flagged => Some("$flagged"),
+        Flag::Answered => Some("$answered"),
+        Flag::Draft => Some("$draft"),
+        _ => None,
+    }
+}
+
+fn flag_from_keyword(keyword: &str) -> Option<Flag> {
+    match keyword.to_ascii_lowercase().as_str() {
+        "$seen" => Some(Flag::Seen),
+        "$flagged" => Some(Flag::Flagged),
+        "$answered" => Some(Flag::Answered),
+        "$draft" => Some(Flag::Draft),
+        _ => None,
+    }
+}
+
+fn default_subscribed() -> bool {
+    true
+}
+
+/// メールボックスのrole（RFC 8621、値はSPECIAL-USEの属性名と同じ）
+fn mailbox_role(role: &str) -> Option<FolderType> {
+    match role {
+        "inbox" => Some(FolderType::Inbox),
+        "sent" => Some(FolderType::Sent),
+        "drafts" => Some(FolderType::Drafts),
+        "trash" => Some(FolderType::Trash),
+        "junk" => Some(FolderType::Spam),
+        "archive" => Some(FolderType::Archive),
+        "all" => Some(FolderType::All),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::account::JmapConfig;
+    use std::sync::Mutex as StdMutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// テスト用のJMAPサーバー（メールはEmail/getの形のままメモリに持つ）
+    #[derive(Default)]
+    struct StandIn {
+        state: u64,
+        emails: Vec<Value>,
+        /// (状態, メールID, created/updated/destroyed)
+        changes: Vec<(u64, String, &'static str)>,
+        /// Email/getで要求されたID
+        fetched: Vec<Vec<String>>,
+        submissions: Vec<String>,
+    }
+
+    impl StandIn {
+        fn record(&mut self, id: &str, kind: &'static str) {
+            self.state += 1;
+            self.changes.push((self.state, id.to_string(), kind));
+        }
+
+        fn deliver(&mut self, email: Value) {
+            let id = email["id"].as_str().unwrap().to_string();
+            self.emails.push(email);
+            self.record(&id, "created");
+        }
+
+        fn handle(&mut self, request: Value) -> Value {
+            let mut responses = Vec::new();
+            let mut created = HashMap::new();
+
+            for call in request["methodCalls"].as_array().unwrap() {
+                let (name, args, call_id) = (call[0].as_str().unwrap(), &call[1], &call[2]);
+                let result = match name {
+                    "Mailbox/get" => json!({ "list": [
+                        { "id": "mb-inbox", "name": "Inbox", "parentId": null, "role": "inbox" },
+                        { "id": "mb-archive", "name": "Archive", "parentId": null, "role": "archive" },
+                        { "id": "mb-drafts", "name": "Drafts", "parentId": null, "role": "drafts" },
+                        { "id": "mb-sent", "name": "Sent", "parentId": null, "role": "sent" },
+                    ] }),
+                    "Email/query" => {
+                        let mailbox = args["filter"]["inMailbox"].as_str().unwrap();
+                        let mut emails: Vec<&Value> = self
+                            .emails
+                            .iter()
+                            .filter(|email| email["mailboxIds"][mailbox] == true)
+                            .collect();
+                        emails.sort_by_key(|email| {
+                            std::cmp::Reverse(email["receivedAt"].as_str().unwrap().to_string())
+                        });
+                        let ids: Vec<&Value> = emails
+                            .iter()
+                            .take(args["limit"].as_u64().unwrap() as usize)
+                            .map(|email| &email["id"])
+                            .collect();
+                        json!({ "ids": ids, "queryState": self.state.to_string() })
+                    }
+                    "Email/get" => {
+                        let ids = string_list(&args["ids"]);
+                        let list: Vec<&Value> = ids
+                            .iter()
+                            .filter_map(|id| self.emails.iter().find(|e| e["id"] == id.as_str()))
+                            .collect();
+                        let response = json!({ "list": list, "state": self.state.to_string() });
+                        self.fetched.push(ids);
+                        response
+                    }
+                    "Email/changes" => {
+                        let since: u64 = args["sinceState"].as_str().unwrap().parse().unwrap();
+                        let mut result = json!({
+                            "oldState": since.to_string(),
+                            "newState": self.state.to_string(),
+                            "hasMoreChanges": false,
+                            "created": [], "updated": [], "destroyed": [],
+                        });
+                        for (_, id, kind) in self.changes.iter().filter(|(s, _, _)| *s > since) {
+                            result[*kind].as_array_mut().unwrap().push(json!(id));
+                        }
+                        result
+                    }
+                    "Email/set" => self.email_set(args, &mut created),
+                    "Identity/get" => json!({ "list": [
+                        { "id": "identity-1", "email": "me@example.com", "name": "Me" }
+                    ] }),
+                    "EmailSubmission/set" => {
+                        let reference = args["create"]["submission"]["emailId"].as_str().unwrap();
+                        let email_id = created[reference.trim_start_matches('#')].clone();
+                        self.apply_patch(&email_id, &args["onSuccessUpdateEmail"]["#submission"]);
+                        self.submissions.push(email_id);
+                        json!({ "created": { "submission": { "id": "submission-1" } } })
+                    }
+                    _ => {
+                        responses.push(json!(["error", { "type": "unknownMethod" }, call_id]));
+                        continue;
+                    }
+                };
+                responses.push(json!([name, result, call_id]));
+            }
+
+            json!({ "methodResponses": responses, "sessionState": "0" })
+        }
+
+        fn email_set(&mut self, args: &Value, created: &mut HashMap<String, String>) -> Value {
+            let mut result = json!({
+                "created": {}, "updated": {}, "destroyed": [],
+                "notCreated": {}, "notUpdated": {}, "notDestroyed": {},
+            });
+
+            for (creation_id, email) in args["create"].as_object().into_iter().flatten() {
+                let id = format!("email-{}", self.emails.len() + 1);
+                let mut email = email.clone();
+                email["id"] = json!(id);
+                email["receivedAt"] = json!("2023-11-16T00:00:00Z");
+                self.deliver(email);
+                created.insert(creation_id.clone(), id.clone());
+                result["created"][creation_id] = json!({ "id": id });
+            }
+
+            for (id, patch) in args["update"].as_object().into_iter().flatten() {
+                if self.apply_patch(id, patch) {
+                    result["updated"][id] = Value::Null;
+                } else {
+                    result["notUpdated"][id] = json!({ "type": "notFound" });
+                }
+            }
+
+            for id in string_list(&args["destroy"]) {
+                match self.emails.iter().position(|e| e["id"] == id.as_str()) {
+                    Some(index) => {
+                        self.emails.remove(index);
+                        self.record(&id, "destroyed");
+                        result["destroyed"].as_array_mut().unwrap().push(json!(id));
+                    }
+                    None => result["notDestroyed"][&id] = json!({ "type": "notFound" }),
+                }
+            }
+
+            result
+        }
+
+        /// `keywords/$seen` のようなパスでプロパティを書き換える（nullは削除）
+        fn apply_patch(&mut self, id: &str, patch: &Value) -> bool {
+            let Some(email) = self.emails.iter_mut().find(|e| e["id"] == id) else {
+                return false;
+            };
+            for (path, value) in patch.as_object().unwrap() {
+                let (property, key) = path.split_once('/').unwrap();
+                if value.is_null() {
+                    if let Some(map) = email[property].as_object_mut() {
+                        map.remove(key);
+                    }
+                } else {
+                    email[property][key] = value.clone();
+                }
+            }
+            self.record(id, "updated");
+            true
+        }
+    }
+
+    fn email(id: &str, received_at: &str, seen: bool, subject: &str) -> Value {
+        json!({
+            "id": id,
+            "threadId": format!("thread-{}", id),
+            "mailboxIds": { "mb-inbox": true },
+            "keywords": if seen { json!({ "$seen": true }) } else { json!({}) },
+            "from": [{ "name": "Alice", "email": "alice@example.com" }],
+            "to": [{ "name": null, "email": "me@example.com" }],
+            "subject": subject,
+            "receivedAt": received_at,
+            "textBody": [{ "partId": "1", "type": "text/plain" }],
+            "htmlBody": [{ "partId": "1", "type": "text/plain" }],
+            "attachments": [],
+            "bodyValues": { "1": { "value": format!("{} body", subject) } },
+        })
+    }
+
+    async fn serve(listener: TcpListener, base_url: String, stand_in: Arc<StdMutex<StandIn>>) {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let (head, body) = read_request(&mut stream).await;
+            let request_line = head.lines().next().unwrap_or_default().to_string();
+            let authorized = head.to_ascii_lowercase().contains("authorization: basic ");
+
+            let (status, response) = if !authorized {
+                ("401 Unauthorized", json!({}))
+            } else if request_line.starts_with("GET /.well-known/jmap ") {
+                let session = json!({
+                    "apiUrl": format!("{}/api", base_url),
+                    "uploadUrl": format!("{}/upload/{{accountId}}/", base_url),
+                    "primaryAccounts": {
+                        CAPABILITY_MAIL: "account-1",
+                        CAPABILITY_SUBMISSION: "account-1",
+                    },
+                });
+                ("200 OK", session)
+            } else if request_line.starts_with("POST /api ") {
+                let request = serde_json::from_slice(&body).unwrap();
+                ("200 OK", stand_in.lock().unwrap().handle(request))
+            } else {
+                ("404 Not Found", json!({}))
+            };
+
+            let response = response.to_string();
+            let _ = stream
+                .write_all(
+                    format!(
+                        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status,
+                        response.len(),
+                        response
+                    )
+                    .as_bytes(),
+                )
+                .await;
+        }
+    }
+
+    /// ヘッダーとContent-Length分の本文を読む
+    async fn read_request(stream: &mut TcpStream) -> (String, Vec<u8>) {
+        let mut buffer = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let header_end = loop {
+            if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+            let n = stream.read(&mut chunk).await.unwrap();
+            if n == 0 {
+                return (String::new(), Vec::new());
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+        };
+
+        let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+        let length = head
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        while buffer.len() < header_end + length {
+            let n = stream.read(&mut chunk).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            buffer.extend_from_slice(&chunk[..n]);
+        }
+
+        (head, buffer[header_end..].to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_sync_flags_move_and_send_against_stand_in() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let base_url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let stand_in = Arc::new(StdMutex::new(StandIn {
+            emails: vec![
+                email("e1", "2023-11-14T22:13:20Z", false, "Invoice"),
+                email("e2", "2023-11-14T23:00:00Z", true, "Lunch"),
+            ],
+            ..StandIn::default()
+        }));
+        tokio::spawn(serve(listener, base_url.clone(), stand_in.clone()));
+
+        let mut account = Account {
+            email: "me@example.com".to_string(),
+            jmap: Some(JmapConfig {
+                session_url: format!("{}/.well-known/jmap", base_url),
+            }),
+            ..Account::default()
+        };
+        account.imap.username = "me".to_string();
+        account.imap.password = "secret".to_string();
+
+        let mut client = JmapClient::new(account);
+        client.connect().await.unwrap();
+        assert_eq!(
+            client
+                .list_folders()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|folder| folder.name)
+                .collect::<Vec<_>>(),
+            vec!["INBOX", "Archive", "Drafts", "Sent"]
+        );
+
+        let messages = client.fetch_messages("INBOX", Some(10)).await.unwrap();
+        let ids: Vec<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["e2", "e1"]);
+        assert!(messages[1].is_unread());
+        assert_eq!(messages[1].body.get_display_content(), "Invoice body");
+        assert_eq!(messages[1].from[0].email, "alice@example.com");
+        assert_eq!(messages[1].date.timestamp(), 1_700_000_000);
+
+        client
+            .set_message_flag("e1", &Flag::Seen, true)
+            .await
+            .unwrap();
+        stand_in
+            .lock()
+            .unwrap()
+            .deliver(email("e3", "2023-11-15T01:00:00Z", false, "New"));
+
+        // 2回目はEmail/changesで分かった変更分と新着だけを取得する
+        let messages = client.fetch_messages("INBOX", Some(10)).await.unwrap();
+        let ids: Vec<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["e3", "e2", "e1"]);
+        assert!(!messages[2].is_unread());
+        let mut fetched = stand_in.lock().unwrap().fetched.last().cloned().unwrap();
+        fetched.sort();
+        assert_eq!(fetched, vec!["e1", "e3"]);
+
+        client.move_message("e2", "INBOX", "Archive").await.unwrap();
+        assert_eq!(client.fetch_messages("INBOX", None).await.unwrap().len(), 2);
+        let archived = client.fetch_messages("Archive", None).await.unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].id, "e2");
+        assert_eq!(archived[0].folder, "Archive");
+
+        assert!(matches!(
+            client.set_message_flag("missing", &Flag::Seen, true).await,
+            Err(MailError::NotFound(_))
+        ));
+
+        let message = Message::new(
+            String::new(),
+            Vec::new(),
+            vec![Address::new("bob@example.com".to_string(), None)],
+            "Hello".to_string(),
+            MessageBody::new_plain("Hi Bob".to_string()),
+            "default".to_string(),
+            "Drafts".to_string(),
+        );
+        client.send_message(&message).await.unwrap();
+        assert_eq!(stand_in.lock().unwrap().submissions.len(), 1);
+
+        // 送信に成功した下書きは送信済みへ移る
+        assert!(client
+            .fetch_messages("Drafts", None)
+            .await
+            .unwrap()
+            .is_empty());
+        let sent = client.fetch_messages("Sent", None).await.unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].subject, "Hello");
+        assert_eq!(sent[0].to[0].email, "bob@example.com");
+        assert!(!sent[0].flags.contains(&Flag::Draft));
+    }
+}
@@ -0,0 +1,417 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::{mime, Account, Flag, Folder, MailError, MailResult, Message};
+
+/// 受信箱以外のフォルダーはルート直下の `.名前` ディレクトリ（Maildir++）
+const INBOX: &str = "INBOX";
+
+/// ファイル名の `:2,` 以降が情報部（フラグ）
+const INFO_SEPARATOR: &str = ":2,";
+
+/// mbsync/offlineimapなどで同期済みのMaildirを読むクライアント
+///
+/// メッセージIDにはファイル名の一意な部分（情報部を除いたもの）を使う。
+/// フラグの変更と移動はファイルのリネームで行うので、途中で中断しても壊れない。
+pub struct MaildirClient {
+    account: Account,
+    root: PathBuf,
+}
+
+/// Maildir内のメッセージファイル
+struct MaildirEntry {
+    path: PathBuf,
+    unique: String,
+    info: String,
+}
+
+impl MaildirClient {
+    pub fn new(account: Account) -> MailResult<Self> {
+        let root = account
+            .maildir
+            .as_ref()
+            .map(|maildir| maildir.root())
+            .ok_or_else(|| MailError::Parse("Account has no Maildir configured".to_string()))?;
+
+        Ok(Self { account, root })
+    }
+
+    /// ルートがMaildirとして読めるか確認
+    pub fn connect(&self) -> MailResult<()> {
+        if !is_maildir(&self.root) {
+            return Err(MailError::Connection(format!(
+                "Not a Maildir (cur/new/tmp not found): {}",
+                self.root.display()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// 受信箱とMaildir++のサブフォルダー（`Work.Projects` のように `.` 区切り）
+    #[allow(dead_code)]
+    pub fn list_folders(&self) -> MailResult<Vec<Folder>> {
+        let mut folders = Vec::new();
+        for entry in read_dir(&self.root)? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(folder) = name.strip_prefix('.') else {
+                continue;
+            };
+            if folder.is_empty() || folder == "." || !is_maildir(&entry.path()) {
+                continue;
+            }
+            folders.push(folder.to_string());
+        }
+        folders.sort();
+        folders.insert(0, INBOX.to_string());
+
+        // Maildir++のサブフォルダーは `.Work.Projects` のように `.` で区切る
+        Ok(folders
+            .into_iter()
+            .map(|name| Folder::new(name, Some(".")))
+            .collect())
+    }
+
+    /// 新着（new）を既読扱いの置き場（cur）へ移してから、新しい順にメッセージを読む
+    pub fn fetch_messages(&self, folder: &str, limit: Option<usize>) -> MailResult<Vec<Message>> {
+        let dir = self.folder_path(folder)?;
+        self.deliver_new(&dir)?;
+
+        let mut files = Vec::new();
+        for entry in read_dir(&dir.join("cur"))? {
+            let path = entry.path();
+            let Some(entry) = parse_entry(&path) else {
+                continue;
+            };
+            let modified = fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((modified, entry));
+        }
+
+        files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        if let Some(limit) = limit {
+            files.truncate(limit);
+        }
+
+        let mut messages = Vec::new();
+        for (_, entry) in files {
+            match self.read_message(&entry, folder) {
+                Ok(message) => messages.push(message),
+                // 読めないファイルは飛ばして残りを表示する
+                Err(_) => continue,
+            }
+        }
+
+        messages.sort_by_key(|message| std::cmp::Reverse(message.date));
+        Ok(messages)
+    }
+
+    /// メッセージ本文を取得
+    #[allow(dead_code)]
+    pub fn fetch_message_body(&self, folder: &str, message_id: &str) -> MailResult<String> {
+        let entry = self.find_message(folder, message_id)?;
+        Ok(self
+            .read_message(&entry, folder)?
+            .body
+            .get_display_content())
+    }
+
+    /// フラグを付ける・外す（ファイル名の情報部を書き換える）
+    pub fn set_message_flag(
+        &self,
+        folder: &str,
+        message_id: &str,
+        flag: &Flag,
+        enabled: bool,
+    ) -> MailResult<()> {
+        let flag_char = info_char(flag)
+            .ok_or_else(|| MailError::Protocol(format!("Flag {:?} is not supported", flag)))?;
+        let entry = self.find_message(folder, message_id)?;
+
+        let mut info: Vec<char> = entry.info.chars().filter(|c| *c != flag_char).collect();
+        if enabled {
+            info.push(flag_char);
+        }
+        info.sort_unstable();
+        let info: String = info.into_iter().collect();
+
+        // newにあるメッセージもフラグを付けた時点でcurへ移す
+        let dir = self.folder_path(folder)?.join("cur");
+        rename(&entry.path, &dir.join(file_name(&entry.unique, &info)))
+    }
+
+    /// 別のフォルダーへ移動（同じファイルシステム内のリネーム）
+    pub fn move_message(
+        &self,
+        message_id: &str,
+        from_folder: &str,
+        to_folder: &str,
+    ) -> MailResult<()> {
+        let entry = self.find_message(from_folder, message_id)?;
+        let target = self.folder_path(to_folder)?;
+        if !is_maildir(&target) {
+            return Err(MailError::NotFound(format!("Folder {}", to_folder)));
+        }
+
+        rename(
+            &entry.path,
+            &target
+                .join("cur")
+                .join(file_name(&entry.unique, &entry.info)),
+        )
+    }
+
+    /// メッセージを追加する（tmpに書いてからcurへリネーム）
+    pub fn append_message(&self, folder: &str, raw: &[u8], flags: &[Flag]) -> MailResult<String> {
+        let dir = self.folder_path(folder)?;
+        if !is_maildir(&dir) {
+            return Err(MailError::NotFound(format!("Folder {}", folder)));
+        }
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let unique = format!(
+            "{}.{}.rustmail",
+            now.as_secs(),
+            uuid::Uuid::new_v4().simple()
+        );
+        let mut info: Vec<char> = flags.iter().filter_map(info_char).collect();
+        info.sort_unstable();
+        info.dedup();
+        let info: String = info.into_iter().collect();
+
+        let tmp = dir.join("tmp").join(&unique);
+        fs::write(&tmp, raw)
+            .map_err(|e| MailError::Io(format!("Failed to write message: {}", e)))?;
+        rename(&tmp, &dir.join("cur").join(file_name(&unique, &info)))?;
+
+        Ok(unique)
+    }
+
+    /// メッセージファイルを削除
+    pub fn delete_message(&self, folder: &str, message_id: &str) -> MailResult<()> {
+        let entry = self.find_message(folder, message_id)?;
+        fs::remove_file(&entry.path)
+            .map_err(|e| MailError::Io(format!("Failed to delete message: {}", e)))
+    }
+
+    fn read_message(&self, entry: &MaildirEntry, folder: &str) -> MailResult<Message> {
+        let raw = fs::read(&entry.path)
+            .map_err(|e| MailError::Io(format!("Failed to read message: {}", e)))?;
+        let mut message =
+            mime::parse_message(&raw, entry.unique.clone(), &self.account.id, folder)?;
+        message.flags = flags_from_info(&entry.info);
+        Ok(message)
+    }
+
+    fn folder_path(&self, folder: &str) -> MailResult<PathBuf> {
+        if folder == INBOX {
+            return Ok(self.root.clone());
+        }
+
+        if folder.is_empty()
+            || folder.starts_with('.')
+            || folder.contains(['/', '\\'])
+            || folder.contains("..")
+        {
+            return Err(MailError::Parse(format!("Invalid folder name: {}", folder)));
+        }
+
+        Ok(self.root.join(format!(".{}", folder)))
+    }
+
+    fn find_message(&self, folder: &str, message_id: &str) -> MailResult<MaildirEntry> {
+        let dir = self.folder_path(folder)?;
+        for sub in ["cur", "new"] {
+            for entry in read_dir(&dir.join(sub))? {
+                if let Some(entry) = parse_entry(&entry.path()) {
+                    if entry.unique == message_id {
+                        return Ok(entry);
+                    }
+                }
+            }
+        }
+
+        Err(MailError::NotFound(format!(
+            "Message {} in {}",
+            message_id, folder
+        )))
+    }
+
+    fn deliver_new(&self, dir: &Path) -> MailResult<()> {
+        for entry in read_dir(&dir.join("new"))? {
+            if let Some(entry) = parse_entry(&entry.path()) {
+                rename(
+                    &entry.path,
+                    &dir.join("cur").join(file_name(&entry.unique, &entry.info)),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_maildir(path: &Path) -> bool {
+    ["cur", "new", "tmp"]
+        .iter()
+        .all(|sub| path.join(sub).is_dir())
+}
+
+fn read_dir(path: &Path) -> MailResult<Vec<fs::DirEntry>> {
+    fs::read_dir(path)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| MailError::Io(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+fn rename(from: &Path, to: &Path) -> MailResult<()> {
+    fs::rename(from, to).map_err(|e| MailError::Io(format!("Failed to rename message: {}", e)))
+}
+
+/// ファイル名を一意な部分と情報部に分ける（ドットファイルは対象外）
+fn parse_entry(path: &Path) -> Option<MaildirEntry> {
+    if !path.is_file() {
+        return None;
+    }
+    let name = path.file_name()?.to_str()?;
+    if name.starts_with('.') {
+        return None;
+    }
+
+    let (unique, info) = match name.split_once(INFO_SEPARATOR) {
+        Some((unique, info)) => (unique, info),
+        None => (name, ""),
+    };
+
+    Some(MaildirEntry {
+        path: path.to_path_buf(),
+        unique: unique.to_string(),
+        info: info.to_string(),
+    })
+}
+
+fn file_name(unique: &str, info: &str) -> String {
+    format!("{}{}{}", unique, INFO_SEPARATOR, info)
+}
+
+fn flags_from_info(info: &str) -> Vec<Flag> {
+    info.chars()
+        .filter_map(|c| match c {
+            'S' => Some(Flag::Seen),
+            'R' => Some(Flag::Answered),
+            'F' => Some(Flag::Flagged),
+            'T' => Some(Flag::Deleted),
+            'D' => Some(Flag::Draft),
+            _ => None,
+        })
+        .collect()
+}
+
+fn info_char(flag: &Flag) -> Option<char> {
+    match flag {
+        Flag::Seen => Some('S'),
+        Flag::Answered => Some('R'),
+        Flag::Flagged => Some('F'),
+        Flag::Deleted => Some('T'),
+        Flag::Draft => Some('D'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::account::MaildirConfig;
+
+    const MESSAGE: &str = "From: Alice <alice@example.com>\r\n\
+        To: bob@example.com\r\n\
+        Subject: Maildir test\r\n\
+        Date: Tue, 14 Nov 2023 22:13:20 +0000\r\n\
+        \r\n\
+        hello\r\n";
+
+    fn make_maildir(path: &Path) {
+        for sub in ["cur", "new", "tmp"] {
+            fs::create_dir_all(path.join(sub)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_flag_and_move() {
+        let root = std::env::temp_dir().join(format!("rustmail-maildir-{}", uuid::Uuid::new_v4()));
+        make_maildir(&root);
+        make_maildir(&root.join(".Archive"));
+        make_maildir(&root.join(".Work.Projects"));
+        fs::write(root.join("new").join("1700000000.1.host"), MESSAGE).unwrap();
+        fs::write(root.join("cur").join("1700000001.2.host:2,FS"), MESSAGE).unwrap();
+
+        let account = Account {
+            maildir: Some(MaildirConfig { path: root.clone() }),
+            ..Account::default()
+        };
+        let client = MaildirClient::new(account).unwrap();
+        client.connect().unwrap();
+
+        assert_eq!(
+            client
+                .list_folders()
+                .unwrap()
+                .into_iter()
+                .map(|folder| folder.name)
+                .collect::<Vec<_>>(),
+            vec!["INBOX", "Archive", "Work.Projects"]
+        );
+
+        let messages = client.fetch_messages("INBOX", None).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].subject, "Maildir test");
+        let new = messages
+            .iter()
+            .find(|m| m.id == "1700000000.1.host")
+            .unwrap();
+        assert!(new.is_unread());
+        let old = messages
+            .iter()
+            .find(|m| m.id == "1700000001.2.host")
+            .unwrap();
+        assert_eq!(old.flags, vec![Flag::Flagged, Flag::Seen]);
+
+        // newにあったメッセージはcurへ移っている
+        assert!(root.join("cur").join("1700000000.1.host:2,").exists());
+
+        client
+            .set_message_flag("INBOX", "1700000000.1.host", &Flag::Seen, true)
+            .unwrap();
+        client
+            .set_message_flag("INBOX", "1700000000.1.host", &Flag::Answered, true)
+            .unwrap();
+        client
+            .set_message_flag("INBOX", "1700000001.2.host", &Flag::Flagged, false)
+            .unwrap();
+        assert!(root.join("cur").join("1700000000.1.host:2,RS").exists());
+        assert!(root.join("cur").join("1700000001.2.host:2,S").exists());
+
+        client
+            .move_message("1700000000.1.host", "INBOX", "Archive")
+            .unwrap();
+        assert!(root
+            .join(".Archive")
+            .join("cur")
+            .join("1700000000.1.host:2,RS")
+            .exists());
+        assert_eq!(client.fetch_messages("INBOX", None).unwrap().len(), 1);
+
+        client
+            .delete_message("Archive", "1700000000.1.host")
+            .unwrap();
+        assert!(matches!(
+            client.delete_message("Archive", "1700000000.1.host"),
+            Err(MailError::NotFound(_))
+        ));
+        assert!(client.folder_path("../etc").is_err());
+
+        fs::remove_dir_all(&root).ok();
+    }
+}
@@ -1,6 +1,9 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+/// IMAPで本文を省いて一覧を取得したメッセージの仮の本文
+pub const BODY_PLACEHOLDER: &str = "本文を読み込み中...";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: String,
@@ -15,6 +18,24 @@ pub struct Message {
     pub account_id: String,
     pub folder: String,
     pub attachments: Vec<Attachment>,
+    /// Gmailのスレッド（会話）ID
+    #[serde(default)]
+    pub thread_id: Option<String>,
+    /// Message-IDヘッダー（山括弧なし。スレッドの組み立てに使う）
+    #[serde(default)]
+    pub message_id: Option<String>,
+    /// In-Reply-Toヘッダーの最初のMessage-ID（山括弧なし）
+    #[serde(default)]
+    pub in_reply_to: Option<String>,
+    /// Referencesヘッダーに並ぶMessage-ID（古い順、山括弧なし）
+    #[serde(default)]
+    pub references: Vec<String>,
+    /// 取得できた場合の元のRFC 822メッセージ
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<Vec<u8>>,
+    /// 本文をまだ取得していないか（IMAPの一覧では本文を省いて取得する）
+    #[serde(default)]
+    pub body_pending: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -121,9 +142,30 @@ impl Message {
             account_id,
             folder,
             attachments: Vec::new(),
+            thread_id: None,
+            message_id: None,
+            in_reply_to: None,
+            references: Vec::new(),
+            raw: None,
+            body_pending: false,
         }
     }
 
+    /// Message-ID・In-Reply-To・Referencesのヘッダーの値を設定する
+    ///
+    /// 値は `<a@example.com> <b@example.com>` のような生のヘッダーでよい。
+    pub fn set_thread_headers(
+        &mut self,
+        message_id: Option<&str>,
+        in_reply_to: Option<&str>,
+        references: Option<&str>,
+    ) {
+        self.message_id = message_id.and_then(|value| parse_message_ids(value).into_iter().next());
+        self.in_reply_to =
+            in_reply_to.and_then(|value| parse_message_ids(value).into_iter().next());
+        self.references = references.map(parse_message_ids).unwrap_or_default();
+    }
+
     pub fn is_unread(&self) -> bool {
         !self.flags.contains(&Flag::Seen)
     }
@@ -232,3 +274,17 @@ impl Attachment {
         }
     }
 }
+
+/// ヘッダーの値からMessage-IDを取り出す（`<...>` の中身。山括弧がなければ空白区切りの語）
+pub fn parse_message_ids(value: &str) -> Vec<String> {
+    if !value.contains('<') {
+        return value.split_whitespace().map(str::to_string).collect();
+    }
+    value
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(id, _)| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect()
+}
@@ -0,0 +1,311 @@
+use chrono::{TimeZone, Utc};
+use lettre::address::Envelope;
+use lettre::message::header::ContentType;
+use lettre::message::{Mailbox, MultiPart, SinglePart};
+use mail_parser::{MessageParser, MimeHeaders};
+use std::time::SystemTime;
+
+use super::{Address, Attachment, MailError, MailResult, Message, MessageBody, MessagePart};
+
+/// RFC 5322形式の生メッセージを `Message` に変換
+///
+/// Maildirなどサーバーを介さずに読むメッセージ用。元のバイト列は `raw` に残す。
+pub fn parse_message(
+    raw: &[u8],
+    id: String,
+    account_id: &str,
+    folder: &str,
+) -> MailResult<Message> {
+    let parsed = MessageParser::default()
+        .parse(raw)
+        .ok_or_else(|| MailError::Parse(format!("Invalid message: {}", id)))?;
+
+    let addresses = |address: Option<&mail_parser::Address>| -> Vec<Address> {
+        address
+            .map(|address| {
+                address
+                    .iter()
+                    .filter_map(|addr| {
+                        Some(Address::new(
+                            addr.address()?.to_string(),
+                            addr.name().map(|name| name.to_string()),
+                        ))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    // text/plainとtext/htmlの代替パートがあれば両方、片方しかなければその形式で保持
+    let part_text = |id: Option<&usize>| -> Option<(bool, String)> {
+        let part = parsed.part(*id?)?;
+        Some((part.is_text_html(), part.text_contents()?.to_string()))
+    };
+    let body = match (
+        part_text(parsed.text_body.first()),
+        part_text(parsed.html_body.first()),
+    ) {
+        (Some((false, text)), Some((true, html))) => MessageBody::Multipart {
+            parts: vec![
+                MessagePart {
+                    content_type: "text/plain".to_string(),
+                    content: text,
+                    encoding: None,
+                },
+                MessagePart {
+                    content_type: "text/html".to_string(),
+                    content: html,
+                    encoding: None,
+                },
+            ],
+        },
+        (_, Some((true, html))) | (Some((true, html)), _) => MessageBody::new_html(html),
+        (Some((false, text)), _) | (_, Some((false, text))) => MessageBody::new_plain(text),
+        (None, None) => MessageBody::new_plain(String::new()),
+    };
+
+    let mut message = Message::new(
+        id,
+        addresses(parsed.from()),
+        addresses(parsed.to()),
+        parsed.subject().unwrap_or_default().to_string(),
+        body,
+        account_id.to_string(),
+        folder.to_string(),
+    );
+    message.cc = addresses(parsed.cc());
+    message.bcc = addresses(parsed.bcc());
+
+    if let Some(date) = parsed
+        .date()
+        .and_then(|date| Utc.timestamp_opt(date.to_timestamp(), 0).single())
+    {
+        message.date = date;
+    }
+
+    message.attachments = parsed
+        .attachments()
+        .map(|part| {
+            let content_type = part
+                .content_type()
+                .map(|ct| match ct.subtype() {
+                    Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+                    None => ct.ctype().to_string(),
+                })
+                .unwrap_or_else(|| "application/octet-stream".to_string());
+            let data = part.contents().to_vec();
+            Attachment {
+                filename: part.attachment_name().unwrap_or("attachment").to_string(),
+                content_type,
+                size: data.len(),
+                data,
+            }
+        })
+        .collect();
+
+    message.message_id = parsed.message_id().map(str::to_string);
+    message.in_reply_to = parsed
+        .in_reply_to()
+        .as_text_list()
+        .and_then(|ids| ids.first().map(|id| id.to_string()));
+    message.references = text_list(parsed.references());
+
+    message.raw = Some(raw.to_vec());
+
+    Ok(message)
+}
+
+/// 生メッセージのMessage-ID（山括弧なし）
+pub fn message_id(raw: &[u8]) -> Option<String> {
+    MessageParser::default()
+        .parse_headers(raw)?
+        .message_id()
+        .map(|id| id.to_string())
+}
+
+/// ヘッダー部分（IMAPの `BODY[HEADER.FIELDS (REFERENCES)]` など）のReferences
+pub fn references(headers: &[u8]) -> Vec<String> {
+    MessageParser::default()
+        .parse_headers(headers)
+        .map(|parsed| text_list(parsed.references()))
+        .unwrap_or_default()
+}
+
+fn text_list(value: &mail_parser::HeaderValue) -> Vec<String> {
+    value
+        .as_text_list()
+        .map(|ids| ids.into_iter().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// `Message` をRFC 5322形式のバイト列にする
+///
+/// 元のメッセージが残っていればそのまま使う。IMAPのヘッダーだけ取得したものなど
+/// `raw` がない場合は、保存されている項目（本文・宛先・添付ファイル）から組み立て直す。
+pub fn to_rfc5322(message: &Message) -> MailResult<Vec<u8>> {
+    if let Some(raw) = &message.raw {
+        return Ok(raw.clone());
+    }
+
+    let mailboxes = |addresses: &[Address]| -> Vec<Mailbox> {
+        addresses
+            .iter()
+            .filter_map(|addr| Some(Mailbox::new(addr.name.clone(), addr.email.parse().ok()?)))
+            .collect()
+    };
+
+    // 差出人のアドレスが分からないメッセージ（旧スキーマから移行したものなど）も書き出せるようにする
+    let placeholder = || -> lettre::Address { "unknown@unknown.invalid".parse().unwrap() };
+    let from = mailboxes(&message.from)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| {
+            Mailbox::new(
+                message.from.first().and_then(|addr| addr.name.clone()),
+                placeholder(),
+            )
+        });
+
+    let mut builder = lettre::Message::builder()
+        .from(from)
+        .subject(&message.subject)
+        .date(SystemTime::from(message.date))
+        .keep_bcc()
+        // エンベロープは出力に含まれないので、宛先のないメッセージでも作れるようにしておく
+        .envelope(
+            Envelope::new(None, vec![placeholder()])
+                .map_err(|e| MailError::Parse(format!("Invalid envelope: {}", e)))?,
+        );
+    for to in mailboxes(&message.to) {
+        builder = builder.to(to);
+    }
+    for cc in mailboxes(&message.cc) {
+        builder = builder.cc(cc);
+    }
+    for bcc in mailboxes(&message.bcc) {
+        builder = builder.bcc(bcc);
+    }
+
+    let alternative = |parts: &[MessagePart]| -> MultiPart {
+        let mut parts = parts.iter().map(|part| {
+            if part.content_type.starts_with("text/html") {
+                SinglePart::html(part.content.clone())
+            } else {
+                SinglePart::plain(part.content.clone())
+            }
+        });
+        let first = parts
+            .next()
+            .unwrap_or_else(|| SinglePart::plain(String::new()));
+        parts.fold(
+            MultiPart::alternative().singlepart(first),
+            |alternative, part| alternative.singlepart(part),
+        )
+    };
+
+    // 添付ファイルがあるときだけmultipart/mixedで包む
+    let email = if message.attachments.is_empty() {
+        match &message.body {
+            MessageBody::Plain(text) => builder.singlepart(SinglePart::plain(text.clone())),
+            MessageBody::Html(html) => builder.singlepart(SinglePart::html(html.clone())),
+            MessageBody::Multipart { parts } => builder.multipart(alternative(parts)),
+        }
+    } else {
+        let mixed = match &message.body {
+            MessageBody::Plain(text) => {
+                MultiPart::mixed().singlepart(SinglePart::plain(text.clone()))
+            }
+            MessageBody::Html(html) => {
+                MultiPart::mixed().singlepart(SinglePart::html(html.clone()))
+            }
+            MessageBody::Multipart { parts } => MultiPart::mixed().multipart(alternative(parts)),
+        };
+        let mixed = message.attachments.iter().fold(mixed, |mixed, attachment| {
+            let content_type = ContentType::parse(&attachment.content_type)
+                .unwrap_or_else(|_| ContentType::parse("application/octet-stream").unwrap());
+            mixed.singlepart(
+                lettre::message::Attachment::new(attachment.filename.clone())
+                    .body(attachment.data.clone(), content_type),
+            )
+        });
+        builder.multipart(mixed)
+    }
+    .map_err(|e| MailError::Parse(format!("Failed to build message: {}", e)))?;
+
+    Ok(email.formatted())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multipart_with_attachment() {
+        let raw = concat!(
+            "From: =?UTF-8?B?5bGx55Sw?= <yamada@example.com>\r\n",
+            "To: bob@example.com, Carol <carol@example.com>\r\n",
+            "Subject: =?UTF-8?B?6KaL56mN5pu4?=\r\n",
+            "Date: Tue, 14 Nov 2023 22:13:20 +0000\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/mixed; boundary=\"b1\"\r\n",
+            "\r\n",
+            "--b1\r\n",
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "\r\n",
+            "添付をご確認ください\r\n",
+            "--b1\r\n",
+            "Content-Type: application/pdf; name=\"quote.pdf\"\r\n",
+            "Content-Disposition: attachment; filename=\"quote.pdf\"\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "JVBERg==\r\n",
+            "--b1--\r\n",
+        );
+
+        let message = parse_message(raw.as_bytes(), "1".to_string(), "work", "INBOX").unwrap();
+        assert_eq!(message.subject, "見積書");
+        assert_eq!(message.from[0].email, "yamada@example.com");
+        assert_eq!(message.from[0].name.as_deref(), Some("山田"));
+        assert_eq!(message.to.len(), 2);
+        assert_eq!(message.date.timestamp(), 1_700_000_000);
+        assert!(message
+            .body
+            .get_display_content()
+            .contains("添付をご確認ください"));
+        assert_eq!(message.attachments.len(), 1);
+        assert_eq!(message.attachments[0].filename, "quote.pdf");
+        assert_eq!(message.attachments[0].content_type, "application/pdf");
+        assert_eq!(message.attachments[0].data, b"%PDF");
+        assert_eq!(message.raw.as_deref(), Some(raw.as_bytes()));
+    }
+
+    #[test]
+    fn test_rebuilds_message_without_raw() {
+        let mut message = Message::new(
+            "42".to_string(),
+            vec![Address::new(
+                "yamada@example.com".to_string(),
+                Some("山田".to_string()),
+            )],
+            vec![],
+            "見積書".to_string(),
+            MessageBody::new_plain("From here on\n本文".to_string()),
+            "work".to_string(),
+            "INBOX".to_string(),
+        );
+        message.add_attachment(Attachment::new(
+            "quote.pdf".to_string(),
+            "application/pdf".to_string(),
+            b"%PDF".to_vec(),
+        ));
+
+        let raw = to_rfc5322(&message).unwrap();
+        let parsed = parse_message(&raw, "42".to_string(), "work", "INBOX").unwrap();
+        assert_eq!(parsed.subject, "見積書");
+        assert_eq!(parsed.from[0].name.as_deref(), Some("山田"));
+        assert_eq!(parsed.date.timestamp(), message.date.timestamp());
+        assert!(parsed.body.get_display_content().contains("From here on"));
+        assert_eq!(parsed.attachments.len(), 1);
+        assert_eq!(parsed.attachments[0].data, b"%PDF");
+    }
+}
@@ -1,20 +1,35 @@
 pub mod account;
 pub mod client;
+pub mod folder;
 pub mod gmail_api;
 pub mod imap_client;
+pub mod jmap_client;
+pub mod maildir;
 pub mod message;
+pub mod mime;
 pub mod oauth;
+pub mod oauth_callback;
+pub mod pop3_client;
 pub mod smtp_client;
+pub mod thread;
+pub mod token_provider;
 
 pub use account::{Account, AuthMethod, FolderMapping, FolderType, ImapConfig, SmtpConfig};
 pub use client::MailClient;
+pub use folder::{folder_tree, resolve_mappings, Folder, FolderNode, FolderOperation};
 pub use gmail_api::GmailApiClient;
 pub use imap_client::ImapClient;
-pub use message::{Address, Flag, Message, MessageBody};
+pub use jmap_client::JmapClient;
+pub use maildir::MaildirClient;
+pub use message::{Address, Attachment, Flag, Message, MessageBody, MessagePart, BODY_PLACEHOLDER};
 pub use oauth::{
-    GoogleOAuthClient, GoogleOAuthConfig, GoogleTokens, GoogleUserInfo, OAuthFlowManager,
+    GoogleUserInfo, OAuthClient, OAuthConfig, OAuthFlowManager, OAuthProvider, OAuthTokens,
 };
+pub use oauth_callback::{is_headless_session, open_browser, OAuthCallbackServer};
+pub use pop3_client::{Pop3Client, Pop3Summary};
 pub use smtp_client::SmtpClient;
+pub use thread::{build_server_threads, build_threads, ServerThread, Thread, ThreadNode};
+pub use token_provider::TokenProvider;
 
 use std::error::Error;
 use std::fmt;
@@ -30,6 +45,8 @@ pub enum MailError {
     #[allow(dead_code)]
     Io(String),
     Parse(String),
+    /// 操作対象のメッセージやフォルダーがサーバーに存在しない
+    NotFound(String),
 }
 
 impl fmt::Display for MailError {
@@ -40,6 +57,7 @@ impl fmt::Display for MailError {
             MailError::Protocol(msg) => write!(f, "Protocol error: {}", msg),
             MailError::Io(msg) => write!(f, "I/O error: {}", msg),
             MailError::Parse(msg) => write!(f, "Parse error: {}", msg),
+            MailError::NotFound(msg) => write!(f, "Not found: {}", msg),
         }
     }
 }
@@ -1,37 +1,242 @@
 use anyhow::{Context, Result};
 use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
 use oauth2::{
-    basic::BasicClient, AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
-    RedirectUrl, Scope, TokenResponse, TokenUrl,
+    basic::{BasicClient, BasicTokenType},
+    devicecode::StandardDeviceAuthorizationResponse,
+    AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
+    DeviceAuthorizationUrl, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse,
+    TokenUrl,
 };
 use reqwest;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use url::Url;
 
+const DEFAULT_REDIRECT_URI: &str = "http://localhost:8080/oauth/callback";
+
 // Google OAuth2設定
 const GOOGLE_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/auth";
 const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
-const GOOGLE_REDIRECT_URI: &str = "http://localhost:8080/oauth/callback";
+const GOOGLE_DEVICE_AUTH_URL: &str = "https://oauth2.googleapis.com/device/code";
 
 // Gmail API スコープ
 const GMAIL_READONLY_SCOPE: &str = "https://www.googleapis.com/auth/gmail.readonly";
 const GMAIL_MODIFY_SCOPE: &str = "https://www.googleapis.com/auth/gmail.modify";
 const GMAIL_SEND_SCOPE: &str = "https://www.googleapis.com/auth/gmail.send";
 
+// Microsoft identity platform（Microsoft 365 / Outlook.com）
+const MICROSOFT_AUTHORITY: &str = "https://login.microsoftonline.com";
+const MICROSOFT_DEFAULT_TENANT: &str = "common";
+const MICROSOFT_IMAP_SCOPE: &str = "https://outlook.office.com/IMAP.AccessAsUser.All";
+const MICROSOFT_SMTP_SCOPE: &str = "https://outlook.office.com/SMTP.Send";
+const MICROSOFT_OFFLINE_SCOPE: &str = "offline_access";
+
+// Yahoo
+const YAHOO_AUTH_URL: &str = "https://api.login.yahoo.com/oauth2/request_auth";
+const YAHOO_TOKEN_URL: &str = "https://api.login.yahoo.com/oauth2/get_token";
+const YAHOO_MAIL_SCOPE: &str = "mail-w";
+
+// Fastmail
+const FASTMAIL_AUTH_URL: &str = "https://api.fastmail.com/oauth/authorize";
+const FASTMAIL_TOKEN_URL: &str = "https://api.fastmail.com/oauth/refresh";
+const FASTMAIL_IMAP_SCOPE: &str = "https://www.fastmail.com/dev/protocol-imap";
+const FASTMAIL_SMTP_SCOPE: &str = "https://www.fastmail.com/dev/protocol-smtp";
+
+/// OAuth2の認可サーバー
+///
+/// 設定で `provider` を省略した場合は従来どおりGoogleとして扱う
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OAuthProvider {
+    #[default]
+    Google,
+    Microsoft,
+    Yahoo,
+    Fastmail,
+    /// エンドポイントとスコープをすべて設定で指定する
+    Custom,
+}
+
+/// プロバイダーの認可エンドポイントとスコープ
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderEndpoints {
+    pub auth_url: String,
+    pub token_url: String,
+    /// デバイスフローに対応していないプロバイダーはNone
+    pub device_auth_url: Option<String>,
+    pub scopes: Vec<String>,
+    /// 認証URLに追加するパラメータ（リフレッシュトークンの要求など）
+    pub extra_auth_params: Vec<(&'static str, &'static str)>,
+}
+
+impl OAuthProvider {
+    /// リダイレクトURIのループバックのポートを登録時と変えてよいか
+    ///
+    /// Googleのデスクトップアプリは任意のポートを受け付けるが、固定のポートで
+    /// 登録したアプリ（Microsoftや独自のプロバイダーなど）は違うポートを拒否する。
+    pub fn allows_any_loopback_port(&self) -> bool {
+        matches!(self, OAuthProvider::Google)
+    }
+
+    /// 組み込みのプリセット（Customの場合はNone）
+    pub fn preset(&self, tenant: Option<&str>) -> Option<ProviderEndpoints> {
+        let to_strings = |scopes: &[&str]| scopes.iter().map(|s| s.to_string()).collect();
+
+        match self {
+            OAuthProvider::Google => Some(ProviderEndpoints {
+                auth_url: GOOGLE_AUTH_URL.to_string(),
+                token_url: GOOGLE_TOKEN_URL.to_string(),
+                device_auth_url: Some(GOOGLE_DEVICE_AUTH_URL.to_string()),
+                scopes: to_strings(&[GMAIL_READONLY_SCOPE, GMAIL_MODIFY_SCOPE, GMAIL_SEND_SCOPE]),
+                extra_auth_params: vec![("access_type", "offline"), ("prompt", "consent")],
+            }),
+            OAuthProvider::Microsoft => {
+                let tenant = tenant
+                    .filter(|t| !t.is_empty())
+                    .unwrap_or(MICROSOFT_DEFAULT_TENANT);
+                let base = format!("{}/{}/oauth2/v2.0", MICROSOFT_AUTHORITY, tenant);
+                Some(ProviderEndpoints {
+                    auth_url: format!("{}/authorize", base),
+                    token_url: format!("{}/token", base),
+                    device_auth_url: Some(format!("{}/devicecode", base)),
+                    scopes: to_strings(&[
+                        MICROSOFT_IMAP_SCOPE,
+                        MICROSOFT_SMTP_SCOPE,
+                        MICROSOFT_OFFLINE_SCOPE,
+                    ]),
+                    extra_auth_params: Vec::new(),
+                })
+            }
+            OAuthProvider::Yahoo => Some(ProviderEndpoints {
+                auth_url: YAHOO_AUTH_URL.to_string(),
+                token_url: YAHOO_TOKEN_URL.to_string(),
+                device_auth_url: None,
+                scopes: to_strings(&[YAHOO_MAIL_SCOPE]),
+                extra_auth_params: Vec::new(),
+            }),
+            OAuthProvider::Fastmail => Some(ProviderEndpoints {
+                auth_url: FASTMAIL_AUTH_URL.to_string(),
+                token_url: FASTMAIL_TOKEN_URL.to_string(),
+                device_auth_url: None,
+                scopes: to_strings(&[FASTMAIL_IMAP_SCOPE, FASTMAIL_SMTP_SCOPE]),
+                extra_auth_params: Vec::new(),
+            }),
+            OAuthProvider::Custom => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GoogleOAuthConfig {
+pub struct OAuthConfig {
+    #[serde(default)]
+    pub provider: OAuthProvider,
     pub client_id: String,
-    pub client_secret: String,
+    /// 公開クライアント（PKCEのみで認証する）の場合は不要
+    ///
+    /// 設定ファイルには書き込まず、秘密情報の保存先で管理する
+    #[serde(default, skip_serializing)]
+    pub client_secret: Option<String>,
     pub redirect_uri: String,
+    /// ブラウザもローカルのコールバックも使えない環境向けにデバイスフローで認証する
+    #[serde(default)]
+    pub use_device_flow: bool,
+    /// Microsoftのテナント（テナントID・ドメイン・"organizations" など。省略時は "common"）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// 以下はプリセットを上書きする場合、またはCustomプロバイダーで指定する
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_auth_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+}
+
+impl OAuthConfig {
+    /// プリセットに設定の上書きを反映したエンドポイントとスコープ
+    pub fn endpoints(&self) -> Result<ProviderEndpoints> {
+        let mut endpoints = match self.provider.preset(self.tenant.as_deref()) {
+            Some(preset) => preset,
+            None => ProviderEndpoints {
+                auth_url: self
+                    .auth_url
+                    .clone()
+                    .context("auth_url is required for a custom OAuth provider")?,
+                token_url: self
+                    .token_url
+                    .clone()
+                    .context("token_url is required for a custom OAuth provider")?,
+                device_auth_url: None,
+                scopes: Vec::new(),
+                extra_auth_params: Vec::new(),
+            },
+        };
+
+        if let Some(auth_url) = &self.auth_url {
+            endpoints.auth_url = auth_url.clone();
+        }
+        if let Some(token_url) = &self.token_url {
+            endpoints.token_url = token_url.clone();
+        }
+        if let Some(device_auth_url) = &self.device_auth_url {
+            endpoints.device_auth_url = Some(device_auth_url.clone());
+        }
+        if !self.scopes.is_empty() {
+            endpoints.scopes = self.scopes.clone();
+        }
+
+        Ok(endpoints)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct GoogleTokens {
+pub struct OAuthTokens {
     pub access_token: String,
     pub refresh_token: Option<String>,
     pub expires_in: Option<u64>,
     pub token_type: String,
+    /// アクセストークンの失効時刻（取得時刻 + expires_in）
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl OAuthTokens {
+    fn from_response<T: TokenResponse<BasicTokenType>>(token_result: &T) -> Self {
+        let expires_in = token_result.expires_in();
+        Self {
+            access_token: token_result.access_token().secret().clone(),
+            refresh_token: token_result.refresh_token().map(|rt| rt.secret().clone()),
+            expires_in: expires_in.map(|duration| duration.as_secs()),
+            token_type: "Bearer".to_string(),
+            expires_at: expires_in
+                .and_then(|duration| chrono::Duration::from_std(duration).ok())
+                .map(|duration| Utc::now() + duration),
+        }
+    }
+
+    /// 指定した余裕時間以内に失効するかどうか
+    ///
+    /// 失効時刻が不明なトークン（旧形式の設定）は期限切れ扱いにする
+    pub fn expires_within(&self, margin: chrono::Duration) -> bool {
+        match self.expires_at {
+            Some(expires_at) => Utc::now() + margin >= expires_at,
+            None => true,
+        }
+    }
+}
+
+/// デバイス認可リクエストの結果（RFC 8628）
+///
+/// ユーザーには `verification_uri` と `user_code` を提示する
+#[derive(Debug, Clone)]
+pub struct DeviceAuthorization {
+    pub verification_uri: String,
+    pub verification_uri_complete: Option<String>,
+    pub user_code: String,
+    pub expires_in: std::time::Duration,
+    response: StandardDeviceAuthorizationResponse,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,72 +246,137 @@ pub struct GoogleUserInfo {
     pub picture: Option<String>,
 }
 
-pub struct GoogleOAuthClient {
+pub struct OAuthClient {
     oauth_client: BasicClient,
-    config: GoogleOAuthConfig,
+    config: OAuthConfig,
+    endpoints: ProviderEndpoints,
     http_client: reqwest::Client,
 }
 
-impl GoogleOAuthClient {
-    pub fn new(config: GoogleOAuthConfig) -> Result<Self> {
-        let oauth_client = BasicClient::new(
+impl OAuthClient {
+    pub fn new(config: OAuthConfig) -> Result<Self> {
+        let client_secret = config
+            .client_secret
+            .as_ref()
+            .filter(|secret| !secret.is_empty())
+            .map(|secret| ClientSecret::new(secret.clone()));
+
+        let endpoints = config.endpoints()?;
+
+        let mut oauth_client = BasicClient::new(
             ClientId::new(config.client_id.clone()),
-            Some(ClientSecret::new(config.client_secret.clone())),
-            AuthUrl::new(GOOGLE_AUTH_URL.to_string())
+            client_secret,
+            AuthUrl::new(endpoints.auth_url.clone())
                 .context("Invalid authorization endpoint URL")?,
-            Some(
-                TokenUrl::new(GOOGLE_TOKEN_URL.to_string())
-                    .context("Invalid token endpoint URL")?,
-            ),
+            Some(TokenUrl::new(endpoints.token_url.clone()).context("Invalid token endpoint URL")?),
         )
         .set_redirect_uri(
             RedirectUrl::new(config.redirect_uri.clone()).context("Invalid redirect URL")?,
         )
         .set_auth_type(AuthType::RequestBody);
 
+        if let Some(device_auth_url) = &endpoints.device_auth_url {
+            oauth_client = oauth_client.set_device_authorization_url(
+                DeviceAuthorizationUrl::new(device_auth_url.clone())
+                    .context("Invalid device authorization endpoint URL")?,
+            );
+        }
+
         let http_client = reqwest::Client::new();
 
         Ok(Self {
             oauth_client,
             config,
+            endpoints,
             http_client,
         })
     }
 
     /// 認証URLを生成
-    pub fn get_authorization_url(&self) -> (Url, CsrfToken) {
-        self.oauth_client
+    ///
+    /// PKCE（S256）のコードチャレンジを付与し、トークン交換に使うベリファイアを返す
+    pub fn get_authorization_url(&self) -> (Url, CsrfToken, PkceCodeVerifier) {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let mut request = self
+            .oauth_client
             .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new(GMAIL_READONLY_SCOPE.to_string()))
-            .add_scope(Scope::new(GMAIL_MODIFY_SCOPE.to_string()))
-            .add_scope(Scope::new(GMAIL_SEND_SCOPE.to_string()))
-            .add_extra_param("access_type", "offline")
-            .add_extra_param("prompt", "consent")
-            .url()
+            .add_scopes(self.scopes())
+            .set_pkce_challenge(pkce_challenge);
+        for (name, value) in &self.endpoints.extra_auth_params {
+            request = request.add_extra_param(*name, *value);
+        }
+        let (auth_url, csrf_token) = request.url();
+
+        (auth_url, csrf_token, pkce_verifier)
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        self.endpoints
+            .scopes
+            .iter()
+            .map(|scope| Scope::new(scope.clone()))
+            .collect()
+    }
+
+    /// デバイス認可リクエストを送信し、ユーザーコードと確認URLを取得
+    pub async fn start_device_authorization(&self) -> Result<DeviceAuthorization> {
+        let response: StandardDeviceAuthorizationResponse = self
+            .oauth_client
+            .exchange_device_code()
+            .context("This OAuth provider does not support the device flow")?
+            .add_scopes(self.scopes())
+            .request_async(oauth2::reqwest::async_http_client)
+            .await
+            .context("Failed to request device authorization")?;
+
+        Ok(DeviceAuthorization {
+            verification_uri: response.verification_uri().to_string(),
+            verification_uri_complete: response
+                .verification_uri_complete()
+                .map(|uri| uri.secret().clone()),
+            user_code: response.user_code().secret().clone(),
+            expires_in: response.expires_in(),
+            response,
+        })
+    }
+
+    /// ユーザーが認可するまでトークンエンドポイントをポーリング
+    ///
+    /// 間隔はサーバー指定のintervalに従い、slow_downを受けるたびに5秒延ばす
+    pub async fn poll_device_token(
+        &self,
+        authorization: &DeviceAuthorization,
+    ) -> Result<OAuthTokens> {
+        let token_result = self
+            .oauth_client
+            .exchange_device_access_token(&authorization.response)
+            .request_async(oauth2::reqwest::async_http_client, tokio::time::sleep, None)
+            .await
+            .context("Failed to obtain token with device code")?;
+
+        Ok(OAuthTokens::from_response(&token_result))
     }
 
     /// 認証コードをアクセストークンに交換
     pub async fn exchange_code_for_token(
         &self,
         authorization_code: String,
-    ) -> Result<GoogleTokens> {
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<OAuthTokens> {
         let token_result = self
             .oauth_client
             .exchange_code(AuthorizationCode::new(authorization_code))
+            .set_pkce_verifier(pkce_verifier)
             .request_async(oauth2::reqwest::async_http_client)
             .await
             .context("Failed to exchange authorization code for token")?;
 
-        Ok(GoogleTokens {
-            access_token: token_result.access_token().secret().clone(),
-            refresh_token: token_result.refresh_token().map(|rt| rt.secret().clone()),
-            expires_in: token_result.expires_in().map(|duration| duration.as_secs()),
-            token_type: "Bearer".to_string(),
-        })
+        Ok(OAuthTokens::from_response(&token_result))
     }
 
     /// リフレッシュトークンで新しいアクセストークンを取得
-    pub async fn refresh_access_token(&self, refresh_token: String) -> Result<GoogleTokens> {
+    pub async fn refresh_access_token(&self, refresh_token: String) -> Result<OAuthTokens> {
         let refresh_token = oauth2::RefreshToken::new(refresh_token);
 
         let token_result = self
@@ -116,16 +386,22 @@ impl GoogleOAuthClient {
             .await
             .context("Failed to refresh access token")?;
 
-        Ok(GoogleTokens {
-            access_token: token_result.access_token().secret().clone(),
-            refresh_token: token_result.refresh_token().map(|rt| rt.secret().clone()),
-            expires_in: token_result.expires_in().map(|duration| duration.as_secs()),
-            token_type: "Bearer".to_string(),
-        })
+        let mut tokens = OAuthTokens::from_response(&token_result);
+        // Googleなどはリフレッシュ時に新しいリフレッシュトークンを返さないので引き継ぐ
+        // （Microsoftは毎回新しいものを返すのでそちらを使う）
+        if tokens.refresh_token.is_none() {
+            tokens.refresh_token = Some(refresh_token.secret().clone());
+        }
+
+        Ok(tokens)
     }
 
-    /// ユーザー情報を取得
+    /// ユーザー情報を取得（Googleのみ）
     pub async fn get_user_info(&self, access_token: &str) -> Result<GoogleUserInfo> {
+        if self.config.provider != OAuthProvider::Google {
+            anyhow::bail!("User info is only available for Google accounts");
+        }
+
         let response = self
             .http_client
             .get("https://www.googleapis.com/oauth2/v2/userinfo")
@@ -156,8 +432,12 @@ impl GoogleOAuthClient {
         general_purpose::STANDARD.encode(auth_string.as_bytes())
     }
 
-    /// アクセストークンの有効性を検証
+    /// アクセストークンの有効性を検証（Googleのみ）
     pub async fn validate_token(&self, access_token: &str) -> Result<bool> {
+        if self.config.provider != OAuthProvider::Google {
+            anyhow::bail!("Token validation is only available for Google accounts");
+        }
+
         let response = self
             .http_client
             .get("https://www.googleapis.com/oauth2/v1/tokeninfo")
@@ -170,19 +450,35 @@ impl GoogleOAuthClient {
     }
 }
 
-impl Default for GoogleOAuthConfig {
+impl Default for OAuthConfig {
     fn default() -> Self {
         Self {
+            provider: OAuthProvider::Google,
             client_id: "YOUR_GOOGLE_CLIENT_ID".to_string(),
-            client_secret: "YOUR_GOOGLE_CLIENT_SECRET".to_string(),
-            redirect_uri: GOOGLE_REDIRECT_URI.to_string(),
+            client_secret: Some("YOUR_GOOGLE_CLIENT_SECRET".to_string()),
+            redirect_uri: DEFAULT_REDIRECT_URI.to_string(),
+            use_device_flow: false,
+            tenant: None,
+            auth_url: None,
+            token_url: None,
+            device_auth_url: None,
+            scopes: Vec::new(),
         }
     }
 }
 
+/// 開始済みでコールバック待ちの認証フロー
+pub struct PendingFlow {
+    pub csrf_token: CsrfToken,
+    /// PKCEのコードベリファイア（認証コードと一緒にトークンエンドポイントへ送る）
+    pub pkce_verifier: PkceCodeVerifier,
+    /// 認証URLに使ったリダイレクトURI（トークン交換でも同じ値が必要）
+    pub redirect_uri: String,
+}
+
 // OAuth2認証フロー管理
 pub struct OAuthFlowManager {
-    pending_flows: HashMap<String, CsrfToken>,
+    pending_flows: HashMap<String, PendingFlow>,
 }
 
 impl OAuthFlowManager {
@@ -192,21 +488,38 @@ impl OAuthFlowManager {
         }
     }
 
-    pub fn start_flow(&mut self, state: String, csrf_token: CsrfToken) {
-        self.pending_flows.insert(state, csrf_token);
+    pub fn start_flow(
+        &mut self,
+        state: String,
+        csrf_token: CsrfToken,
+        pkce_verifier: PkceCodeVerifier,
+        redirect_uri: String,
+    ) {
+        self.pending_flows.insert(
+            state,
+            PendingFlow {
+                csrf_token,
+                pkce_verifier,
+                redirect_uri,
+            },
+        );
     }
 
-    pub fn validate_and_complete_flow(&mut self, state: &str, received_state: &str) -> Result<()> {
-        let stored_token = self
+    pub fn validate_and_complete_flow(
+        &mut self,
+        state: &str,
+        received_state: &str,
+    ) -> Result<PendingFlow> {
+        let flow = self
             .pending_flows
             .remove(state)
             .context("Invalid or expired OAuth flow")?;
 
-        if stored_token.secret() != received_state {
+        if flow.csrf_token.secret() != received_state {
             anyhow::bail!("CSRF token mismatch");
         }
 
-        Ok(())
+        Ok(flow)
     }
 }
 
@@ -222,8 +535,8 @@ mod tests {
 
     #[test]
     fn test_xoauth2_string_generation() {
-        let config = GoogleOAuthConfig::default();
-        let client = GoogleOAuthClient::new(config).unwrap();
+        let config = OAuthConfig::default();
+        let client = OAuthClient::new(config).unwrap();
 
         let email = "test@gmail.com";
         let access_token = "test_token";
@@ -236,4 +549,105 @@ mod tests {
         assert!(decoded_str.contains("user=test@gmail.com"));
         assert!(decoded_str.contains("auth=Bearer test_token"));
     }
+
+    #[test]
+    fn test_authorization_url_uses_pkce() {
+        let client = OAuthClient::new(OAuthConfig::default()).unwrap();
+        let (auth_url, _csrf_token, pkce_verifier) = client.get_authorization_url();
+
+        let challenge = auth_url
+            .query_pairs()
+            .find(|(key, _)| key == "code_challenge")
+            .map(|(_, value)| value.to_string())
+            .unwrap();
+        let method = auth_url
+            .query_pairs()
+            .find(|(key, _)| key == "code_challenge_method")
+            .map(|(_, value)| value.to_string());
+
+        assert_eq!(method.as_deref(), Some("S256"));
+        assert_eq!(
+            challenge,
+            PkceCodeChallenge::from_code_verifier_sha256(&pkce_verifier).as_str()
+        );
+    }
+
+    #[test]
+    fn test_public_client_without_secret() {
+        let config = OAuthConfig {
+            client_secret: None,
+            ..OAuthConfig::default()
+        };
+        assert!(OAuthClient::new(config).is_ok());
+
+        // 旧形式の設定（client_secretなし）も読み込める
+        let config: OAuthConfig = serde_json::from_str(
+            r#"{"client_id": "id", "redirect_uri": "http://localhost:8080/oauth/callback"}"#,
+        )
+        .unwrap();
+        assert!(config.client_secret.is_none());
+        assert!(!config.use_device_flow);
+        assert_eq!(config.provider, OAuthProvider::Google);
+    }
+
+    #[test]
+    fn test_microsoft_preset_with_tenant() {
+        let config: OAuthConfig = serde_json::from_str(
+            r#"{"provider": "Microsoft", "tenant": "contoso.onmicrosoft.com",
+                "client_id": "id", "redirect_uri": "http://localhost:8080/oauth/callback"}"#,
+        )
+        .unwrap();
+        let endpoints = config.endpoints().unwrap();
+
+        assert_eq!(
+            endpoints.token_url,
+            "https://login.microsoftonline.com/contoso.onmicrosoft.com/oauth2/v2.0/token"
+        );
+        assert!(endpoints.scopes.contains(&MICROSOFT_IMAP_SCOPE.to_string()));
+        assert!(endpoints.scopes.contains(&MICROSOFT_SMTP_SCOPE.to_string()));
+        assert!(endpoints.scopes.contains(&"offline_access".to_string()));
+
+        // Google固有のパラメータは付かない
+        let client = OAuthClient::new(config).unwrap();
+        let (auth_url, _, _) = client.get_authorization_url();
+        assert!(auth_url.as_str().starts_with(
+            "https://login.microsoftonline.com/contoso.onmicrosoft.com/oauth2/v2.0/authorize"
+        ));
+        assert!(!auth_url.query_pairs().any(|(key, _)| key == "access_type"));
+    }
+
+    #[test]
+    fn test_custom_provider_requires_endpoints() {
+        let mut config = OAuthConfig {
+            provider: OAuthProvider::Custom,
+            ..OAuthConfig::default()
+        };
+        assert!(config.endpoints().is_err());
+
+        config.auth_url = Some("https://auth.example.com/authorize".to_string());
+        config.token_url = Some("https://auth.example.com/token".to_string());
+        config.scopes = vec!["mail".to_string()];
+        let endpoints = config.endpoints().unwrap();
+        assert_eq!(endpoints.scopes, vec!["mail".to_string()]);
+        assert!(endpoints.device_auth_url.is_none());
+    }
+
+    #[test]
+    fn test_tokens_expiry() {
+        let mut tokens = OAuthTokens {
+            access_token: "token".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires_in: Some(3600),
+            token_type: "Bearer".to_string(),
+            expires_at: Some(Utc::now() + chrono::Duration::seconds(3600)),
+        };
+        assert!(!tokens.expires_within(chrono::Duration::minutes(5)));
+
+        tokens.expires_at = Some(Utc::now() + chrono::Duration::seconds(60));
+        assert!(tokens.expires_within(chrono::Duration::minutes(5)));
+
+        // 失効時刻が保存されていない旧形式のトークンは更新対象
+        tokens.expires_at = None;
+        assert!(tokens.expires_within(chrono::Duration::minutes(5)));
+    }
 }
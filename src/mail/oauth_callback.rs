@@ -0,0 +1,299 @@
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use url::Url;
+
+const SUCCESS_PAGE: &str =
+    "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Rustmail</title></head>\
+<body><h1>認証が完了しました</h1><p>このタブを閉じてRustmailに戻ってください。</p></body></html>";
+
+/// 接続してからリクエストを送り終えるまでの待ち時間（何も送らない接続で待ち受けが止まらないように）
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+const ERROR_PAGE: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Rustmail</title></head>\
+<body><h1>認証に失敗しました</h1><p>このタブを閉じて、ターミナルのメッセージを確認してください。</p></body></html>";
+
+/// 認可サーバーからリダイレクトで受け取った値
+#[derive(Debug, Clone)]
+pub struct OAuthCallback {
+    pub code: String,
+    pub state: String,
+}
+
+/// OAuth2のリダイレクトを受け取るローカルHTTPリスナー
+///
+/// 設定されたリダイレクトURIのポートが使えない場合、任意のポートを受け付ける
+/// プロバイダーならエフェメラルポートで待ち受ける。その場合は `redirect_uri()` が
+/// 実際に使うURIを返すので、認証URLの生成とトークン交換の両方でそれを使うこと。
+pub struct OAuthCallbackServer {
+    listener: TcpListener,
+    redirect_uri: String,
+    callback_path: String,
+    read_timeout: Duration,
+}
+
+impl OAuthCallbackServer {
+    pub async fn bind(redirect_uri: &str, allow_any_port: bool) -> Result<Self> {
+        let url = Url::parse(redirect_uri).context("Invalid redirect URL")?;
+
+        let host = url.host_str().unwrap_or("localhost");
+        if host != "localhost" && host != "127.0.0.1" {
+            anyhow::bail!("Redirect URI is not a loopback address: {}", redirect_uri);
+        }
+
+        let port = url.port().unwrap_or(80);
+        let listener = match TcpListener::bind(("127.0.0.1", port)).await {
+            Ok(listener) => listener,
+            Err(_) if allow_any_port => TcpListener::bind(("127.0.0.1", 0))
+                .await
+                .context("Failed to bind loopback listener")?,
+            Err(e) => anyhow::bail!(
+                "Port {} of the redirect URI is in use ({}). Free the port or register another redirect URI",
+                port,
+                e
+            ),
+        };
+
+        let actual_port = listener
+            .local_addr()
+            .context("Failed to get listener address")?
+            .port();
+
+        let callback_path = url.path().to_string();
+        let redirect_uri = format!("http://{}:{}{}", host, actual_port, callback_path);
+
+        Ok(Self {
+            listener,
+            redirect_uri,
+            callback_path,
+            read_timeout: REQUEST_READ_TIMEOUT,
+        })
+    }
+
+    /// 実際に待ち受けているリダイレクトURI
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// コールバックを受け取るまで待機
+    pub async fn wait_for_callback(&self, timeout: Duration) -> Result<OAuthCallback> {
+        tokio::time::timeout(timeout, self.accept_callback())
+            .await
+            .context("Timed out waiting for the OAuth2 redirect")?
+    }
+
+    async fn accept_callback(&self) -> Result<OAuthCallback> {
+        loop {
+            let (mut stream, _) = self
+                .listener
+                .accept()
+                .await
+                .context("Failed to accept connection")?;
+
+            let Ok(Some(target)) =
+                tokio::time::timeout(self.read_timeout, read_request_target(&mut stream)).await
+            else {
+                continue;
+            };
+
+            // 不正なリクエスト（ローカルの別のクライアントなど）には400を返して待ち続ける
+            let Ok(url) = Url::parse(&format!("http://localhost{}", target)) else {
+                let _ = write_response(&mut stream, "400 Bad Request", "").await;
+                continue;
+            };
+
+            // favicon等のコールバック以外のリクエストは無視する
+            if url.path() != self.callback_path {
+                let _ = write_response(&mut stream, "404 Not Found", "").await;
+                continue;
+            }
+
+            let param = |name: &str| {
+                url.query_pairs()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.to_string())
+            };
+
+            if let Some(error) = param("error") {
+                let _ = write_response(&mut stream, "400 Bad Request", ERROR_PAGE).await;
+                anyhow::bail!("Authorization was denied: {}", error);
+            }
+
+            match (param("code"), param("state")) {
+                (Some(code), Some(state)) => {
+                    let _ = write_response(&mut stream, "200 OK", SUCCESS_PAGE).await;
+                    return Ok(OAuthCallback { code, state });
+                }
+                // codeもerrorもないものは認可サーバーからのリダイレクトではない
+                _ => {
+                    let _ = write_response(&mut stream, "400 Bad Request", "").await;
+                    continue;
+                }
+            }
+        }
+    }
+}
+
+/// リクエスト行からリクエストターゲット（パスとクエリ）を取り出す
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+
+    while !buffer.windows(4).any(|w| w == b"\r\n\r\n") && buffer.len() < 8192 {
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&buffer);
+    let mut parts = request.lines().next()?.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Some(target.to_string()),
+        _ => None,
+    }
+}
+
+async fn write_response(stream: &mut TcpStream, status: &str, body: &str) -> Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// ブラウザもループバックのコールバックも使えない環境かどうか
+///
+/// SSH越しの場合はブラウザが開けてもリダイレクトがこのマシンに届かない
+pub fn is_headless_session() -> bool {
+    if std::env::var_os("SSH_CONNECTION").is_some() || std::env::var_os("SSH_TTY").is_some() {
+        return true;
+    }
+
+    cfg!(target_os = "linux")
+        && std::env::var_os("DISPLAY").is_none()
+        && std::env::var_os("WAYLAND_DISPLAY").is_none()
+}
+
+/// 既定のブラウザでURLを開く（開けなかった場合はfalse）
+pub fn open_browser(url: &str) -> bool {
+    if is_headless_session() {
+        return false;
+    }
+
+    let result = if cfg!(target_os = "macos") {
+        std::process::Command::new("open").arg(url).spawn()
+    } else if cfg!(target_os = "windows") {
+        std::process::Command::new("cmd")
+            .args(["/C", "start", "", url])
+            .spawn()
+    } else {
+        std::process::Command::new("xdg-open").arg(url).spawn()
+    };
+
+    result.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_receives_code_and_state() {
+        let server = OAuthCallbackServer::bind("http://localhost:0/oauth/callback", false)
+            .await
+            .unwrap();
+        let redirect_uri = Url::parse(server.redirect_uri()).unwrap();
+        let port = redirect_uri.port().unwrap();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            stream
+                .write_all(
+                    b"GET /oauth/callback?code=abc&state=xyz HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        });
+
+        let callback = server
+            .wait_for_callback(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(callback.code, "abc");
+        assert_eq!(callback.state, "xyz");
+
+        let response = client.await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("このタブを閉じて"));
+    }
+
+    #[tokio::test]
+    async fn test_busy_port_falls_back_only_when_allowed() {
+        let busy = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = busy.local_addr().unwrap().port();
+        let redirect_uri = format!("http://localhost:{}/oauth/callback", port);
+
+        // 登録したポートしか受け付けないプロバイダーでは別のポートにしない
+        let error = OAuthCallbackServer::bind(&redirect_uri, false)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains(&port.to_string()));
+
+        let server = OAuthCallbackServer::bind(&redirect_uri, true)
+            .await
+            .unwrap();
+        let actual_port = Url::parse(server.redirect_uri()).unwrap().port().unwrap();
+        assert_ne!(actual_port, port);
+    }
+
+    #[tokio::test]
+    async fn test_ignores_silent_and_malformed_requests() {
+        let mut server = OAuthCallbackServer::bind("http://localhost:0/oauth/callback", false)
+            .await
+            .unwrap();
+        server.read_timeout = Duration::from_millis(200);
+        let port = Url::parse(server.redirect_uri()).unwrap().port().unwrap();
+
+        let client = tokio::spawn(async move {
+            // 何も送らない接続
+            let _silent = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+
+            let mut malformed = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            malformed
+                .write_all(b"GET :99999999 HTTP/1.1\r\n\r\n")
+                .await
+                .unwrap();
+
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            stream
+                .write_all(b"GET /oauth/callback?code=abc&state=xyz HTTP/1.1\r\n\r\n")
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+            malformed.read_to_string(&mut response).await.unwrap();
+            response
+        });
+
+        let callback = server
+            .wait_for_callback(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(callback.code, "abc");
+        assert!(client
+            .await
+            .unwrap()
+            .starts_with("HTTP/1.1 400 Bad Request"));
+    }
+}
@@ -0,0 +1,488 @@
+use async_native_tls::{TlsConnector, TlsStream};
+use chrono::{DateTime, Utc};
+use futures::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use std::collections::HashMap;
+use tokio::net::TcpStream;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+use super::{mime, Account, AuthMethod, MailError, MailResult, Message};
+
+/// 接続・応答を待つ時間
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// 平文・TLS・STLS後の接続をまとめて扱う
+trait Pop3Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Pop3Stream for T {}
+
+/// POP3で受信するクライアント
+///
+/// POP3はセッション中メールドロップをロックし、新着も反映されないので、
+/// 接続は保持せずに受信のたびにログインする。
+pub struct Pop3Client {
+    account: Account,
+}
+
+/// 1回の受信の結果
+#[derive(Debug, Default)]
+pub struct Pop3Summary {
+    pub downloaded: usize,
+    pub deleted: usize,
+    /// 解析できずに飛ばした件数（サーバーに残し、次回また試す）
+    pub unparsable: usize,
+    /// 受信後もサーバーに残っているメッセージのUIDL
+    pub on_server: Vec<String>,
+}
+
+impl Pop3Client {
+    pub fn new(account: Account) -> Self {
+        Self { account }
+    }
+
+    /// ログインできるか確認する
+    pub async fn connect(&self) -> MailResult<()> {
+        let session = self.open().await?;
+        session.quit().await
+    }
+
+    /// 未取得のメッセージを受信箱へダウンロードし、設定に従ってサーバーから削除する
+    ///
+    /// `known` はこれまでに取得したUIDLと取得日時。`store` が保存に成功したメッセージだけを
+    /// 取得済みとして扱う。DELEはQUITで確定するので、途中で失敗した場合は何も削除されない。
+    pub async fn download<F>(
+        &self,
+        known: &HashMap<String, DateTime<Utc>>,
+        mut store: F,
+    ) -> MailResult<Pop3Summary>
+    where
+        F: FnMut(&str, &Message) -> MailResult<()>,
+    {
+        let pop3 = self.account.pop3.clone().unwrap_or_default();
+        let folder = self.account.get_inbox_folder();
+
+        let mut session = self.open().await?;
+        let listing = session.uidl().await?;
+
+        let now = Utc::now();
+        let mut downloaded_at = known.clone();
+        let mut summary = Pop3Summary::default();
+
+        for (number, uidl) in &listing {
+            if known.contains_key(uidl) {
+                continue;
+            }
+
+            let raw = session.retr(*number).await?;
+            let message = match mime::parse_message(&raw, uidl.clone(), &self.account.id, &folder) {
+                Ok(message) => message,
+                Err(_) => {
+                    // 取得済みにしないので、サーバーには残り次回また試す
+                    summary.unparsable += 1;
+                    continue;
+                }
+            };
+            store(uidl, &message)?;
+            downloaded_at.insert(uidl.clone(), now);
+            summary.downloaded += 1;
+        }
+
+        for (number, uidl) in &listing {
+            let delete = downloaded_at
+                .get(uidl)
+                .is_some_and(|at| pop3.should_delete(*at, now));
+            if delete {
+                session.dele(*number).await?;
+                summary.deleted += 1;
+            } else {
+                summary.on_server.push(uidl.clone());
+            }
+        }
+
+        session.quit().await?;
+        Ok(summary)
+    }
+
+    /// 接続してログインしたセッションを返す
+    async fn open(&self) -> MailResult<Pop3Session> {
+        let config = &self.account.imap;
+        let pop3 = self.account.pop3.clone().unwrap_or_default();
+
+        if config.auth_method == AuthMethod::OAuth2 {
+            return Err(MailError::Authentication(
+                "OAuth2 is not supported for POP3".to_string(),
+            ));
+        }
+
+        let tcp_stream = tokio::time::timeout(
+            TIMEOUT,
+            TcpStream::connect((config.server.as_str(), config.port)),
+        )
+        .await
+        .map_err(|_| MailError::Connection("TCP connection timeout (30 seconds)".to_string()))?
+        .map_err(|e| MailError::Connection(format!("TCP connection failed: {}", e)))?;
+
+        let stream: Box<dyn Pop3Stream> = if config.use_tls {
+            Box::new(tls(&config.server, tcp_stream.compat()).await?)
+        } else {
+            Box::new(tcp_stream.compat())
+        };
+
+        let mut session = Pop3Session::new(stream);
+        let greeting = session.response().await?;
+
+        if !config.use_tls && config.use_starttls {
+            session.command("STLS").await?;
+            session = session.starttls(&config.server).await?;
+        }
+
+        let login_failed = |e: MailError| match e {
+            MailError::Protocol(msg) => MailError::Authentication(format!("Login failed: {}", msg)),
+            e => e,
+        };
+
+        if pop3.apop {
+            let timestamp = apop_timestamp(&greeting).ok_or_else(|| {
+                MailError::Authentication("Server does not support APOP".to_string())
+            })?;
+            session
+                .command(&format!(
+                    "APOP {} {}",
+                    config.username,
+                    apop_digest(timestamp, &config.password)
+                ))
+                .await
+                .map_err(login_failed)?;
+        } else {
+            session
+                .command(&format!("USER {}", config.username))
+                .await
+                .map_err(login_failed)?;
+            session
+                .command(&format!("PASS {}", config.password))
+                .await
+                .map_err(login_failed)?;
+        }
+
+        Ok(session)
+    }
+}
+
+struct Pop3Session {
+    stream: BufReader<Box<dyn Pop3Stream>>,
+}
+
+impl Pop3Session {
+    fn new(stream: Box<dyn Pop3Stream>) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    /// STLSの応答後にTLSへ切り替える
+    async fn starttls(self, server: &str) -> MailResult<Self> {
+        let stream = self.stream.into_inner();
+        Ok(Self::new(Box::new(tls(server, stream).await?)))
+    }
+
+    async fn read_line(&mut self) -> MailResult<Vec<u8>> {
+        let mut line = Vec::new();
+        let read = tokio::time::timeout(TIMEOUT, self.stream.read_until(b'\n', &mut line))
+            .await
+            .map_err(|_| MailError::Connection("POP3 response timeout".to_string()))?
+            .map_err(|e| MailError::Connection(format!("POP3 read failed: {}", e)))?;
+        if read == 0 {
+            return Err(MailError::Connection(
+                "POP3 server closed the connection".to_string(),
+            ));
+        }
+        Ok(line)
+    }
+
+    /// 1行の応答を読み、`+OK` に続く文字列を返す（`-ERR` はProtocolエラー）
+    async fn response(&mut self) -> MailResult<String> {
+        let line = self.read_line().await?;
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        match line.strip_prefix("+OK") {
+            Some(rest) => Ok(rest.trim_start().to_string()),
+            None => Err(MailError::Protocol(line)),
+        }
+    }
+
+    async fn command(&mut self, command: &str) -> MailResult<String> {
+        self.stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await
+            .map_err(|e| MailError::Connection(format!("POP3 write failed: {}", e)))?;
+        self.stream
+            .flush()
+            .await
+            .map_err(|e| MailError::Connection(format!("POP3 write failed: {}", e)))?;
+        self.response().await
+    }
+
+    /// `.` だけの行までの複数行応答を、行頭のドットの重ねを外して読む
+    async fn multiline(&mut self) -> MailResult<Vec<u8>> {
+        let mut data = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            if line == b".\r\n" || line == b".\n" {
+                return Ok(data);
+            }
+            match line.strip_prefix(b".") {
+                Some(unstuffed) => data.extend_from_slice(unstuffed),
+                None => data.extend_from_slice(&line),
+            }
+        }
+    }
+
+    /// メッセージ番号とUIDLの一覧
+    async fn uidl(&mut self) -> MailResult<Vec<(u32, String)>> {
+        self.command("UIDL").await.map_err(|e| match e {
+            MailError::Protocol(msg) => {
+                MailError::Protocol(format!("Server does not support UIDL: {}", msg))
+            }
+            e => e,
+        })?;
+
+        let listing = self.multiline().await?;
+        Ok(String::from_utf8_lossy(&listing)
+            .lines()
+            .filter_map(|line| {
+                let (number, uidl) = line.trim().split_once(' ')?;
+                Some((number.parse().ok()?, uidl.trim().to_string()))
+            })
+            .collect())
+    }
+
+    async fn retr(&mut self, number: u32) -> MailResult<Vec<u8>> {
+        self.command(&format!("RETR {}", number)).await?;
+        self.multiline().await
+    }
+
+    async fn dele(&mut self, number: u32) -> MailResult<()> {
+        self.command(&format!("DELE {}", number)).await.map(|_| ())
+    }
+
+    /// DELEしたメッセージはQUITで初めて削除される
+    async fn quit(mut self) -> MailResult<()> {
+        self.command("QUIT").await.map(|_| ())
+    }
+}
+
+async fn tls<S>(server: &str, stream: S) -> MailResult<TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::time::timeout(TIMEOUT, TlsConnector::new().connect(server, stream))
+        .await
+        .map_err(|_| MailError::Connection("TLS connection timeout (30 seconds)".to_string()))?
+        .map_err(|e| MailError::Connection(format!("TLS connection failed: {}", e)))
+}
+
+/// 挨拶の `<...>` 部分（APOPのタイムスタンプ）
+fn apop_timestamp(greeting: &str) -> Option<&str> {
+    let start = greeting.find('<')?;
+    let end = start + greeting[start..].find('>')?;
+    Some(&greeting[start..=end])
+}
+
+fn apop_digest(timestamp: &str, password: &str) -> String {
+    format!("{:x}", md5::compute(format!("{}{}", timestamp, password)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::account::Pop3Config;
+    use chrono::Duration;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt as _, AsyncWriteExt as _, BufReader as TokioBufReader};
+    use tokio::net::TcpListener;
+
+    /// RFC 1939の例
+    const GREETING: &str = "+OK POP3 server ready <1896.697170952@dbc.mtview.ca.us>";
+    const PASSWORD: &str = "tanstaaf";
+    const DIGEST: &str = "c4c9334bac560ecc979e58001b3e22fb";
+
+    /// テスト用のPOP3サーバー（QUITでDELEを確定する）
+    async fn serve(listener: TcpListener, maildrop: Arc<Mutex<Vec<(String, String)>>>) {
+        while let Ok((stream, _)) = listener.accept().await {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = TokioBufReader::new(reader).lines();
+            let mut deleted = Vec::new();
+            writer
+                .write_all(format!("{}\r\n", GREETING).as_bytes())
+                .await
+                .unwrap();
+
+            while let Ok(Some(line)) = lines.next_line().await {
+                let (command, argument) = line.split_once(' ').unwrap_or((&line, ""));
+                let messages = maildrop.lock().unwrap().clone();
+                let reply = match command {
+                    "USER" => "+OK\r\n".to_string(),
+                    "PASS" if argument == PASSWORD => "+OK logged in\r\n".to_string(),
+                    "APOP" if argument == format!("me {}", DIGEST) => {
+                        "+OK logged in\r\n".to_string()
+                    }
+                    "PASS" | "APOP" => "-ERR invalid password\r\n".to_string(),
+                    "UIDL" => {
+                        let mut reply = "+OK\r\n".to_string();
+                        for (index, (uidl, _)) in messages.iter().enumerate() {
+                            reply.push_str(&format!("{} {}\r\n", index + 1, uidl));
+                        }
+                        reply + ".\r\n"
+                    }
+                    "RETR" => {
+                        let (_, raw) = &messages[argument.parse::<usize>().unwrap() - 1];
+                        let mut reply = "+OK\r\n".to_string();
+                        for line in raw.split("\r\n") {
+                            if line.starts_with('.') {
+                                reply.push('.');
+                            }
+                            reply.push_str(line);
+                            reply.push_str("\r\n");
+                        }
+                        reply + ".\r\n"
+                    }
+                    "DELE" => {
+                        deleted.push(argument.parse::<usize>().unwrap() - 1);
+                        "+OK\r\n".to_string()
+                    }
+                    "QUIT" => {
+                        let mut index = 0;
+                        maildrop.lock().unwrap().retain(|_| {
+                            index += 1;
+                            !deleted.contains(&(index - 1))
+                        });
+                        writer.write_all(b"+OK bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => "-ERR unknown command\r\n".to_string(),
+                };
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        }
+    }
+
+    fn raw(subject: &str) -> (String, String) {
+        (
+            format!("uidl-{}", subject),
+            format!(
+                "From: alice@example.com\r\nSubject: {}\r\n\r\nHello\r\n.dotted line\r\n",
+                subject
+            ),
+        )
+    }
+
+    #[test]
+    fn test_apop_digest() {
+        assert_eq!(
+            apop_digest("<1896.697170952@dbc.mtview.ca.us>", PASSWORD),
+            DIGEST
+        );
+        assert_eq!(
+            apop_timestamp(GREETING),
+            Some("<1896.697170952@dbc.mtview.ca.us>")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_tracks_uidls_and_deletes_old_messages() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let maildrop = Arc::new(Mutex::new(vec![raw("one"), raw("two")]));
+        tokio::spawn(serve(listener, maildrop.clone()));
+
+        let mut account = Account {
+            pop3: Some(Pop3Config {
+                apop: true,
+                leave_on_server_days: Some(7),
+                delete_after_download: false,
+            }),
+            ..Account::default()
+        };
+        account.imap.server = "127.0.0.1".to_string();
+        account.imap.port = port;
+        account.imap.use_tls = false;
+        account.imap.use_starttls = false;
+        account.imap.username = "me".to_string();
+        account.imap.password = PASSWORD.to_string();
+
+        // 初回はすべて取得し、サーバーに残す
+        let mut stored = Vec::new();
+        let summary = Pop3Client::new(account.clone())
+            .download(&HashMap::new(), |uidl, message| {
+                stored.push((uidl.to_string(), message.clone()));
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(summary.downloaded, 2);
+        assert_eq!(summary.deleted, 0);
+        assert_eq!(summary.on_server, vec!["uidl-one", "uidl-two"]);
+        assert_eq!(stored[0].1.id, "uidl-one");
+        assert_eq!(stored[0].1.folder, "INBOX");
+        assert!(stored[0].1.is_unread());
+        assert!(stored[1]
+            .1
+            .body
+            .get_display_content()
+            .contains("\n.dotted line"));
+
+        // 取得済みのものは再取得せず、保持期間を過ぎたものだけ削除する
+        maildrop.lock().unwrap().push(raw("three"));
+        let known = HashMap::from([
+            ("uidl-one".to_string(), Utc::now() - Duration::days(8)),
+            ("uidl-two".to_string(), Utc::now() - Duration::days(1)),
+        ]);
+        let mut stored = Vec::new();
+        let summary = Pop3Client::new(account.clone())
+            .download(&known, |uidl, _| {
+                stored.push(uidl.to_string());
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(stored, vec!["uidl-three"]);
+        assert_eq!(summary.deleted, 1);
+        assert_eq!(summary.on_server, vec!["uidl-two", "uidl-three"]);
+        let remaining: Vec<String> = maildrop
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|m| m.0.clone())
+            .collect();
+        assert_eq!(remaining, vec!["uidl-two", "uidl-three"]);
+
+        // 保存に失敗した場合は何も削除しない
+        account.pop3 = Some(Pop3Config {
+            delete_after_download: true,
+            ..Pop3Config::default()
+        });
+        maildrop.lock().unwrap().push(raw("four"));
+        let known = HashMap::from([
+            ("uidl-two".to_string(), Utc::now()),
+            ("uidl-three".to_string(), Utc::now()),
+        ]);
+        let result = Pop3Client::new(account.clone())
+            .download(&known, |_, _| Err(MailError::Io("disk full".to_string())))
+            .await;
+        assert!(matches!(result, Err(MailError::Io(_))));
+        assert_eq!(maildrop.lock().unwrap().len(), 3);
+
+        // USER/PASSでログインし、取得したものはすぐに削除する
+        let summary = Pop3Client::new(account.clone())
+            .download(&known, |_, _| Ok(()))
+            .await
+            .unwrap();
+        assert_eq!(summary.downloaded, 1);
+        assert_eq!(summary.deleted, 3);
+        assert!(summary.on_server.is_empty());
+        assert!(maildrop.lock().unwrap().is_empty());
+
+        account.imap.password = "wrong".to_string();
+        assert!(matches!(
+            Pop3Client::new(account).connect().await,
+            Err(MailError::Authentication(_))
+        ));
+    }
+}
@@ -3,7 +3,6 @@ use lettre::transport::smtp::client::{Tls, TlsParameters};
 use lettre::{Message as LettreMessage, SmtpTransport, Transport};
 use std::time::{Duration, SystemTime};
 
-use super::oauth::GoogleOAuthClient;
 use super::{Account, AuthMethod, MailError, MailResult, Message};
 
 pub struct SmtpClient {
@@ -91,20 +90,8 @@ impl SmtpClient {
                 MailError::Authentication("No OAuth2 tokens available".to_string())
             })?;
 
-        let oauth_config =
-            self.account.oauth_config.as_ref().ok_or_else(|| {
-                MailError::Authentication("No OAuth2 config available".to_string())
-            })?;
-
-        let oauth_client = GoogleOAuthClient::new(oauth_config.clone()).map_err(|e| {
-            MailError::Authentication(format!("OAuth2 client creation failed: {}", e))
-        })?;
-
-        let xoauth2_string =
-            oauth_client.generate_xoauth2_string(&self.account.email, &tokens.access_token);
-
-        // OAuth2用のカスタム認証
-        let creds = Credentials::new(self.account.email.clone(), xoauth2_string);
+        // XOAUTH2の認証文字列はlettreが組み立てるので、アクセストークンをそのまま渡す
+        let creds = Credentials::new(self.account.email.clone(), tokens.access_token.clone());
 
         Ok(transport_builder
             .credentials(creds)
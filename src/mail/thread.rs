@@ -0,0 +1,638 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{Address, Message};
+
+/// 会話（スレッド）単位でまとめたメッセージ群
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Thread {
+    pub id: String,
+    pub account_id: String,
+    pub snippet: String,
+    /// 古い順に並んだメッセージ
+    pub messages: Vec<Message>,
+}
+
+impl Thread {
+    pub fn new(id: String, account_id: String, mut messages: Vec<Message>) -> Self {
+        messages.sort_by_key(|m| m.date);
+        Self {
+            id,
+            account_id,
+            snippet: String::new(),
+            messages,
+        }
+    }
+
+    pub fn message_count(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// 最初のメッセージの件名をスレッドの件名とする
+    pub fn subject(&self) -> &str {
+        self.messages
+            .first()
+            .map(|m| m.subject.as_str())
+            .unwrap_or("")
+    }
+
+    pub fn latest_date(&self) -> Option<DateTime<Utc>> {
+        self.messages.iter().map(|m| m.date).max()
+    }
+
+    pub fn has_unread(&self) -> bool {
+        self.messages.iter().any(|m| m.is_unread())
+    }
+
+    /// 送信者を登場順に重複なしで返す
+    pub fn participants(&self) -> Vec<&Address> {
+        let mut participants: Vec<&Address> = Vec::new();
+        for message in &self.messages {
+            for address in &message.from {
+                if !participants
+                    .iter()
+                    .any(|p| p.email.eq_ignore_ascii_case(&address.email))
+                {
+                    participants.push(address);
+                }
+            }
+        }
+        participants
+    }
+
+    pub fn get_participants_display(&self) -> String {
+        let participants = self.participants();
+        if participants.is_empty() {
+            return "Unknown Sender".to_string();
+        }
+        participants
+            .iter()
+            .map(|p| p.display_name())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn format_date(&self) -> String {
+        self.latest_date()
+            .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// メッセージ一覧に表示するスレッドの木の節
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadNode {
+    /// 元のメッセージ一覧での位置
+    pub message: usize,
+    /// 返信（古い順）
+    pub children: Vec<ThreadNode>,
+}
+
+impl ThreadNode {
+    /// この節より下にある返信の数
+    pub fn reply_count(&self) -> usize {
+        self.children
+            .iter()
+            .map(|child| 1 + child.reply_count())
+            .sum()
+    }
+
+    fn latest_date(&self, messages: &[Message]) -> DateTime<Utc> {
+        self.children
+            .iter()
+            .map(|child| child.latest_date(messages))
+            .fold(messages[self.message].date, DateTime::max)
+    }
+}
+
+/// メッセージをJWZアルゴリズム（<https://www.jwz.org/doc/threading.html>）でスレッドの木にまとめる
+///
+/// Message-ID・In-Reply-To・Referencesで親子を決め、つながらなかったものは件名
+/// （`Re:` などを除いたもの）が同じスレッドにまとめる。返信は古い順、スレッドは
+/// 最新のメッセージが新しい順に並べる。
+pub fn build_threads(messages: &[Message]) -> Vec<ThreadNode> {
+    let mut threader = Threader {
+        messages,
+        containers: Vec::new(),
+        ids: HashMap::new(),
+    };
+    for index in 0..messages.len() {
+        threader.add_message(index);
+    }
+
+    let roots: Vec<usize> = (0..threader.containers.len())
+        .filter(|&c| threader.containers[c].parent.is_none())
+        .collect();
+    let roots = threader.prune(roots, true);
+    let roots = threader.group_by_subject(roots);
+
+    let mut nodes: Vec<ThreadNode> = roots
+        .into_iter()
+        .filter_map(|root| threader.node(root))
+        .collect();
+    nodes.sort_by_key(|node| std::cmp::Reverse(node.latest_date(messages)));
+    nodes
+}
+
+/// サーバーが組んだスレッドの木（IMAPの `THREAD` 応答）
+///
+/// `uid` がないのは、参照されているだけでフォルダーにないメッセージ。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerThread {
+    pub uid: Option<u32>,
+    pub children: Vec<ServerThread>,
+}
+
+/// サーバーが組んだスレッドの木を、メッセージ一覧の位置を指す木にする
+///
+/// 木にないメッセージ（スレッドを取得した後に届いたものなど）は単独のスレッドにする。
+/// 返信とスレッドの並び順は `build_threads` と同じ。
+pub fn build_server_threads(messages: &[Message], threads: &[ServerThread]) -> Vec<ThreadNode> {
+    let positions: HashMap<u32, usize> = messages
+        .iter()
+        .enumerate()
+        .filter_map(|(index, message)| Some((message.id.parse().ok()?, index)))
+        .collect();
+    let mut placed = vec![false; messages.len()];
+
+    let mut nodes: Vec<ThreadNode> = threads
+        .iter()
+        .filter_map(|thread| server_node(thread, messages, &positions, &mut placed))
+        .collect();
+    nodes.extend(
+        (0..messages.len())
+            .filter(|&index| !placed[index])
+            .map(|message| ThreadNode {
+                message,
+                children: Vec::new(),
+            }),
+    );
+    nodes.sort_by_key(|node| std::cmp::Reverse(node.latest_date(messages)));
+    nodes
+}
+
+fn server_node(
+    thread: &ServerThread,
+    messages: &[Message],
+    positions: &HashMap<u32, usize>,
+    placed: &mut [bool],
+) -> Option<ThreadNode> {
+    let mut children: Vec<ThreadNode> = thread
+        .children
+        .iter()
+        .filter_map(|child| server_node(child, messages, positions, placed))
+        .collect();
+    children.sort_by_key(|child| messages[child.message].date);
+
+    let message = thread
+        .uid
+        .and_then(|uid| positions.get(&uid).copied())
+        .filter(|&index| !placed[index]);
+    match message {
+        Some(message) => {
+            placed[message] = true;
+            Some(ThreadNode { message, children })
+        }
+        None => promote_oldest(children, messages),
+    }
+}
+
+/// メッセージのない節を最も古い子で置き換え、残りの子をその返信にする
+fn promote_oldest(children: Vec<ThreadNode>, messages: &[Message]) -> Option<ThreadNode> {
+    let mut children = children.into_iter();
+    let mut first = children.next()?;
+    first.children.extend(children);
+    first
+        .children
+        .sort_by_key(|child| messages[child.message].date);
+    Some(first)
+}
+
+/// JWZアルゴリズムのコンテナ（メッセージのないものは、参照されただけで手元にないメッセージ）
+#[derive(Default)]
+struct Container {
+    message: Option<usize>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+struct Threader<'a> {
+    messages: &'a [Message],
+    containers: Vec<Container>,
+    ids: HashMap<String, usize>,
+}
+
+impl Threader<'_> {
+    fn add_message(&mut self, index: usize) {
+        let message = &self.messages[index];
+        let container = match message.message_id.as_deref() {
+            Some(id) => {
+                let container = self.container_for(id);
+                if self.containers[container].message.is_some() {
+                    // Message-IDが重複しているものは別のメッセージとして扱う
+                    self.new_container()
+                } else {
+                    container
+                }
+            }
+            None => self.new_container(),
+        };
+        self.containers[container].message = Some(index);
+
+        let mut references = message.references.clone();
+        if let Some(in_reply_to) = &message.in_reply_to {
+            if references.last() != Some(in_reply_to) {
+                references.push(in_reply_to.clone());
+            }
+        }
+        let references: Vec<usize> = references.iter().map(|id| self.container_for(id)).collect();
+
+        // Referencesの並びで親子をつなぐ（すでに親があるものはそのまま）
+        for pair in references.windows(2) {
+            if self.containers[pair[1]].parent.is_none() {
+                self.link(pair[0], pair[1]);
+            }
+        }
+
+        // このメッセージの親は自分のヘッダーが示すものを正とする
+        self.unlink(container);
+        if let Some(&parent) = references.last() {
+            self.link(parent, container);
+        }
+    }
+
+    fn container_for(&mut self, id: &str) -> usize {
+        if let Some(&container) = self.ids.get(id) {
+            return container;
+        }
+        let container = self.new_container();
+        self.ids.insert(id.to_string(), container);
+        container
+    }
+
+    fn new_container(&mut self) -> usize {
+        self.containers.push(Container::default());
+        self.containers.len() - 1
+    }
+
+    /// `ancestor` が `container` 自身か、その祖先か
+    fn is_ancestor(&self, ancestor: usize, container: usize) -> bool {
+        let mut current = Some(container);
+        while let Some(c) = current {
+            if c == ancestor {
+                return true;
+            }
+            current = self.containers[c].parent;
+        }
+        false
+    }
+
+    /// `child` を `parent` の子にする（循環する場合はつながない）
+    fn link(&mut self, parent: usize, child: usize) {
+        if self.is_ancestor(child, parent) {
+            return;
+        }
+        self.unlink(child);
+        self.containers[child].parent = Some(parent);
+        self.containers[parent].children.push(child);
+    }
+
+    fn unlink(&mut self, child: usize) {
+        if let Some(parent) = self.containers[child].parent.take() {
+            self.containers[parent].children.retain(|&c| c != child);
+        }
+    }
+
+    /// メッセージのないコンテナを取り除き、子を親に引き上げる
+    ///
+    /// 最上位では、子が2つ以上あるものはスレッドをまとめるために残す。
+    fn prune(&mut self, siblings: Vec<usize>, root: bool) -> Vec<usize> {
+        let mut kept = Vec::new();
+        for container in siblings {
+            let children = std::mem::take(&mut self.containers[container].children);
+            let children = self.prune(children, false);
+            if self.containers[container].message.is_some() || (root && children.len() > 1) {
+                self.containers[container].children = children;
+                kept.push(container);
+                continue;
+            }
+            let parent = self.containers[container].parent;
+            for &child in &children {
+                self.containers[child].parent = parent;
+            }
+            if root {
+                kept.extend(self.prune(children, true));
+            } else {
+                kept.extend(children);
+            }
+        }
+        kept
+    }
+
+    /// 件名が同じ最上位のスレッドをまとめる
+    fn group_by_subject(&mut self, roots: Vec<usize>) -> Vec<usize> {
+        let mut table: HashMap<String, usize> = HashMap::new();
+        for &root in &roots {
+            let Some((subject, reply)) = self.subject(root) else {
+                continue;
+            };
+            let preferred = match table.get(&subject) {
+                None => true,
+                Some(&existing) => {
+                    let dummy = self.containers[root].message.is_none();
+                    let existing_dummy = self.containers[existing].message.is_none();
+                    let existing_reply = self.subject(existing).is_some_and(|(_, reply)| reply);
+                    (dummy && !existing_dummy) || (!existing_dummy && existing_reply && !reply)
+                }
+            };
+            if preferred {
+                table.insert(subject, root);
+            }
+        }
+
+        let mut grouped = Vec::new();
+        for root in roots {
+            let Some((subject, reply)) = self.subject(root) else {
+                grouped.push(root);
+                continue;
+            };
+            let Some(&other) = table.get(&subject) else {
+                grouped.push(root);
+                continue;
+            };
+            if other == root {
+                grouped.push(root);
+                continue;
+            }
+
+            let dummy = self.containers[root].message.is_none();
+            let other_dummy = self.containers[other].message.is_none();
+            let other_reply = self.subject(other).is_some_and(|(_, reply)| reply);
+            if dummy && other_dummy {
+                for child in std::mem::take(&mut self.containers[root].children) {
+                    self.containers[child].parent = None;
+                    self.link(other, child);
+                }
+            } else if other_dummy || (reply && !other_reply) {
+                self.link(other, root);
+            } else {
+                // どちらも返信（またはどちらも返信でない）なら、まとめるための空のコンテナを作る
+                let parent = self.new_container();
+                self.link(parent, other);
+                self.link(parent, root);
+                table.insert(subject, parent);
+                if let Some(position) = grouped.iter().position(|&c| c == other) {
+                    grouped[position] = parent;
+                }
+            }
+        }
+        grouped.retain(|&c| self.containers[c].parent.is_none());
+        grouped
+    }
+
+    /// まとめるための件名（返信の接頭辞を除いて小文字にしたもの）と、返信かどうか
+    fn subject(&self, container: usize) -> Option<(String, bool)> {
+        let container = &self.containers[container];
+        let message = match container.message {
+            Some(message) => message,
+            None => container
+                .children
+                .iter()
+                .find_map(|&child| self.containers[child].message)?,
+        };
+        let (subject, reply) = base_subject(&self.messages[message].subject);
+        (!subject.is_empty()).then_some((subject, reply))
+    }
+
+    fn node(&self, container: usize) -> Option<ThreadNode> {
+        let mut children: Vec<ThreadNode> = self.containers[container]
+            .children
+            .iter()
+            .filter_map(|&child| self.node(child))
+            .collect();
+        children.sort_by_key(|child| self.messages[child.message].date);
+
+        match self.containers[container].message {
+            Some(message) => Some(ThreadNode { message, children }),
+            None => promote_oldest(children, self.messages),
+        }
+    }
+}
+
+/// 返信・転送の接頭辞（`Re:`・`Fwd[2]:`・`AW:`・`返信:` など）を除いた件名と、除いたかどうか
+fn base_subject(subject: &str) -> (String, bool) {
+    const PREFIXES: &[&str] = &["re", "fwd", "fw", "aw", "sv", "wg", "返信", "転送"];
+
+    let mut rest = subject.trim();
+    let mut reply = false;
+    'strip: loop {
+        for prefix in PREFIXES {
+            let Some(head) = rest.get(..prefix.len()) else {
+                continue;
+            };
+            if !head.eq_ignore_ascii_case(prefix) {
+                continue;
+            }
+            let mut after = &rest[prefix.len()..];
+            // 「Re[2]:」のような返信回数
+            if let Some(counted) = after.strip_prefix('[') {
+                if let Some((count, tail)) = counted.split_once(']') {
+                    if !count.is_empty() && count.chars().all(|c| c.is_ascii_digit()) {
+                        after = tail;
+                    }
+                }
+            }
+            if let Some(tail) = after
+                .trim_start()
+                .strip_prefix(':')
+                .or_else(|| after.trim_start().strip_prefix('：'))
+            {
+                rest = tail.trim_start();
+                reply = true;
+                continue 'strip;
+            }
+        }
+        break;
+    }
+
+    let subject = rest
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    (subject, reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::MessageBody;
+    use chrono::TimeZone;
+
+    fn message(
+        id: &str,
+        subject: &str,
+        minute: i64,
+        in_reply_to: Option<&str>,
+        references: &[&str],
+    ) -> Message {
+        let mut message = Message::new(
+            id.to_string(),
+            Vec::new(),
+            Vec::new(),
+            subject.to_string(),
+            MessageBody::new_plain(String::new()),
+            "work".to_string(),
+            "INBOX".to_string(),
+        );
+        message.date = Utc.timestamp_opt(1_700_000_000 + minute * 60, 0).unwrap();
+        message.message_id = Some(format!("{}@example.com", id));
+        message.in_reply_to = in_reply_to.map(|id| format!("{}@example.com", id));
+        message.references = references
+            .iter()
+            .map(|id| format!("{}@example.com", id))
+            .collect();
+        message
+    }
+
+    /// (件名, 深さ) の並びにする
+    fn flatten(nodes: &[ThreadNode], messages: &[Message], depth: usize) -> Vec<(String, usize)> {
+        nodes
+            .iter()
+            .flat_map(|node| {
+                let mut rows = vec![(messages[node.message].id.clone(), depth)];
+                rows.extend(flatten(&node.children, messages, depth + 1));
+                rows
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_build_threads_links_references_and_subjects() {
+        let messages = vec![
+            // 返信が先に届いても親の下に入る
+            message("c", "Re: 会議", 30, Some("b"), &["a", "b"]),
+            message("a", "会議", 10, None, &[]),
+            message("b", "Re: 会議", 20, Some("a"), &["a"]),
+            message("d", "RE: 会議", 25, None, &["a"]),
+            // 親（x）が手元にない2通は、空のコンテナ経由で古い方の下にまとまる
+            message("y", "Re: 旅行", 5, None, &["x"]),
+            message("z", "Re: 旅行", 50, None, &["x"]),
+            // 参照がなくても件名が同じならまとめる
+            message("f", "Fwd[2]: 請求書", 3, None, &[]),
+            message("e", "請求書", 1, None, &[]),
+            message("g", "単独", 40, None, &[]),
+        ];
+
+        let threads = build_threads(&messages);
+        let rows: Vec<(String, usize)> = flatten(&threads, &messages, 0);
+        let rows: Vec<(&str, usize)> = rows.iter().map(|(id, d)| (id.as_str(), *d)).collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("y", 0),
+                ("z", 1),
+                ("g", 0),
+                ("a", 0),
+                ("b", 1),
+                ("c", 2),
+                ("d", 1),
+                ("e", 0),
+                ("f", 1),
+            ]
+        );
+        assert_eq!(threads[2].reply_count(), 3);
+    }
+
+    #[test]
+    fn test_build_threads_ignores_reference_loops() {
+        let messages = vec![
+            message("a", "ループ", 0, None, &["b"]),
+            message("b", "別件", 1, None, &["a"]),
+        ];
+        let threads = build_threads(&messages);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].reply_count(), 1);
+    }
+
+    #[test]
+    fn test_thread_orders_messages_and_dedups_participants() {
+        let from = |email: &str, name: Option<&str>, minute: i64| {
+            let mut message = message(email, "件名", minute, None, &[]);
+            message.from = vec![Address::new(email.to_string(), name.map(str::to_string))];
+            message
+        };
+        let thread = Thread::new(
+            "t1".to_string(),
+            "work".to_string(),
+            vec![
+                from("bob@example.com", None, 20),
+                from("alice@example.com", Some("Alice"), 10),
+                // 大文字小文字だけが違うアドレスは同じ人
+                from("Alice@Example.com", Some("Alice 2"), 30),
+                from("carol@example.com", Some("Carol"), 40),
+            ],
+        );
+
+        // メッセージは古い順、参加者は（古い順の）登場順で重複なし
+        assert_eq!(thread.messages[0].from[0].email, "alice@example.com");
+        assert_eq!(
+            thread.get_participants_display(),
+            "Alice, bob@example.com, Carol"
+        );
+        assert_eq!(thread.subject(), "件名");
+        assert_eq!(
+            Thread::new("t2".to_string(), "work".to_string(), Vec::new())
+                .get_participants_display(),
+            "Unknown Sender"
+        );
+    }
+
+    #[test]
+    fn test_build_server_threads() {
+        let messages = vec![
+            message("1", "会議", 0, None, &[]),
+            message("2", "Re: 会議", 10, None, &[]),
+            message("3", "Re: 会議", 20, None, &[]),
+            // スレッドを取得した後に届いたメッセージ
+            message("4", "別件", 5, None, &[]),
+        ];
+        let leaf = |uid| ServerThread {
+            uid: Some(uid),
+            children: Vec::new(),
+        };
+        // 親がフォルダーにない `((1 2)(3))`
+        let threads = vec![ServerThread {
+            uid: None,
+            children: vec![
+                ServerThread {
+                    uid: Some(1),
+                    children: vec![leaf(2)],
+                },
+                leaf(3),
+            ],
+        }];
+
+        let nodes = build_server_threads(&messages, &threads);
+        assert_eq!(
+            flatten(&nodes, &messages, 0),
+            vec![
+                ("1".to_string(), 0),
+                ("2".to_string(), 1),
+                ("3".to_string(), 1),
+                ("4".to_string(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_base_subject() {
+        assert_eq!(
+            base_subject("Re: RE[3]: Fwd:  Weekly   Report"),
+            ("weekly report".to_string(), true)
+        );
+        assert_eq!(base_subject("返信：会議"), ("会議".to_string(), true));
+        assert_eq!(base_subject("Review"), ("review".to_string(), false));
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use tokio::sync::Mutex;
+
+use super::oauth::{OAuthClient, OAuthConfig, OAuthTokens};
+use super::{Account, MailError, MailResult};
+
+/// 失効のこの時間前になったらトークンを更新する
+const REFRESH_MARGIN_SECS: i64 = 300;
+
+/// 更新したトークンを保存するためのコールバック（アカウントID, 新しいトークン）
+pub type PersistHook = Box<dyn Fn(&str, &OAuthTokens) + Send + Sync>;
+
+struct TokenEntry {
+    oauth_config: Option<OAuthConfig>,
+    tokens: Option<OAuthTokens>,
+}
+
+/// Gmail API / IMAP / SMTPで共有するOAuth2アクセストークンの供給元
+///
+/// 失効が近づいたトークンや、サーバーに拒否されたトークンをリフレッシュし、
+/// 結果を登録されたフックで設定ファイルに保存する。
+pub struct TokenProvider {
+    entries: Mutex<HashMap<String, TokenEntry>>,
+    /// アカウントごとのリフレッシュ用ロック（同時の更新要求を1回の通信にまとめる）
+    refresh_locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    persist_hook: StdMutex<Option<PersistHook>>,
+}
+
+impl TokenProvider {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            refresh_locks: Mutex::new(HashMap::new()),
+            persist_hook: StdMutex::new(None),
+        }
+    }
+
+    /// トークン更新時に呼ばれる保存処理を設定
+    pub fn set_persist_hook(&self, hook: PersistHook) {
+        if let Ok(mut persist_hook) = self.persist_hook.lock() {
+            *persist_hook = Some(hook);
+        }
+    }
+
+    /// アカウントのOAuth設定とトークンを登録
+    ///
+    /// 既に登録済みの場合はOAuth設定だけを更新し、リフレッシュ済みのトークンは保持する
+    pub async fn register(&self, account: &Account) {
+        let mut entries = self.entries.lock().await;
+        entries
+            .entry(account.id.clone())
+            .and_modify(|entry| {
+                entry.oauth_config = account.oauth_config.clone();
+                if entry.tokens.is_none() {
+                    entry.tokens = account.tokens.clone();
+                }
+            })
+            .or_insert_with(|| TokenEntry {
+                oauth_config: account.oauth_config.clone(),
+                tokens: account.tokens.clone(),
+            });
+    }
+
+    /// 新しく取得したトークンを登録して保存
+    pub async fn set_tokens(&self, account_id: &str, tokens: OAuthTokens) {
+        {
+            let mut entries = self.entries.lock().await;
+            let entry = entries.entry(account_id.to_string()).or_insert(TokenEntry {
+                oauth_config: None,
+                tokens: None,
+            });
+            entry.tokens = Some(tokens.clone());
+        }
+        self.persist(account_id, &tokens);
+    }
+
+    /// 有効なアクセストークンを取得（失効間近なら先にリフレッシュ）
+    pub async fn access_token(&self, account_id: &str) -> MailResult<String> {
+        let (access_token, needs_refresh) = {
+            let entries = self.entries.lock().await;
+            let entry = entries
+                .get(account_id)
+                .ok_or_else(|| MailError::Authentication("Account not registered".to_string()))?;
+
+            let tokens = entry.tokens.as_ref().ok_or_else(|| {
+                MailError::Authentication("No OAuth2 tokens available".to_string())
+            })?;
+
+            let can_refresh = tokens.refresh_token.is_some() && entry.oauth_config.is_some();
+            (
+                tokens.access_token.clone(),
+                can_refresh
+                    && tokens.expires_within(chrono::Duration::seconds(REFRESH_MARGIN_SECS)),
+            )
+        };
+
+        if needs_refresh {
+            return self.refresh(account_id, None).await;
+        }
+        Ok(access_token)
+    }
+
+    /// 期限に関係なくリフレッシュする（401やAUTHENTICATIONFAILEDを受けた場合）
+    pub async fn force_refresh(&self, account_id: &str) -> MailResult<String> {
+        let rejected = {
+            let entries = self.entries.lock().await;
+            entries
+                .get(account_id)
+                .and_then(|entry| entry.tokens.as_ref())
+                .map(|tokens| tokens.access_token.clone())
+        };
+        self.refresh(account_id, Some(rejected.unwrap_or_default()))
+            .await
+    }
+
+    /// トークンをリフレッシュする
+    ///
+    /// 同じアカウントの更新は1つずつ行い、待っている間に他の呼び出しが更新した場合は
+    /// その結果を使う（`rejected` はサーバーに拒否されたトークン、Noneなら期限で判断する）。
+    /// 通信中もトークン一覧のロックは手放すので、他のアカウントのトークン取得は待たせない。
+    async fn refresh(&self, account_id: &str, rejected: Option<String>) -> MailResult<String> {
+        let refresh_lock = self
+            .refresh_locks
+            .lock()
+            .await
+            .entry(account_id.to_string())
+            .or_default()
+            .clone();
+        let _refreshing = refresh_lock.lock().await;
+
+        let (oauth_config, refresh_token) = {
+            let entries = self.entries.lock().await;
+            let entry = entries
+                .get(account_id)
+                .ok_or_else(|| MailError::Authentication("Account not registered".to_string()))?;
+
+            if let Some(tokens) = &entry.tokens {
+                let refreshed = match &rejected {
+                    Some(rejected) => tokens.access_token != *rejected,
+                    None => !tokens.expires_within(chrono::Duration::seconds(REFRESH_MARGIN_SECS)),
+                };
+                if refreshed {
+                    return Ok(tokens.access_token.clone());
+                }
+            }
+
+            let oauth_config = entry
+                .oauth_config
+                .clone()
+                .ok_or_else(|| MailError::Authentication("No OAuth config".to_string()))?;
+            let refresh_token = entry
+                .tokens
+                .as_ref()
+                .and_then(|t| t.refresh_token.clone())
+                .ok_or_else(|| {
+                    MailError::Authentication("No refresh token available".to_string())
+                })?;
+            (oauth_config, refresh_token)
+        };
+
+        let oauth_client = OAuthClient::new(oauth_config).map_err(|e| {
+            MailError::Authentication(format!("OAuth client creation failed: {}", e))
+        })?;
+        let new_tokens = oauth_client
+            .refresh_access_token(refresh_token)
+            .await
+            .map_err(|e| MailError::Authentication(format!("Token refresh failed: {}", e)))?;
+
+        {
+            let mut entries = self.entries.lock().await;
+            if let Some(entry) = entries.get_mut(account_id) {
+                entry.tokens = Some(new_tokens.clone());
+            }
+        }
+        self.persist(account_id, &new_tokens);
+        Ok(new_tokens.access_token)
+    }
+
+    fn persist(&self, account_id: &str, tokens: &OAuthTokens) {
+        if let Ok(persist_hook) = self.persist_hook.lock() {
+            if let Some(hook) = persist_hook.as_ref() {
+                hook(account_id, tokens);
+            }
+        }
+    }
+}
+
+impl Default for TokenProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::{ImapConfig, OAuthProvider, SmtpConfig};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// リクエスト数を数えるトークンエンドポイント（応答は少し遅らせる）
+    async fn token_endpoint() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                let mut buffer = [0u8; 4096];
+                let _ = stream.read(&mut buffer).await;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+
+                let body = format!(
+                    r#"{{"access_token":"new-{}","token_type":"Bearer","expires_in":3600}}"#,
+                    count
+                );
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        (url, requests)
+    }
+
+    async fn provider_with_expired_token(token_url: String) -> TokenProvider {
+        let mut account = Account::new(
+            "work".to_string(),
+            "Work".to_string(),
+            "me@example.com".to_string(),
+            ImapConfig::default(),
+            SmtpConfig::default(),
+        );
+        account.oauth_config = Some(OAuthConfig {
+            provider: OAuthProvider::Custom,
+            client_id: "client".to_string(),
+            auth_url: Some("http://127.0.0.1/authorize".to_string()),
+            token_url: Some(token_url),
+            ..OAuthConfig::default()
+        });
+        account.tokens = Some(OAuthTokens {
+            access_token: "old".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            expires_in: Some(3600),
+            token_type: "Bearer".to_string(),
+            expires_at: Some(chrono::Utc::now()),
+        });
+
+        let provider = TokenProvider::new();
+        provider.register(&account).await;
+        provider
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_refreshes_share_one_request() {
+        let (token_url, requests) = token_endpoint().await;
+        let provider = provider_with_expired_token(token_url).await;
+
+        // 失効間近のトークンを同時に要求しても更新は1回
+        let (first, second) =
+            tokio::join!(provider.access_token("work"), provider.access_token("work"));
+        assert_eq!(first.unwrap(), "new-1");
+        assert_eq!(second.unwrap(), "new-1");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // 同じトークンが同時に拒否された場合も更新は1回
+        let (first, second) = tokio::join!(
+            provider.force_refresh("work"),
+            provider.force_refresh("work")
+        );
+        assert_eq!(first.unwrap(), "new-2");
+        assert_eq!(second.unwrap(), "new-2");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+}
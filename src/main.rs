@@ -1,4 +1,5 @@
 mod app;
+mod cli;
 mod mail;
 mod search;
 mod storage;
@@ -9,6 +10,7 @@ use std::error::Error;
 use std::io;
 use std::time::Duration;
 
+use clap::Parser;
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event},
     execute,
@@ -17,12 +19,21 @@ use crossterm::{
 use ratatui::{backend::CrosstermBackend, Terminal};
 
 use app::App;
-use mail::{Account, AuthMethod, FolderMapping, FolderType, ImapConfig, MailClient, SmtpConfig};
-use storage::Config;
+use cli::Cli;
+use mail::{
+    is_headless_session, open_browser, Account, AuthMethod, FolderMapping, FolderType, ImapConfig,
+    MailClient, OAuthCallbackServer, OAuthConfig, OAuthTokens, SmtpConfig,
+};
+use storage::{Config, Database};
 use ui::render_ui;
 
+/// ブラウザでの認証完了を待つ時間
+const OAUTH_CALLBACK_TIMEOUT_SECS: u64 = 300;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
     // 設定の読み込み
     let mut config = Config::load().unwrap_or_else(|_| {
         eprintln!("設定ファイルの読み込みに失敗しました。デフォルト設定を使用します。");
@@ -34,6 +45,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
         eprintln!("ディレクトリ作成に失敗しました: {}", e);
     }
 
+    // サブコマンド（mboxの取り込みなど）はTUIを開かずに実行する
+    if let Some(command) = cli.command {
+        return cli::run(command, &config).await;
+    }
+
     // デモ用のアカウントを追加（実際のアプリケーションでは設定から読み込み）
     if config.accounts.is_empty() {
         let demo_account = create_demo_account();
@@ -46,11 +62,24 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     // アプリケーション状態を初期化
     let mut app = App::new();
+    app.accounts = config.accounts.clone();
+
+    // ローカルキャッシュを開き、ネットワークを待たずに表示できるようにする
+    match Database::new(config.get_database_file()) {
+        Ok(database) => app.database = Some(database),
+        Err(e) => eprintln!("キャッシュデータベースを開けませんでした: {}", e),
+    }
     app.config = config;
+    app.load_cached_messages();
 
     // メールクライアントを初期化
     let mut mail_client = MailClient::new();
 
+    // リフレッシュされたOAuth2トークンは設定ファイルに書き戻す
+    mail_client
+        .token_provider()
+        .set_persist_hook(Box::new(persist_refreshed_tokens));
+
     // 設定からアカウントを追加
     let accounts_to_add = app.config.accounts.clone();
     for account in &accounts_to_add {
@@ -72,95 +101,43 @@ async fn main() -> Result<(), Box<dyn Error>> {
     for (account_index, account_id, account_email) in oauth_accounts_to_process {
         println!("OAuth2認証が必要です: {}", account_email);
 
-        match mail_client.start_oauth_flow(&account_id).await {
-            Ok(auth_url) => {
-                println!("ブラウザで以下のURLにアクセスして認証を完了してください:");
-                println!("{}", auth_url);
-
-                // 認証コードの手動入力を受け付ける
-                println!("\n認証完了後、以下の情報を入力してください:");
-                println!("1. 認証コード（code=の部分）");
-                println!("2. state値（上記URLに含まれている &state= の部分）");
-                println!("例: 認証コード: 4/0AUJR-x4M5gPe77uV8eRUzOJ-B7xYqdVAMlowxFOoo_KhTIgI_NYbIdxc34lS80Et7_cHFQ");
-                println!("例: state値: jItq_R8pT63cRebOPtmzNQ");
-
-                use std::io::Write;
-                print!("\n認証コード: ");
-                std::io::stdout().flush().unwrap();
-
-                let mut auth_code = String::new();
-                std::io::stdin().read_line(&mut auth_code).unwrap();
-                let auth_code = auth_code.trim().to_string();
-
-                print!("state値: ");
-                std::io::stdout().flush().unwrap();
-
-                let mut state_value = String::new();
-                std::io::stdin().read_line(&mut state_value).unwrap();
-                let state_value = state_value.trim().to_string();
-
-                if !auth_code.is_empty() && !state_value.is_empty() {
-                    // OAuth2コールバックを処理
-                    match mail_client
-                        .handle_oauth_callback(&account_id, auth_code, state_value)
-                        .await
-                    {
-                        Ok(_) => {
-                            println!("OAuth2認証が完了しました！");
-
-                            // 設定ファイルに更新されたアカウント情報を保存
-                            for updated_account in mail_client.get_accounts() {
-                                if updated_account.id == account_id {
-                                    app.config.accounts[account_index] = updated_account.clone();
-                                    println!("デバッグ: アカウント情報を更新しました");
-                                    if let Some(tokens) = &updated_account.tokens {
-                                        println!("デバッグ: OAuth2トークンが見つかりました - アクセストークン: {}...", 
-                                                &tokens.access_token[..std::cmp::min(20, tokens.access_token.len())]);
-                                    } else {
-                                        println!("デバッグ: OAuth2トークンが見つかりません");
-                                    }
-                                    break;
-                                }
-                            }
-                            accounts_updated = true;
-                        }
-                        Err(e) => {
-                            eprintln!("OAuth2認証の処理に失敗しました: {}", e);
-                        }
-                    }
-                } else {
-                    eprintln!("認証コードまたはstate値が入力されませんでした。");
+        let oauth_config = app.config.accounts[account_index].oauth_config.clone();
+        // ブラウザのない環境ではデバイスフローを使う（プロバイダーが対応していなければ
+        // 認証URLを表示し、認証コードを貼り付けてもらう）
+        let has_device_flow = oauth_config
+            .as_ref()
+            .and_then(|c| c.endpoints().ok())
+            .is_some_and(|endpoints| endpoints.device_auth_url.is_some());
+        let use_device_flow = oauth_config.as_ref().is_some_and(|c| c.use_device_flow)
+            || (is_headless_session() && has_device_flow);
+
+        let result = if use_device_flow {
+            authorize_with_device_flow(&mut mail_client, &account_id).await
+        } else {
+            let Some((auth_code, state_value)) =
+                obtain_authorization_code(&mail_client, &account_id, oauth_config.as_ref()).await
+            else {
+                continue;
+            };
+
+            // OAuth2コールバックを処理
+            mail_client
+                .handle_oauth_callback(&account_id, auth_code, state_value)
+                .await
+        };
+
+        match result {
+            Ok(_) => {
+                println!("OAuth2認証が完了しました！");
+
+                // 設定ファイルに更新されたアカウント情報を保存
+                if let Some(updated_account) = mail_client.get_account(&account_id) {
+                    app.config.accounts[account_index] = updated_account.clone();
                 }
+                accounts_updated = true;
             }
             Err(e) => {
-                eprintln!("OAuth2フローの開始に失敗しました: {}", e);
-            }
-        }
-    }
-
-    // 既存のトークンが期限切れでないかチェックして、必要に応じてリフレッシュ
-    for (_account_index, account) in app.config.accounts.iter_mut().enumerate() {
-        if account.imap.auth_method == AuthMethod::OAuth2 && account.tokens.is_some() {
-            println!("既存のOAuth2トークンをチェック中: {}", account.email);
-
-            // トークンのリフレッシュを試行
-            match mail_client.refresh_oauth_token(&account.id).await {
-                Ok(_) => {
-                    println!("OAuth2トークンをリフレッシュしました: {}", account.email);
-
-                    // 更新されたトークンを設定に反映
-                    for updated_account in mail_client.get_accounts() {
-                        if updated_account.id == account.id {
-                            *account = updated_account.clone();
-                            accounts_updated = true;
-                            break;
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!("トークンリフレッシュ警告 ({}): {}", account.email, e);
-                    println!("既存のトークンをそのまま使用します");
-                }
+                eprintln!("OAuth2認証の処理に失敗しました: {}", e);
             }
         }
     }
@@ -189,39 +166,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
-    // デモ用のメッセージを読み込み
-    let first_account = app.config.accounts.first().cloned();
-    if let Some(account) = first_account {
-        // OAuth2アカウントでトークンがない場合はスキップ
-        if account.imap.auth_method == AuthMethod::OAuth2 && account.tokens.is_none() {
-            eprintln!("OAuth2認証が完了していません。認証後に再起動してください。");
-        } else {
-            // IMAP接続を行う（OAuth2トークンが存在する場合も含む）
-            println!("IMAP接続を試行中: {}", account.email);
-            match mail_client.connect_imap(&account.id).await {
-                Ok(_) => {
-                    println!("IMAP接続が成功しました: {}", account.email);
-
-                    // メッセージ取得を試行
-                    match mail_client
-                        .fetch_messages(&account.id, "INBOX", Some(10))
-                        .await
-                    {
-                        Ok(messages) => {
-                            println!("メッセージを {} 件取得しました", messages.len());
-                            app.messages = messages;
-                        }
-                        Err(e) => {
-                            eprintln!("メッセージの読み込みに失敗しました: {}", e);
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("IMAP接続に失敗しました ({}): {}", account.email, e);
-                }
-            }
-        }
-    }
+    // 最初のアカウントの同期はTUIを表示してから行う
+    app.request_sync();
 
     // ターミナルのセットアップ
     enable_raw_mode()?;
@@ -252,12 +198,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
 async fn run_app<B: ratatui::backend::Backend>(
     terminal: &mut Terminal<B>,
     app: &mut App,
-    _mail_client: &mut MailClient,
+    mail_client: &mut MailClient,
 ) -> Result<(), Box<dyn Error>> {
     loop {
         // UIを描画
         terminal.draw(|f| render_ui(f, app))?;
 
+        // 起動時の同期やキー操作で積まれた非同期処理を、描画後に実行する
+        if !app.pending_actions.is_empty() {
+            app.process_pending_actions(mail_client).await;
+            continue;
+        }
+
         // イベントを同期的にポーリング（短いタイムアウト付き）
         if event::poll(Duration::from_millis(100))? {
             match event::read()? {
@@ -276,6 +228,9 @@ async fn run_app<B: ratatui::backend::Backend>(
             }
         }
 
+        // プレビューの本文の取得など、時間で進める処理
+        app.tick();
+
         // 他の非同期タスクに時間を譲る
         tokio::task::yield_now().await;
     }
@@ -283,6 +238,127 @@ async fn run_app<B: ratatui::backend::Backend>(
     Ok(())
 }
 
+/// デバイスフローで認証する（ブラウザのない環境向け）
+///
+/// 別の端末で確認URLを開いてコードを入力してもらい、認可されるまでポーリングする
+async fn authorize_with_device_flow(
+    mail_client: &mut MailClient,
+    account_id: &str,
+) -> mail::MailResult<()> {
+    let authorization = mail_client.start_device_flow(account_id).await?;
+
+    println!("スマートフォンや別のPCのブラウザで以下のURLを開き、コードを入力してください:");
+    println!("  URL:    {}", authorization.verification_uri);
+    println!("  コード: {}", authorization.user_code);
+    if let Some(uri) = &authorization.verification_uri_complete {
+        println!("（コード入力済みのURL: {}）", uri);
+    }
+    println!(
+        "\n認証の完了を待っています（有効期限 {} 分）...",
+        authorization.expires_in.as_secs() / 60
+    );
+
+    mail_client
+        .complete_device_flow(account_id, &authorization)
+        .await
+}
+
+/// ブラウザでの認証を行い、認証コードとstate値を受け取る
+///
+/// ループバックリスナーでリダイレクトを受け取り、待ち受けできない場合や
+/// リダイレクトがこのマシンに届かない環境（SSH越しなど）では手動入力にする
+async fn obtain_authorization_code(
+    mail_client: &MailClient,
+    account_id: &str,
+    oauth_config: Option<&OAuthConfig>,
+) -> Option<(String, String)> {
+    let server = match oauth_config.filter(|_| !is_headless_session()) {
+        Some(oauth_config) => match OAuthCallbackServer::bind(
+            &oauth_config.redirect_uri,
+            oauth_config.provider.allows_any_loopback_port(),
+        )
+        .await
+        {
+            Ok(server) => Some(server),
+            Err(e) => {
+                eprintln!("リダイレクトの待ち受けを開始できませんでした: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let auth_url = match mail_client
+        .start_oauth_flow_with_redirect(account_id, server.as_ref().map(|s| s.redirect_uri()))
+        .await
+    {
+        Ok(auth_url) => auth_url,
+        Err(e) => {
+            eprintln!("OAuth2フローの開始に失敗しました: {}", e);
+            return None;
+        }
+    };
+
+    if open_browser(&auth_url) {
+        println!(
+            "ブラウザで認証ページを開きました。開かない場合は以下のURLにアクセスしてください:"
+        );
+    } else {
+        println!("ブラウザで以下のURLにアクセスして認証を完了してください:");
+    }
+    println!("{}", auth_url);
+
+    let Some(server) = server else {
+        return prompt_authorization_code();
+    };
+
+    println!("\n認証の完了を待っています ({}) ...", server.redirect_uri());
+    match server
+        .wait_for_callback(Duration::from_secs(OAUTH_CALLBACK_TIMEOUT_SECS))
+        .await
+    {
+        Ok(callback) => Some((callback.code, callback.state)),
+        Err(e) => {
+            eprintln!("認証コードを受け取れませんでした: {}", e);
+            None
+        }
+    }
+}
+
+/// 認証コードとstate値を手動で入力してもらう
+fn prompt_authorization_code() -> Option<(String, String)> {
+    println!("\n認証完了後、以下の情報を入力してください:");
+    println!("1. 認証コード（code=の部分）");
+    println!("2. state値（上記URLに含まれている &state= の部分）");
+
+    use std::io::Write;
+    print!("\n認証コード: ");
+    std::io::stdout().flush().ok()?;
+
+    let mut auth_code = String::new();
+    std::io::stdin().read_line(&mut auth_code).ok()?;
+    let auth_code = auth_code.trim().to_string();
+
+    print!("state値: ");
+    std::io::stdout().flush().ok()?;
+
+    let mut state_value = String::new();
+    std::io::stdin().read_line(&mut state_value).ok()?;
+    let state_value = state_value.trim().to_string();
+
+    if auth_code.is_empty() || state_value.is_empty() {
+        eprintln!("認証コードまたはstate値が入力されませんでした。");
+        return None;
+    }
+
+    Some((auth_code, state_value))
+}
+
+fn persist_refreshed_tokens(account_id: &str, tokens: &OAuthTokens) {
+    // TUI表示中にも呼ばれるため、失敗しても画面には出力しない
+    let _ = Config::store_tokens(account_id, tokens);
+}
+
 fn create_demo_account() -> Account {
     // デモ用のアカウントを作成
     let mut account = Account::default();
@@ -294,7 +370,8 @@ fn create_demo_account() -> Account {
         server: "imap.example.com".to_string(),
         port: 993,
         username: "demo@example.com".to_string(),
-        password: "password".to_string(), // 実際の実装では暗号化
+        password: "password".to_string(),
+        password_command: None,
         use_tls: true,
         use_starttls: false,
         auth_method: AuthMethod::Plain,
@@ -327,7 +404,8 @@ fn create_demo_account() -> Account {
         server: "smtp.example.com".to_string(),
         port: 587,
         username: "demo@example.com".to_string(),
-        password: "password".to_string(), // 実際の実装では暗号化
+        password: "password".to_string(),
+        password_command: None,
         use_tls: false,
         use_starttls: true,
         auth_method: AuthMethod::Plain,
@@ -1,7 +1,16 @@
 // 検索機能の実装
 
+mod query;
+mod remote;
+
+pub use query::{Query, Term};
+
 use crate::mail::Message;
 
+/// スニペット中の一致箇所の始まりと終わり（本文に現れない制御文字）
+pub const SNIPPET_START: char = '\u{2}';
+pub const SNIPPET_END: char = '\u{3}';
+
 pub struct SearchEngine {
     // 将来的にインデックスなどを保持
 }
@@ -11,26 +20,17 @@ impl SearchEngine {
         Self {}
     }
 
-    pub fn search<'a>(&self, query: &str, messages: &'a [Message]) -> Vec<&'a Message> {
-        // 簡単なテキスト検索の実装
-        messages
+    /// クエリ言語（`Query`）でメッセージを絞り込む
+    pub fn search<'a>(
+        &self,
+        query: &str,
+        messages: &'a [Message],
+    ) -> Result<Vec<&'a Message>, String> {
+        let query = Query::parse(query)?;
+        Ok(messages
             .iter()
-            .filter(|message| {
-                message
-                    .subject
-                    .to_lowercase()
-                    .contains(&query.to_lowercase())
-                    || message
-                        .body
-                        .get_display_content()
-                        .to_lowercase()
-                        .contains(&query.to_lowercase())
-                    || message
-                        .get_sender_display()
-                        .to_lowercase()
-                        .contains(&query.to_lowercase())
-            })
-            .collect()
+            .filter(|message| query.matches(message))
+            .collect())
     }
 
     pub fn search_by_sender<'a>(&self, sender: &str, messages: &'a [Message]) -> Vec<&'a Message> {
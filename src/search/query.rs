@@ -0,0 +1,781 @@
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use rusqlite::types::Value;
+
+use super::{SNIPPET_END, SNIPPET_START};
+use crate::mail::{Address, Flag, Message};
+
+/// 検索クエリ（`from:alice has:attachment (invoice OR 請求書)` など）
+///
+/// 空白で区切った条件はすべて満たす必要がある（AND）。`AND`・`OR`・`NOT` は大文字で
+/// 書いたときだけ演算子として扱い、`"..."` で囲んだ語句はそのまま1つの検索語になる。
+/// 文字列の比較は大文字小文字を区別しない部分一致。
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Term(Term),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+/// 検索条件1つ
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    /// 件名・本文・差出人・宛先（To/Cc）のいずれか
+    Text(String),
+    From(String),
+    To(String),
+    Cc(String),
+    Subject(String),
+    Body(String),
+    HasAttachment,
+    /// フラグが付いているか（`is:unread` は `Flag(Seen, false)`）
+    Flag(Flag, bool),
+    /// この日より前（当日を含まない、ローカル時刻）
+    Before(NaiveDate),
+    /// この日以降（当日を含む、ローカル時刻）
+    After(NaiveDate),
+    On(NaiveDate),
+    /// `Message::get_size` のバイト数より大きい
+    Larger(usize),
+    Smaller(usize),
+    Folder(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word {
+        field: Option<String>,
+        value: String,
+    },
+}
+
+impl Query {
+    /// クエリ文字列を解析する（空の場合はすべてに一致する）
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            position: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Ok(Query::And(Vec::new()));
+        }
+
+        let query = parser.parse_or()?;
+        match parser.next() {
+            None => Ok(query),
+            Some(Token::Close) => Err("Unmatched ')'".to_string()),
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+        }
+    }
+
+    pub fn matches(&self, message: &Message) -> bool {
+        match self {
+            Query::Term(term) => term.matches(message),
+            Query::And(queries) => queries.iter().all(|query| query.matches(message)),
+            Query::Or(queries) => queries.iter().any(|query| query.matches(message)),
+            Query::Not(query) => !query.matches(message),
+        }
+    }
+
+    /// SQLiteの条件式に変換する
+    ///
+    /// `m` を messages テーブルの別名として参照する。値は `params` の末尾に追加し、
+    /// `?NNN` の番号で参照するので、`params` に先に入っている値の番号はそのまま使える。
+    pub fn to_sql(&self, params: &mut Vec<Value>) -> String {
+        let join = |queries: &[Query], params: &mut Vec<Value>, operator: &str| {
+            let conditions: Vec<String> = queries.iter().map(|q| q.to_sql(params)).collect();
+            format!("({})", conditions.join(operator))
+        };
+
+        match self {
+            Query::Term(term) => term.to_sql(params),
+            Query::And(queries) if queries.is_empty() => "1".to_string(),
+            Query::Or(queries) if queries.is_empty() => "0".to_string(),
+            Query::And(queries) => join(queries, params, " AND "),
+            Query::Or(queries) => join(queries, params, " OR "),
+            Query::Not(query) => format!("NOT {}", query.to_sql(params)),
+        }
+    }
+
+    /// 順位付けとスニペットに使う全文検索の式
+    ///
+    /// NOTの中にない検索語のうち索引で引けるものをORでつないだもの。なければ `None`。
+    pub fn fts_match(&self) -> Option<String> {
+        let mut texts = Vec::new();
+        self.collect_texts(&mut texts);
+        let phrases: Vec<String> = texts.into_iter().filter_map(fts_phrase).collect();
+        (!phrases.is_empty()).then(|| phrases.join(" OR "))
+    }
+
+    /// 検索語の一致箇所を `SNIPPET_START`/`SNIPPET_END` で囲んだ抜粋（本文、なければ件名から）
+    ///
+    /// 索引で引けない短い語（`会議` など）で一致したときに、FTSのスニペットの代わりに使う。
+    pub fn snippet(&self, message: &Message) -> Option<String> {
+        let mut texts = Vec::new();
+        self.collect_texts(&mut texts);
+        if texts.is_empty() {
+            return None;
+        }
+
+        [message.body.get_display_content(), message.subject.clone()]
+            .iter()
+            .find_map(|text| {
+                let chars: Vec<char> = text
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    .chars()
+                    .collect();
+                texts
+                    .iter()
+                    .filter_map(|needle| find_ignore_case(&chars, needle))
+                    .min()
+                    .map(|(start, end)| excerpt(&chars, start, end))
+            })
+    }
+
+    fn collect_texts<'a>(&'a self, texts: &mut Vec<&'a str>) {
+        match self {
+            Query::Term(Term::Text(text)) => texts.push(text),
+            Query::Term(_) | Query::Not(_) => {}
+            Query::And(queries) | Query::Or(queries) => {
+                for query in queries {
+                    query.collect_texts(texts);
+                }
+            }
+        }
+    }
+}
+
+impl Term {
+    fn new(field: Option<&str>, value: String) -> Result<Self, String> {
+        let Some(field) = field else {
+            return Ok(Term::Text(value));
+        };
+
+        let term = match field.to_lowercase().as_str() {
+            "from" => Term::From(value),
+            "to" => Term::To(value),
+            "cc" => Term::Cc(value),
+            "subject" => Term::Subject(value),
+            "body" => Term::Body(value),
+            "folder" | "in" => Term::Folder(value),
+            "has" if value.eq_ignore_ascii_case("attachment") => Term::HasAttachment,
+            "has" => return Err(format!("Unknown has: value: {}", value)),
+            "is" => match value.to_lowercase().as_str() {
+                "unread" => Term::Flag(Flag::Seen, false),
+                "read" => Term::Flag(Flag::Seen, true),
+                "flagged" | "starred" => Term::Flag(Flag::Flagged, true),
+                "answered" => Term::Flag(Flag::Answered, true),
+                "draft" => Term::Flag(Flag::Draft, true),
+                _ => return Err(format!("Unknown is: value: {}", value)),
+            },
+            "before" => Term::Before(parse_date(&value)?),
+            "after" => Term::After(parse_date(&value)?),
+            "on" => Term::On(parse_date(&value)?),
+            "larger" => Term::Larger(parse_size(&value)?),
+            "smaller" => Term::Smaller(parse_size(&value)?),
+            // `https://...` などフィールドでないものは普通の検索語
+            _ => Term::Text(format!("{}:{}", field, value)),
+        };
+        Ok(term)
+    }
+
+    fn matches(&self, message: &Message) -> bool {
+        match self {
+            Term::Text(text) => {
+                contains(&message.subject, text)
+                    || contains(&message.body.get_display_content(), text)
+                    || [&message.from, &message.to, &message.cc]
+                        .iter()
+                        .any(|addresses| addresses_contain(addresses, text))
+            }
+            Term::From(text) => addresses_contain(&message.from, text),
+            Term::To(text) => addresses_contain(&message.to, text),
+            Term::Cc(text) => addresses_contain(&message.cc, text),
+            Term::Subject(text) => contains(&message.subject, text),
+            Term::Body(text) => contains(&message.body.get_display_content(), text),
+            Term::HasAttachment => message.has_attachments(),
+            Term::Flag(flag, present) => message.flags.contains(flag) == *present,
+            Term::Before(date) => message.date < start_of_day(*date),
+            Term::After(date) => message.date >= start_of_day(*date),
+            Term::On(date) => {
+                message.date >= start_of_day(*date)
+                    && date
+                        .succ_opt()
+                        .is_none_or(|next| message.date < start_of_day(next))
+            }
+            Term::Larger(size) => message.get_size() > *size,
+            Term::Smaller(size) => message.get_size() < *size,
+            Term::Folder(folder) => message.folder.eq_ignore_ascii_case(folder),
+        }
+    }
+
+    fn to_sql(&self, params: &mut Vec<Value>) -> String {
+        match self {
+            Term::Text(text) => {
+                if let Some(phrase) = fts_phrase(text) {
+                    return format!(
+                        "m.pk IN (SELECT rowid FROM messages_fts WHERE messages_fts MATCH {})",
+                        bind(params, Value::Text(phrase))
+                    );
+                }
+                // 3-gramの索引で引けない短い語は部分一致で探す
+                let pattern = bind(params, like_pattern(text));
+                format!(
+                    "(m.subject LIKE {0} ESCAPE '\\' OR m.body LIKE {0} ESCAPE '\\' OR {1})",
+                    pattern,
+                    addresses_sql(&["from", "to", "cc"], &pattern)
+                )
+            }
+            Term::From(text) => addresses_sql(&["from"], &bind(params, like_pattern(text))),
+            Term::To(text) => addresses_sql(&["to"], &bind(params, like_pattern(text))),
+            Term::Cc(text) => addresses_sql(&["cc"], &bind(params, like_pattern(text))),
+            Term::Subject(text) => {
+                format!(
+                    "m.subject LIKE {} ESCAPE '\\'",
+                    bind(params, like_pattern(text))
+                )
+            }
+            Term::Body(text) => {
+                format!(
+                    "m.body LIKE {} ESCAPE '\\'",
+                    bind(params, like_pattern(text))
+                )
+            }
+            Term::HasAttachment => {
+                "EXISTS (SELECT 1 FROM attachments WHERE message_pk = m.pk)".to_string()
+            }
+            Term::Flag(flag, present) => {
+                // flagsはserdeで書いたJSON配列（単純なフラグは "Seen" のような文字列）
+                let name = serde_json::to_value(flag)
+                    .ok()
+                    .and_then(|value| value.as_str().map(str::to_string))
+                    .unwrap_or_default();
+                format!(
+                    "{}EXISTS (SELECT 1 FROM json_each(m.flags) WHERE value = {})",
+                    if *present { "" } else { "NOT " },
+                    bind(params, Value::Text(name))
+                )
+            }
+            Term::Before(date) => format!("m.date < {}", bind_day(params, *date)),
+            Term::After(date) => format!("m.date >= {}", bind_day(params, *date)),
+            Term::On(date) => match date.succ_opt() {
+                Some(next) => format!(
+                    "(m.date >= {} AND m.date < {})",
+                    bind_day(params, *date),
+                    bind_day(params, next)
+                ),
+                None => format!("m.date >= {}", bind_day(params, *date)),
+            },
+            Term::Larger(size) => format!(
+                "{} > {}",
+                SIZE_SQL,
+                bind(params, Value::Integer(*size as i64))
+            ),
+            Term::Smaller(size) => format!(
+                "{} < {}",
+                SIZE_SQL,
+                bind(params, Value::Integer(*size as i64))
+            ),
+            Term::Folder(folder) => format!(
+                "m.folder = {} COLLATE NOCASE",
+                bind(params, Value::Text(folder.clone()))
+            ),
+        }
+    }
+}
+
+/// `Message::get_size` と同じ計算（本文のバイト数と添付ファイルのサイズの合計）
+const SIZE_SQL: &str = "(CASE WHEN m.body_type = 'multipart'
+        THEN (SELECT COALESCE(SUM(length(CAST(content AS BLOB))), 0)
+              FROM message_parts WHERE message_pk = m.pk)
+        ELSE COALESCE(length(CAST(m.body_content AS BLOB)), 0) END
+    + (SELECT COALESCE(SUM(size), 0) FROM attachments WHERE message_pk = m.pk))";
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// `a OR b OR ...`
+    fn parse_or(&mut self) -> Result<Query, String> {
+        let mut operands = vec![self.parse_and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            operands.push(self.parse_and()?);
+        }
+        Ok(single_or(operands, Query::Or))
+    }
+
+    /// `a AND b` または並べただけの `a b`
+    fn parse_and(&mut self) -> Result<Query, String> {
+        let mut operands = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) | Some(Token::Or) => break,
+                Some(Token::And) => self.position += 1,
+                _ => {}
+            }
+            operands.push(self.parse_unary()?);
+        }
+        Ok(single_or(operands, Query::And))
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Query::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Open) => {
+                let query = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err("Missing ')'".to_string()),
+                }
+            }
+            Some(Token::Word { field, value }) => {
+                Ok(Query::Term(Term::new(field.as_deref(), value)?))
+            }
+            Some(token) => Err(format!("Unexpected {:?}", token)),
+            None => Err("Unexpected end of query".to_string()),
+        }
+    }
+}
+
+fn single_or(mut operands: Vec<Query>, combine: fn(Vec<Query>) -> Query) -> Query {
+    if operands.len() == 1 {
+        operands.remove(0)
+    } else {
+        combine(operands)
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                tokens.push(Token::Word {
+                    field: None,
+                    value: read_quoted(&mut chars)?,
+                });
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted_value = None;
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+                    // `from:"John Smith"`
+                    if c == '"' && word.ends_with(':') {
+                        quoted_value = Some(read_quoted(&mut chars)?);
+                        break;
+                    }
+                    word.push(c);
+                }
+
+                tokens.push(match (word.as_str(), quoted_value) {
+                    ("AND", None) => Token::And,
+                    ("OR", None) => Token::Or,
+                    ("NOT", None) => Token::Not,
+                    (_, Some(value)) => Token::Word {
+                        field: Some(word.trim_end_matches(':').to_string()),
+                        value,
+                    },
+                    _ => match word.split_once(':') {
+                        Some((field, value)) if !field.is_empty() && !value.is_empty() => {
+                            Token::Word {
+                                field: Some(field.to_string()),
+                                value: value.to_string(),
+                            }
+                        }
+                        _ => Token::Word {
+                            field: None,
+                            value: word,
+                        },
+                    },
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// 開きの `"` の後から閉じの `"` までを読む
+fn read_quoted(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    let mut phrase = String::new();
+    for c in chars.by_ref() {
+        if c == '"' {
+            return Ok(phrase);
+        }
+        phrase.push(c);
+    }
+    Err("Unterminated quote".to_string())
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y/%m/%d"))
+        .map_err(|_| format!("Invalid date (use YYYY-MM-DD): {}", value))
+}
+
+/// `500`、`10k`、`2MB` など（1Kは1024バイト）
+fn parse_size(value: &str) -> Result<usize, String> {
+    let lower = value.to_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" | "kb" => 1024,
+        "m" | "mb" => 1024 * 1024,
+        "g" | "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Invalid size: {}", value)),
+    };
+    digits
+        .parse::<usize>()
+        .map(|size| size * multiplier)
+        .map_err(|_| format!("Invalid size: {}", value))
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|local| local.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&midnight))
+}
+
+fn contains(text: &str, needle: &str) -> bool {
+    text.to_lowercase().contains(&needle.to_lowercase())
+}
+
+fn addresses_contain(addresses: &[Address], needle: &str) -> bool {
+    addresses.iter().any(|address| {
+        contains(&address.email, needle)
+            || address
+                .name
+                .as_deref()
+                .is_some_and(|name| contains(name, needle))
+    })
+}
+
+/// 検索語をFTS5のフレーズにする（`"週次報告"`）
+///
+/// 索引は3-gramなので、フレーズは大文字小文字を区別しない部分一致になる。
+/// 3文字に満たない語は索引で引けないので `None`。
+fn fts_phrase(text: &str) -> Option<String> {
+    (text.chars().count() >= 3).then(|| format!("\"{}\"", text.replace('"', "\"\"")))
+}
+
+/// 大文字小文字を区別せずに `needle` を探し、最初の一致の範囲（文字単位）を返す
+fn find_ignore_case(chars: &[char], needle: &str) -> Option<(usize, usize)> {
+    let needle: Vec<char> = needle.chars().flat_map(char::to_lowercase).collect();
+    if needle.is_empty() || needle.len() > chars.len() {
+        return None;
+    }
+    (0..=chars.len() - needle.len())
+        .find(|&start| {
+            chars[start..start + needle.len()]
+                .iter()
+                .zip(&needle)
+                .all(|(c, n)| c.to_lowercase().eq(n.to_lowercase()))
+        })
+        .map(|start| (start, start + needle.len()))
+}
+
+/// 一致箇所の前後を切り出して印を付ける
+fn excerpt(chars: &[char], start: usize, end: usize) -> String {
+    const BEFORE: usize = 12;
+    const AFTER: usize = 24;
+
+    let from = start.saturating_sub(BEFORE);
+    let to = (end + AFTER).min(chars.len());
+    let text = |range: std::ops::Range<usize>| chars[range].iter().collect::<String>();
+    format!(
+        "{}{}{}{}{}{}{}",
+        if from > 0 { "…" } else { "" },
+        text(from..start),
+        SNIPPET_START,
+        text(start..end),
+        SNIPPET_END,
+        text(end..to),
+        if to < chars.len() { "…" } else { "" }
+    )
+}
+
+fn bind(params: &mut Vec<Value>, value: Value) -> String {
+    params.push(value);
+    format!("?{}", params.len())
+}
+
+fn bind_day(params: &mut Vec<Value>, date: NaiveDate) -> String {
+    bind(params, Value::Integer(start_of_day(date).timestamp()))
+}
+
+/// LIKEの部分一致パターン（`%`・`_`・`\` はエスケープする）
+fn like_pattern(text: &str) -> Value {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    Value::Text(format!("%{}%", escaped))
+}
+
+fn addresses_sql(kinds: &[&str], pattern: &str) -> String {
+    let kinds: Vec<String> = kinds.iter().map(|kind| format!("'{}'", kind)).collect();
+    format!(
+        "EXISTS (SELECT 1 FROM message_addresses a
+                 WHERE a.message_pk = m.pk AND a.kind IN ({})
+                   AND (a.email LIKE {1} ESCAPE '\\' OR a.name LIKE {1} ESCAPE '\\'))",
+        kinds.join(", "),
+        pattern
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::{Attachment, MessageBody};
+    use crate::storage::Database;
+
+    fn text(value: &str) -> Query {
+        Query::Term(Term::Text(value.to_string()))
+    }
+
+    #[test]
+    fn test_parse_operators_and_precedence() {
+        let query = Query::parse(
+            r#"from:"Alice Smith" "weekly report" OR NOT (is:unread AND has:attachment)"#,
+        )
+        .unwrap();
+        assert_eq!(
+            query,
+            Query::Or(vec![
+                Query::And(vec![
+                    Query::Term(Term::From("Alice Smith".to_string())),
+                    text("weekly report"),
+                ]),
+                Query::Not(Box::new(Query::And(vec![
+                    Query::Term(Term::Flag(Flag::Seen, false)),
+                    Query::Term(Term::HasAttachment),
+                ]))),
+            ])
+        );
+
+        // 小文字のorや未知のフィールドはただの検索語
+        assert_eq!(
+            Query::parse("cats or https://example.com").unwrap(),
+            Query::And(vec![text("cats"), text("or"), text("https://example.com")])
+        );
+        assert_eq!(
+            Query::parse("larger:2M before:2024/01/31").unwrap(),
+            Query::And(vec![
+                Query::Term(Term::Larger(2 * 1024 * 1024)),
+                Query::Term(Term::Before(NaiveDate::from_ymd_opt(2024, 1, 31).unwrap())),
+            ])
+        );
+        assert_eq!(Query::parse("  ").unwrap(), Query::And(Vec::new()));
+
+        for invalid in [
+            "\"open",
+            "(a OR b",
+            "a)",
+            "before:yesterday",
+            "larger:10q",
+            "is:bogus",
+            "NOT",
+        ] {
+            assert!(Query::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    fn message(id: &str, from: &str, subject: &str, body: &str, day: u32) -> Message {
+        let mut message = Message::new(
+            id.to_string(),
+            vec![Address::new(
+                format!("{}@example.com", from.to_lowercase()),
+                Some(from.to_string()),
+            )],
+            vec![Address::new("me@example.com".to_string(), None)],
+            subject.to_string(),
+            MessageBody::new_plain(body.to_string()),
+            "work".to_string(),
+            "INBOX".to_string(),
+        );
+        message.date = Local
+            .with_ymd_and_hms(2024, 3, day, 12, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc);
+        message
+    }
+
+    #[test]
+    fn test_memory_and_sqlite_agree() {
+        let mut invoice = message("1", "Alice", "Invoice 100%", "請求書を送ります", 1);
+        invoice.add_attachment(Attachment::new(
+            "invoice.pdf".to_string(),
+            "application/pdf".to_string(),
+            vec![0; 4096],
+        ));
+        invoice.cc = vec![Address::new("carol@example.com".to_string(), None)];
+
+        let mut lunch = message("2", "Bob", "Lunch?", "Ramen at noon", 2);
+        lunch.flags = vec![Flag::Seen, Flag::Flagged];
+        lunch.folder = "Archive".to_string();
+
+        let mut report = message("3", "Alice", "Weekly report", "All green", 3);
+        report.flags = vec![Flag::Seen];
+
+        let messages = vec![invoice, lunch, report];
+        let mut db = Database::new(":memory:").unwrap();
+        for message in &messages {
+            db.store_message(message).unwrap();
+        }
+
+        let cases = [
+            ("alice", vec!["1", "3"]),
+            ("from:bob", vec!["2"]),
+            ("to:me@example", vec!["1", "2", "3"]),
+            ("cc:carol", vec!["1"]),
+            ("subject:\"weekly report\"", vec!["3"]),
+            ("body:請求書", vec!["1"]),
+            ("100%", vec!["1"]),
+            ("has:attachment", vec!["1"]),
+            ("is:unread", vec!["1"]),
+            ("is:starred OR is:unread", vec!["1", "2"]),
+            ("from:alice NOT is:read", vec!["1"]),
+            ("before:2024-03-02", vec!["1"]),
+            ("after:2024-03-02", vec!["2", "3"]),
+            ("on:2024-03-02", vec!["2"]),
+            ("larger:4k", vec!["1"]),
+            ("smaller:10", vec!["3"]),
+            ("folder:archive", vec!["2"]),
+            ("(from:alice OR from:bob) AND NOT folder:INBOX", vec!["2"]),
+            ("", vec!["1", "2", "3"]),
+        ];
+
+        for (input, expected) in cases {
+            let query = Query::parse(input).unwrap();
+
+            let mut in_memory: Vec<&str> = messages
+                .iter()
+                .filter(|m| query.matches(m))
+                .map(|m| m.id.as_str())
+                .collect();
+            in_memory.sort();
+            assert_eq!(in_memory, expected, "in memory: {}", input);
+
+            let hits = db.search_messages(&query, Some("work")).unwrap();
+            let mut in_sqlite: Vec<&str> = hits.iter().map(|hit| hit.message.id.as_str()).collect();
+            in_sqlite.sort();
+            assert_eq!(in_sqlite, expected, "sqlite: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_japanese_search_finds_words_inside_sentences() {
+        let messages = vec![
+            message(
+                "1",
+                "Tanaka",
+                "【重要】来週の定例会議について",
+                "お疲れ様です。来週火曜日の定例会議は第二会議室で行います。\n資料は前日までに共有してください。",
+                1,
+            ),
+            message(
+                "2",
+                "Sato",
+                "請求書送付のご案内（2024年3月分）",
+                "いつもお世話になっております。株式会社サンプルの佐藤です。\n3月分の請求書をお送りします。",
+                2,
+            ),
+            message(
+                "3",
+                "Suzuki",
+                "Re: 新幹線の予約",
+                "東京駅から新大阪駅までの指定席を予約しました。",
+                3,
+            ),
+        ];
+        let mut db = Database::new(":memory:").unwrap();
+        for message in &messages {
+            db.store_message(message).unwrap();
+        }
+
+        let cases = [
+            ("定例会議", vec!["1"]),
+            ("請求書", vec!["2"]),
+            ("サンプル", vec!["2"]),
+            ("新大阪", vec!["3"]),
+            ("body:第二会議室", vec!["1"]),
+            ("2024年3月", vec!["2"]),
+            // 3文字未満の語は索引を使わずに探す
+            ("会議", vec!["1"]),
+            ("駅", vec!["3"]),
+            ("会議 OR 予約", vec!["1", "3"]),
+            ("来週 NOT 資料", vec![]),
+        ];
+        for (input, expected) in cases {
+            let query = Query::parse(input).unwrap();
+
+            let mut in_memory: Vec<&str> = messages
+                .iter()
+                .filter(|m| query.matches(m))
+                .map(|m| m.id.as_str())
+                .collect();
+            in_memory.sort();
+            assert_eq!(in_memory, expected, "in memory: {}", input);
+
+            let hits = db.search_messages(&query, None).unwrap();
+            let mut in_sqlite: Vec<&str> = hits.iter().map(|hit| hit.message.id.as_str()).collect();
+            in_sqlite.sort();
+            assert_eq!(in_sqlite, expected, "sqlite: {}", input);
+        }
+
+        // 索引で一致した語も、索引を使わなかった語も一致箇所に印が付く
+        for (input, marked) in [("定例会議", "定例会議"), ("会議", "会議")] {
+            let hits = db
+                .search_messages(&Query::parse(input).unwrap(), None)
+                .unwrap();
+            let snippet = hits[0].snippet.as_deref().unwrap();
+            assert!(
+                snippet.contains(&format!("{}{}{}", SNIPPET_START, marked, SNIPPET_END)),
+                "{}: {:?}",
+                input,
+                snippet
+            );
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use chrono::NaiveDate;
+
+use super::query::{Query, Term};
+use crate::mail::Flag;
+
+/// サーバー側の検索（IMAPの `UID SEARCH` とGmailの検索演算子）への変換
+///
+/// サーバーでの検索は1つのフォルダーが対象なので、`folder:` の条件は検索するフォルダーと
+/// 同じなら常に一致、違えば常に不一致として扱う。
+impl Query {
+    /// サーバーで検索するフォルダー（最上位のANDにある `folder:` の条件）
+    pub fn folder(&self) -> Option<&str> {
+        match self {
+            Query::Term(Term::Folder(folder)) => Some(folder),
+            Query::And(queries) => queries.iter().find_map(|query| match query {
+                Query::Term(Term::Folder(folder)) => Some(folder.as_str()),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// IMAPの `UID SEARCH` の検索キー（`FROM "alice" UNSEEN` など）
+    ///
+    /// 非ASCIIの文字を含む場合は `CHARSET UTF-8` を付ける。
+    pub fn to_imap_search(&self, mailbox: &str) -> String {
+        let criteria = self.imap_criteria(mailbox);
+        if criteria.is_ascii() {
+            criteria
+        } else {
+            format!("CHARSET UTF-8 {}", criteria)
+        }
+    }
+
+    fn imap_criteria(&self, mailbox: &str) -> String {
+        match self {
+            Query::Term(term) => term.imap_criteria(mailbox),
+            Query::And(queries) if queries.is_empty() => "ALL".to_string(),
+            Query::Or(queries) if queries.is_empty() => "NOT ALL".to_string(),
+            Query::And(queries) => {
+                let keys: Vec<String> = queries.iter().map(|q| q.imap_criteria(mailbox)).collect();
+                format!("({})", keys.join(" "))
+            }
+            // IMAPのORは2項なので右から畳み込む
+            Query::Or(queries) => queries
+                .iter()
+                .map(|q| q.imap_criteria(mailbox))
+                .rev()
+                .reduce(|rest, key| format!("OR {} {}", key, rest))
+                .unwrap_or_default(),
+            Query::Not(query) => format!("NOT {}", query.imap_criteria(mailbox)),
+        }
+    }
+
+    /// GmailのIMAP拡張の検索キー（`X-GM-RAW "from:alice is:unread"`）
+    pub fn to_x_gm_raw(&self, folder: &str) -> Option<String> {
+        let raw = format!("X-GM-RAW {}", imap_quote(&self.to_gmail_search(folder)?));
+        Some(if raw.is_ascii() {
+            raw
+        } else {
+            format!("CHARSET UTF-8 {}", raw)
+        })
+    }
+
+    /// Gmailの検索演算子（`from:"alice" is:unread` など。IMAPの `X-GM-RAW` とAPIの `q`）
+    ///
+    /// Gmailで表せない条件（`is:answered` など）を含む場合は `None`。
+    pub fn to_gmail_search(&self, folder: &str) -> Option<String> {
+        self.gmail_criteria(folder).map(|criteria| match criteria {
+            Gmail::All => String::new(),
+            Gmail::Criteria(criteria) => criteria,
+        })
+    }
+
+    fn gmail_criteria(&self, folder: &str) -> Option<Gmail> {
+        match self {
+            Query::Term(term) => term.gmail_criteria(folder),
+            Query::And(queries) => {
+                let mut parts = Vec::new();
+                for query in queries {
+                    if let Gmail::Criteria(criteria) = query.gmail_criteria(folder)? {
+                        parts.push(criteria);
+                    }
+                }
+                Some(match parts.len() {
+                    0 => Gmail::All,
+                    1 => Gmail::Criteria(parts.remove(0)),
+                    _ => Gmail::Criteria(format!("({})", parts.join(" "))),
+                })
+            }
+            Query::Or(queries) => {
+                let mut parts = Vec::new();
+                for query in queries {
+                    match query.gmail_criteria(folder)? {
+                        Gmail::All => return Some(Gmail::All),
+                        Gmail::Criteria(criteria) => parts.push(criteria),
+                    }
+                }
+                // 空のORはどれにも一致しないが、Gmailには表す演算子がない
+                (!parts.is_empty()).then(|| Gmail::Criteria(format!("({})", parts.join(" OR "))))
+            }
+            Query::Not(query) => match query.gmail_criteria(folder)? {
+                Gmail::All => None,
+                Gmail::Criteria(criteria) => Some(Gmail::Criteria(format!("-{}", criteria))),
+            },
+        }
+    }
+}
+
+/// Gmailの検索演算子に変換した条件（`All` は何も書かなくてよい常に一致する条件）
+enum Gmail {
+    All,
+    Criteria(String),
+}
+
+impl Term {
+    fn imap_criteria(&self, mailbox: &str) -> String {
+        match self {
+            Term::Text(text) => format!("TEXT {}", imap_quote(text)),
+            Term::From(text) => format!("FROM {}", imap_quote(text)),
+            Term::To(text) => format!("TO {}", imap_quote(text)),
+            Term::Cc(text) => format!("CC {}", imap_quote(text)),
+            Term::Subject(text) => format!("SUBJECT {}", imap_quote(text)),
+            Term::Body(text) => format!("BODY {}", imap_quote(text)),
+            // IMAPには添付ファイルの条件がないので、multipart/mixedのメッセージで近似する
+            Term::HasAttachment => "HEADER Content-Type \"multipart/mixed\"".to_string(),
+            Term::Flag(flag, present) => {
+                let (set, unset) = match flag {
+                    Flag::Seen => ("SEEN", "UNSEEN"),
+                    Flag::Answered => ("ANSWERED", "UNANSWERED"),
+                    Flag::Flagged => ("FLAGGED", "UNFLAGGED"),
+                    Flag::Deleted => ("DELETED", "UNDELETED"),
+                    Flag::Draft => ("DRAFT", "UNDRAFT"),
+                    Flag::Recent => ("RECENT", "OLD"),
+                    Flag::Custom(keyword) => {
+                        let key = if *present { "KEYWORD" } else { "UNKEYWORD" };
+                        return format!("{} {}", key, keyword);
+                    }
+                };
+                if *present { set } else { unset }.to_string()
+            }
+            Term::Before(date) => format!("BEFORE {}", imap_date(*date)),
+            Term::After(date) => format!("SINCE {}", imap_date(*date)),
+            Term::On(date) => format!("ON {}", imap_date(*date)),
+            Term::Larger(size) => format!("LARGER {}", size),
+            Term::Smaller(size) => format!("SMALLER {}", size),
+            Term::Folder(folder) if folder.eq_ignore_ascii_case(mailbox) => "ALL".to_string(),
+            Term::Folder(_) => "NOT ALL".to_string(),
+        }
+    }
+
+    fn gmail_criteria(&self, folder: &str) -> Option<Gmail> {
+        let criteria = match self {
+            // Gmailには本文だけを対象にする演算子がないので、件名なども含めて探す
+            Term::Text(text) | Term::Body(text) => gmail_quote(text),
+            Term::From(text) => format!("from:{}", gmail_quote(text)),
+            Term::To(text) => format!("to:{}", gmail_quote(text)),
+            Term::Cc(text) => format!("cc:{}", gmail_quote(text)),
+            Term::Subject(text) => format!("subject:{}", gmail_quote(text)),
+            Term::HasAttachment => "has:attachment".to_string(),
+            Term::Flag(Flag::Seen, true) => "is:read".to_string(),
+            Term::Flag(Flag::Seen, false) => "is:unread".to_string(),
+            Term::Flag(Flag::Flagged, true) => "is:starred".to_string(),
+            Term::Flag(Flag::Flagged, false) => "-is:starred".to_string(),
+            Term::Flag(Flag::Draft, true) => "in:drafts".to_string(),
+            Term::Flag(Flag::Draft, false) => "-in:drafts".to_string(),
+            Term::Flag(..) => return None,
+            Term::Before(date) => format!("before:{}", gmail_date(*date)),
+            Term::After(date) => format!("after:{}", gmail_date(*date)),
+            Term::On(date) => match date.succ_opt() {
+                Some(next) => format!("(after:{} before:{})", gmail_date(*date), gmail_date(next)),
+                None => format!("after:{}", gmail_date(*date)),
+            },
+            Term::Larger(size) => format!("larger:{}", size),
+            Term::Smaller(size) => format!("smaller:{}", size),
+            Term::Folder(name) if name.eq_ignore_ascii_case(folder) => return Some(Gmail::All),
+            Term::Folder(_) => return None,
+        };
+        Some(Gmail::Criteria(criteria))
+    }
+}
+
+/// IMAPのquoted string（改行は検索語に含められないので空白にする）
+fn imap_quote(text: &str) -> String {
+    let escaped = text
+        .replace(['\r', '\n'], " ")
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+fn imap_date(date: NaiveDate) -> String {
+    date.format("%-d-%b-%Y").to_string()
+}
+
+/// Gmailの語句（`"` はエスケープできないので取り除く）
+fn gmail_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', ""))
+}
+
+fn gmail_date(date: NaiveDate) -> String {
+    date.format("%Y/%m/%d").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translates_to_imap_and_gmail() {
+        let query = Query::parse(
+            r#"from:alice ("週次 報告" OR is:starred) NOT is:read before:2024-03-05 larger:1k"#,
+        )
+        .unwrap();
+        assert_eq!(
+            query.to_imap_search("INBOX"),
+            "CHARSET UTF-8 (FROM \"alice\" OR TEXT \"週次 報告\" FLAGGED NOT SEEN \
+             BEFORE 5-Mar-2024 LARGER 1024)"
+        );
+        assert_eq!(
+            query.to_gmail_search("INBOX").unwrap(),
+            "(from:\"alice\" (\"週次 報告\" OR is:starred) -is:read before:2024/03/05 larger:1024)"
+        );
+
+        // 検索するフォルダーの条件はGmailでは書かずに済む
+        let query = Query::parse("folder:inbox (cats OR dogs OR \"birds\")").unwrap();
+        assert_eq!(query.folder(), Some("inbox"));
+        assert_eq!(
+            query.to_imap_search("INBOX"),
+            "(ALL OR TEXT \"cats\" OR TEXT \"dogs\" TEXT \"birds\")"
+        );
+        assert_eq!(
+            query.to_gmail_search("INBOX").unwrap(),
+            "(\"cats\" OR \"dogs\" OR \"birds\")"
+        );
+        assert_eq!(
+            Query::parse("folder:Archive cats")
+                .unwrap()
+                .to_imap_search("INBOX"),
+            "(NOT ALL TEXT \"cats\")"
+        );
+
+        // Gmailで表せない条件があればサーバーでは検索しない
+        assert!(Query::parse("is:answered")
+            .unwrap()
+            .to_gmail_search("INBOX")
+            .is_none());
+        assert!(Query::parse("NOT folder:INBOX")
+            .unwrap()
+            .to_gmail_search("INBOX")
+            .is_none());
+        assert_eq!(
+            Query::parse("is:answered").unwrap().to_imap_search("INBOX"),
+            "ANSWERED"
+        );
+        assert_eq!(
+            Query::parse("subject:\"請求書\" has:attachment")
+                .unwrap()
+                .to_x_gm_raw("INBOX")
+                .unwrap(),
+            "CHARSET UTF-8 X-GM-RAW \"(subject:\\\"請求書\\\" has:attachment)\""
+        );
+        assert_eq!(
+            Query::parse("").unwrap().to_gmail_search("INBOX").unwrap(),
+            ""
+        );
+    }
+}
@@ -2,8 +2,15 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::{Path, PathBuf};
 
+use super::secrets::{run_password_command, SecretStore, SecretStoreKind};
 use super::{StorageError, StorageResult};
-use crate::mail::Account;
+use crate::mail::{Account, OAuthTokens};
+
+// 秘密情報の保存先でのキー（"アカウントID:種類"）
+const IMAP_PASSWORD_KEY: &str = "imap_password";
+const SMTP_PASSWORD_KEY: &str = "smtp_password";
+const CLIENT_SECRET_KEY: &str = "oauth_client_secret";
+const TOKENS_KEY: &str = "oauth_tokens";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -21,6 +28,9 @@ pub struct AppConfig {
     pub download_attachments: bool,
     pub data_dir: PathBuf,
     pub log_level: String,
+    /// パスワードやトークンの保存先
+    #[serde(default)]
+    pub secret_store: SecretStoreKind,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,8 +40,32 @@ pub struct UiConfig {
     pub show_preview_pane: bool,
     pub date_format: String,
     pub time_format: String,
+    /// フォルダー一覧の幅（画面幅に対する%）
     pub folder_pane_width: u16,
+    /// プレビューを下に出すときのメール一覧の高さ（%）
     pub message_list_height: u16,
+    /// プレビューを右に出すときのメール一覧の幅（%）
+    #[serde(default = "default_message_list_width")]
+    pub message_list_width: u16,
+    /// プレビューを出す位置
+    #[serde(default)]
+    pub preview_position: PreviewPosition,
+    /// フォルダー一覧に購読しているフォルダーだけを表示する
+    #[serde(default)]
+    pub subscribed_folders_only: bool,
+}
+
+/// プレビューを出す位置（メール一覧の下か右）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewPosition {
+    #[default]
+    Bottom,
+    Right,
+}
+
+fn default_message_list_width() -> u16 {
+    50
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,6 +105,7 @@ impl Default for Config {
                 download_attachments: false,
                 data_dir: data_dir.clone(),
                 log_level: "info".to_string(),
+                secret_store: SecretStoreKind::default(),
             },
             ui: UiConfig {
                 theme: "default".to_string(),
@@ -80,6 +115,9 @@ impl Default for Config {
                 time_format: "%H:%M".to_string(),
                 folder_pane_width: 20,
                 message_list_height: 50,
+                message_list_width: default_message_list_width(),
+                preview_position: PreviewPosition::default(),
+                subscribed_folders_only: false,
             },
             keybindings: KeyBindings {
                 quit: "q".to_string(),
@@ -157,11 +195,32 @@ impl Config {
         self.get_data_dir().join("rustmail.db")
     }
 
+    pub fn get_vault_file() -> PathBuf {
+        Self::get_config_dir().join("secrets.vault")
+    }
+
     pub fn load() -> StorageResult<Self> {
         let config_file = Self::get_config_file();
 
         if config_file.exists() {
-            Self::load_from_file(config_file)
+            let mut config = Self::load_from_file(&config_file)?;
+
+            // 旧形式の設定ファイルに平文で書かれている秘密情報を保存先に移す
+            let inline_secrets = config.collect_secrets();
+            if !inline_secrets.is_empty() {
+                let mut store = config.open_secret_store()?;
+                for (key, value) in &inline_secrets {
+                    store.set(key, value)?;
+                }
+                config.save_to_file(&config_file)?;
+                println!(
+                    "設定ファイルの認証情報 {} 件を安全な保存先に移行しました",
+                    inline_secrets.len()
+                );
+            }
+
+            config.resolve_secrets()?;
+            Ok(config)
         } else {
             // 設定ファイルが存在しない場合はデフォルト設定を作成
             let config = Self::default();
@@ -170,11 +229,152 @@ impl Config {
         }
     }
 
+    /// 設定を保存（秘密情報は保存先に書き、設定ファイルには含めない）
     pub fn save(&self) -> StorageResult<()> {
+        let secrets = self.collect_secrets();
+        let stale_keys = self.stale_secret_keys();
+        if !secrets.is_empty() || !stale_keys.is_empty() {
+            let mut store = self.open_secret_store()?;
+            for (key, value) in &secrets {
+                store.set(key, value)?;
+            }
+            for key in &stale_keys {
+                store.delete(key)?;
+            }
+        }
+
         let config_file = Self::get_config_file();
         self.save_to_file(config_file)
     }
 
+    /// 更新されたOAuth2トークンだけを保存先に書き込む
+    pub fn store_tokens(account_id: &str, tokens: &OAuthTokens) -> StorageResult<()> {
+        let config = Self::load_from_file(Self::get_config_file())?;
+        let value = serde_json::to_string(tokens)
+            .map_err(|e| StorageError::Parse(format!("Failed to serialize tokens: {}", e)))?;
+
+        config
+            .open_secret_store()?
+            .set(&secret_key(account_id, TOKENS_KEY), &value)
+    }
+
+    fn open_secret_store(&self) -> StorageResult<SecretStore> {
+        SecretStore::open(self.app.secret_store, &Self::get_vault_file())
+    }
+
+    /// 保存先に書き込むべき秘密情報（キー, 値）を集める
+    ///
+    /// `password_command` で取得するパスワードは保存しない
+    fn collect_secrets(&self) -> Vec<(String, String)> {
+        let mut secrets = Vec::new();
+
+        for account in &self.accounts {
+            if account.imap.password_command.is_none() && !account.imap.password.is_empty() {
+                secrets.push((
+                    secret_key(&account.id, IMAP_PASSWORD_KEY),
+                    account.imap.password.clone(),
+                ));
+            }
+
+            if account.smtp.password_command.is_none() && !account.smtp.password.is_empty() {
+                secrets.push((
+                    secret_key(&account.id, SMTP_PASSWORD_KEY),
+                    account.smtp.password.clone(),
+                ));
+            }
+
+            if let Some(client_secret) = account
+                .oauth_config
+                .as_ref()
+                .and_then(|c| c.client_secret.as_ref())
+                .filter(|s| !s.is_empty())
+            {
+                secrets.push((
+                    secret_key(&account.id, CLIENT_SECRET_KEY),
+                    client_secret.clone(),
+                ));
+            }
+
+            if let Some(tokens) = &account.tokens {
+                if let Ok(value) = serde_json::to_string(tokens) {
+                    secrets.push((secret_key(&account.id, TOKENS_KEY), value));
+                }
+            }
+        }
+
+        secrets
+    }
+
+    /// `password_command` に切り替えたため不要になった保存済みパスワードのキー
+    fn stale_secret_keys(&self) -> Vec<String> {
+        let mut keys = Vec::new();
+
+        for account in &self.accounts {
+            if account.imap.password_command.is_some() {
+                keys.push(secret_key(&account.id, IMAP_PASSWORD_KEY));
+            }
+            if account.smtp.password_command.is_some() {
+                keys.push(secret_key(&account.id, SMTP_PASSWORD_KEY));
+            }
+        }
+
+        keys
+    }
+
+    /// パスワードコマンドと保存先から秘密情報を読み込む
+    fn resolve_secrets(&mut self) -> StorageResult<()> {
+        if self.accounts.is_empty() {
+            return Ok(());
+        }
+
+        let store = self.open_secret_store()?;
+
+        for account in &mut self.accounts {
+            for (config_password, command, key) in [
+                (
+                    &mut account.imap.password,
+                    &account.imap.password_command,
+                    IMAP_PASSWORD_KEY,
+                ),
+                (
+                    &mut account.smtp.password,
+                    &account.smtp.password_command,
+                    SMTP_PASSWORD_KEY,
+                ),
+            ] {
+                match command {
+                    Some(command) => match run_password_command(command) {
+                        Ok(password) => *config_password = password,
+                        Err(e) => eprintln!(
+                            "アカウント {} のパスワードコマンドが失敗しました: {}",
+                            account.id, e
+                        ),
+                    },
+                    None => {
+                        if let Some(password) = store.get(&secret_key(&account.id, key))? {
+                            *config_password = password;
+                        }
+                    }
+                }
+            }
+
+            if let Some(oauth_config) = account.oauth_config.as_mut() {
+                if oauth_config.client_secret.is_none() {
+                    oauth_config.client_secret =
+                        store.get(&secret_key(&account.id, CLIENT_SECRET_KEY))?;
+                }
+            }
+
+            if account.tokens.is_none() {
+                if let Some(value) = store.get(&secret_key(&account.id, TOKENS_KEY))? {
+                    account.tokens = serde_json::from_str(&value).ok();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn add_account(&mut self, account: Account) -> StorageResult<()> {
         account.validate().map_err(StorageError::Config)?;
 
@@ -237,3 +437,48 @@ impl Config {
         Ok(())
     }
 }
+
+fn secret_key(account_id: &str, kind: &str) -> String {
+    format!("{}:{}", account_id, kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::OAuthConfig;
+
+    #[test]
+    fn test_secrets_are_not_serialized() {
+        let mut account = Account::default();
+        account.imap.password = "imap-secret".to_string();
+        account.smtp.password = "smtp-secret".to_string();
+        account.oauth_config = Some(OAuthConfig {
+            client_secret: Some("client-secret".to_string()),
+            ..OAuthConfig::default()
+        });
+        account.tokens = Some(OAuthTokens {
+            access_token: "access-secret".to_string(),
+            refresh_token: Some("refresh-secret".to_string()),
+            expires_in: None,
+            token_type: "Bearer".to_string(),
+            expires_at: None,
+        });
+
+        let mut config = Config::default();
+        config.accounts.push(account);
+
+        let json = serde_json::to_string(&config).unwrap();
+        for secret in [
+            "imap-secret",
+            "smtp-secret",
+            "client-secret",
+            "access-secret",
+            "refresh-secret",
+        ] {
+            assert!(!json.contains(secret), "{} was serialized", secret);
+        }
+
+        // 保存先に移す対象としては全て集められる
+        assert_eq!(config.collect_secrets().len(), 4);
+    }
+}
@@ -1,159 +1,391 @@
-use rusqlite::{params, Connection};
+use chrono::{TimeZone, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, Row};
 use std::path::Path;
 
-use super::{StorageError, StorageResult};
-use crate::mail::Message;
+use super::{journal, migrations, StorageError, StorageResult};
+use crate::mail::{Address, Attachment, Flag, Message, MessageBody, MessagePart};
+use crate::search::{Query, SNIPPET_END, SNIPPET_START};
+
+/// 同期で取得した範囲
+///
+/// 範囲内にあってサーバーから返らなかったメッセージは、サーバーで削除されたとみなす。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchedRange {
+    /// フォルダー全体を取得した
+    All,
+    /// 指定したUID以降をすべて取得した
+    FromUid(u32),
+    /// 範囲を特定できない一部だけを取得した（何も削除しない）
+    Partial,
+}
+
+/// 検索結果の1件
+#[derive(Debug, Clone)]
+pub struct SearchHit {
+    pub message: Message,
+    /// 一致箇所の前後の抜粋（検索語で一致したときのみ）
+    pub snippet: Option<String>,
+}
 
-#[allow(dead_code)]
 pub struct Database {
-    conn: Connection,
+    pub(super) conn: Connection,
 }
 
 #[allow(dead_code)]
 impl Database {
     pub fn new<P: AsRef<Path>>(db_path: P) -> StorageResult<Self> {
-        let conn = Connection::open(db_path)
+        let db_path = db_path.as_ref();
+        let mut conn = Connection::open(db_path)
             .map_err(|e| StorageError::Database(format!("Failed to open database: {}", e)))?;
 
-        let mut db = Self { conn };
-        db.init_tables()?;
-        Ok(db)
-    }
-
-    fn init_tables(&mut self) -> StorageResult<()> {
-        // メッセージテーブル
-        self.conn
-            .execute(
-                "CREATE TABLE IF NOT EXISTS messages (
-                id TEXT PRIMARY KEY,
-                account_id TEXT NOT NULL,
-                folder TEXT NOT NULL,
-                subject TEXT,
-                from_addr TEXT,
-                to_addr TEXT,
-                date INTEGER,
-                body TEXT,
-                flags TEXT,
-                raw_message TEXT
-            )",
-                [],
-            )
-            .map_err(|e| {
-                StorageError::Database(format!("Failed to create messages table: {}", e))
-            })?;
-
-        // アカウントテーブル
-        self.conn
-            .execute(
-                "CREATE TABLE IF NOT EXISTS accounts (
-                id TEXT PRIMARY KEY,
-                name TEXT NOT NULL,
-                email TEXT NOT NULL,
-                config TEXT NOT NULL
-            )",
-                [],
-            )
-            .map_err(|e| {
-                StorageError::Database(format!("Failed to create accounts table: {}", e))
-            })?;
-
-        // フォルダテーブル
-        self.conn
-            .execute(
-                "CREATE TABLE IF NOT EXISTS folders (
-                id TEXT PRIMARY KEY,
-                account_id TEXT NOT NULL,
-                name TEXT NOT NULL,
-                server_name TEXT NOT NULL,
-                message_count INTEGER DEFAULT 0,
-                unread_count INTEGER DEFAULT 0,
-                FOREIGN KEY (account_id) REFERENCES accounts(id)
-            )",
-                [],
-            )
-            .map_err(|e| {
-                StorageError::Database(format!("Failed to create folders table: {}", e))
-            })?;
-
-        // 全文検索用FTSテーブル
-        self.conn
-            .execute(
-                "CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
-                subject, from_addr, to_addr, body,
-                content=messages,
-                content_rowid=rowid
-            )",
-                [],
-            )
-            .map_err(|e| StorageError::Database(format!("Failed to create FTS table: {}", e)))?;
-
-        // インデックス作成
-        self.conn
-            .execute(
-                "CREATE INDEX IF NOT EXISTS idx_messages_account_folder 
-             ON messages(account_id, folder)",
-                [],
-            )
-            .map_err(|e| StorageError::Database(format!("Failed to create index: {}", e)))?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")
+            .map_err(|e| StorageError::Database(format!("Failed to enable foreign keys: {}", e)))?;
 
-        self.conn
-            .execute(
-                "CREATE INDEX IF NOT EXISTS idx_messages_date 
-             ON messages(date DESC)",
-                [],
-            )
-            .map_err(|e| StorageError::Database(format!("Failed to create date index: {}", e)))?;
+        migrations::migrate(&mut conn, db_path)?;
 
-        Ok(())
+        Ok(Self { conn })
     }
 
     pub fn store_message(&mut self, message: &Message) -> StorageResult<()> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|e| StorageError::Database(format!("Failed to begin transaction: {}", e)))?;
+        Self::upsert_message(&tx, message)?;
+        tx.commit()
+            .map_err(|e| StorageError::Database(format!("Failed to commit transaction: {}", e)))
+    }
+
+    /// メッセージを保存（同じアカウント・フォルダー・IDの行は更新してpkを保つ）
+    pub(super) fn upsert_message(conn: &Connection, message: &Message) -> StorageResult<()> {
         let flags_json = serde_json::to_string(&message.flags)
             .map_err(|e| StorageError::Database(format!("Failed to serialize flags: {}", e)))?;
-
-        self.conn
-            .execute(
-                "INSERT OR REPLACE INTO messages (
+        let references_json = serde_json::to_string(&message.references).map_err(|e| {
+            StorageError::Database(format!("Failed to serialize references: {}", e))
+        })?;
+
+        let (body_type, body_content) = match &message.body {
+            MessageBody::Plain(content) => ("plain", Some(content.as_str())),
+            MessageBody::Html(content) => ("html", Some(content.as_str())),
+            MessageBody::Multipart { .. } => ("multipart", None),
+        };
+
+        let display_body = message.body.get_display_content();
+        let search_from = search_text(&message.from);
+        let search_to = search_text(
+            &message
+                .to
+                .iter()
+                .chain(&message.cc)
+                .chain(&message.bcc)
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+
+        let pk: i64 = conn
+            .query_row(
+                "INSERT INTO messages (
                 id, account_id, folder, subject, from_addr, to_addr,
-                date, body, flags, raw_message
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                date, date_nanos, body_type, body_content, body, flags, thread_id, raw_message,
+                header_message_id, in_reply_to, reference_ids, body_pending
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)
+            ON CONFLICT (account_id, folder, id) DO UPDATE SET
+                subject = excluded.subject,
+                from_addr = excluded.from_addr,
+                to_addr = excluded.to_addr,
+                date = excluded.date,
+                date_nanos = excluded.date_nanos,
+                body_type = excluded.body_type,
+                body_content = excluded.body_content,
+                body = excluded.body,
+                flags = excluded.flags,
+                thread_id = excluded.thread_id,
+                raw_message = COALESCE(excluded.raw_message, messages.raw_message),
+                header_message_id = excluded.header_message_id,
+                in_reply_to = excluded.in_reply_to,
+                reference_ids = excluded.reference_ids,
+                body_pending = excluded.body_pending
+            RETURNING pk",
                 params![
                     message.id,
                     message.account_id,
                     message.folder,
                     message.subject,
-                    message.get_sender_display(),
-                    message.get_recipients_display(),
+                    search_from,
+                    search_to,
                     message.date.timestamp(),
-                    message.body.get_display_content(),
+                    message.date.timestamp_subsec_nanos(),
+                    body_type,
+                    body_content,
+                    display_body,
                     flags_json,
-                    message.body.get_display_content()
+                    message.thread_id,
+                    message.raw,
+                    message.message_id,
+                    message.in_reply_to,
+                    references_json,
+                    message.body_pending
                 ],
+                |row| row.get(0),
             )
             .map_err(|e| StorageError::Database(format!("Failed to store message: {}", e)))?;
 
-        // FTSテーブルも更新
-        self.conn
-            .execute(
-                "INSERT OR REPLACE INTO messages_fts (
-                rowid, subject, from_addr, to_addr, body
-            ) VALUES (
-                (SELECT rowid FROM messages WHERE id = ?1),
-                ?2, ?3, ?4, ?5
-            )",
+        // 子テーブルは入れ直す
+        for table in ["message_addresses", "message_parts", "attachments"] {
+            conn.execute(
+                &format!("DELETE FROM {} WHERE message_pk = ?1", table),
+                params![pk],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to clear {}: {}", table, e)))?;
+        }
+
+        for (kind, addresses) in [
+            ("from", &message.from),
+            ("to", &message.to),
+            ("cc", &message.cc),
+            ("bcc", &message.bcc),
+        ] {
+            for (position, address) in addresses.iter().enumerate() {
+                conn.execute(
+                    "INSERT INTO message_addresses (message_pk, kind, position, email, name)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![pk, kind, position, address.email, address.name],
+                )
+                .map_err(|e| StorageError::Database(format!("Failed to store address: {}", e)))?;
+            }
+        }
+
+        if let MessageBody::Multipart { parts } = &message.body {
+            for (position, part) in parts.iter().enumerate() {
+                conn.execute(
+                    "INSERT INTO message_parts (message_pk, position, content_type, content, encoding)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![pk, position, part.content_type, part.content, part.encoding],
+                )
+                .map_err(|e| {
+                    StorageError::Database(format!("Failed to store message part: {}", e))
+                })?;
+            }
+        }
+
+        for (position, attachment) in message.attachments.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO attachments (message_pk, position, filename, content_type, size, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
                 params![
-                    message.id,
-                    message.subject,
-                    message.get_sender_display(),
-                    message.get_recipients_display(),
-                    message.body.get_display_content()
+                    pk,
+                    position,
+                    attachment.filename,
+                    attachment.content_type,
+                    attachment.size,
+                    attachment.data
                 ],
             )
-            .map_err(|e| StorageError::Database(format!("Failed to update FTS: {}", e)))?;
+            .map_err(|e| StorageError::Database(format!("Failed to store attachment: {}", e)))?;
+        }
+
+        // messages_ftsはトリガーで更新される
+
+        Ok(())
+    }
+
+    /// サーバーから取得したフォルダーの内容をキャッシュに反映
+    ///
+    /// 取得したメッセージは追加・更新し、取得範囲 `range` にあるのにサーバーから
+    /// 返らなかったメッセージは削除されたものとして消す。取得範囲の外のキャッシュ
+    /// （古いメッセージやサーバー検索で保存したものなど）はそのまま残す。
+    pub fn merge_folder(
+        &mut self,
+        account_id: &str,
+        folder: &str,
+        messages: &[Message],
+        range: FetchedRange,
+    ) -> StorageResult<()> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|e| StorageError::Database(format!("Failed to begin transaction: {}", e)))?;
+
+        for message in messages {
+            // 一覧の取得は本文を含まないので、本文を取得済みのメッセージはフラグだけ更新する
+            if message.body_pending && Self::update_loaded_flags(&tx, message)? {
+                continue;
+            }
+            Self::upsert_message(&tx, message)?;
+        }
+
+        let window = match range {
+            FetchedRange::All => Some(None),
+            FetchedRange::FromUid(uid) => Some(Some(uid)),
+            FetchedRange::Partial => None,
+        };
+
+        if let Some(lowest_uid) = window {
+            let fetched_ids =
+                serde_json::to_string(&messages.iter().map(|m| m.id.as_str()).collect::<Vec<_>>())
+                    .map_err(|e| {
+                        StorageError::Database(format!("Failed to serialize ids: {}", e))
+                    })?;
+
+            // オフラインでこのフォルダーへ移動したメッセージはサーバーにまだないので残す
+            tx.execute(
+                "DELETE FROM messages
+                 WHERE account_id = ?1 AND folder = ?2
+                   AND (?3 IS NULL OR CAST(id AS INTEGER) >= ?3)
+                   AND id NOT IN (SELECT value FROM json_each(?4))
+                   AND id NOT IN (
+                       SELECT message_id FROM pending_operations
+                       WHERE account_id = ?1
+                         AND json_extract(operation, '$.type') = 'Move'
+                         AND json_extract(operation, '$.to_folder') = ?2
+                   )",
+                params![account_id, folder, lowest_uid, fetched_ids],
+            )
+            .map_err(|e| {
+                StorageError::Database(format!("Failed to remove deleted messages: {}", e))
+            })?;
+        }
+
+        journal::reapply_pending_operations(&tx, account_id, folder)?;
+
+        tx.commit()
+            .map_err(|e| StorageError::Database(format!("Failed to commit transaction: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// 本文を取得済みなら、フラグだけを更新してtrueを返す
+    fn update_loaded_flags(conn: &Connection, message: &Message) -> StorageResult<bool> {
+        let flags_json = serde_json::to_string(&message.flags)
+            .map_err(|e| StorageError::Database(format!("Failed to serialize flags: {}", e)))?;
+
+        let updated = conn
+            .execute(
+                "UPDATE messages SET flags = ?1
+                 WHERE account_id = ?2 AND folder = ?3 AND id = ?4
+                   AND body_pending = 0",
+                params![flags_json, message.account_id, message.folder, message.id],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to update flags: {}", e)))?;
+
+        Ok(updated > 0)
+    }
+
+    /// messagesテーブルの行からメッセージ本体を復元（子テーブルは `load_details` で読む）
+    fn message_from_row(row: &Row) -> rusqlite::Result<(i64, Message)> {
+        let pk: i64 = row.get("pk")?;
+        let body_type: String = row.get("body_type")?;
+        let body_content: Option<String> = row.get("body_content")?;
+        let body = match body_type.as_str() {
+            "html" => MessageBody::Html(body_content.unwrap_or_default()),
+            "multipart" => MessageBody::Multipart { parts: Vec::new() },
+            _ => MessageBody::Plain(body_content.unwrap_or_default()),
+        };
+
+        let mut message = Message::new(
+            row.get("id")?,
+            Vec::new(),
+            Vec::new(),
+            row.get("subject")?,
+            body,
+            row.get("account_id")?,
+            row.get("folder")?,
+        );
+
+        message.date = Utc
+            .timestamp_opt(row.get("date")?, row.get("date_nanos")?)
+            .single()
+            .unwrap_or_default();
+        message.flags =
+            serde_json::from_str::<Vec<Flag>>(&row.get::<_, String>("flags")?).unwrap_or_default();
+        message.thread_id = row.get("thread_id")?;
+        message.raw = row.get("raw_message")?;
+        message.message_id = row.get("header_message_id")?;
+        message.in_reply_to = row.get("in_reply_to")?;
+        message.references =
+            serde_json::from_str(&row.get::<_, String>("reference_ids")?).unwrap_or_default();
+        message.body_pending = row.get("body_pending")?;
+
+        Ok((pk, message))
+    }
+
+    /// アドレス・パート・添付ファイルを読み込む
+    fn load_details(&self, pk: i64, message: &mut Message) -> rusqlite::Result<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT kind, email, name FROM message_addresses
+             WHERE message_pk = ?1 ORDER BY kind, position",
+        )?;
+        let addresses = stmt.query_map(params![pk], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Address::new(row.get(1)?, row.get(2)?),
+            ))
+        })?;
+        for address in addresses {
+            let (kind, address) = address?;
+            match kind.as_str() {
+                "from" => message.from.push(address),
+                "to" => message.to.push(address),
+                "cc" => message.cc.push(address),
+                "bcc" => message.bcc.push(address),
+                _ => {}
+            }
+        }
+
+        if let MessageBody::Multipart { parts } = &mut message.body {
+            let mut stmt = self.conn.prepare_cached(
+                "SELECT content_type, content, encoding FROM message_parts
+                 WHERE message_pk = ?1 ORDER BY position",
+            )?;
+            let rows = stmt.query_map(params![pk], |row| {
+                Ok(MessagePart {
+                    content_type: row.get(0)?,
+                    content: row.get(1)?,
+                    encoding: row.get(2)?,
+                })
+            })?;
+            for part in rows {
+                parts.push(part?);
+            }
+        }
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT filename, content_type, size, data FROM attachments
+             WHERE message_pk = ?1 ORDER BY position",
+        )?;
+        let rows = stmt.query_map(params![pk], |row| {
+            Ok(Attachment {
+                filename: row.get(0)?,
+                content_type: row.get(1)?,
+                size: row.get(2)?,
+                data: row.get(3)?,
+            })
+        })?;
+        for attachment in rows {
+            message.attachments.push(attachment?);
+        }
 
         Ok(())
     }
 
+    /// クエリ結果の行をすべて完全なメッセージとして読み込む
+    fn collect_messages(
+        &self,
+        rows: impl Iterator<Item = rusqlite::Result<(i64, Message)>>,
+    ) -> StorageResult<Vec<Message>> {
+        let mut messages = Vec::new();
+        for row in rows {
+            let (pk, mut message) =
+                row.map_err(|e| StorageError::Database(format!("Failed to load message: {}", e)))?;
+            self.load_details(pk, &mut message).map_err(|e| {
+                StorageError::Database(format!("Failed to load message details: {}", e))
+            })?;
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
     pub fn get_messages(
         &self,
         account_id: &str,
@@ -167,86 +399,124 @@ impl Database {
         let mut stmt = self
             .conn
             .prepare(
-                "SELECT id, account_id, folder, subject, from_addr, to_addr, 
-                    date, body, flags, raw_message
-             FROM messages 
-             WHERE account_id = ?1 AND folder = ?2 
-             ORDER BY date DESC 
+                "SELECT * FROM messages
+             WHERE account_id = ?1 AND folder = ?2
+             ORDER BY date DESC, date_nanos DESC
              LIMIT ?3 OFFSET ?4",
             )
             .map_err(|e| StorageError::Database(format!("Failed to prepare statement: {}", e)))?;
 
-        let message_iter = stmt
-            .query_map(params![account_id, folder, limit, offset], |row| {
-                // TODO: データベースから完全なメッセージオブジェクトを復元
-                // 現在は簡単な実装のみ
-                Ok(Message::new(
-                    row.get(0)?,
-                    vec![], // from
-                    vec![], // to
-                    row.get(3)?,
-                    crate::mail::MessageBody::new_plain(row.get(7)?),
-                    row.get(1)?,
-                    row.get(2)?,
-                ))
-            })
+        let rows = stmt
+            .query_map(
+                params![account_id, folder, limit, offset],
+                Self::message_from_row,
+            )
             .map_err(|e| StorageError::Database(format!("Failed to query messages: {}", e)))?;
 
-        let mut messages = Vec::new();
-        for message in message_iter {
-            messages.push(
-                message.map_err(|e| {
-                    StorageError::Database(format!("Failed to load message: {}", e))
-                })?,
-            );
-        }
-
-        Ok(messages)
+        self.collect_messages(rows)
     }
 
-    pub fn search_messages(&self, account_id: &str, query: &str) -> StorageResult<Vec<Message>> {
+    /// 検索クエリに一致するメッセージを取得する（`account_id` が `None` なら全アカウント）
+    ///
+    /// 検索語があればbm25で関連度の高い順（件名と差出人の一致を重く見る）に並べ、
+    /// 一致箇所を `SNIPPET_START`/`SNIPPET_END` で囲んだスニペットを付ける。
+    /// 検索語がなければ新しい順。
+    pub fn search_messages(
+        &self,
+        query: &Query,
+        account_id: Option<&str>,
+    ) -> StorageResult<Vec<SearchHit>> {
+        let mut values = Vec::new();
+        let (ranking, order) = match query.fts_match() {
+            Some(expression) => {
+                values.push(Value::Text(expression));
+                values.push(Value::Text(SNIPPET_START.to_string()));
+                values.push(Value::Text(SNIPPET_END.to_string()));
+                (
+                    "LEFT JOIN (
+                     SELECT rowid,
+                            bm25(messages_fts, 10.0, 5.0, 2.0, 1.0) AS score,
+                            snippet(messages_fts, -1, ?2, ?3, '…', 32) AS snippet
+                     FROM messages_fts WHERE messages_fts MATCH ?1
+                 ) hit ON hit.rowid = m.pk",
+                    "hit.score IS NULL, hit.score,",
+                )
+            }
+            None => ("LEFT JOIN (SELECT NULL AS snippet) hit ON 0", ""),
+        };
+        let account = match account_id {
+            Some(account_id) => {
+                values.push(Value::Text(account_id.to_string()));
+                format!("m.account_id = ?{}", values.len())
+            }
+            None => "1".to_string(),
+        };
+        let condition = query.to_sql(&mut values);
+
         let mut stmt = self
             .conn
-            .prepare(
-                "SELECT m.id, m.account_id, m.folder, m.subject, m.from_addr, 
-                    m.to_addr, m.date, m.body, m.flags, m.raw_message
-             FROM messages m
-             JOIN messages_fts fts ON m.rowid = fts.rowid
-             WHERE m.account_id = ?1 AND messages_fts MATCH ?2
-             ORDER BY m.date DESC",
-            )
+            .prepare(&format!(
+                "SELECT m.*, hit.snippet AS snippet FROM messages m
+                 {}
+                 WHERE {} AND {}
+                 ORDER BY {} m.date DESC, m.date_nanos DESC",
+                ranking, account, condition, order
+            ))
             .map_err(|e| {
                 StorageError::Database(format!("Failed to prepare search statement: {}", e))
             })?;
 
-        let message_iter = stmt
-            .query_map(params![account_id, query], |row| {
-                // TODO: 完全なメッセージオブジェクトを復元
-                Ok(Message::new(
-                    row.get(0)?,
-                    vec![], // from
-                    vec![], // to
-                    row.get(3)?,
-                    crate::mail::MessageBody::new_plain(row.get(7)?),
-                    row.get(1)?,
-                    row.get(2)?,
+        let rows = stmt
+            .query_map(params_from_iter(values), |row| {
+                Ok((
+                    Self::message_from_row(row)?,
+                    row.get::<_, Option<String>>("snippet")?,
                 ))
             })
             .map_err(|e| StorageError::Database(format!("Failed to search messages: {}", e)))?;
 
-        let mut messages = Vec::new();
-        for message in message_iter {
-            messages.push(message.map_err(|e| {
-                StorageError::Database(format!("Failed to load search result: {}", e))
-            })?);
+        let mut hits = Vec::new();
+        for row in rows {
+            let ((pk, mut message), snippet) =
+                row.map_err(|e| StorageError::Database(format!("Failed to load message: {}", e)))?;
+            self.load_details(pk, &mut message).map_err(|e| {
+                StorageError::Database(format!("Failed to load message details: {}", e))
+            })?;
+            // 索引で引けない短い語だけで一致したときはここで抜粋を作る
+            let snippet = snippet.or_else(|| query.snippet(&message));
+            hits.push(SearchHit { message, snippet });
         }
+        Ok(hits)
+    }
 
-        Ok(messages)
+    /// メッセージがキャッシュにあるか
+    pub fn has_message(
+        &self,
+        account_id: &str,
+        folder: &str,
+        message_id: &str,
+    ) -> StorageResult<bool> {
+        self.conn
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM messages
+                                WHERE account_id = ?1 AND folder = ?2 AND id = ?3)",
+                params![account_id, folder, message_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to look up message: {}", e)))
     }
 
-    pub fn delete_message(&mut self, message_id: &str) -> StorageResult<()> {
+    pub fn delete_message(
+        &mut self,
+        account_id: &str,
+        folder: &str,
+        message_id: &str,
+    ) -> StorageResult<()> {
         self.conn
-            .execute("DELETE FROM messages WHERE id = ?1", params![message_id])
+            .execute(
+                "DELETE FROM messages WHERE account_id = ?1 AND folder = ?2 AND id = ?3",
+                params![account_id, folder, message_id],
+            )
             .map_err(|e| StorageError::Database(format!("Failed to delete message: {}", e)))?;
 
         Ok(())
@@ -254,16 +524,19 @@ impl Database {
 
     pub fn update_message_flags(
         &mut self,
+        account_id: &str,
+        folder: &str,
         message_id: &str,
-        flags: &[crate::mail::Flag],
+        flags: &[Flag],
     ) -> StorageResult<()> {
         let flags_json = serde_json::to_string(flags)
             .map_err(|e| StorageError::Database(format!("Failed to serialize flags: {}", e)))?;
 
         self.conn
             .execute(
-                "UPDATE messages SET flags = ?1 WHERE id = ?2",
-                params![flags_json, message_id],
+                "UPDATE messages SET flags = ?1
+                 WHERE account_id = ?2 AND folder = ?3 AND id = ?4",
+                params![flags_json, account_id, folder, message_id],
             )
             .map_err(|e| StorageError::Database(format!("Failed to update flags: {}", e)))?;
 
@@ -301,3 +574,344 @@ impl Database {
         Ok(())
     }
 }
+
+/// 検索用にアドレスを「名前 <メール>」形式で連結
+fn search_text(addresses: &[Address]) -> String {
+    addresses
+        .iter()
+        .map(|address| match &address.name {
+            Some(name) => format!("{} <{}>", name, address.email),
+            None => address.email.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::BODY_PLACEHOLDER;
+
+    fn message(id: &str, minutes_ago: i64) -> Message {
+        let mut message = Message::new(
+            id.to_string(),
+            vec![Address::new(
+                "alice@example.com".to_string(),
+                Some("Alice".to_string()),
+            )],
+            vec![],
+            format!("Subject {}", id),
+            MessageBody::new_plain("body".to_string()),
+            "work".to_string(),
+            "INBOX".to_string(),
+        );
+        message.date = Utc::now() - chrono::Duration::minutes(minutes_ago);
+        message
+    }
+
+    #[test]
+    fn test_merge_folder() {
+        let mut db = Database::new(":memory:").unwrap();
+
+        db.merge_folder(
+            "work",
+            "INBOX",
+            &[message("1", 300), message("2", 20), message("3", 10)],
+            FetchedRange::All,
+        )
+        .unwrap();
+
+        // UID 3以降だけを取得した。範囲外の1と2は、取得したメッセージより
+        // 新しい日付でも残る
+        let mut updated = message("3", 10);
+        updated.mark_as_read();
+        db.merge_folder(
+            "work",
+            "INBOX",
+            &[message("4", 5), updated, message("5", 400)],
+            FetchedRange::FromUid(3),
+        )
+        .unwrap();
+
+        let cached = db.get_messages("work", "INBOX", None, None).unwrap();
+        let ids: Vec<_> = cached.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["4", "3", "2", "1", "5"]);
+
+        let message3 = cached.iter().find(|m| m.id == "3").unwrap();
+        assert!(!message3.is_unread());
+        assert_eq!(message3.get_sender_display(), "Alice");
+
+        // UID 2以降を取得したのに返らなかった2は、サーバーで削除されている
+        db.merge_folder(
+            "work",
+            "INBOX",
+            &[message("3", 10), message("4", 5), message("5", 400)],
+            FetchedRange::FromUid(2),
+        )
+        .unwrap();
+        let cached = db.get_messages("work", "INBOX", None, None).unwrap();
+        let ids: Vec<_> = cached.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, vec!["4", "3", "1", "5"]);
+
+        // 範囲を特定できない取得では何も削除しない
+        db.merge_folder("work", "INBOX", &[message("4", 5)], FetchedRange::Partial)
+            .unwrap();
+        assert_eq!(
+            db.get_messages("work", "INBOX", None, None).unwrap().len(),
+            4
+        );
+
+        // 同じIDでも別フォルダーのメッセージは別物として扱う
+        let mut other = message("1", 0);
+        other.folder = "Archive".to_string();
+        db.store_message(&other).unwrap();
+        assert_eq!(
+            db.get_messages("work", "INBOX", None, None).unwrap().len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_merge_folder_keeps_loaded_bodies() {
+        let mut db = Database::new(":memory:").unwrap();
+        let mut loaded = message("1", 10);
+        loaded.raw = Some(b"Subject: test\r\n\r\nbody\r\n".to_vec());
+        db.store_message(&loaded).unwrap();
+
+        // IMAPの一覧で取得し直しても、取得済みの本文は残してフラグだけ反映する
+        let mut listed = message("1", 10);
+        listed.body = MessageBody::new_plain(BODY_PLACEHOLDER.to_string());
+        listed.raw = None;
+        listed.body_pending = true;
+        listed.mark_as_read();
+        db.merge_folder("work", "INBOX", &[listed], FetchedRange::All)
+            .unwrap();
+
+        let cached = db.get_messages("work", "INBOX", None, None).unwrap();
+        assert!(!cached[0].body_pending);
+        assert!(!cached[0].is_unread());
+        assert_eq!(cached[0].raw, loaded.raw);
+    }
+
+    fn full_message() -> Message {
+        let mut message = Message::new(
+            "42".to_string(),
+            vec![Address::new(
+                "alice@example.com".to_string(),
+                Some("Alice 山田".to_string()),
+            )],
+            vec![
+                Address::new("bob@example.com".to_string(), None),
+                Address::new("carol@example.com".to_string(), Some("Carol".to_string())),
+            ],
+            "Re: 会議の件".to_string(),
+            MessageBody::new_html("<p>こんにちは</p>".to_string()),
+            "work".to_string(),
+            "INBOX".to_string(),
+        );
+        message.cc = vec![Address::new("dave@example.com".to_string(), None)];
+        message.bcc = vec![Address::new(
+            "erin@example.com".to_string(),
+            Some("Erin".to_string()),
+        )];
+        message.date = Utc.timestamp_opt(1_700_000_000, 123_456_789).unwrap();
+        message.flags = vec![
+            Flag::Seen,
+            Flag::Flagged,
+            Flag::Custom("$Label1".to_string()),
+        ];
+        message.thread_id = Some("thread-1".to_string());
+        message.message_id = Some("reply@example.com".to_string());
+        message.in_reply_to = Some("start@example.com".to_string());
+        message.references = vec![
+            "root@example.com".to_string(),
+            "start@example.com".to_string(),
+        ];
+        message.raw = Some(b"Subject: Re: test\r\n\r\nbody\r\n".to_vec());
+        message.add_attachment(Attachment::new(
+            "report.pdf".to_string(),
+            "application/pdf".to_string(),
+            vec![0x25, 0x50, 0x44, 0x46, 0x00, 0xff],
+        ));
+        message.add_attachment(Attachment::new(
+            "photo.jpg".to_string(),
+            "image/jpeg".to_string(),
+            vec![0xff, 0xd8],
+        ));
+        message
+    }
+
+    fn assert_same(actual: &Message, expected: &Message) {
+        assert_eq!(
+            serde_json::to_value(actual).unwrap(),
+            serde_json::to_value(expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_round_trip_all_fields() {
+        let mut db = Database::new(":memory:").unwrap();
+        let message = full_message();
+        db.store_message(&message).unwrap();
+
+        let loaded = db.get_messages("work", "INBOX", None, None).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_same(&loaded[0], &message);
+    }
+
+    #[test]
+    fn test_round_trip_multipart_and_plain() {
+        let mut db = Database::new(":memory:").unwrap();
+
+        let mut multipart = full_message();
+        multipart.id = "43".to_string();
+        multipart.body = MessageBody::Multipart {
+            parts: vec![
+                MessagePart {
+                    content_type: "text/plain".to_string(),
+                    content: "plain".to_string(),
+                    encoding: Some("quoted-printable".to_string()),
+                },
+                MessagePart {
+                    content_type: "text/html".to_string(),
+                    content: "<b>html</b>".to_string(),
+                    encoding: None,
+                },
+            ],
+        };
+
+        let mut plain = message("44", 0);
+        plain.from.clear();
+        plain.raw = None;
+
+        db.store_message(&multipart).unwrap();
+        db.store_message(&plain).unwrap();
+
+        let loaded = db.get_messages("work", "INBOX", None, None).unwrap();
+        let find = |id: &str| loaded.iter().find(|m| m.id == id).unwrap();
+        assert_same(find("43"), &multipart);
+        assert_same(find("44"), &plain);
+    }
+
+    #[test]
+    fn test_update_replaces_details() {
+        let mut db = Database::new(":memory:").unwrap();
+        let mut message = full_message();
+        db.store_message(&message).unwrap();
+
+        message.to.truncate(1);
+        message.attachments.clear();
+        message.flags = vec![Flag::Answered];
+        db.store_message(&message).unwrap();
+
+        let loaded = db.get_messages("work", "INBOX", None, None).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_same(&loaded[0], &message);
+    }
+
+    #[test]
+    fn test_search_and_delete_restore_full_messages() {
+        let mut db = Database::new(":memory:").unwrap();
+        let message = full_message();
+        db.store_message(&message).unwrap();
+
+        let found = db
+            .search_messages(&Query::parse("carol").unwrap(), Some("work"))
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_same(&found[0].message, &message);
+
+        db.delete_message("work", "INBOX", "42").unwrap();
+        assert!(db
+            .get_messages("work", "INBOX", None, None)
+            .unwrap()
+            .is_empty());
+
+        // 子テーブルの行も消える
+        let orphans: i64 = db
+            .conn
+            .query_row(
+                "SELECT (SELECT COUNT(*) FROM message_addresses)
+                      + (SELECT COUNT(*) FROM attachments)",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(orphans, 0);
+        assert_eq!(fts_hits(&db, "carol"), 0);
+    }
+
+    fn fts_hits(db: &Database, expression: &str) -> i64 {
+        db.conn
+            .query_row(
+                "SELECT COUNT(*) FROM messages_fts WHERE messages_fts MATCH ?1",
+                [expression],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fts_index_follows_updates() {
+        let mut db = Database::new(":memory:").unwrap();
+        let mut message = message("1", 0);
+        message.subject = "Quarterly budget".to_string();
+        db.store_message(&message).unwrap();
+        assert_eq!(fts_hits(&db, "budget"), 1);
+
+        // 書き換え前の語は索引から消える
+        message.subject = "Holiday plans".to_string();
+        db.store_message(&message).unwrap();
+        assert_eq!(fts_hits(&db, "budget"), 0);
+        assert_eq!(fts_hits(&db, "holiday"), 1);
+
+        // フラグだけの更新では索引を書き換えない
+        db.update_message_flags("work", "INBOX", "1", &[Flag::Seen])
+            .unwrap();
+        assert_eq!(fts_hits(&db, "holiday"), 1);
+
+        db.delete_message("work", "INBOX", "1").unwrap();
+        assert_eq!(fts_hits(&db, "holiday"), 0);
+    }
+
+    #[test]
+    fn test_search_ranks_by_relevance_across_accounts() {
+        let mut db = Database::new(":memory:").unwrap();
+
+        let mut newest = message("1", 10);
+        newest.body =
+            MessageBody::new_plain("Lunch menu, with a passing mention of the release".into());
+        let mut in_subject = message("2", 300);
+        in_subject.subject = "Release notes".to_string();
+        in_subject.body = MessageBody::new_plain("The release ships on Friday.".into());
+        in_subject.account_id = "home".to_string();
+        let unrelated = message("3", 200);
+        for message in [&newest, &in_subject, &unrelated] {
+            db.store_message(message).unwrap();
+        }
+
+        let query = Query::parse("release").unwrap();
+        let hits = db.search_messages(&query, None).unwrap();
+        let ids: Vec<&str> = hits.iter().map(|hit| hit.message.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "1"]);
+
+        let snippet = hits[1].snippet.as_deref().unwrap();
+        assert!(
+            snippet.contains(&format!("{}release{}", SNIPPET_START, SNIPPET_END)),
+            "{}",
+            snippet
+        );
+
+        // アカウントを指定すればそのアカウントだけ
+        let hits = db.search_messages(&query, Some("work")).unwrap();
+        assert_eq!(hits.len(), 1);
+
+        // 検索語がなければ新しい順でスニペットなし
+        let hits = db
+            .search_messages(&Query::parse("").unwrap(), None)
+            .unwrap();
+        let ids: Vec<&str> = hits.iter().map(|hit| hit.message.id.as_str()).collect();
+        assert_eq!(ids, vec!["1", "3", "2"]);
+        assert!(hits.iter().all(|hit| hit.snippet.is_none()));
+    }
+}
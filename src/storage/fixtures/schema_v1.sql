@@ -0,0 +1,43 @@
+-- スキーマv1（バージョン管理導入前のレイアウト）で作ったデータベースの再現
+CREATE TABLE messages (
+    id TEXT PRIMARY KEY,
+    account_id TEXT NOT NULL,
+    folder TEXT NOT NULL,
+    subject TEXT,
+    from_addr TEXT,
+    to_addr TEXT,
+    date INTEGER,
+    body TEXT,
+    flags TEXT,
+    raw_message TEXT
+);
+CREATE TABLE accounts (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    config TEXT NOT NULL
+);
+CREATE TABLE folders (
+    id TEXT PRIMARY KEY,
+    account_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    server_name TEXT NOT NULL,
+    message_count INTEGER DEFAULT 0,
+    unread_count INTEGER DEFAULT 0,
+    FOREIGN KEY (account_id) REFERENCES accounts(id)
+);
+CREATE VIRTUAL TABLE messages_fts USING fts5(
+    subject, from_addr, to_addr, body,
+    content=messages,
+    content_rowid=rowid
+);
+CREATE INDEX idx_messages_account_folder ON messages(account_id, folder);
+CREATE INDEX idx_messages_date ON messages(date DESC);
+
+INSERT INTO messages VALUES
+    ('101', 'work', 'INBOX', '週次ミーティング', 'Alice', 'Bob, Carol', 1700000000,
+     '議事録を共有します', '["Seen"]', '議事録を共有します'),
+    ('102', 'work', 'INBOX', 'Invoice', 'billing@example.com', 'Bob', 1700003600,
+     'Please find attached', '[]', 'Please find attached');
+INSERT INTO messages_fts (rowid, subject, from_addr, to_addr, body)
+    SELECT rowid, subject, from_addr, to_addr, body FROM messages;
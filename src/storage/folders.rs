@@ -0,0 +1,208 @@
+use rusqlite::params;
+
+use super::{Database, StorageError, StorageResult};
+use crate::mail::{Folder, FolderType};
+
+/// サーバーから最後に取得したフォルダー一覧（オフラインでもフォルダーを表示するため）
+impl Database {
+    pub fn folders(&self, account_id: &str) -> StorageResult<Vec<Folder>> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT name, delimiter, selectable, role, subscribed FROM folders
+                 WHERE account_id = ?1 ORDER BY rowid",
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to prepare statement: {}", e)))?;
+
+        let rows = stmt
+            .query_map(params![account_id], |row| {
+                let role: Option<String> = row.get(3)?;
+                Ok(Folder {
+                    name: row.get(0)?,
+                    delimiter: row.get(1)?,
+                    selectable: row.get(2)?,
+                    role: role.and_then(|role| serde_json::from_str::<FolderType>(&role).ok()),
+                    subscribed: row.get(4)?,
+                })
+            })
+            .map_err(|e| StorageError::Database(format!("Failed to query folders: {}", e)))?;
+
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| StorageError::Database(format!("Failed to read row: {}", e)))
+    }
+
+    /// アカウントのフォルダー一覧をサーバーから取得したものに置き換える
+    pub fn replace_folders(&mut self, account_id: &str, folders: &[Folder]) -> StorageResult<()> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|e| StorageError::Database(format!("Failed to begin transaction: {}", e)))?;
+
+        tx.execute(
+            "DELETE FROM folders WHERE account_id = ?1",
+            params![account_id],
+        )
+        .map_err(|e| StorageError::Database(format!("Failed to delete folders: {}", e)))?;
+        for folder in folders {
+            let role = folder
+                .role
+                .as_ref()
+                .and_then(|role| serde_json::to_string(role).ok());
+            tx.execute(
+                "INSERT OR REPLACE INTO folders
+                    (account_id, name, delimiter, selectable, role, subscribed)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    account_id,
+                    folder.name,
+                    folder.delimiter,
+                    folder.selectable,
+                    role,
+                    folder.subscribed
+                ],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to store folder: {}", e)))?;
+        }
+
+        tx.commit()
+            .map_err(|e| StorageError::Database(format!("Failed to commit transaction: {}", e)))
+    }
+
+    /// サーバーでフォルダー名を変更した後、キャッシュと未反映の操作のフォルダー名を合わせる
+    ///
+    /// `delimiter` があれば、その区切り文字の下にあるサブフォルダーも移す。
+    pub fn rename_cached_folder(
+        &mut self,
+        account_id: &str,
+        from: &str,
+        to: &str,
+        delimiter: Option<&str>,
+    ) -> StorageResult<()> {
+        let prefix = delimiter.map(|delimiter| format!("{}{}", from, delimiter));
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|e| StorageError::Database(format!("Failed to begin transaction: {}", e)))?;
+
+        for table in ["messages", "pending_operations"] {
+            tx.execute(
+                &format!(
+                    "UPDATE {} SET folder = ?3 || substr(folder, length(?2) + 1)
+                     WHERE account_id = ?1
+                       AND (folder = ?2 OR substr(folder, 1, length(?4)) = ?4)",
+                    table
+                ),
+                params![account_id, from, to, prefix],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to rename folder: {}", e)))?;
+        }
+
+        tx.commit()
+            .map_err(|e| StorageError::Database(format!("Failed to commit transaction: {}", e)))
+    }
+
+    /// サーバーで削除したフォルダーのメッセージと未反映の操作をキャッシュから消す
+    pub fn delete_cached_folder(&mut self, account_id: &str, folder: &str) -> StorageResult<()> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|e| StorageError::Database(format!("Failed to begin transaction: {}", e)))?;
+
+        for table in ["messages", "pending_operations"] {
+            tx.execute(
+                &format!(
+                    "DELETE FROM {} WHERE account_id = ?1 AND folder = ?2",
+                    table
+                ),
+                params![account_id, folder],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to delete folder: {}", e)))?;
+        }
+
+        tx.commit()
+            .map_err(|e| StorageError::Database(format!("Failed to commit transaction: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::{Address, Flag, Message, MessageBody};
+    use crate::storage::MailOperation;
+
+    #[test]
+    fn test_replace_folders() {
+        let mut db = Database::new(":memory:").unwrap();
+        let mut gmail = Folder::new("[Gmail]", Some("/"));
+        gmail.selectable = false;
+        gmail.subscribed = false;
+        let sent = Folder::new("[Gmail]/Sent Mail", Some("/")).with_role(Some(FolderType::Sent));
+        db.replace_folders(
+            "work",
+            &[Folder::new("INBOX", Some("/")), gmail.clone(), sent.clone()],
+        )
+        .unwrap();
+        db.replace_folders("home", &[Folder::new("INBOX", None)])
+            .unwrap();
+        assert_eq!(
+            db.folders("work").unwrap(),
+            vec![Folder::new("INBOX", Some("/")), gmail, sent]
+        );
+
+        // 取得し直した一覧で置き換わる（他のアカウントはそのまま）
+        db.replace_folders("work", &[Folder::new("Archive", Some("."))])
+            .unwrap();
+        assert_eq!(
+            db.folders("work").unwrap(),
+            vec![Folder::new("Archive", Some("."))]
+        );
+        assert_eq!(db.folders("home").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_rename_and_delete_cached_folder() {
+        let mut db = Database::new(":memory:").unwrap();
+        for (id, folder) in [
+            ("1", "Projects"),
+            ("2", "Projects/2024"),
+            ("3", "Projects2"),
+            ("4", "INBOX"),
+        ] {
+            let mut message = Message::new(
+                id.to_string(),
+                vec![Address::new("alice@example.com".to_string(), None)],
+                Vec::new(),
+                format!("Message {}", id),
+                MessageBody::new_plain(String::new()),
+                "work".to_string(),
+                folder.to_string(),
+            );
+            message.flags = vec![Flag::Seen];
+            db.store_message(&message).unwrap();
+        }
+        let flag = MailOperation::SetFlag {
+            flag: Flag::Flagged,
+            enabled: true,
+        };
+        db.record_operation("work", "Projects/2024", "2", &flag)
+            .unwrap();
+
+        // サブフォルダーも移るが、名前が前方一致するだけのフォルダーはそのまま
+        db.rename_cached_folder("work", "Projects", "Work", Some("/"))
+            .unwrap();
+        let count = |db: &Database, folder: &str| db.get_folder_stats("work", folder).unwrap().0;
+        assert_eq!(count(&db, "Work"), 1);
+        assert_eq!(count(&db, "Work/2024"), 1);
+        assert_eq!(count(&db, "Projects2"), 1);
+        assert_eq!(count(&db, "Projects"), 0);
+        assert_eq!(
+            db.pending_operations("work").unwrap()[0].folder,
+            "Work/2024"
+        );
+
+        db.delete_cached_folder("work", "Work/2024").unwrap();
+        assert_eq!(count(&db, "Work/2024"), 0);
+        assert_eq!(db.pending_operation_count().unwrap(), 0);
+        assert_eq!(count(&db, "Work"), 1);
+    }
+}
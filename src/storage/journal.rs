@@ -0,0 +1,448 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use super::{Database, StorageError, StorageResult};
+use crate::mail::Flag;
+
+/// オフライン中に行い、まだサーバーへ反映していないメッセージ操作
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum MailOperation {
+    SetFlag { flag: Flag, enabled: bool },
+    Move { to_folder: String },
+    Delete,
+}
+
+/// ジャーナルに記録された操作（`folder` はサーバー上でメッセージがあるフォルダー）
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingOperation {
+    pub id: i64,
+    pub account_id: String,
+    pub folder: String,
+    pub message_id: String,
+    pub operation: MailOperation,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+impl Database {
+    /// 操作をキャッシュへ即座に適用し、サーバーへの反映待ちとして記録する
+    ///
+    /// オフラインで移動したメッセージへの操作は、サーバー上の元のフォルダーに対する
+    /// 操作へ書き換える（移動先での UID はまだ分からないため）。
+    pub fn record_operation(
+        &mut self,
+        account_id: &str,
+        folder: &str,
+        message_id: &str,
+        operation: &MailOperation,
+    ) -> StorageResult<()> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|e| StorageError::Database(format!("Failed to begin transaction: {}", e)))?;
+
+        apply_operation(&tx, account_id, folder, message_id, operation)?;
+
+        match find_pending_move(&tx, account_id, folder, message_id)? {
+            Some((move_id, source_folder)) => match operation {
+                MailOperation::SetFlag { .. } => {
+                    // フラグ変更を元のフォルダーで先に反映し、移動はその後ろへ並べ直す
+                    insert_operation(&tx, account_id, &source_folder, message_id, operation)?;
+                    tx.execute(
+                        "UPDATE pending_operations
+                         SET id = (SELECT MAX(id) + 1 FROM pending_operations)
+                         WHERE id = ?1",
+                        params![move_id],
+                    )
+                    .map_err(|e| {
+                        StorageError::Database(format!("Failed to reorder operation: {}", e))
+                    })?;
+                }
+                MailOperation::Move { to_folder } => {
+                    delete_operation(&tx, move_id)?;
+                    if *to_folder != source_folder {
+                        insert_operation(&tx, account_id, &source_folder, message_id, operation)?;
+                    }
+                }
+                MailOperation::Delete => {
+                    delete_operation(&tx, move_id)?;
+                    insert_operation(&tx, account_id, &source_folder, message_id, operation)?;
+                }
+            },
+            None => insert_operation(&tx, account_id, folder, message_id, operation)?,
+        }
+
+        tx.commit()
+            .map_err(|e| StorageError::Database(format!("Failed to commit transaction: {}", e)))?;
+
+        Ok(())
+    }
+
+    /// アカウントの反映待ち操作を記録順に取得
+    pub fn pending_operations(&self, account_id: &str) -> StorageResult<Vec<PendingOperation>> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT id, account_id, folder, message_id, operation, attempts, last_error
+                 FROM pending_operations WHERE account_id = ?1 ORDER BY id",
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to prepare statement: {}", e)))?;
+
+        let rows = stmt
+            .query_map(params![account_id], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, u32>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                ))
+            })
+            .map_err(|e| StorageError::Database(format!("Failed to query operations: {}", e)))?;
+
+        let mut operations = Vec::new();
+        for row in rows {
+            let (id, account_id, folder, message_id, operation, attempts, last_error) =
+                row.map_err(|e| StorageError::Database(format!("Failed to read row: {}", e)))?;
+            let operation = serde_json::from_str(&operation)
+                .map_err(|e| StorageError::Parse(format!("Invalid pending operation: {}", e)))?;
+            operations.push(PendingOperation {
+                id,
+                account_id,
+                folder,
+                message_id,
+                operation,
+                attempts,
+                last_error,
+            });
+        }
+
+        Ok(operations)
+    }
+
+    /// 反映待ちの操作があるアカウントのID（最初に操作を記録した順）
+    pub fn pending_operation_accounts(&self) -> StorageResult<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT account_id FROM pending_operations
+                 GROUP BY account_id ORDER BY MIN(id)",
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to prepare statement: {}", e)))?;
+
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(|e| StorageError::Database(format!("Failed to query operations: {}", e)))?;
+
+        rows.collect::<Result<Vec<_>, _>>()
+            .map_err(|e| StorageError::Database(format!("Failed to read row: {}", e)))
+    }
+
+    /// 全アカウントの反映待ち操作の件数
+    pub fn pending_operation_count(&self) -> StorageResult<usize> {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM pending_operations", [], |row| {
+                row.get::<_, usize>(0)
+            })
+            .map_err(|e| StorageError::Database(format!("Failed to count operations: {}", e)))
+    }
+
+    /// サーバーへ反映した（または破棄した）操作をジャーナルから消す
+    pub fn complete_operation(&mut self, id: i64) -> StorageResult<()> {
+        delete_operation(&self.conn, id)
+    }
+
+    /// 反映に失敗した操作のエラーを記録し、これまでの試行回数を返す
+    pub fn record_operation_failure(&mut self, id: i64, error: &str) -> StorageResult<u32> {
+        self.conn
+            .query_row(
+                "UPDATE pending_operations SET attempts = attempts + 1, last_error = ?2
+                 WHERE id = ?1 RETURNING attempts",
+                params![id, error],
+                |row| row.get(0),
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to record failure: {}", e)))
+    }
+}
+
+/// サーバーから取り直したフォルダーに、まだ反映していない操作をかけ直す
+pub(super) fn reapply_pending_operations(
+    conn: &Connection,
+    account_id: &str,
+    folder: &str,
+) -> StorageResult<()> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT message_id, operation FROM pending_operations
+             WHERE account_id = ?1 AND folder = ?2 ORDER BY id",
+        )
+        .map_err(|e| StorageError::Database(format!("Failed to prepare statement: {}", e)))?;
+
+    let rows = stmt
+        .query_map(params![account_id, folder], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(|e| StorageError::Database(format!("Failed to query operations: {}", e)))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| StorageError::Database(format!("Failed to read row: {}", e)))?;
+
+    for (message_id, operation) in rows {
+        let operation: MailOperation = serde_json::from_str(&operation)
+            .map_err(|e| StorageError::Parse(format!("Invalid pending operation: {}", e)))?;
+        apply_operation(conn, account_id, folder, &message_id, &operation)?;
+    }
+
+    Ok(())
+}
+
+/// キャッシュ上のメッセージに操作を適用（キャッシュにない場合は何もしない）
+fn apply_operation(
+    conn: &Connection,
+    account_id: &str,
+    folder: &str,
+    message_id: &str,
+    operation: &MailOperation,
+) -> StorageResult<()> {
+    match operation {
+        MailOperation::SetFlag { flag, enabled } => {
+            let flags_json: Option<String> = conn
+                .query_row(
+                    "SELECT flags FROM messages
+                     WHERE account_id = ?1 AND folder = ?2 AND id = ?3",
+                    params![account_id, folder, message_id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| StorageError::Database(format!("Failed to read flags: {}", e)))?;
+            let Some(flags_json) = flags_json else {
+                return Ok(());
+            };
+
+            let mut flags: Vec<Flag> = serde_json::from_str(&flags_json).unwrap_or_default();
+            flags.retain(|f| f != flag);
+            if *enabled {
+                flags.push(flag.clone());
+            }
+            let flags_json = serde_json::to_string(&flags)
+                .map_err(|e| StorageError::Database(format!("Failed to serialize flags: {}", e)))?;
+
+            conn.execute(
+                "UPDATE messages SET flags = ?4
+                 WHERE account_id = ?1 AND folder = ?2 AND id = ?3",
+                params![account_id, folder, message_id, flags_json],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to update flags: {}", e)))?;
+        }
+        MailOperation::Move { to_folder } => {
+            // 移動先に同じIDのメッセージがある場合は、次の同期まで一覧から消しておく
+            conn.execute(
+                "UPDATE OR IGNORE messages SET folder = ?4
+                 WHERE account_id = ?1 AND folder = ?2 AND id = ?3",
+                params![account_id, folder, message_id, to_folder],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to move message: {}", e)))?;
+            conn.execute(
+                "DELETE FROM messages WHERE account_id = ?1 AND folder = ?2 AND id = ?3",
+                params![account_id, folder, message_id],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to move message: {}", e)))?;
+        }
+        MailOperation::Delete => {
+            conn.execute(
+                "DELETE FROM messages WHERE account_id = ?1 AND folder = ?2 AND id = ?3",
+                params![account_id, folder, message_id],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to delete message: {}", e)))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// `folder` へのオフライン移動が反映待ちなら、その操作IDと移動元フォルダーを返す
+fn find_pending_move(
+    conn: &Connection,
+    account_id: &str,
+    folder: &str,
+    message_id: &str,
+) -> StorageResult<Option<(i64, String)>> {
+    conn.query_row(
+        "SELECT id, folder FROM pending_operations
+         WHERE account_id = ?1 AND message_id = ?2
+           AND json_extract(operation, '$.type') = 'Move'
+           AND json_extract(operation, '$.to_folder') = ?3
+         ORDER BY id DESC LIMIT 1",
+        params![account_id, message_id, folder],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+    .map_err(|e| StorageError::Database(format!("Failed to query operations: {}", e)))
+}
+
+fn insert_operation(
+    conn: &Connection,
+    account_id: &str,
+    folder: &str,
+    message_id: &str,
+    operation: &MailOperation,
+) -> StorageResult<()> {
+    // 同じフラグへの未反映の変更は最新のものだけ残す
+    if let MailOperation::SetFlag { flag, .. } = operation {
+        let flag_json = serde_json::to_string(flag)
+            .map_err(|e| StorageError::Database(format!("Failed to serialize flag: {}", e)))?;
+        conn.execute(
+            "DELETE FROM pending_operations
+             WHERE account_id = ?1 AND folder = ?2 AND message_id = ?3
+               AND json_extract(operation, '$.type') = 'SetFlag'
+               AND json_extract(operation, '$.flag') = json_extract(?4, '$')",
+            params![account_id, folder, message_id, flag_json],
+        )
+        .map_err(|e| StorageError::Database(format!("Failed to coalesce operation: {}", e)))?;
+    }
+
+    let operation_json = serde_json::to_string(operation)
+        .map_err(|e| StorageError::Database(format!("Failed to serialize operation: {}", e)))?;
+
+    conn.execute(
+        "INSERT INTO pending_operations (account_id, folder, message_id, operation, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            account_id,
+            folder,
+            message_id,
+            operation_json,
+            Utc::now().timestamp()
+        ],
+    )
+    .map_err(|e| StorageError::Database(format!("Failed to record operation: {}", e)))?;
+
+    Ok(())
+}
+
+fn delete_operation(conn: &Connection, id: i64) -> StorageResult<()> {
+    conn.execute("DELETE FROM pending_operations WHERE id = ?1", params![id])
+        .map_err(|e| StorageError::Database(format!("Failed to delete operation: {}", e)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::{Address, Message, MessageBody};
+    use crate::storage::FetchedRange;
+
+    fn database_with_message(id: &str, folder: &str) -> Database {
+        let mut db = Database::new(":memory:").unwrap();
+        let message = Message::new(
+            id.to_string(),
+            vec![Address::new("alice@example.com".to_string(), None)],
+            Vec::new(),
+            "件名".to_string(),
+            MessageBody::new_plain("本文".to_string()),
+            "work".to_string(),
+            folder.to_string(),
+        );
+        db.store_message(&message).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_operations_apply_locally_and_survive_merge() {
+        let mut db = database_with_message("7", "INBOX");
+        let seen = MailOperation::SetFlag {
+            flag: Flag::Seen,
+            enabled: true,
+        };
+        db.record_operation("work", "INBOX", "7", &seen).unwrap();
+
+        let cached = db.get_messages("work", "INBOX", None, None).unwrap();
+        assert!(!cached[0].is_unread());
+
+        // サーバーはまだ未読のまま返してくるが、ジャーナルの操作がかけ直される
+        let mut server_copy = cached[0].clone();
+        server_copy.flags.clear();
+        db.merge_folder("work", "INBOX", &[server_copy], FetchedRange::All)
+            .unwrap();
+        let cached = db.get_messages("work", "INBOX", None, None).unwrap();
+        assert!(!cached[0].is_unread());
+
+        // 同じフラグの変更は1件にまとめられる
+        db.record_operation(
+            "work",
+            "INBOX",
+            "7",
+            &MailOperation::SetFlag {
+                flag: Flag::Seen,
+                enabled: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(db.pending_operation_count().unwrap(), 1);
+
+        let pending = db.pending_operations("work").unwrap();
+        db.complete_operation(pending[0].id).unwrap();
+        assert_eq!(db.pending_operation_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_operations_on_moved_message_target_source_folder() {
+        let mut db = database_with_message("7", "INBOX");
+        let to_archive = MailOperation::Move {
+            to_folder: "Archive".to_string(),
+        };
+        db.record_operation("work", "INBOX", "7", &to_archive)
+            .unwrap();
+        assert!(db
+            .get_messages("work", "INBOX", None, None)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            db.get_messages("work", "Archive", None, None)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // 移動先でのフラグ変更は、移動前のフォルダーで先に反映される
+        let flagged = MailOperation::SetFlag {
+            flag: Flag::Flagged,
+            enabled: true,
+        };
+        db.record_operation("work", "Archive", "7", &flagged)
+            .unwrap();
+        let pending = db.pending_operations("work").unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].folder, "INBOX");
+        assert_eq!(pending[0].operation, flagged);
+        assert_eq!(pending[1].operation, to_archive);
+
+        // 移動先での削除は、移動を取り消して元のフォルダーでの削除になる
+        db.record_operation("work", "Archive", "7", &MailOperation::Delete)
+            .unwrap();
+        let pending = db.pending_operations("work").unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[1].folder, "INBOX");
+        assert_eq!(pending[1].operation, MailOperation::Delete);
+        assert!(db
+            .get_messages("work", "Archive", None, None)
+            .unwrap()
+            .is_empty());
+
+        let attempts = db
+            .record_operation_failure(pending[1].id, "timeout")
+            .unwrap();
+        assert_eq!(attempts, 1);
+
+        // 検索結果から操作した他のアカウントの分も反映の対象になる
+        db.record_operation("home", "INBOX", "3", &MailOperation::Delete)
+            .unwrap();
+        assert_eq!(
+            db.pending_operation_accounts().unwrap(),
+            vec!["work", "home"]
+        );
+    }
+}
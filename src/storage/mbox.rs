@@ -0,0 +1,347 @@
+use chrono::{DateTime, Utc};
+use std::io::{self, BufRead, Read, Write};
+
+use crate::mail::Flag;
+
+/// mboxの方言
+///
+/// - mboxrd: 本文中の `From ` 行（と既に `>` の付いたもの）に `>` を1つ足して区切りと区別する。
+///   読むときは1つ外すので、元のメッセージが正確に戻る（mutt、新しめのThunderbird）
+/// - mboxcl2: `Content-Length` ヘッダーで本文の長さを示し、本文は一切書き換えない
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MboxFormat {
+    Mboxrd,
+    Mboxcl2,
+}
+
+/// mboxファイルからメッセージを1通ずつ取り出す
+///
+/// ファイル全体を読み込まずに順に処理するので、大きなアーカイブでも使える。
+/// 取り出したメッセージは区切り行と引用を外した元のバイト列（改行はファイルのまま）。
+pub struct MboxReader<R> {
+    reader: R,
+    format: MboxFormat,
+    /// 次のメッセージの区切り行（読みすぎた分）
+    peeked: Option<Vec<u8>>,
+    bytes_read: u64,
+}
+
+impl<R: BufRead> MboxReader<R> {
+    pub fn new(reader: R, format: MboxFormat) -> Self {
+        Self {
+            reader,
+            format,
+            peeked: None,
+            bytes_read: 0,
+        }
+    }
+
+    /// これまでに読んだバイト数（進捗表示用）
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    fn read_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+
+        let mut line = Vec::new();
+        let read = self.reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.bytes_read += read as u64;
+        Ok(Some(line))
+    }
+
+    fn next_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        // 先頭の区切り行まで読み飛ばす
+        loop {
+            match self.read_line()? {
+                None => return Ok(None),
+                Some(line) if is_separator(&line) => break,
+                Some(_) => continue,
+            }
+        }
+
+        let mut message = Vec::new();
+        if self.format == MboxFormat::Mboxcl2 {
+            let mut content_length = None;
+            while let Some(line) = self.read_line()? {
+                if let Some(value) = header_value(&line, "Content-Length") {
+                    content_length = value.trim().parse::<u64>().ok();
+                }
+                let end_of_headers = is_blank(&line);
+                message.extend_from_slice(&line);
+                if end_of_headers {
+                    break;
+                }
+            }
+
+            // Content-Lengthがなければmboxo同様に次の区切り行までを本文とする
+            if let Some(length) = content_length {
+                let read = (&mut self.reader).take(length).read_to_end(&mut message)?;
+                self.bytes_read += read as u64;
+                return Ok(Some(message));
+            }
+        }
+
+        let unquote = self.format == MboxFormat::Mboxrd;
+        while let Some(line) = self.read_line()? {
+            if is_separator(&line) {
+                self.peeked = Some(line);
+                break;
+            }
+            if unquote && is_quoted_from(&line) {
+                message.extend_from_slice(&line[1..]);
+            } else {
+                message.extend_from_slice(&line);
+            }
+        }
+
+        // 区切り行の前の空行はメッセージに含めない
+        if message.ends_with(b"\r\n\r\n") {
+            message.truncate(message.len() - 2);
+        } else if message.ends_with(b"\n\n") {
+            message.truncate(message.len() - 1);
+        }
+
+        Ok(Some(message))
+    }
+}
+
+impl<R: BufRead> Iterator for MboxReader<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_message().transpose()
+    }
+}
+
+/// メッセージを1通mboxに書き出す
+///
+/// 改行はLFにそろえる。区切り行の送信者には封筒の差出人（不明ならMAILER-DAEMON）を使う。
+pub fn write_message<W: Write>(
+    writer: &mut W,
+    raw: &[u8],
+    sender: &str,
+    date: DateTime<Utc>,
+    format: MboxFormat,
+) -> io::Result<()> {
+    let mut raw = to_lf(raw);
+    if !raw.ends_with(b"\n") {
+        raw.push(b'\n');
+    }
+
+    let sender = if sender.is_empty() || sender.contains(char::is_whitespace) {
+        "MAILER-DAEMON"
+    } else {
+        sender
+    };
+    writeln!(
+        writer,
+        "From {} {}",
+        sender,
+        date.format("%a %b %e %H:%M:%S %Y")
+    )?;
+
+    match format {
+        MboxFormat::Mboxrd => {
+            for line in raw.split_inclusive(|b| *b == b'\n') {
+                if line.starts_with(b"From ") || is_quoted_from(line) {
+                    writer.write_all(b">")?;
+                }
+                writer.write_all(line)?;
+            }
+        }
+        MboxFormat::Mboxcl2 => {
+            let (headers, body) = split_headers(&raw);
+            let mut lines = headers.split_inclusive(|b| *b == b'\n').peekable();
+            while let Some(line) = lines.next() {
+                if header_value(line, "Content-Length").is_none() {
+                    writer.write_all(line)?;
+                    continue;
+                }
+                // 元のContent-Lengthは継続行ごと捨てる
+                while lines
+                    .peek()
+                    .is_some_and(|next| next.starts_with(b" ") || next.starts_with(b"\t"))
+                {
+                    lines.next();
+                }
+            }
+            writeln!(writer, "Content-Length: {}", body.len())?;
+            writer.write_all(b"\n")?;
+            writer.write_all(body)?;
+        }
+    }
+
+    writer.write_all(b"\n")
+}
+
+/// mutt/Thunderbirdが書くStatus・X-Status・X-Mozilla-Statusヘッダーからフラグを読む
+pub fn status_flags(raw: &[u8]) -> Vec<Flag> {
+    let mut flags = Vec::new();
+    let mut add = |flag: Flag| {
+        if !flags.contains(&flag) {
+            flags.push(flag);
+        }
+    };
+
+    let (headers, _) = split_headers(raw);
+    for line in headers.split_inclusive(|b| *b == b'\n') {
+        if let Some(value) = header_value(line, "Status") {
+            if value.contains('R') {
+                add(Flag::Seen);
+            }
+        } else if let Some(value) = header_value(line, "X-Status") {
+            for (c, flag) in [
+                ('A', Flag::Answered),
+                ('F', Flag::Flagged),
+                ('D', Flag::Deleted),
+                ('T', Flag::Draft),
+            ] {
+                if value.contains(c) {
+                    add(flag);
+                }
+            }
+        } else if let Some(value) = header_value(line, "X-Mozilla-Status") {
+            let Ok(bits) = u32::from_str_radix(value.trim(), 16) else {
+                continue;
+            };
+            for (mask, flag) in [
+                (0x0001, Flag::Seen),
+                (0x0002, Flag::Answered),
+                (0x0004, Flag::Flagged),
+                (0x0008, Flag::Deleted),
+            ] {
+                if bits & mask != 0 {
+                    add(flag);
+                }
+            }
+        }
+    }
+
+    flags
+}
+
+fn is_separator(line: &[u8]) -> bool {
+    line.starts_with(b"From ")
+}
+
+/// `>From `、`>>From ` のように引用された区切り行か
+fn is_quoted_from(line: &[u8]) -> bool {
+    let unquoted = line.iter().position(|b| *b != b'>').unwrap_or(line.len());
+    unquoted > 0 && line[unquoted..].starts_with(b"From ")
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line == b"\n" || line == b"\r\n"
+}
+
+/// ヘッダー行が指定の名前なら値を返す（名前は大文字小文字を区別しない）
+fn header_value<'a>(line: &'a [u8], name: &str) -> Option<&'a str> {
+    let colon = line.iter().position(|b| *b == b':')?;
+    if !line[..colon].eq_ignore_ascii_case(name.as_bytes()) {
+        return None;
+    }
+    std::str::from_utf8(&line[colon + 1..]).ok()
+}
+
+/// ヘッダー（末尾の空行を除く）と本文に分ける
+fn split_headers(raw: &[u8]) -> (&[u8], &[u8]) {
+    let mut offset = 0;
+    for line in raw.split_inclusive(|b| *b == b'\n') {
+        if is_blank(line) {
+            return (&raw[..offset], &raw[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (raw, &[])
+}
+
+fn to_lf(raw: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(raw.len());
+    let mut bytes = raw.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' && bytes.peek() == Some(&&b'\n') {
+            continue;
+        }
+        converted.push(byte);
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const MESSAGE: &[u8] = b"From: Alice <alice@example.com>\r\n\
+        Subject: quoting\r\n\
+        Status: RO\r\n\
+        X-Status: F\r\n\
+        \r\n\
+        From the start\r\n\
+        >From quoted once\r\n\
+        >>From quoted twice\r\n\
+        \r\n";
+
+    fn round_trip(format: MboxFormat) -> Vec<Vec<u8>> {
+        let date = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let mut mbox = Vec::new();
+        write_message(&mut mbox, MESSAGE, "alice@example.com", date, format).unwrap();
+        write_message(&mut mbox, b"Subject: second\n\nbody", "", date, format).unwrap();
+
+        let text = String::from_utf8(mbox.clone()).unwrap();
+        assert!(text.starts_with("From alice@example.com Tue Nov 14 22:13:20 2023\n"));
+        assert!(text.contains("\nFrom MAILER-DAEMON Tue Nov 14 22:13:20 2023\n"));
+
+        let reader = MboxReader::new(mbox.as_slice(), format);
+        reader.collect::<io::Result<Vec<_>>>().unwrap()
+    }
+
+    #[test]
+    fn test_mboxrd_round_trip() {
+        let messages = round_trip(MboxFormat::Mboxrd);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], to_lf(MESSAGE));
+        assert_eq!(messages[1], b"Subject: second\n\nbody\n");
+        assert_eq!(status_flags(&messages[0]), vec![Flag::Seen, Flag::Flagged]);
+    }
+
+    #[test]
+    fn test_mboxcl2_round_trip() {
+        let messages = round_trip(MboxFormat::Mboxcl2);
+        assert_eq!(messages.len(), 2);
+        let first = String::from_utf8(messages[0].clone()).unwrap();
+        assert!(first.contains("Content-Length: 54\n"));
+        assert!(first.ends_with("\nFrom the start\n>From quoted once\n>>From quoted twice\n\n"));
+        assert_eq!(
+            messages[1],
+            b"Subject: second\nContent-Length: 5\n\nbody\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_reads_unquoted_mboxcl2_body() {
+        // Content-Lengthがあれば本文中の `From ` 行で区切らない
+        let mbox = b"From a@example.com Tue Nov 14 22:13:20 2023\n\
+            Content-Length: 16\n\
+            \n\
+            From inside\n\
+            end\n\
+            \n\
+            From b@example.com Tue Nov 14 22:13:20 2023\n\
+            Subject: next\n\
+            \n\
+            body\n";
+        let messages: Vec<_> = MboxReader::new(&mbox[..], MboxFormat::Mboxcl2)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].ends_with(b"\n\nFrom inside\nend\n"));
+        assert_eq!(messages[1], b"Subject: next\n\nbody\n");
+    }
+}
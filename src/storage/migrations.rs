@@ -0,0 +1,457 @@
+use rusqlite::Connection;
+use std::path::Path;
+
+use super::{StorageError, StorageResult};
+
+/// このバイナリが扱えるスキーマの最新バージョン
+pub const LATEST_VERSION: i32 = 12;
+
+/// スキーマの変更1回分
+///
+/// 各ステップは1つのトランザクションで実行し、`PRAGMA user_version` も同じ
+/// トランザクション内で更新する。途中で失敗した場合は前のバージョンのまま残る。
+struct Migration {
+    version: i32,
+    description: &'static str,
+    sql: &'static str,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "初期スキーマ",
+        sql: "
+            CREATE TABLE IF NOT EXISTS messages (
+                id TEXT PRIMARY KEY,
+                account_id TEXT NOT NULL,
+                folder TEXT NOT NULL,
+                subject TEXT,
+                from_addr TEXT,
+                to_addr TEXT,
+                date INTEGER,
+                body TEXT,
+                flags TEXT,
+                raw_message TEXT
+            );
+            CREATE TABLE IF NOT EXISTS accounts (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                email TEXT NOT NULL,
+                config TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS folders (
+                id TEXT PRIMARY KEY,
+                account_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                server_name TEXT NOT NULL,
+                message_count INTEGER DEFAULT 0,
+                unread_count INTEGER DEFAULT 0,
+                FOREIGN KEY (account_id) REFERENCES accounts(id)
+            );
+            CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+                subject, from_addr, to_addr, body,
+                content=messages,
+                content_rowid=rowid
+            );
+            CREATE INDEX IF NOT EXISTS idx_messages_account_folder
+                ON messages(account_id, folder);
+            CREATE INDEX IF NOT EXISTS idx_messages_date ON messages(date DESC);
+        ",
+    },
+    Migration {
+        version: 2,
+        description: "メッセージを損失なく保存するスキーマ",
+        sql: "
+            DROP TABLE IF EXISTS messages_fts;
+            DROP INDEX IF EXISTS idx_messages_account_folder;
+            DROP INDEX IF EXISTS idx_messages_date;
+            ALTER TABLE messages RENAME TO messages_v1;
+
+            -- from_addr / to_addr / body は検索用の表示文字列で、復元には使わない
+            CREATE TABLE messages (
+                pk INTEGER PRIMARY KEY,
+                id TEXT NOT NULL,
+                account_id TEXT NOT NULL,
+                folder TEXT NOT NULL,
+                subject TEXT NOT NULL DEFAULT '',
+                from_addr TEXT,
+                to_addr TEXT,
+                date INTEGER NOT NULL,
+                date_nanos INTEGER NOT NULL DEFAULT 0,
+                body_type TEXT NOT NULL,
+                body_content TEXT,
+                body TEXT,
+                flags TEXT NOT NULL DEFAULT '[]',
+                thread_id TEXT,
+                raw_message BLOB,
+                UNIQUE (account_id, folder, id)
+            );
+
+            -- from / to / cc / bcc を順序付きで保持
+            CREATE TABLE message_addresses (
+                message_pk INTEGER NOT NULL REFERENCES messages(pk) ON DELETE CASCADE,
+                kind TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                email TEXT NOT NULL,
+                name TEXT,
+                PRIMARY KEY (message_pk, kind, position)
+            );
+
+            CREATE TABLE message_parts (
+                message_pk INTEGER NOT NULL REFERENCES messages(pk) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                content_type TEXT NOT NULL,
+                content TEXT NOT NULL,
+                encoding TEXT,
+                PRIMARY KEY (message_pk, position)
+            );
+
+            CREATE TABLE attachments (
+                message_pk INTEGER NOT NULL REFERENCES messages(pk) ON DELETE CASCADE,
+                position INTEGER NOT NULL,
+                filename TEXT NOT NULL,
+                content_type TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                data BLOB NOT NULL,
+                PRIMARY KEY (message_pk, position)
+            );
+
+            -- v1は本文の表示文字列と送信者の表示名しか持たないので、それを引き継ぐ
+            -- （raw_messageには表示文字列が入っていたので捨てる）
+            INSERT OR IGNORE INTO messages (
+                id, account_id, folder, subject, from_addr, to_addr,
+                date, body_type, body_content, body, flags
+            )
+            SELECT id, account_id, folder, COALESCE(subject, ''), from_addr, to_addr,
+                   COALESCE(date, 0), 'plain', body, body, COALESCE(flags, '[]')
+            FROM messages_v1;
+
+            INSERT INTO message_addresses (message_pk, kind, position, email, name)
+            SELECT pk, 'from', 0, '', from_addr FROM messages
+            WHERE from_addr IS NOT NULL AND from_addr <> 'Unknown Sender';
+
+            DROP TABLE messages_v1;
+
+            CREATE VIRTUAL TABLE messages_fts USING fts5(
+                subject, from_addr, to_addr, body,
+                content=messages,
+                content_rowid=pk
+            );
+            INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+
+            CREATE INDEX idx_messages_account_folder ON messages(account_id, folder);
+            CREATE INDEX idx_messages_date ON messages(date DESC);
+        ",
+    },
+    Migration {
+        version: 3,
+        description: "オフライン操作のジャーナル",
+        sql: "
+            -- operationはMailOperationのJSON。idの順にサーバーへ反映する
+            CREATE TABLE pending_operations (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                account_id TEXT NOT NULL,
+                folder TEXT NOT NULL,
+                message_id TEXT NOT NULL,
+                operation TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                last_error TEXT
+            );
+            CREATE INDEX idx_pending_operations_account
+                ON pending_operations(account_id, id);
+        ",
+    },
+    Migration {
+        version: 4,
+        description: "POP3で取得済みのUIDL",
+        sql: "
+            -- ローカルで削除したメッセージを再取得しないよう、messagesとは別に持つ
+            CREATE TABLE pop3_uidls (
+                account_id TEXT NOT NULL,
+                uidl TEXT NOT NULL,
+                downloaded_at INTEGER NOT NULL,
+                PRIMARY KEY (account_id, uidl)
+            );
+        ",
+    },
+    Migration {
+        version: 5,
+        description: "全文検索インデックスをトリガーで更新",
+        sql: "
+            -- 外部コンテンツのFTSは削除時に古い値が必要なので、アプリからではなく
+            -- トリガーで更新する（以前の INSERT OR REPLACE では古い語が残っていた）
+            DROP TABLE IF EXISTS messages_fts;
+            CREATE VIRTUAL TABLE messages_fts USING fts5(
+                subject, from_addr, to_addr, body,
+                content=messages,
+                content_rowid=pk
+            );
+
+            CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+                INSERT INTO messages_fts (rowid, subject, from_addr, to_addr, body)
+                VALUES (new.pk, new.subject, new.from_addr, new.to_addr, new.body);
+            END;
+            CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, subject, from_addr, to_addr, body)
+                VALUES ('delete', old.pk, old.subject, old.from_addr, old.to_addr, old.body);
+            END;
+            CREATE TRIGGER messages_fts_update
+            AFTER UPDATE OF subject, from_addr, to_addr, body ON messages BEGIN
+                INSERT INTO messages_fts (messages_fts, rowid, subject, from_addr, to_addr, body)
+                VALUES ('delete', old.pk, old.subject, old.from_addr, old.to_addr, old.body);
+                INSERT INTO messages_fts (rowid, subject, from_addr, to_addr, body)
+                VALUES (new.pk, new.subject, new.from_addr, new.to_addr, new.body);
+            END;
+
+            INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+        ",
+    },
+    Migration {
+        version: 6,
+        description: "全文検索を3-gramで分割",
+        sql: "
+            -- unicode61は日本語の連なりを1語として扱うので、文中の語で引けない。
+            -- 3-gramなら言語を問わず3文字以上の部分文字列で引ける（トリガーはそのまま使う）
+            DROP TABLE messages_fts;
+            CREATE VIRTUAL TABLE messages_fts USING fts5(
+                subject, from_addr, to_addr, body,
+                content=messages,
+                content_rowid=pk,
+                tokenize='trigram'
+            );
+            INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+        ",
+    },
+    Migration {
+        version: 7,
+        description: "保存した検索（仮想フォルダー）",
+        sql: "
+            CREATE TABLE saved_searches (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                query TEXT NOT NULL
+            );
+        ",
+    },
+    Migration {
+        version: 8,
+        description: "サーバーのフォルダー一覧",
+        sql: "
+            -- 初期スキーマのfoldersテーブルは使われていなかったので作り直す
+            DROP TABLE IF EXISTS folders;
+            CREATE TABLE folders (
+                account_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                delimiter TEXT,
+                selectable INTEGER NOT NULL DEFAULT 1,
+                PRIMARY KEY (account_id, name)
+            );
+        ",
+    },
+    Migration {
+        version: 9,
+        description: "フォルダーの役割（SPECIAL-USE）",
+        sql: "
+            ALTER TABLE folders ADD COLUMN role TEXT;
+        ",
+    },
+    Migration {
+        version: 10,
+        description: "フォルダーの購読状態",
+        sql: "
+            ALTER TABLE folders ADD COLUMN subscribed INTEGER NOT NULL DEFAULT 1;
+        ",
+    },
+    Migration {
+        version: 11,
+        description: "スレッドの組み立てに使うヘッダー",
+        sql: "
+            -- idはIMAPのUIDなどサーバーでの識別子なので、Message-IDヘッダーは別に持つ。
+            -- reference_idsはReferencesのMessage-IDを並べたJSON配列
+            ALTER TABLE messages ADD COLUMN header_message_id TEXT;
+            ALTER TABLE messages ADD COLUMN in_reply_to TEXT;
+            ALTER TABLE messages ADD COLUMN reference_ids TEXT NOT NULL DEFAULT '[]';
+        ",
+    },
+    Migration {
+        version: 12,
+        description: "本文を省いて取得したメッセージの印",
+        sql: "
+            ALTER TABLE messages ADD COLUMN body_pending INTEGER NOT NULL DEFAULT 0;
+            -- これまではIMAPの一覧で入れた仮の本文で見分けていた
+            UPDATE messages SET body_pending = 1
+            WHERE raw_message IS NULL AND body_type = 'plain'
+              AND body_content = '本文を読み込み中...';
+        ",
+    },
+];
+
+/// データベースを最新のスキーマまで移行する
+///
+/// 既存のデータベースを更新する前に `<ファイル名>.v<旧バージョン>.bak` へ
+/// バックアップを取る。このバイナリより新しいスキーマのデータベースは開かない。
+pub fn migrate(conn: &mut Connection, db_path: &Path) -> StorageResult<()> {
+    let mut version = schema_version(conn)?;
+    if version == 0 {
+        version = detect_unversioned_schema(conn)?;
+    }
+
+    if version > LATEST_VERSION {
+        return Err(StorageError::Database(format!(
+            "Database schema version {} is newer than this version of rustmail supports ({}). \
+             Please upgrade rustmail.",
+            version, LATEST_VERSION
+        )));
+    }
+
+    if version == LATEST_VERSION {
+        return Ok(());
+    }
+
+    if version > 0 {
+        backup(conn, db_path, version)?;
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        let tx = conn.transaction().map_err(|e| {
+            StorageError::Database(format!("Failed to begin migration transaction: {}", e))
+        })?;
+
+        tx.execute_batch(migration.sql).map_err(|e| {
+            StorageError::Database(format!(
+                "Migration to v{} ({}) failed: {}",
+                migration.version, migration.description, e
+            ))
+        })?;
+        tx.pragma_update(None, "user_version", migration.version)
+            .map_err(|e| StorageError::Database(format!("Failed to set user_version: {}", e)))?;
+
+        tx.commit().map_err(|e| {
+            StorageError::Database(format!(
+                "Failed to commit migration to v{}: {}",
+                migration.version, e
+            ))
+        })?;
+    }
+
+    Ok(())
+}
+
+pub fn schema_version(conn: &Connection) -> StorageResult<i32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(|e| StorageError::Database(format!("Failed to read user_version: {}", e)))
+}
+
+/// バージョン管理導入前に作られたデータベースのバージョンを推定する
+///
+/// テーブルがなければ新規（0）、pk列を持つmessagesテーブルがあればv2、それ以外はv1
+fn detect_unversioned_schema(conn: &Connection) -> StorageResult<i32> {
+    let count = |sql: &str| -> StorageResult<i64> {
+        conn.query_row(sql, [], |row| row.get(0))
+            .map_err(|e| StorageError::Database(format!("Failed to inspect schema: {}", e)))
+    };
+
+    let has_messages =
+        count("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'messages'")? > 0;
+    if !has_messages {
+        return Ok(0);
+    }
+
+    let has_pk = count("SELECT COUNT(*) FROM pragma_table_info('messages') WHERE name = 'pk'")? > 0;
+    let version = if has_pk { 2 } else { 1 };
+
+    conn.pragma_update(None, "user_version", version)
+        .map_err(|e| StorageError::Database(format!("Failed to set user_version: {}", e)))?;
+
+    Ok(version)
+}
+
+/// 移行前のデータベースをコピーしておく（メモリ上のデータベースは対象外）
+fn backup(conn: &Connection, db_path: &Path, version: i32) -> StorageResult<()> {
+    if db_path.as_os_str() == ":memory:" {
+        return Ok(());
+    }
+
+    let file_name = db_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "rustmail.db".to_string());
+    let backup_path = db_path.with_file_name(format!("{}.v{}.bak", file_name, version));
+
+    if backup_path.exists() {
+        std::fs::remove_file(&backup_path)
+            .map_err(|e| StorageError::Io(format!("Failed to replace old backup: {}", e)))?;
+    }
+
+    conn.execute(
+        "VACUUM INTO ?1",
+        [backup_path.to_string_lossy().to_string()],
+    )
+    .map_err(|e| StorageError::Database(format!("Failed to back up database: {}", e)))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::Query;
+    use crate::storage::Database;
+
+    fn temp_db_path() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustmail-migration-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("rustmail.db")
+    }
+
+    #[test]
+    fn test_migrates_v1_fixture() {
+        let path = temp_db_path();
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(include_str!("fixtures/schema_v1.sql"))
+                .unwrap();
+        }
+
+        let db = Database::new(&path).unwrap();
+
+        let messages = db.get_messages("work", "INBOX", None, None).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].id, "102");
+        assert_eq!(messages[1].subject, "週次ミーティング");
+        assert_eq!(messages[1].get_sender_display(), "Alice");
+        assert_eq!(messages[1].date.timestamp(), 1_700_000_000);
+        assert!(!messages[1].is_unread());
+        assert!(messages[0].is_unread());
+
+        // 既存のメッセージもFTSの索引に入る
+        let query = Query::parse("Invoice").unwrap();
+        assert_eq!(db.search_messages(&query, Some("work")).unwrap().len(), 1);
+
+        // 移行前のバックアップが残る
+        let backup = path.with_file_name("rustmail.db.v1.bak");
+        let conn = Connection::open(&backup).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+
+        drop(db);
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), LATEST_VERSION);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_refuses_newer_database() {
+        let path = temp_db_path();
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.pragma_update(None, "user_version", LATEST_VERSION + 1)
+                .unwrap();
+        }
+
+        assert!(Database::new(&path).is_err());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).ok();
+    }
+}
@@ -1,7 +1,17 @@
 pub mod config;
 pub mod database;
+pub mod folders;
+pub mod journal;
+pub mod mbox;
+mod migrations;
+pub mod pop3;
+pub mod saved_search;
+pub mod secrets;
 
-pub use config::Config;
+pub use config::{Config, PreviewPosition};
+pub use database::{Database, FetchedRange, SearchHit};
+pub use journal::MailOperation;
+pub use saved_search::SavedSearch;
 
 use std::error::Error;
 use std::fmt;
@@ -13,6 +23,7 @@ pub enum StorageError {
     #[allow(dead_code)]
     Database(String),
     Config(String),
+    Secret(String),
 }
 
 impl fmt::Display for StorageError {
@@ -22,6 +33,7 @@ impl fmt::Display for StorageError {
             StorageError::Parse(msg) => write!(f, "Parse error: {}", msg),
             StorageError::Database(msg) => write!(f, "Database error: {}", msg),
             StorageError::Config(msg) => write!(f, "Config error: {}", msg),
+            StorageError::Secret(msg) => write!(f, "Secret store error: {}", msg),
         }
     }
 }
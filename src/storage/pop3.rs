@@ -0,0 +1,112 @@
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::params;
+use std::collections::HashMap;
+
+use super::{Database, StorageError, StorageResult};
+use crate::mail::Message;
+
+impl Database {
+    /// POP3で取得済みのUIDLと取得日時
+    pub fn pop3_uidls(&self, account_id: &str) -> StorageResult<HashMap<String, DateTime<Utc>>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT uidl, downloaded_at FROM pop3_uidls WHERE account_id = ?1")
+            .map_err(|e| StorageError::Database(format!("Failed to prepare statement: {}", e)))?;
+
+        let rows = stmt
+            .query_map(params![account_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .map_err(|e| StorageError::Database(format!("Failed to query UIDLs: {}", e)))?;
+
+        let mut uidls = HashMap::new();
+        for row in rows {
+            let (uidl, downloaded_at) =
+                row.map_err(|e| StorageError::Database(format!("Failed to read row: {}", e)))?;
+            let downloaded_at = Utc
+                .timestamp_opt(downloaded_at, 0)
+                .single()
+                .unwrap_or_else(Utc::now);
+            uidls.insert(uidl, downloaded_at);
+        }
+
+        Ok(uidls)
+    }
+
+    /// POP3でダウンロードしたメッセージを保存し、UIDLを取得済みにする
+    pub fn store_pop3_message(&mut self, uidl: &str, message: &Message) -> StorageResult<()> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|e| StorageError::Database(format!("Failed to begin transaction: {}", e)))?;
+
+        Self::upsert_message(&tx, message)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO pop3_uidls (account_id, uidl, downloaded_at)
+             VALUES (?1, ?2, ?3)",
+            params![message.account_id, uidl, Utc::now().timestamp()],
+        )
+        .map_err(|e| StorageError::Database(format!("Failed to record UIDL: {}", e)))?;
+
+        tx.commit()
+            .map_err(|e| StorageError::Database(format!("Failed to commit transaction: {}", e)))
+    }
+
+    /// サーバーから消えたメッセージのUIDLを忘れる（二度と返ってこないので保持する必要がない）
+    pub fn prune_pop3_uidls(
+        &mut self,
+        account_id: &str,
+        on_server: &[String],
+    ) -> StorageResult<()> {
+        let on_server = serde_json::to_string(on_server)
+            .map_err(|e| StorageError::Database(format!("Failed to serialize UIDLs: {}", e)))?;
+
+        self.conn
+            .execute(
+                "DELETE FROM pop3_uidls
+                 WHERE account_id = ?1 AND uidl NOT IN (SELECT value FROM json_each(?2))",
+                params![account_id, on_server],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to prune UIDLs: {}", e)))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::{Address, MessageBody};
+
+    #[test]
+    fn test_uidls_outlive_local_deletion_until_pruned() {
+        let mut db = Database::new(":memory:").unwrap();
+        let message = Message::new(
+            "uidl-1".to_string(),
+            vec![Address::new("alice@example.com".to_string(), None)],
+            Vec::new(),
+            "件名".to_string(),
+            MessageBody::new_plain("本文".to_string()),
+            "legacy".to_string(),
+            "INBOX".to_string(),
+        );
+        db.store_pop3_message("uidl-1", &message).unwrap();
+        assert_eq!(
+            db.get_messages("legacy", "INBOX", None, None)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // ローカルで削除しても取得済みのまま（サーバーに残っていても再取得しない）
+        db.delete_message("legacy", "INBOX", "uidl-1").unwrap();
+        assert!(db.pop3_uidls("legacy").unwrap().contains_key("uidl-1"));
+        assert!(db.pop3_uidls("other").unwrap().is_empty());
+
+        db.prune_pop3_uidls("legacy", &["uidl-1".to_string()])
+            .unwrap();
+        assert_eq!(db.pop3_uidls("legacy").unwrap().len(), 1);
+        db.prune_pop3_uidls("legacy", &[]).unwrap();
+        assert!(db.pop3_uidls("legacy").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter};
+
+use super::{Database, StorageError, StorageResult};
+use crate::mail::Flag;
+use crate::search::{Query, Term};
+
+/// 名前を付けて保存した検索（フォルダー一覧に仮想フォルダーとして表示する）
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedSearch {
+    pub name: String,
+    /// 検索クエリ（`Query::parse` で解析する文字列）
+    pub query: String,
+}
+
+impl Database {
+    /// 保存した検索を保存した順に取得
+    pub fn saved_searches(&self) -> StorageResult<Vec<SavedSearch>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT name, query FROM saved_searches ORDER BY id")
+            .map_err(|e| StorageError::Database(format!("Failed to prepare statement: {}", e)))?;
+
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(SavedSearch {
+                    name: row.get(0)?,
+                    query: row.get(1)?,
+                })
+            })
+            .map_err(|e| {
+                StorageError::Database(format!("Failed to query saved searches: {}", e))
+            })?;
+
+        rows.collect::<Result<_, _>>()
+            .map_err(|e| StorageError::Database(format!("Failed to read row: {}", e)))
+    }
+
+    /// 検索を保存する（同じ名前があればクエリを置き換え、並び順はそのまま）
+    pub fn save_search(&mut self, name: &str, query: &str) -> StorageResult<()> {
+        self.conn
+            .execute(
+                "INSERT INTO saved_searches (name, query) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO UPDATE SET query = excluded.query",
+                params![name, query],
+            )
+            .map_err(|e| StorageError::Database(format!("Failed to save search: {}", e)))?;
+        Ok(())
+    }
+
+    pub fn delete_saved_search(&mut self, name: &str) -> StorageResult<()> {
+        self.conn
+            .execute("DELETE FROM saved_searches WHERE name = ?1", params![name])
+            .map_err(|e| StorageError::Database(format!("Failed to delete saved search: {}", e)))?;
+        Ok(())
+    }
+
+    /// 検索クエリに一致する未読メッセージの数（全アカウント）
+    pub fn count_unread(&self, query: &Query) -> StorageResult<usize> {
+        let unread = Query::And(vec![
+            query.clone(),
+            Query::Term(Term::Flag(Flag::Seen, false)),
+        ]);
+        let mut values: Vec<Value> = Vec::new();
+        let condition = unread.to_sql(&mut values);
+
+        self.conn
+            .query_row(
+                &format!("SELECT COUNT(*) FROM messages m WHERE {}", condition),
+                params_from_iter(values),
+                |row| row.get::<_, i64>(0),
+            )
+            .map(|count| count as usize)
+            .map_err(|e| StorageError::Database(format!("Failed to count messages: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mail::{Address, Message, MessageBody};
+
+    #[test]
+    fn test_saved_searches_and_unread_counts() {
+        let mut db = Database::new(":memory:").unwrap();
+        for (id, account, subject, seen) in [
+            ("1", "work", "請求書 3月分", false),
+            ("2", "home", "Invoice for March", false),
+            ("3", "work", "Invoice paid", true),
+        ] {
+            let mut message = Message::new(
+                id.to_string(),
+                vec![Address::new("billing@example.com".to_string(), None)],
+                Vec::new(),
+                subject.to_string(),
+                MessageBody::new_plain(String::new()),
+                account.to_string(),
+                "INBOX".to_string(),
+            );
+            if seen {
+                message.flags = vec![Flag::Seen];
+            }
+            db.store_message(&message).unwrap();
+        }
+
+        db.save_search("Invoices", "invoice OR 請求書").unwrap();
+        db.save_search("Billing", "from:billing").unwrap();
+        db.save_search("Invoices", "invoice").unwrap();
+        assert_eq!(
+            db.saved_searches().unwrap(),
+            vec![
+                SavedSearch {
+                    name: "Invoices".to_string(),
+                    query: "invoice".to_string(),
+                },
+                SavedSearch {
+                    name: "Billing".to_string(),
+                    query: "from:billing".to_string(),
+                },
+            ]
+        );
+
+        // 全アカウントの未読を数える
+        let count = |query: &str| db.count_unread(&Query::parse(query).unwrap()).unwrap();
+        assert_eq!(count("invoice"), 1);
+        assert_eq!(count("from:billing"), 2);
+        assert_eq!(count("請求書"), 1);
+
+        db.delete_saved_search("Billing").unwrap();
+        assert_eq!(db.saved_searches().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,355 @@
+use argon2::Argon2;
+use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+use super::{StorageError, StorageResult};
+
+const KEYRING_SERVICE: &str = "rustmail";
+const VAULT_VERSION: u32 = 1;
+const VAULT_PASSPHRASE_ENV: &str = "RUSTMAIL_VAULT_PASSPHRASE";
+
+/// 一度入力したボールトのパスフレーズ（トークン更新のたびに聞き直さない）
+static VAULT_PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// キーリングが使えるかどうか（確認と警告は1回だけ行う）
+static KEYRING_AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+/// パスワード・クライアントシークレット・トークンの保存先
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SecretStoreKind {
+    /// OSのキーリング（Secret Service / カーネルキーリング / Keychain / Credential Manager）
+    #[default]
+    Keyring,
+    /// パスフレーズで暗号化したローカルファイル
+    Vault,
+}
+
+/// 秘密情報の保存先
+pub enum SecretStore {
+    Keyring,
+    Vault(Vault),
+}
+
+impl SecretStore {
+    /// 設定された保存先を開く
+    ///
+    /// キーリングが使えない環境（Secret Serviceが動いていないSSHセッションなど）では
+    /// 暗号化ボールトに切り替える
+    pub fn open(kind: SecretStoreKind, vault_path: &Path) -> StorageResult<Self> {
+        if kind == SecretStoreKind::Keyring && keyring_available() {
+            return Ok(SecretStore::Keyring);
+        }
+
+        let passphrase = vault_passphrase()?;
+        Ok(SecretStore::Vault(Vault::open(vault_path, passphrase)?))
+    }
+
+    pub fn get(&self, key: &str) -> StorageResult<Option<String>> {
+        match self {
+            SecretStore::Keyring => match keyring_entry(key)?.get_password() {
+                Ok(value) => Ok(Some(value)),
+                Err(keyring::Error::NoEntry) => Ok(None),
+                Err(e) => Err(StorageError::Secret(format!(
+                    "Failed to read {} from keyring: {}",
+                    key, e
+                ))),
+            },
+            SecretStore::Vault(vault) => Ok(vault.get(key)),
+        }
+    }
+
+    /// 値を保存（同じ値が保存済みなら何もしない）
+    pub fn set(&mut self, key: &str, value: &str) -> StorageResult<()> {
+        if self.get(key)?.as_deref() == Some(value) {
+            return Ok(());
+        }
+
+        match self {
+            SecretStore::Keyring => keyring_entry(key)?.set_password(value).map_err(|e| {
+                StorageError::Secret(format!("Failed to write {} to keyring: {}", key, e))
+            }),
+            SecretStore::Vault(vault) => vault.set(key, value),
+        }
+    }
+
+    pub fn delete(&mut self, key: &str) -> StorageResult<()> {
+        match self {
+            SecretStore::Keyring => match keyring_entry(key)?.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+                Err(e) => Err(StorageError::Secret(format!(
+                    "Failed to delete {} from keyring: {}",
+                    key, e
+                ))),
+            },
+            SecretStore::Vault(vault) => vault.delete(key),
+        }
+    }
+}
+
+fn keyring_entry(key: &str) -> StorageResult<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, key)
+        .map_err(|e| StorageError::Secret(format!("Invalid keyring entry {}: {}", key, e)))
+}
+
+fn keyring_available() -> bool {
+    *KEYRING_AVAILABLE.get_or_init(|| {
+        let result = keyring::Entry::new(KEYRING_SERVICE, "availability-check")
+            .and_then(|entry| entry.get_password());
+        match result {
+            Ok(_) | Err(keyring::Error::NoEntry) => true,
+            Err(e) => {
+                eprintln!(
+                    "キーリングを利用できないため暗号化ボールトを使用します: {}",
+                    e
+                );
+                false
+            }
+        }
+    })
+}
+
+/// ボールトのパスフレーズを環境変数またはターミナルから取得
+fn vault_passphrase() -> StorageResult<String> {
+    if let Some(passphrase) = VAULT_PASSPHRASE.get() {
+        return Ok(passphrase.clone());
+    }
+
+    let passphrase = match std::env::var(VAULT_PASSPHRASE_ENV) {
+        Ok(passphrase) if !passphrase.is_empty() => passphrase,
+        _ => rpassword::prompt_password("ボールトのパスフレーズ: ")
+            .map_err(|e| StorageError::Secret(format!("Failed to read passphrase: {}", e)))?,
+    };
+
+    if passphrase.is_empty() {
+        return Err(StorageError::Secret(
+            "Vault passphrase is empty".to_string(),
+        ));
+    }
+
+    Ok(VAULT_PASSPHRASE.get_or_init(|| passphrase).clone())
+}
+
+/// `password_command` を実行し、標準出力の1行目を秘密情報として返す
+///
+/// `pass show mail/work` や `op read op://Private/Mail/password` などを想定
+pub fn run_password_command(command: &str) -> StorageResult<String> {
+    let output = if cfg!(target_os = "windows") {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .map_err(|e| StorageError::Secret(format!("Failed to run password command: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(StorageError::Secret(format!(
+            "Password command exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| StorageError::Secret("Password command output is not UTF-8".to_string()))?;
+
+    Ok(stdout.lines().next().unwrap_or_default().to_string())
+}
+
+#[derive(Serialize, Deserialize)]
+struct VaultFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// パスフレーズで暗号化した秘密情報のファイル
+///
+/// Argon2idで導出した鍵とXChaCha20-Poly1305で中身全体を暗号化し、
+/// 書き込みのたびにソルトとノンスを作り直す
+pub struct Vault {
+    path: PathBuf,
+    passphrase: String,
+    entries: BTreeMap<String, String>,
+}
+
+impl Vault {
+    /// ボールトを開く（ファイルがなければ空のボールトとして扱う）
+    pub fn open(path: &Path, passphrase: String) -> StorageResult<Self> {
+        let entries = if path.exists() {
+            let content = fs::read_to_string(path)
+                .map_err(|e| StorageError::Io(format!("Failed to read vault: {}", e)))?;
+            let file: VaultFile = serde_json::from_str(&content)
+                .map_err(|e| StorageError::Parse(format!("Failed to parse vault: {}", e)))?;
+            Self::decrypt(&file, &passphrase)?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            passphrase,
+            entries,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.entries.get(key).cloned()
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> StorageResult<()> {
+        self.entries.insert(key.to_string(), value.to_string());
+        self.write()
+    }
+
+    pub fn delete(&mut self, key: &str) -> StorageResult<()> {
+        if self.entries.remove(key).is_some() {
+            self.write()?;
+        }
+        Ok(())
+    }
+
+    fn derive_key(passphrase: &str, salt: &[u8]) -> StorageResult<[u8; 32]> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| StorageError::Secret(format!("Key derivation failed: {}", e)))?;
+        Ok(key)
+    }
+
+    fn decrypt(file: &VaultFile, passphrase: &str) -> StorageResult<BTreeMap<String, String>> {
+        if file.version != VAULT_VERSION {
+            return Err(StorageError::Secret(format!(
+                "Unsupported vault version: {}",
+                file.version
+            )));
+        }
+
+        let decode = |value: &str| {
+            general_purpose::STANDARD
+                .decode(value)
+                .map_err(|e| StorageError::Parse(format!("Invalid vault encoding: {}", e)))
+        };
+        let salt = decode(&file.salt)?;
+        let nonce = decode(&file.nonce)?;
+        let ciphertext = decode(&file.ciphertext)?;
+
+        if nonce.len() != 24 {
+            return Err(StorageError::Parse("Invalid vault nonce".to_string()));
+        }
+
+        let key = Self::derive_key(passphrase, &salt)?;
+        let plaintext = XChaCha20Poly1305::new(&key.into())
+            .decrypt(XNonce::from_slice(&nonce), ciphertext.as_ref())
+            .map_err(|_| {
+                StorageError::Secret(
+                    "Failed to decrypt vault (wrong passphrase or corrupted file)".to_string(),
+                )
+            })?;
+
+        serde_json::from_slice(&plaintext)
+            .map_err(|e| StorageError::Parse(format!("Failed to parse vault contents: {}", e)))
+    }
+
+    fn write(&self) -> StorageResult<()> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let key = Self::derive_key(&self.passphrase, &salt)?;
+
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext = serde_json::to_vec(&self.entries)
+            .map_err(|e| StorageError::Parse(format!("Failed to serialize vault: {}", e)))?;
+        let ciphertext = XChaCha20Poly1305::new(&key.into())
+            .encrypt(&nonce, plaintext.as_ref())
+            .map_err(|_| StorageError::Secret("Failed to encrypt vault".to_string()))?;
+
+        let file = VaultFile {
+            version: VAULT_VERSION,
+            salt: general_purpose::STANDARD.encode(salt),
+            nonce: general_purpose::STANDARD.encode(nonce),
+            ciphertext: general_purpose::STANDARD.encode(ciphertext),
+        };
+        let content = serde_json::to_string_pretty(&file)
+            .map_err(|e| StorageError::Parse(format!("Failed to serialize vault: {}", e)))?;
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                StorageError::Io(format!("Failed to create vault directory: {}", e))
+            })?;
+        }
+
+        // 書き込み途中で壊れないよう一時ファイルからリネームする
+        let tmp_path = self.path.with_extension("tmp");
+        write_private(&tmp_path, content.as_bytes())?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| StorageError::Io(format!("Failed to write vault: {}", e)))?;
+
+        Ok(())
+    }
+}
+
+/// 所有者だけが読み書きできるファイルとして書き込む
+fn write_private(path: &Path, content: &[u8]) -> StorageResult<()> {
+    use std::io::Write;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(path)
+        .map_err(|e| StorageError::Io(format!("Failed to open {}: {}", path.display(), e)))?;
+    file.write_all(content)
+        .map_err(|e| StorageError::Io(format!("Failed to write {}: {}", path.display(), e)))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_vault_path() -> PathBuf {
+        std::env::temp_dir().join(format!("rustmail-vault-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_vault_round_trip() {
+        let path = temp_vault_path();
+
+        let mut vault = Vault::open(&path, "correct horse".to_string()).unwrap();
+        vault.set("work:imap_password", "s3cret").unwrap();
+
+        // ファイルに平文が含まれない
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("s3cret"));
+
+        let vault = Vault::open(&path, "correct horse".to_string()).unwrap();
+        assert_eq!(vault.get("work:imap_password").as_deref(), Some("s3cret"));
+
+        assert!(Vault::open(&path, "wrong".to_string()).is_err());
+
+        fs::remove_file(&path).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_password_command() {
+        assert_eq!(
+            run_password_command("printf 'pw\\nsecond line\\n'").unwrap(),
+            "pw"
+        );
+        assert!(run_password_command("exit 1").is_err());
+    }
+}
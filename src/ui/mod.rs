@@ -11,7 +11,10 @@ use ratatui::{
     Frame,
 };
 
-use crate::app::{App, AppMode, InputMode};
+use crate::app::{App, AppMode, FolderEntry, InputMode};
+use crate::mail::FolderOperation;
+use crate::search::{SNIPPET_END, SNIPPET_START};
+use crate::storage::PreviewPosition;
 
 pub fn render_ui(f: &mut Frame, app: &mut App) {
     let size = f.size();
@@ -19,6 +22,7 @@ pub fn render_ui(f: &mut Frame, app: &mut App) {
     match app.mode {
         AppMode::MailList => render_mail_list(f, app, size),
         AppMode::MailView => render_mail_view(f, app, size),
+        AppMode::ThreadView => render_thread_view(f, app, size),
         AppMode::Compose => render_compose(f, app, size),
         AppMode::Help => render_help(f, app, size),
         AppMode::Settings => render_settings(f, app, size),
@@ -40,19 +44,37 @@ fn render_mail_list(f: &mut Frame, app: &mut App, area: Rect) {
     render_tab_bar(f, app, chunks[0]);
 
     // メイン部分を左右に分割
+    let folder_width = app.config.ui.folder_pane_width.min(100);
     let main_chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([
-            Constraint::Percentage(25), // フォルダ一覧
-            Constraint::Percentage(75), // メール一覧
+            Constraint::Percentage(folder_width),       // フォルダ一覧
+            Constraint::Percentage(100 - folder_width), // メール一覧
         ])
         .split(chunks[1]);
 
     // フォルダ一覧
     render_folder_list(f, app, main_chunks[0]);
 
-    // メール一覧
-    render_message_list(f, app, main_chunks[1]);
+    // メール一覧（プレビューを出すときは下か右に分ける）
+    if app.preview_visible() {
+        let (direction, list_size) = match app.config.ui.preview_position {
+            PreviewPosition::Bottom => (Direction::Vertical, app.config.ui.message_list_height),
+            PreviewPosition::Right => (Direction::Horizontal, app.config.ui.message_list_width),
+        };
+        let list_size = list_size.min(100);
+        let list_chunks = Layout::default()
+            .direction(direction)
+            .constraints([
+                Constraint::Percentage(list_size),
+                Constraint::Percentage(100 - list_size),
+            ])
+            .split(main_chunks[1]);
+        render_message_list(f, app, list_chunks[0]);
+        render_preview(f, app, list_chunks[1]);
+    } else {
+        render_message_list(f, app, main_chunks[1]);
+    }
 
     // ステータスバー
     render_status_bar(f, app, chunks[2]);
@@ -61,6 +83,26 @@ fn render_mail_list(f: &mut Frame, app: &mut App, area: Rect) {
     if app.input_mode == InputMode::Search {
         render_search_bar(f, app, area);
     }
+
+    if app.input_mode == InputMode::Label {
+        render_label_bar(f, app, area);
+    }
+
+    if app.input_mode == InputMode::Move {
+        render_move_bar(f, app, area);
+    }
+
+    if app.input_mode == InputMode::SaveSearch {
+        render_save_search_bar(f, app, area);
+    }
+
+    if app.input_mode == InputMode::FolderName {
+        render_folder_name_bar(f, app, area);
+    }
+
+    if app.input_mode == InputMode::Confirm {
+        render_confirm_dialog(f, app, area);
+    }
 }
 
 fn render_mail_view(f: &mut Frame, app: &mut App, area: Rect) {
@@ -84,6 +126,124 @@ fn render_mail_view(f: &mut Frame, app: &mut App, area: Rect) {
     }
 
     render_status_bar(f, app, chunks[2]);
+
+    if app.input_mode == InputMode::Move {
+        render_move_bar(f, app, area);
+    }
+}
+
+fn render_thread_view(f: &mut Frame, app: &mut App, area: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1), // タブバー
+            Constraint::Min(0),    // 会話表示
+            Constraint::Length(1), // ステータスバー
+        ])
+        .split(area);
+
+    render_tab_bar(f, app, chunks[0]);
+
+    if let Some(thread) = &app.current_thread {
+        let mut lines: Vec<Line> = Vec::new();
+        for (i, message) in thread.messages.iter().enumerate() {
+            let header_style = if message.is_unread() {
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::Yellow)
+            };
+            lines.push(Line::from(Span::styled(
+                format!(
+                    "── [{}/{}] {}  {}",
+                    i + 1,
+                    thread.message_count(),
+                    message.get_sender_display(),
+                    message.format_date()
+                ),
+                header_style,
+            )));
+            lines.push(Line::from(Span::styled(
+                format!("To: {}", message.get_recipients_display()),
+                Style::default().fg(Color::Gray),
+            )));
+            lines.push(Line::from(""));
+
+            for line in collapse_quoted(&message.body.get_display_content(), app.show_quoted) {
+                let style = if line.starts_with('>') || line.starts_with("[引用") {
+                    Style::default().fg(Color::DarkGray)
+                } else {
+                    Style::default()
+                };
+                lines.push(Line::from(Span::styled(line, style)));
+            }
+            lines.push(Line::from(""));
+        }
+
+        let title = format!(
+            "{} ({}件) - z: 引用の表示切替",
+            thread.subject(),
+            thread.message_count()
+        );
+        let paragraph = Paragraph::new(lines)
+            .block(Block::default().title(title).borders(Borders::ALL))
+            .wrap(ratatui::widgets::Wrap { trim: false })
+            .scroll((app.thread_scroll, 0));
+        f.render_widget(paragraph, chunks[1]);
+    } else {
+        let block = Block::default().title("会話表示").borders(Borders::ALL);
+        let paragraph = Paragraph::new("会話が選択されていません").block(block);
+        f.render_widget(paragraph, chunks[1]);
+    }
+
+    render_status_bar(f, app, chunks[2]);
+
+    if app.input_mode == InputMode::Label {
+        render_label_bar(f, app, area);
+    }
+}
+
+/// 引用行（">"で始まる行）の連続を1行の要約に折りたたむ
+///
+/// 引用の直前にある「On ... wrote:」のような引用元の行も、引用と一緒に折りたたむ。
+fn collapse_quoted(body: &str, show_quoted: bool) -> Vec<String> {
+    let is_quoted = |line: &str| line.trim_start().starts_with('>');
+    let lines: Vec<&str> = body.lines().collect();
+    let mut result = Vec::new();
+    let mut quoted_run = 0;
+
+    for (i, line) in lines.iter().enumerate() {
+        let attribution = !show_quoted
+            && is_attribution(line)
+            && lines.get(i + 1).is_some_and(|next| is_quoted(next));
+        if is_quoted(line) || attribution {
+            if show_quoted {
+                result.push(line.trim_start().to_string());
+            } else {
+                quoted_run += 1;
+            }
+            continue;
+        }
+
+        if quoted_run > 0 {
+            result.push(format!("[引用 {} 行を省略]", quoted_run));
+            quoted_run = 0;
+        }
+        result.push(line.to_string());
+    }
+
+    if quoted_run > 0 {
+        result.push(format!("[引用 {} 行を省略]", quoted_run));
+    }
+
+    result
+}
+
+/// 引用元を示す行（「On Mon, Alice wrote:」「2024年3月1日 山田 <...>:」など）
+fn is_attribution(line: &str) -> bool {
+    let line = line.trim();
+    line.ends_with("wrote:") || line.ends_with("書きました:") || line.ends_with("書きました：")
 }
 
 fn render_compose(f: &mut Frame, app: &mut App, area: Rect) {
@@ -129,7 +289,41 @@ fn render_help(f: &mut Frame, _app: &mut App, area: Rect) {
         "  R      : 全員に返信",
         "  f      : 転送",
         "  d      : 削除",
+        "  u      : 既読/未読の切り替え",
+        "  s      : スターの切り替え",
+        "  m      : フォルダーへ移動",
         "  /      : 検索",
+        "  S      : 表示中の検索を保存（仮想フォルダー）",
+        "  Tab    : フォルダー一覧へ移動",
+        "  g      : サーバーと同期",
+        "  o      : オフラインモードの切り替え",
+        "  t      : 会話表示の切り替え (Gmail)",
+        "  T      : スレッド表示・日付順の切り替え",
+        "  Space  : スレッドの返信を畳む・広げる",
+        "  p      : プレビューの表示・非表示",
+        "  P      : プレビューの位置（下・右）の切り替え",
+        "  +/-    : メール一覧を大きく・小さく（残りがプレビュー）",
+        "  >/<    : フォルダー一覧を広く・狭く",
+        "",
+        "■ フォルダー一覧",
+        "  j/k    : フォルダーを選択",
+        "  Enter  : フォルダー・保存した検索を開く",
+        "  Space  : 子フォルダーを畳む・広げる（←/→ でも可）",
+        "  n      : フォルダーを作成",
+        "  r      : フォルダー名を変更",
+        "  d      : フォルダーを削除",
+        "  s      : フォルダーを購読する・購読をやめる",
+        "  A      : 購読中のフォルダーだけ表示・すべて表示",
+        "  x      : 保存した検索を削除",
+        "  Tab/Esc: メール一覧に戻る",
+        "",
+        "■ 会話表示 (Gmail)",
+        "  Enter  : 会話を開く",
+        "  j/k    : スクロール",
+        "  z      : 引用の表示/非表示",
+        "  a      : 会話をアーカイブ",
+        "  M      : 会話をミュート",
+        "  L      : 会話にラベルを付ける",
         "",
         "■ メール表示画面",
         "  q/Esc  : メール一覧に戻る",
@@ -137,6 +331,9 @@ fn render_help(f: &mut Frame, _app: &mut App, area: Rect) {
         "  R      : 全員に返信",
         "  f      : 転送",
         "  d      : 削除",
+        "  u      : 既読/未読の切り替え",
+        "  s      : スターの切り替え",
+        "  m      : フォルダーへ移動",
         "",
         "■ 作成画面",
         "  Esc    : メール一覧に戻る",
@@ -182,15 +379,65 @@ fn render_tab_bar(f: &mut Frame, app: &App, area: Rect) {
 }
 
 fn render_folder_list(f: &mut Frame, app: &mut App, area: Rect) {
-    let folders = ["受信箱 (5)", "送信済み", "下書き", "ゴミ箱", "アーカイブ"];
-
-    let items: Vec<ListItem> = folders
-        .iter()
-        .map(|folder| ListItem::new(Line::from(Span::raw(*folder))))
+    let items: Vec<ListItem> = app
+        .folder_entries()
+        .into_iter()
+        .map(|entry| match entry {
+            FolderEntry::Folder {
+                node,
+                unread,
+                collapsed,
+            } => {
+                let open = app.active_search.is_none() && node.name == app.current_folder;
+                let mut style = if node.selectable {
+                    Style::default()
+                } else {
+                    Style::default().fg(Color::DarkGray)
+                };
+                if !node.subscribed {
+                    style = style.add_modifier(Modifier::ITALIC);
+                }
+                if open {
+                    style = style.add_modifier(Modifier::BOLD);
+                }
+                let marker = match (node.has_children, collapsed) {
+                    (false, _) => "  ",
+                    (true, true) => "▸ ",
+                    (true, false) => "▾ ",
+                };
+                let mut label = format!("{}{}{}", "  ".repeat(node.depth), marker, node.label);
+                if unread > 0 {
+                    label.push_str(&format!(" ({})", unread));
+                }
+                ListItem::new(Line::from(Span::styled(label, style)))
+            }
+            FolderEntry::SavedSearch { name, unread, .. } => {
+                let mut style = Style::default().fg(Color::Cyan);
+                if app.current_saved_search.as_deref() == Some(name.as_str()) {
+                    style = style.add_modifier(Modifier::BOLD);
+                }
+                let label = if unread > 0 {
+                    format!("🔍 {} ({})", name, unread)
+                } else {
+                    format!("🔍 {}", name)
+                };
+                ListItem::new(Line::from(Span::styled(label, style)))
+            }
+        })
         .collect();
 
+    let border_style = if app.folder_focus {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default()
+    };
     let list = List::new(items)
-        .block(Block::default().title("フォルダ").borders(Borders::ALL))
+        .block(
+            Block::default()
+                .title("フォルダ")
+                .borders(Borders::ALL)
+                .border_style(border_style),
+        )
         .style(Style::default().fg(Color::White))
         .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
         .highlight_symbol(">> ");
@@ -199,10 +446,16 @@ fn render_folder_list(f: &mut Frame, app: &mut App, area: Rect) {
 }
 
 fn render_message_list(f: &mut Frame, app: &mut App, area: Rect) {
-    let items: Vec<ListItem> = app
-        .messages
+    if app.conversation_mode {
+        render_thread_list(f, app, area);
+        return;
+    }
+
+    let rows = app.message_rows();
+    let items: Vec<ListItem> = rows
         .iter()
-        .map(|message| {
+        .map(|row| {
+            let message = &app.messages[row.index];
             let style = if message.is_unread() {
                 Style::default().add_modifier(Modifier::BOLD)
             } else {
@@ -216,7 +469,20 @@ fn render_message_list(f: &mut Frame, app: &mut App, area: Rect) {
             };
             let flag_indicator = if message.is_flagged() { "🏴 " } else { "" };
 
+            // スレッドの木での位置（返信は字下げし、畳んだものは返信数を出す）
+            let mut tree = String::new();
+            if row.depth > 0 {
+                tree.push_str(&"  ".repeat(row.depth - 1));
+                tree.push_str("└ ");
+            }
+            if row.collapsed {
+                tree.push_str(&format!("▸ ({}) ", row.replies));
+            } else if row.replies > 0 {
+                tree.push_str("▾ ");
+            }
+
             let line = Line::from(vec![
+                Span::styled(tree, Style::default().fg(Color::DarkGray)),
                 Span::styled(
                     format!("{}{}● ", attachment_indicator, flag_indicator),
                     style,
@@ -228,7 +494,15 @@ fn render_message_list(f: &mut Frame, app: &mut App, area: Rect) {
                 Span::styled(message.format_date(), Style::default().fg(Color::Gray)),
             ]);
 
-            ListItem::new(line).style(style)
+            let mut lines = vec![line];
+            if let Some(snippet) = app.search_snippet(message) {
+                lines.push(snippet_line(
+                    &format!("{}/{}", message.account_id, message.folder),
+                    snippet,
+                ));
+            }
+
+            ListItem::new(lines).style(style)
         })
         .collect();
 
@@ -241,6 +515,88 @@ fn render_message_list(f: &mut Frame, app: &mut App, area: Rect) {
     f.render_stateful_widget(list, area, &mut app.mail_list_state);
 }
 
+/// 検索結果のスニペットを一致箇所を強調して1行にする
+fn snippet_line(location: &str, snippet: &str) -> Line<'static> {
+    let mut spans = vec![Span::styled(
+        format!("   [{}] ", location),
+        Style::default().fg(Color::DarkGray),
+    )];
+
+    let snippet = snippet.replace(['\r', '\n'], " ");
+    for (index, part) in snippet.split(SNIPPET_START).enumerate() {
+        // 最初の断片以外は SNIPPET_START の直後で、SNIPPET_END までが一致箇所
+        let (matched, rest) = match part.split_once(SNIPPET_END) {
+            Some((matched, rest)) if index > 0 => (matched, rest),
+            _ => ("", part),
+        };
+        if !matched.is_empty() {
+            spans.push(Span::styled(
+                matched.to_string(),
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ));
+        }
+        if !rest.is_empty() {
+            spans.push(Span::styled(
+                rest.to_string(),
+                Style::default().fg(Color::Gray),
+            ));
+        }
+    }
+
+    Line::from(spans)
+}
+
+fn render_thread_list(f: &mut Frame, app: &mut App, area: Rect) {
+    let items: Vec<ListItem> = app
+        .threads
+        .iter()
+        .map(|thread| {
+            let style = if thread.has_unread() {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default()
+            };
+
+            let line = Line::from(vec![
+                Span::styled(format!("({}) ", thread.message_count()), style),
+                Span::styled(thread.get_participants_display(), style),
+                Span::raw(" - "),
+                Span::styled(thread.subject().to_string(), style),
+                Span::raw("  "),
+                Span::styled(thread.format_date(), Style::default().fg(Color::Gray)),
+            ]);
+
+            ListItem::new(line).style(style)
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(Block::default().title("会話一覧").borders(Borders::ALL))
+        .style(Style::default().fg(Color::White))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol(">> ");
+
+    f.render_stateful_widget(list, area, &mut app.mail_list_state);
+}
+
+/// 一覧で選択中のメッセージのプレビュー
+fn render_preview(f: &mut Frame, app: &App, area: Rect) {
+    match app.selected_message() {
+        Some(message) if message.body_pending && !app.is_online() => {
+            let block = Block::default().title("プレビュー").borders(Borders::ALL);
+            let paragraph = Paragraph::new("オフラインのため本文を取得できません").block(block);
+            f.render_widget(paragraph, area);
+        }
+        Some(message) => render_message_detail(f, message, area),
+        None => {
+            let block = Block::default().title("プレビュー").borders(Borders::ALL);
+            f.render_widget(block, area);
+        }
+    }
+}
+
 fn render_message_detail(f: &mut Frame, message: &crate::mail::Message, area: Rect) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
@@ -285,6 +641,7 @@ fn render_status_bar(f: &mut Frame, app: &App, area: Rect) {
         InputMode::Search => format!("検索: {} | {}", app.search_query, app.status_message),
         _ => app.status_message.clone(),
     };
+    let status_text = format!("{} | {}", app.connection_status(), status_text);
 
     let paragraph =
         Paragraph::new(status_text).style(Style::default().bg(Color::Blue).fg(Color::White));
@@ -302,6 +659,88 @@ fn render_search_bar(f: &mut Frame, app: &App, area: Rect) {
     f.render_widget(paragraph, popup_area);
 }
 
+fn render_label_bar(f: &mut Frame, app: &App, area: Rect) {
+    let popup_area = centered_rect(60, 20, area);
+    f.render_widget(Clear, popup_area);
+
+    let label_text = format!("ラベル名: {}", app.label_input);
+    let paragraph = Paragraph::new(label_text)
+        .block(
+            Block::default()
+                .title("ラベルを付ける")
+                .borders(Borders::ALL),
+        )
+        .style(Style::default().bg(Color::Black).fg(Color::White));
+    f.render_widget(paragraph, popup_area);
+}
+
+fn render_move_bar(f: &mut Frame, app: &App, area: Rect) {
+    let popup_area = centered_rect(60, 20, area);
+    f.render_widget(Clear, popup_area);
+
+    let folder_text = format!("移動先フォルダー: {}", app.folder_input);
+    let paragraph = Paragraph::new(folder_text)
+        .block(
+            Block::default()
+                .title("メッセージを移動")
+                .borders(Borders::ALL),
+        )
+        .style(Style::default().bg(Color::Black).fg(Color::White));
+    f.render_widget(paragraph, popup_area);
+}
+
+fn render_save_search_bar(f: &mut Frame, app: &App, area: Rect) {
+    let popup_area = centered_rect(60, 20, area);
+    f.render_widget(Clear, popup_area);
+
+    let name_text = format!(
+        "名前: {}\n検索: {}",
+        app.search_name_input,
+        app.active_search.as_deref().unwrap_or_default()
+    );
+    let paragraph = Paragraph::new(name_text)
+        .block(Block::default().title("検索を保存").borders(Borders::ALL))
+        .style(Style::default().bg(Color::Black).fg(Color::White));
+    f.render_widget(paragraph, popup_area);
+}
+
+fn render_folder_name_bar(f: &mut Frame, app: &App, area: Rect) {
+    let popup_area = centered_rect(60, 20, area);
+    f.render_widget(Clear, popup_area);
+
+    let title = match &app.folder_operation {
+        Some(FolderOperation::Rename { from, .. }) => format!("「{}」の名前を変更", from),
+        _ => "フォルダーを作成".to_string(),
+    };
+    let name_text = format!("フォルダー名: {}", app.folder_name_input);
+    let paragraph = Paragraph::new(name_text)
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .style(Style::default().bg(Color::Black).fg(Color::White));
+    f.render_widget(paragraph, popup_area);
+}
+
+fn render_confirm_dialog(f: &mut Frame, app: &App, area: Rect) {
+    let popup_area = centered_rect(60, 20, area);
+    f.render_widget(Clear, popup_area);
+
+    let description = app
+        .folder_operation
+        .as_ref()
+        .map(FolderOperation::description)
+        .unwrap_or_default();
+    let text = format!("{}\n\nよろしいですか？ (y/n)", description);
+    let paragraph = Paragraph::new(text)
+        .wrap(ratatui::widgets::Wrap { trim: false })
+        .block(
+            Block::default()
+                .title("確認")
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(Color::Yellow)),
+        )
+        .style(Style::default().bg(Color::Black).fg(Color::White));
+    f.render_widget(paragraph, popup_area);
+}
+
 // ポップアップ用のヘルパー関数
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::default()
@@ -322,3 +761,47 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         ])
         .split(popup_layout[1])[1]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collapse_quoted() {
+        let body = "Thanks!\n\
+                    On Mon, 4 Mar 2024, Alice <alice@example.com> wrote:\n\
+                    > First\n\
+                    >> Nested\n\
+                    \x20 > indented\n\
+                    Reply below\n\
+                    > trailing";
+
+        assert_eq!(
+            collapse_quoted(body, false),
+            vec![
+                "Thanks!",
+                "[引用 4 行を省略]",
+                "Reply below",
+                "[引用 1 行を省略]",
+            ]
+        );
+        assert_eq!(
+            collapse_quoted(body, true),
+            vec![
+                "Thanks!",
+                "On Mon, 4 Mar 2024, Alice <alice@example.com> wrote:",
+                "> First",
+                ">> Nested",
+                "> indented",
+                "Reply below",
+                "> trailing",
+            ]
+        );
+
+        // 引用が続かない「wrote:」の行はそのまま残す
+        assert_eq!(
+            collapse_quoted("He wrote:\nnothing quoted", false),
+            vec!["He wrote:", "nothing quoted"]
+        );
+    }
+}